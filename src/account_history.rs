@@ -0,0 +1,291 @@
+//! Bulk export of account history.
+//!
+//! History servers cap how much one request returns and how often they
+//! may be called.  `AccountHistoryExporter` walks a long range in windows,
+//! pages within each window until the server returns a short page, paces
+//! requests to a minimum interval, retries failed requests with backoff,
+//! and thins the snapshots to the requested granularity (the last snapshot
+//! in each bucket wins).  The result is a tidy record set, one row per
+//! balance or position per snapshot, that can be written as CSV, or with
+//! the `arrow` feature as Parquet.
+//!
+//! The server API is behind `AccountHistorySource`, so the exporter works
+//! with whichever client the process uses to reach it.
+
+use crate::csv;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use futures::future::BoxFuture;
+use log::{debug, warn};
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+use std::io::Write;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    /// every snapshot the server has
+    Raw,
+    Minute,
+    FiveMinutes,
+    Hour,
+    Day,
+}
+
+impl Granularity {
+    pub fn bucket(&self) -> Option<Duration> {
+        match self {
+            Granularity::Raw => None,
+            Granularity::Minute => Some(Duration::minutes(1)),
+            Granularity::FiveMinutes => Some(Duration::minutes(5)),
+            Granularity::Hour => Some(Duration::hours(1)),
+            Granularity::Day => Some(Duration::days(1)),
+        }
+    }
+}
+
+/// One point in an account's history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountSnapshot {
+    pub timestamp: DateTime<Utc>,
+    /// (product, amount)
+    pub balances: Vec<(String, Decimal)>,
+    /// (market, signed quantity)
+    pub positions: Vec<(String, Decimal)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryPage {
+    pub account: String,
+    /// inclusive
+    pub from: DateTime<Utc>,
+    /// exclusive
+    pub to: DateTime<Utc>,
+    /// a hint; the exporter thins finer data itself
+    pub granularity: Granularity,
+    pub limit: usize,
+}
+
+/// A server of account history, e.g. the folio service
+pub trait AccountHistorySource: Send + Sync {
+    /// At most `page.limit` snapshots in the page's range, oldest first
+    fn fetch(
+        &self,
+        page: HistoryPage,
+    ) -> BoxFuture<'static, Result<Vec<AccountSnapshot>>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    Balance,
+    Position,
+}
+
+/// A row of the exported history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountHistoryRecord {
+    pub timestamp: DateTime<Utc>,
+    pub account: String,
+    pub kind: RecordKind,
+    /// the product for balances, the market for positions
+    pub symbol: String,
+    pub quantity: Decimal,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExportConfig {
+    pub granularity: Granularity,
+    /// the range asked for in one request
+    pub window: Duration,
+    /// the server's page size limit
+    pub page_limit: usize,
+    /// the minimum time between requests
+    pub min_interval: std::time::Duration,
+    pub max_retries: u32,
+    /// the delay before the first retry, doubled after each
+    pub backoff: std::time::Duration,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            granularity: Granularity::Hour,
+            window: Duration::days(7),
+            page_limit: 1000,
+            min_interval: std::time::Duration::from_millis(200),
+            max_retries: 5,
+            backoff: std::time::Duration::from_secs(1),
+        }
+    }
+}
+
+pub struct AccountHistoryExporter<S> {
+    source: S,
+    config: ExportConfig,
+    last_request: Option<Instant>,
+}
+
+impl<S: AccountHistorySource> AccountHistoryExporter<S> {
+    pub fn new(source: S, config: ExportConfig) -> Result<Self> {
+        if config.window <= Duration::zero() || config.page_limit == 0 {
+            bail!("export window and page limit must be positive");
+        }
+        Ok(Self { source, config, last_request: None })
+    }
+
+    /// Fetch the history of `account` in [from, to), thinned to the
+    /// configured granularity
+    pub async fn snapshots(
+        &mut self,
+        account: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<AccountSnapshot>> {
+        let mut res: Vec<AccountSnapshot> = vec![];
+        let mut window_start = from;
+        while window_start < to {
+            let window_end = (window_start + self.config.window).min(to);
+            let mut page_start = window_start;
+            loop {
+                let page = HistoryPage {
+                    account: account.to_string(),
+                    from: page_start,
+                    to: window_end,
+                    granularity: self.config.granularity,
+                    limit: self.config.page_limit,
+                };
+                let snaps = self.fetch(page).await?;
+                let full = snaps.len() >= self.config.page_limit;
+                let last = snaps.last().map(|s| s.timestamp);
+                for snap in snaps {
+                    self.push(&mut res, snap);
+                }
+                match last {
+                    // a full page may have more at the same timestamp; the
+                    // overlap is deduplicated by push
+                    Some(last) if full && last > page_start => page_start = last,
+                    Some(_) if full => {
+                        warn!("a page of {account} history at {page_start} is all one timestamp, skipping ahead");
+                        page_start += Duration::nanoseconds(1);
+                    }
+                    _ => break,
+                }
+                if page_start >= window_end {
+                    break;
+                }
+            }
+            window_start = window_end;
+        }
+        Ok(res)
+    }
+
+    /// Fetch and flatten the history of `account` in [from, to)
+    pub async fn export(
+        &mut self,
+        account: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<AccountHistoryRecord>> {
+        let snaps = self.snapshots(account, from, to).await?;
+        Ok(flatten(account, &snaps))
+    }
+
+    /// Keep the last snapshot in each granularity bucket
+    fn push(&self, res: &mut Vec<AccountSnapshot>, snap: AccountSnapshot) {
+        let key = |ts: DateTime<Utc>| match self.config.granularity.bucket() {
+            None => Some(ts),
+            Some(b) => ts.duration_trunc(b).ok(),
+        };
+        match res.last_mut() {
+            Some(prev) if prev.timestamp > snap.timestamp => (),
+            Some(prev) if prev == &snap => (),
+            Some(prev) if key(prev.timestamp) == key(snap.timestamp) => *prev = snap,
+            _ => res.push(snap),
+        }
+    }
+
+    async fn fetch(&mut self, page: HistoryPage) -> Result<Vec<AccountSnapshot>> {
+        let mut backoff = self.config.backoff;
+        let mut attempt = 0;
+        loop {
+            if let Some(last) = self.last_request {
+                tokio::time::sleep_until(last + self.config.min_interval).await;
+            }
+            self.last_request = Some(Instant::now());
+            match self.source.fetch(page.clone()).await {
+                Ok(snaps) => return Ok(snaps),
+                Err(e) if attempt < self.config.max_retries => {
+                    debug!(
+                        "account history request failed, retrying in {backoff:?}: {e:?}"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => bail!(
+                    "account history request for {} from {} failed: {e:?}",
+                    page.account,
+                    page.from
+                ),
+            }
+        }
+    }
+}
+
+/// One record per balance and position in each snapshot
+pub fn flatten(account: &str, snaps: &[AccountSnapshot]) -> Vec<AccountHistoryRecord> {
+    let mut res = vec![];
+    for snap in snaps {
+        let rows = snap
+            .balances
+            .iter()
+            .map(|b| (RecordKind::Balance, b))
+            .chain(snap.positions.iter().map(|p| (RecordKind::Position, p)));
+        for (kind, (symbol, quantity)) in rows {
+            res.push(AccountHistoryRecord {
+                timestamp: snap.timestamp,
+                account: account.to_string(),
+                kind,
+                symbol: symbol.clone(),
+                quantity: *quantity,
+            });
+        }
+    }
+    res
+}
+
+/// Write records to a Parquet file at `path`, with the columns of
+/// `write_csv`; see `arrow::account_history_schema`
+#[cfg(feature = "arrow")]
+pub fn write_parquet(
+    path: impl AsRef<std::path::Path>,
+    records: &[AccountHistoryRecord],
+) -> Result<()> {
+    let batch = crate::arrow::account_history_to_record_batch(records)?;
+    crate::arrow::write_parquet(path, &[batch])
+}
+
+/// Write records as CSV with a header row
+pub fn write_csv<'a>(
+    mut w: impl Write,
+    records: impl IntoIterator<Item = &'a AccountHistoryRecord>,
+) -> Result<()> {
+    writeln!(w, "timestamp,account,kind,symbol,quantity")?;
+    for r in records {
+        let kind = match r.kind {
+            RecordKind::Balance => "balance",
+            RecordKind::Position => "position",
+        };
+        writeln!(
+            w,
+            "{},{},{kind},{},{}",
+            r.timestamp.to_rfc3339(),
+            csv::field(&r.account),
+            csv::field(&r.symbol),
+            r.quantity
+        )?;
+    }
+    Ok(())
+}
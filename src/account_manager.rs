@@ -1,4 +1,5 @@
 use crate::{
+    rpc::{Rpc, RpcClient},
     synced::{SyncHandle, Synced},
     ChannelDriver, Common,
 };
@@ -15,11 +16,24 @@ use futures::Stream;
 use futures_util::StreamExt;
 use immutable_chunkmap::map::MapL as Map;
 use log::{debug, error, info};
-use netidx::subscriber::{Event, UpdatesFlags};
-use netidx_protocols::rpc::client::Proc;
+use netidx::subscriber::{Event, UpdatesFlags, Value};
+use serde_derive::{Deserialize, Serialize};
 use std::{pin::Pin, sync::Arc};
 use uuid::Uuid;
 
+/// The account manager's `get-latest-snapshot` proc
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GetLatestSnapshot;
+
+impl Rpc for GetLatestSnapshot {
+    const NAME: &'static str = "get-latest-snapshot";
+    type Response = AccountsUpdate;
+
+    fn args(self) -> Result<Vec<(&'static str, Value)>> {
+        Ok(vec![])
+    }
+}
+
 // CR alee: server side should enforce the List permission...send filtered AccountUpdates
 
 #[derive(Debug, Clone)]
@@ -67,6 +81,7 @@ impl AccountManagerClient {
         use futures::channel::mpsc;
         let com = common.find_component_of_kind("AccountManager")?;
         let (base, _) = common.paths.component(com)?;
+        let rpc = RpcClient::new(&common.subscriber, base.clone());
         let (tx, mut rx) = mpsc::channel(3);
         let updates_sub = common.subscriber.subscribe(base.append("updates"));
         updates_sub.updates(UpdatesFlags::STOP_COLLECTING_LAST, tx);
@@ -75,10 +90,7 @@ impl AccountManagerClient {
         let mut seq;
         loop {
             debug!("loading snapshot");
-            let get_latest_snapshot =
-                Proc::new(&common.subscriber, base.append("get-latest-snapshot"))?;
-            let snap: AccountsUpdate =
-                get_latest_snapshot.call([] as [(&str, _); 0]).await?.cast_to()?;
+            let snap = rpc.call(GetLatestSnapshot).await?;
             epoch = snap.epoch;
             seq = snap.sequence_number;
             state.store(Arc::new(snap.into()));
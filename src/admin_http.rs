@@ -0,0 +1,303 @@
+//! A small optional HTTP admin endpoint, so services that don't run netidx
+//! (and so don't get admin_stats) are still debuggable in production.
+//!
+//! - `GET /health`: 200 if every registered health check passes, else 503
+//!   with the failures
+//! - `GET /log-level`, `PUT /log-level` with the new level as the body
+//! - `GET /dump`: a JSON object with one entry per registered dump
+//!   provider, e.g. subscriptions (`dump_subscriptions`), open orders
+//!   (`dump_orders`), sync states (`dump_synced`)
+//!
+//! Changing the log level is only allowed from loopback, or, if a token is
+//! set with `with_token`, from anywhere with `authorization: Bearer
+//! <token>`.
+//!
+//! It speaks just enough HTTP/1.1 for curl and load balancer probes; bind
+//! it to a private interface.
+
+#[cfg(feature = "netidx")]
+use crate::marketdata::managed_marketdata::ManagedMarketdata;
+use crate::{orderflow::tracker::OrderTracker, synced::Synced};
+use anyhow::{bail, Result};
+use futures::future::{BoxFuture, FutureExt};
+use log::{debug, info, LevelFilter};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::{future::Future, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    task::JoinHandle,
+};
+
+const MAX_REQUEST_LEN: usize = 16 * 1024;
+
+/// a client that hasn't sent its whole request by then is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+type HealthCheck = Box<dyn Fn() -> Result<()> + Send + Sync>;
+type DumpProvider = Box<dyn Fn() -> BoxFuture<'static, Value> + Send + Sync>;
+
+#[derive(Default)]
+pub struct AdminHttp {
+    health: Vec<(String, HealthCheck)>,
+    dumps: Vec<(String, DumpProvider)>,
+    token: Option<String>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: String,
+}
+
+impl AdminHttp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn health_check(
+        &mut self,
+        name: impl Into<String>,
+        f: impl Fn() -> Result<()> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.health.push((name.into(), Box::new(f)));
+        self
+    }
+
+    /// Allow changing the log level from any address that presents the
+    /// token, instead of only from loopback
+    pub fn with_token(&mut self, token: impl Into<String>) -> &mut Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn dump(
+        &mut self,
+        name: impl Into<String>,
+        f: impl Fn() -> Value + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.dumps
+            .push((name.into(), Box::new(move || futures::future::ready(f()).boxed())));
+        self
+    }
+
+    /// A dump provider that has to wait, e.g. on a lock
+    pub fn dump_async<F>(
+        &mut self,
+        name: impl Into<String>,
+        f: impl Fn() -> F + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        F: Future<Output = Value> + Send + 'static,
+    {
+        self.dumps.push((name.into(), Box::new(move || f().boxed())));
+        self
+    }
+
+    /// Dump the book subscriptions of `marketdata` as "subscriptions"
+    #[cfg(feature = "netidx")]
+    pub fn dump_subscriptions(
+        &mut self,
+        marketdata: Arc<ManagedMarketdata>,
+    ) -> &mut Self {
+        self.dump_async("subscriptions", move || {
+            let marketdata = marketdata.clone();
+            async move {
+                let subs = marketdata.subscriptions().await;
+                subs.into_iter()
+                    .map(|s| {
+                        json!({
+                            "market": s.market.name.as_str(),
+                            "path": s.path.to_string(),
+                            "synced": s.synced,
+                            "timestamp": s.timestamp,
+                        })
+                    })
+                    .collect()
+            }
+        })
+    }
+
+    /// Dump the open orders of `tracker` as "orders"
+    pub fn dump_orders(&mut self, tracker: Arc<Mutex<OrderTracker>>) -> &mut Self {
+        self.dump_async("orders", move || {
+            let tracker = tracker.clone();
+            async move {
+                let tracker = tracker.lock().await;
+                tracker
+                    .open_orders()
+                    .map(|o| {
+                        json!({
+                            "id": o.request.id,
+                            "market": o.request.market.name.as_str(),
+                            "dir": o.request.dir,
+                            "price": o.request.price,
+                            "quantity": o.request.quantity,
+                            "filled": o.filled,
+                            "state": format!("{:?}", o.state),
+                            "sent_at": o.sent_at,
+                        })
+                    })
+                    .collect()
+            }
+        })
+    }
+
+    /// Dump the current value of a sync state, e.g. the updates counter of
+    /// a book or the sync time of symbology
+    pub fn dump_synced<T: Serialize + Send + Sync + 'static>(
+        &mut self,
+        name: impl Into<String>,
+        synced: Synced<T>,
+    ) -> &mut Self {
+        self.dump(name, move || json!(&*synced.0.borrow()))
+    }
+
+    /// Bind and serve until the returned task is aborted
+    pub async fn serve(self, addr: SocketAddr) -> Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("admin http listening on {}", listener.local_addr()?);
+        let admin = Arc::new(self);
+        Ok(tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        debug!("admin http accept failed: {e:?}");
+                        continue;
+                    }
+                };
+                let admin = admin.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = admin.handle(stream).await {
+                        debug!("admin http request failed: {e:?}");
+                    }
+                });
+            }
+        }))
+    }
+
+    async fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        let req = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream))
+            .await
+        {
+            Ok(req) => req?,
+            Err(_) => bail!("timed out reading request"),
+        };
+        let authorized = match &self.token {
+            Some(token) => {
+                req.authorization.as_deref() == Some(&format!("Bearer {token}"))
+            }
+            None => stream.peer_addr()?.ip().is_loopback(),
+        };
+        let (status, body) = self.route(&req, authorized).await;
+        let resp = format!(
+            "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(resp.as_bytes()).await?;
+        Ok(stream.shutdown().await?)
+    }
+
+    async fn route(&self, req: &Request, authorized: bool) -> (&'static str, String) {
+        let body = &req.body;
+        match (req.method.as_str(), req.path.split('?').next().unwrap_or("")) {
+            ("GET", "/health") => {
+                let failed: Map<String, Value> = self
+                    .health
+                    .iter()
+                    .filter_map(|(name, f)| {
+                        f().err().map(|e| (name.clone(), json!(e.to_string())))
+                    })
+                    .collect();
+                if failed.is_empty() {
+                    ("200 OK", json!({ "ok": true }).to_string())
+                } else {
+                    (
+                        "503 Service Unavailable",
+                        json!({ "ok": false, "failed": failed }).to_string(),
+                    )
+                }
+            }
+            ("GET", "/log-level") => {
+                ("200 OK", json!({ "level": log::max_level().to_string() }).to_string())
+            }
+            ("PUT" | "POST", "/log-level") if !authorized => {
+                ("403 Forbidden", json!({ "error": "forbidden" }).to_string())
+            }
+            ("PUT" | "POST", "/log-level") => {
+                match LevelFilter::from_str(body.trim().trim_matches('"')) {
+                    Ok(level) => {
+                        log::set_max_level(level);
+                        info!("log level set to {level} over admin http");
+                        ("200 OK", json!({ "level": level.to_string() }).to_string())
+                    }
+                    Err(_) => (
+                        "400 Bad Request",
+                        json!({ "error": format!("invalid log level {body}") })
+                            .to_string(),
+                    ),
+                }
+            }
+            ("GET", "/dump") => {
+                let mut dump = Map::new();
+                for (name, f) in &self.dumps {
+                    dump.insert(name.clone(), f().await);
+                }
+                ("200 OK", Value::Object(dump).to_string())
+            }
+            _ => ("404 Not Found", json!({ "error": "not found" }).to_string()),
+        }
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut buf = Vec::with_capacity(1024);
+    let header_end = loop {
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        if buf.len() > MAX_REQUEST_LEN {
+            bail!("request too large");
+        }
+        let mut chunk = [0u8; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("connection closed mid request");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut parts = lines.next().unwrap_or("").split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+    let headers: Vec<(&str, &str)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .collect();
+    let header = |name: &str| {
+        headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| *v)
+    };
+    let content_length =
+        header("content-length").and_then(|v| v.parse::<usize>().ok()).unwrap_or(0);
+    let authorization = header("authorization").map(|v| v.to_string());
+    if content_length > MAX_REQUEST_LEN {
+        bail!("request body too large");
+    }
+    let mut body = buf[header_end..].to_vec();
+    while body.len() < content_length {
+        let mut chunk = [0u8; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(content_length);
+    let body = String::from_utf8_lossy(&body).to_string();
+    Ok(Request { method, path, authorization, body })
+}
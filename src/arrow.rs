@@ -0,0 +1,218 @@
+//! Materialize marketdata as Apache Arrow record batches and Parquet files.
+//!
+//! Candles from `historical_candles`, bars from `resample` or
+//! `candle_builder`, timestamped trades, and `account_history` records
+//! convert to a `RecordBatch`
+//! with one row per item, ready for DataFusion, Polars, or pyarrow without
+//! a hand written conversion; `write_parquet` writes batches to a file.
+//!
+//! Timestamps are UTC nanoseconds.  Prices and sizes are `Decimal128`
+//! with `DECIMAL_SCALE` places, so they convert exactly; values with more
+//! places are rounded.
+
+use crate::{
+    account_history::{AccountHistoryRecord, RecordKind},
+    marketdata::{resample::Bar, time_and_sales::Print},
+};
+use ::arrow::{
+    array::{
+        ArrayRef, Decimal128Array, StringArray, TimestampNanosecondArray, UInt64Array,
+    },
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    record_batch::RecordBatch,
+};
+use anyhow::{bail, Result};
+use api::{marketdata::CandleV1, Dir};
+use chrono::{DateTime, Utc};
+use parquet::{
+    arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties,
+};
+use rust_decimal::Decimal;
+use std::{fs::File, path::Path, sync::Arc};
+
+/// Decimal places of price and size columns
+pub const DECIMAL_SCALE: i8 = 10;
+
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()))
+}
+
+fn decimal_type() -> DataType {
+    DataType::Decimal128(38, DECIMAL_SCALE)
+}
+
+fn timestamps(ts: impl Iterator<Item = DateTime<Utc>>) -> Result<ArrayRef> {
+    let mut res = vec![];
+    for t in ts {
+        match t.timestamp_nanos_opt() {
+            Some(ns) => res.push(ns),
+            None => bail!("timestamp {t} out of range"),
+        }
+    }
+    Ok(Arc::new(TimestampNanosecondArray::from(res).with_timezone("UTC")))
+}
+
+fn decimals(ds: impl Iterator<Item = Decimal>) -> Result<ArrayRef> {
+    let res = ds
+        .map(|mut d| {
+            d.rescale(DECIMAL_SCALE as u32);
+            d.mantissa()
+        })
+        .collect::<Vec<_>>();
+    Ok(Arc::new(Decimal128Array::from(res).with_precision_and_scale(38, DECIMAL_SCALE)?))
+}
+
+/// time, open, high, low, close, volume
+pub fn candle_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("time", timestamp_type(), false),
+        Field::new("open", decimal_type(), false),
+        Field::new("high", decimal_type(), false),
+        Field::new("low", decimal_type(), false),
+        Field::new("close", decimal_type(), false),
+        Field::new("volume", decimal_type(), false),
+    ]))
+}
+
+/// e.g. the result of `historical_candles::get`
+pub fn candles_to_record_batch(candles: &[CandleV1]) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(
+        candle_schema(),
+        vec![
+            timestamps(candles.iter().map(|c| c.time))?,
+            decimals(candles.iter().map(|c| c.open))?,
+            decimals(candles.iter().map(|c| c.high))?,
+            decimals(candles.iter().map(|c| c.low))?,
+            decimals(candles.iter().map(|c| c.close))?,
+            decimals(candles.iter().map(|c| c.volume))?,
+        ],
+    )?)
+}
+
+/// start, end, open, high, low, close, volume, notional, trades
+pub fn bar_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("start", timestamp_type(), false),
+        Field::new("end", timestamp_type(), false),
+        Field::new("open", decimal_type(), false),
+        Field::new("high", decimal_type(), false),
+        Field::new("low", decimal_type(), false),
+        Field::new("close", decimal_type(), false),
+        Field::new("volume", decimal_type(), false),
+        Field::new("notional", decimal_type(), false),
+        Field::new("trades", DataType::UInt64, false),
+    ]))
+}
+
+pub fn bars_to_record_batch(bars: &[Bar]) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(
+        bar_schema(),
+        vec![
+            timestamps(bars.iter().map(|b| b.start))?,
+            timestamps(bars.iter().map(|b| b.end))?,
+            decimals(bars.iter().map(|b| b.open))?,
+            decimals(bars.iter().map(|b| b.high))?,
+            decimals(bars.iter().map(|b| b.low))?,
+            decimals(bars.iter().map(|b| b.close))?,
+            decimals(bars.iter().map(|b| b.volume))?,
+            decimals(bars.iter().map(|b| b.notional))?,
+            Arc::new(UInt64Array::from_iter_values(bars.iter().map(|b| b.trades))),
+        ],
+    )?)
+}
+
+/// time, price, size, dir; dir is "buy", "sell", or null if unknown
+pub fn trade_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("time", timestamp_type(), false),
+        Field::new("price", decimal_type(), false),
+        Field::new("size", decimal_type(), false),
+        Field::new("dir", DataType::Utf8, true),
+    ]))
+}
+
+pub fn trades_to_record_batch(trades: &[(DateTime<Utc>, Print)]) -> Result<RecordBatch> {
+    let dirs = trades
+        .iter()
+        .map(|(_, p)| {
+            p.dir.map(|d| match d {
+                Dir::Buy => "buy",
+                Dir::Sell => "sell",
+            })
+        })
+        .collect::<StringArray>();
+    Ok(RecordBatch::try_new(
+        trade_schema(),
+        vec![
+            timestamps(trades.iter().map(|(t, _)| *t))?,
+            decimals(trades.iter().map(|(_, p)| p.price))?,
+            decimals(trades.iter().map(|(_, p)| p.size))?,
+            Arc::new(dirs),
+        ],
+    )?)
+}
+
+/// timestamp, account, kind, symbol, quantity; kind is "balance" or
+/// "position", as in `account_history::write_csv`
+pub fn account_history_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("timestamp", timestamp_type(), false),
+        Field::new("account", DataType::Utf8, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("quantity", decimal_type(), false),
+    ]))
+}
+
+pub fn account_history_to_record_batch(
+    records: &[AccountHistoryRecord],
+) -> Result<RecordBatch> {
+    let kinds = records
+        .iter()
+        .map(|r| match r.kind {
+            RecordKind::Balance => Some("balance"),
+            RecordKind::Position => Some("position"),
+        })
+        .collect::<StringArray>();
+    Ok(RecordBatch::try_new(
+        account_history_schema(),
+        vec![
+            timestamps(records.iter().map(|r| r.timestamp))?,
+            Arc::new(records.iter().map(|r| Some(&r.account)).collect::<StringArray>()),
+            Arc::new(kinds),
+            Arc::new(records.iter().map(|r| Some(&r.symbol)).collect::<StringArray>()),
+            decimals(records.iter().map(|r| r.quantity))?,
+        ],
+    )?)
+}
+
+/// Write `batches`, which must share a schema, to a zstd compressed
+/// Parquet file at `path`
+pub fn write_parquet(path: impl AsRef<Path>, batches: &[RecordBatch]) -> Result<()> {
+    let Some(first) = batches.first() else { bail!("no record batches to write") };
+    let props = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(Default::default()))
+        .build();
+    let mut writer =
+        ArrowWriter::try_new(File::create(path)?, first.schema(), Some(props))?;
+    for batch in batches {
+        writer.write(batch)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+/// Fetch historical candles and convert them, see `historical_candles::get`
+#[cfg(feature = "netidx")]
+pub async fn get_historical_candles(
+    common: &crate::Common,
+    market: crate::symbology::MarketRef,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    width: api::marketdata::CandleWidth,
+) -> Result<RecordBatch> {
+    let candles =
+        crate::marketdata::historical_candles::get(common, market, start, end, width)
+            .await?;
+    candles_to_record_batch(&candles)
+}
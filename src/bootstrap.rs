@@ -0,0 +1,166 @@
+//! A well defined starting point for trading.
+//!
+//! `ArchitectClient::bootstrap` loads symbology, accounts, open orders,
+//! recent fills and positions concurrently under one overall deadline and
+//! returns them together as a `BootstrapState`.  Each part records whether
+//! it loaded, failed or ran out of time, so an application can decide
+//! whether it's ready to trade or which parts to retry.
+//!
+//! Symbology is loaded from the configured symbology endpoints; the account
+//! and order state comes from a `BootstrapSource`, so bootstrap works with
+//! whichever client the process uses to reach the Oms and account manager.
+
+use crate::orderflow::{
+    drift::AccountSummary,
+    fill_store::{FillStore, StoredFill},
+    state::TrackedOrder,
+};
+use anyhow::Result;
+use api::AccountId;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use fxhash::FxHashMap;
+use std::{future::Future, time::Duration};
+use tokio::time::Instant;
+
+/// Where the account and order state comes from, e.g. the Oms
+pub trait BootstrapSource: Send + Sync {
+    fn accounts(&self) -> BoxFuture<'static, Result<Vec<AccountId>>>;
+
+    fn open_orders(&self) -> BoxFuture<'static, Result<Vec<TrackedOrder>>>;
+
+    /// Fills at or after `since`
+    fn fills_since(
+        &self,
+        since: DateTime<Utc>,
+    ) -> BoxFuture<'static, Result<Vec<StoredFill>>>;
+
+    fn positions(
+        &self,
+    ) -> BoxFuture<'static, Result<FxHashMap<AccountId, AccountSummary>>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct BootstrapConfig {
+    /// symbology is loaded from each in turn
+    pub symbology_endpoints: Vec<String>,
+    /// how far back to load fills
+    pub fills_lookback: chrono::Duration,
+    /// the overall deadline for every part
+    pub deadline: Duration,
+}
+
+impl Default for BootstrapConfig {
+    fn default() -> Self {
+        Self {
+            symbology_endpoints: vec![],
+            fills_lookback: chrono::Duration::days(1),
+            deadline: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Loaded<T> {
+    Ready(T),
+    Failed(String),
+    TimedOut,
+}
+
+impl<T> Loaded<T> {
+    pub fn is_ready(&self) -> bool {
+        matches!(self, Loaded::Ready(_))
+    }
+
+    pub fn ready(&self) -> Option<&T> {
+        match self {
+            Loaded::Ready(t) => Some(t),
+            Loaded::Failed(_) | Loaded::TimedOut => None,
+        }
+    }
+
+    async fn within(deadline: Instant, fut: impl Future<Output = Result<T>>) -> Self {
+        match tokio::time::timeout_at(deadline, fut).await {
+            Ok(Ok(t)) => Loaded::Ready(t),
+            Ok(Err(e)) => Loaded::Failed(e.to_string()),
+            Err(_) => Loaded::TimedOut,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BootstrapState {
+    pub symbology: Loaded<()>,
+    pub accounts: Loaded<Vec<AccountId>>,
+    pub open_orders: Loaded<Vec<TrackedOrder>>,
+    pub recent_fills: Loaded<FillStore>,
+    pub positions: Loaded<FxHashMap<AccountId, AccountSummary>>,
+    /// when bootstrap started
+    pub started_at: DateTime<Utc>,
+    pub elapsed: Duration,
+}
+
+impl BootstrapState {
+    /// Every part loaded
+    pub fn is_ready(&self) -> bool {
+        self.not_ready().is_empty()
+    }
+
+    /// The parts that failed or timed out, by name
+    pub fn not_ready(&self) -> Vec<&'static str> {
+        [
+            ("symbology", self.symbology.is_ready()),
+            ("accounts", self.accounts.is_ready()),
+            ("open_orders", self.open_orders.is_ready()),
+            ("recent_fills", self.recent_fills.is_ready()),
+            ("positions", self.positions.is_ready()),
+        ]
+        .into_iter()
+        .filter_map(|(name, ready)| (!ready).then_some(name))
+        .collect()
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl crate::ArchitectClient {
+    /// Load everything needed to start trading, concurrently, giving up on
+    /// whatever hasn't loaded by `config.deadline`
+    pub async fn bootstrap(
+        &self,
+        source: &dyn BootstrapSource,
+        config: &BootstrapConfig,
+    ) -> BootstrapState {
+        let started_at = Utc::now();
+        let start = Instant::now();
+        let deadline = start + config.deadline;
+        let (symbology, accounts, open_orders, recent_fills, positions) = tokio::join!(
+            Loaded::within(
+                deadline,
+                self.load_symbology_from_all(&config.symbology_endpoints)
+            ),
+            Loaded::within(deadline, source.accounts()),
+            Loaded::within(deadline, source.open_orders()),
+            Loaded::within(deadline, async {
+                let fills =
+                    source.fills_since(started_at - config.fills_lookback).await?;
+                let mut store = FillStore::new();
+                store.extend(fills);
+                Ok(store)
+            }),
+            Loaded::within(deadline, source.positions()),
+        );
+        let state = BootstrapState {
+            symbology,
+            accounts,
+            open_orders,
+            recent_fills,
+            positions,
+            started_at,
+            elapsed: start.elapsed(),
+        };
+        if !state.is_ready() {
+            log::warn!("bootstrap incomplete, not ready: {:?}", state.not_ready());
+        }
+        state
+    }
+}
@@ -1,7 +1,10 @@
 //! Core channel driver--wraps the underlying netidx pack_channel with
 //! useful specialized functions.
 
-use crate::Common;
+use crate::{
+    reconnect::{Backoff, BackoffConfig},
+    throttled_error, Common,
+};
 use anyhow::{anyhow, bail, Result};
 use api::{
     channel_control::ChannelControlMessage, utils::messaging::MaybeRequest, Address,
@@ -9,7 +12,7 @@ use api::{
 };
 use enumflags2::BitFlags;
 use futures_util::{select_biased, FutureExt};
-use log::{debug, error};
+use log::debug;
 use netidx::{path::Path, subscriber::Subscriber};
 use netidx_protocols::pack_channel;
 use std::sync::{Arc, RwLock};
@@ -105,7 +108,10 @@ impl ChannelDriver {
             let tx_reconnected = tx_reconnected.clone();
             task::spawn({
                 async move {
+                    let mut backoff =
+                        Backoff::new("channel_driver", BackoffConfig::default());
                     loop {
+                        let connected_at = std::time::Instant::now();
                         let res = Self::connect_inner(
                             &subscriber,
                             channel_path.clone(),
@@ -120,9 +126,9 @@ impl ChannelDriver {
                         .await;
                         channel_ready_tx.send_replace(false);
                         if let Err(e) = res {
-                            error!("channel driver error, reconnecting in 1s: {}", e);
-                            let delay = std::time::Duration::from_secs(1);
-                            tokio::time::sleep(delay).await;
+                            throttled_error!("channel driver error, reconnecting: {}", e);
+                            backoff.on_disconnect(connected_at.elapsed());
+                            backoff.wait().await;
                         } else {
                             // graceful shutdown
                             break;
@@ -187,7 +193,7 @@ impl ChannelDriver {
             let buf = std::mem::replace(&mut messages, Vec::new());
             if !buf.is_empty() {
                 if let Err(e) = tx.send(Arc::new(buf)) {
-                    error!("channel driver send error, dropping: {}", e);
+                    throttled_error!("channel driver send error, dropping: {}", e);
                 }
             }
             if closed || conn.is_dead() {
@@ -202,6 +208,11 @@ impl ChannelDriver {
         Ok(())
     }
 
+    /// True while the channel is connected
+    pub fn subscribe_connected(&self) -> watch::Receiver<bool> {
+        self.channel_ready.clone()
+    }
+
     /// Close the channel, waiting for all queued messages to send
     pub async fn close(&mut self) -> Result<()> {
         if let Some((close_tx, join)) = self.close.take() {
@@ -267,6 +278,33 @@ impl ChannelDriver {
         })?
     }
 
+    /// Send several messages to the same destination in a single write
+    pub fn send_batch_to<M>(
+        &self,
+        dst: ComponentId,
+        msgs: impl IntoIterator<Item = M>,
+    ) -> Result<()>
+    where
+        M: Into<TypedMessage>,
+    {
+        self.with_channel(|conn, src| {
+            let user_id = match src {
+                Address::Channel(user_id, _) => Some(user_id),
+                _ => None,
+            };
+            let mut batch = conn.start_batch();
+            for msg in msgs {
+                batch.queue(&Envelope {
+                    src: src.clone(),
+                    dst: Address::Component(dst),
+                    stamp: Stamp::new(user_id, Default::default()),
+                    msg: msg.into(),
+                })?;
+            }
+            conn.send(batch)
+        })?
+    }
+
     pub fn subscribe(&self) -> broadcast::Receiver<Arc<Vec<Envelope<TypedMessage>>>> {
         self.tx.subscribe()
     }
@@ -1,5 +1,13 @@
 //! General purpose client for Architect
 
+#[cfg(feature = "grpc")]
+use crate::reconnect::{Backoff, BackoffConfig};
+use crate::symbology::resolve::{resolve_symbol, ResolvedSymbol};
+#[cfg(feature = "grpc")]
+use crate::{
+    marketdata::ticker_cache::{Ticker, TickerCache},
+    throttled_error, throttled_warn,
+};
 #[cfg(feature = "grpc")]
 use anyhow::{anyhow, Result};
 #[cfg(feature = "grpc")]
@@ -8,31 +16,365 @@ use api::{
     grpc::json_service::{marketdata_client::*, symbology_client::*},
 };
 #[cfg(feature = "grpc")]
+use fxhash::{FxHashMap, FxHashSet};
+#[cfg(feature = "grpc")]
 use hickory_resolver::{config::*, TokioAsyncResolver};
 #[cfg(feature = "grpc")]
-use tonic::codec::Streaming;
+use log::debug;
+#[cfg(feature = "grpc")]
+use parking_lot::Mutex;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+#[cfg(feature = "grpc")]
+use tokio::sync::mpsc;
+#[cfg(feature = "grpc")]
+use tonic::{
+    codec::Streaming,
+    transport::{Channel, Endpoint},
+};
+
+/// HTTP/2 keepalive and stream stall detection settings
+#[derive(Debug, Clone, Copy)]
+pub struct KeepaliveConfig {
+    /// interval between HTTP/2 keepalive pings
+    pub interval: Duration,
+    /// how long to wait for a keepalive ack before closing the connection
+    pub timeout: Duration,
+    /// watched streams that receive nothing for this long are considered
+    /// stalled and are torn down and re-established
+    pub stall_timeout: Duration,
+    /// delay before reconnecting a stalled or failed stream, doubled
+    /// after each failed reconnect, with jitter, and spaced from other
+    /// reconnects in the process, see `reconnect`
+    pub reconnect_delay: Duration,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(10),
+            stall_timeout: Duration::from_secs(60),
+            reconnect_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl KeepaliveConfig {
+    fn backoff(&self) -> Backoff {
+        Backoff::new(
+            "grpc_stream",
+            BackoffConfig { base: self.reconnect_delay, ..Default::default() },
+        )
+    }
+}
+
+/// Counters for watched streams, for monitoring
+#[derive(Debug, Default)]
+pub struct StreamStats {
+    pub stalls: AtomicU64,
+    pub reconnects: AtomicU64,
+    pub errors: AtomicU64,
+}
+
+impl StreamStats {
+    pub fn stalls(&self) -> u64 {
+        self.stalls.load(Ordering::Relaxed)
+    }
+
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PreloadConfig {
+    /// maximum concurrent snapshot requests
+    pub parallelism: usize,
+    /// markets per snapshot request
+    pub chunk_size: usize,
+    /// give up on markets that haven't produced a snapshot by then
+    pub timeout: Duration,
+}
+
+impl Default for PreloadConfig {
+    fn default() -> Self {
+        Self { parallelism: 8, chunk_size: 50, timeout: Duration::from_secs(10) }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PreloadProgress {
+    pub markets_done: usize,
+    pub markets_total: usize,
+}
+
+/// Metadata for a universe, fetched up front by `preload_universe`
+#[cfg(feature = "grpc")]
+#[derive(Debug, Default)]
+pub struct PreloadedUniverse {
+    pub resolved: FxHashMap<String, ResolvedSymbol>,
+    pub l1_books: FxHashMap<MarketId, L1BookSnapshot>,
+    /// symbols that didn't resolve, and why
+    pub unresolved: Vec<(String, String)>,
+    /// markets that didn't produce a snapshot in time
+    pub timed_out: Vec<MarketId>,
+    /// markets whose snapshot request failed, and why
+    pub failed: Vec<(MarketId, String)>,
+    /// 24h tickers of the markets, see `add_tickers`
+    pub tickers: FxHashMap<MarketId, Ticker>,
+}
+
+#[cfg(feature = "grpc")]
+impl PreloadedUniverse {
+    /// Take the tickers of the universe's markets from a ticker cache,
+    /// e.g. one kept current by `ticker_cache::watch_tickers`; markets the
+    /// cache doesn't cover are left out
+    pub fn add_tickers(&mut self, tickers: &TickerCache) {
+        for market in self.resolved.values().flat_map(|r| r.markets.iter()) {
+            if let Some(t) = tickers.get(market) {
+                self.tickers.insert(market.id, *t);
+            }
+        }
+    }
+}
+
+/// An item from a `ResilientStream`
+#[cfg(feature = "grpc")]
+#[derive(Debug, Clone)]
+pub enum StreamEvent<T> {
+    Data(T),
+    /// the upstream stream was lost and has been re-established, having
+    /// been down for about `downtime`; anything published in between was
+    /// missed, so consumers should resync from the items that follow
+    Gap {
+        downtime: Duration,
+    },
+}
+
+/// A stream that survives connection failures: when the upstream errors,
+/// ends, or stalls it reconnects and re-issues the subscription, then
+/// delivers a `StreamEvent::Gap` before the new stream's first item.  The
+/// background task exits when this is dropped.
+#[cfg(feature = "grpc")]
+pub struct ResilientStream<T> {
+    rx: mpsc::Receiver<StreamEvent<T>>,
+    resubscribe: Arc<tokio::sync::Notify>,
+}
+
+#[cfg(feature = "grpc")]
+impl<T> ResilientStream<T> {
+    pub async fn next(&mut self) -> Option<StreamEvent<T>> {
+        self.rx.recv().await
+    }
+
+    /// Tear down the upstream stream and subscribe again, e.g. when the
+    /// consumer detects a sequence gap; a `Gap` is delivered as for a lost
+    /// stream
+    pub fn resubscribe(&self) {
+        self.resubscribe.notify_one();
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl<T> futures::Stream for ResilientStream<T> {
+    type Item = StreamEvent<T>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
 
 #[derive(Default, Debug)]
-pub struct ArchitectClient {}
+pub struct ArchitectClient {
+    keepalive: KeepaliveConfig,
+    stream_stats: Arc<StreamStats>,
+    /// channels by endpoint; tonic channels multiplex requests over one
+    /// HTTP/2 connection, so venues behind the same gateway share one
+    #[cfg(feature = "grpc")]
+    channels: Mutex<FxHashMap<String, Channel>>,
+    /// marketdata endpoints by venue name, see `set_marketdata_endpoint`
+    #[cfg(feature = "grpc")]
+    marketdata_endpoints: Mutex<FxHashMap<String, String>>,
+    #[cfg(feature = "grpc")]
+    default_marketdata_endpoint: Mutex<Option<String>>,
+}
 
 impl ArchitectClient {
+    pub fn with_keepalive(keepalive: KeepaliveConfig) -> Self {
+        Self { keepalive, ..Default::default() }
+    }
+
+    pub fn keepalive(&self) -> &KeepaliveConfig {
+        &self.keepalive
+    }
+
+    /// Resolve a market or product name or id, or an exchange symbol, to
+    /// a product and the markets it trades on.  Symbology must be loaded.
+    pub fn resolve_symbol(&self, input: &str) -> anyhow::Result<ResolvedSymbol> {
+        resolve_symbol(input)
+    }
+
+    /// Stall and reconnect counters across all watched streams
+    pub fn stream_stats(&self) -> Arc<StreamStats> {
+        self.stream_stats.clone()
+    }
+
+    /// Get a channel to the endpoint with the configured HTTP/2 keepalive
+    /// settings, reusing an existing channel to the same endpoint if there
+    /// is one.
+    #[cfg(feature = "grpc")]
+    pub async fn connect(&self, endpoint: impl AsRef<str>) -> Result<Channel> {
+        let key = endpoint.as_ref().trim_end_matches('/').to_string();
+        if let Some(channel) = self.channels.lock().get(&key) {
+            return Ok(channel.clone());
+        }
+        let channel = connect(&self.keepalive, &key).await?;
+        // if we raced with another connect to the same endpoint keep theirs
+        let mut channels = self.channels.lock();
+        let channel = channels.entry(key).or_insert(channel).clone();
+        Ok(channel)
+    }
+
+    /// Drop the pooled channel to the endpoint, e.g. after it has failed,
+    /// so the next `connect` makes a new connection
+    #[cfg(feature = "grpc")]
+    pub fn evict_channel(&self, endpoint: impl AsRef<str>) {
+        let key = endpoint.as_ref().trim_end_matches('/');
+        if self.channels.lock().remove(key).is_some() {
+            debug!("evicted pooled channel to {key}");
+        }
+    }
+
+    /// Route marketdata for `venue` to `endpoint`
+    #[cfg(feature = "grpc")]
+    pub fn set_marketdata_endpoint(&self, venue: &str, endpoint: impl AsRef<str>) {
+        let endpoint = endpoint.as_ref().trim_end_matches('/').to_string();
+        self.marketdata_endpoints.lock().insert(venue.to_string(), endpoint);
+    }
+
+    /// Route marketdata for venues without their own endpoint to
+    /// `endpoint`, or to nowhere with None
+    #[cfg(feature = "grpc")]
+    pub fn set_default_marketdata_endpoint(&self, endpoint: Option<&str>) {
+        *self.default_marketdata_endpoint.lock() =
+            endpoint.map(|e| e.trim_end_matches('/').to_string());
+    }
+
+    /// Route venues to the marketdata endpoints in `config`, see
+    /// `marketdata::venue_config`
+    #[cfg(feature = "grpc")]
+    pub fn apply_marketdata_config(
+        &self,
+        config: &crate::marketdata::venue_config::MarketdataConfig,
+    ) {
+        if let Some(endpoint) = &config.default.endpoint {
+            self.set_default_marketdata_endpoint(Some(endpoint));
+        }
+        for (venue, entry) in &config.venues {
+            if let Some(endpoint) = &entry.endpoint {
+                self.set_marketdata_endpoint(venue, endpoint);
+            }
+        }
+    }
+
+    /// Look up the SRV record for each venue's marketdata service, named
+    /// by `domain_name(venue)`, and route the venue to it
+    #[cfg(feature = "grpc")]
+    pub async fn discover_marketdata_endpoints<S: AsRef<str>>(
+        &self,
+        venues: impl IntoIterator<Item = S>,
+        domain_name: impl Fn(&str) -> String,
+    ) -> Result<()> {
+        for venue in venues {
+            let venue = venue.as_ref();
+            let endpoint = self.resolve_service(&domain_name(venue)).await?;
+            debug!("marketdata for {venue} at {endpoint}");
+            self.set_marketdata_endpoint(venue, endpoint);
+        }
+        Ok(())
+    }
+
+    /// The marketdata endpoint for the market's venue
+    #[cfg(feature = "grpc")]
+    pub fn marketdata_endpoint(
+        &self,
+        market: &crate::symbology::MarketRef,
+    ) -> Result<String> {
+        let venue = market.venue.name.as_str();
+        self.marketdata_endpoints
+            .lock()
+            .get(venue)
+            .cloned()
+            .or_else(|| self.default_marketdata_endpoint.lock().clone())
+            .ok_or_else(|| anyhow!("no marketdata endpoint for venue {venue}"))
+    }
+
+    /// A pooled channel to the market's marketdata endpoint
+    #[cfg(feature = "grpc")]
+    pub async fn marketdata_channel(
+        &self,
+        market: &crate::symbology::MarketRef,
+    ) -> Result<Channel> {
+        let endpoint = self.marketdata_endpoint(market)?;
+        self.connect(endpoint).await
+    }
+
+    /// Group markets by their marketdata endpoint, so a multi venue
+    /// subscription can be split into one request per endpoint
+    #[cfg(feature = "grpc")]
+    pub fn group_by_marketdata_endpoint(
+        &self,
+        markets: impl IntoIterator<Item = crate::symbology::MarketRef>,
+    ) -> Result<FxHashMap<String, Vec<MarketId>>> {
+        let mut res: FxHashMap<String, Vec<MarketId>> = FxHashMap::default();
+        for market in markets {
+            res.entry(self.marketdata_endpoint(&market)?).or_default().push(market.id);
+        }
+        Ok(res)
+    }
+
     #[cfg(feature = "grpc")]
     pub async fn resolve_service(&self, domain_name: &str) -> Result<String> {
+        let mut endpoints = self.resolve_service_all(domain_name).await?;
+        Ok(endpoints.swap_remove(0))
+    }
+
+    /// Every endpoint in the SRV record for `domain_name`, in record order,
+    /// e.g. one per region; see `endpoint_selector` to pick among them
+    #[cfg(feature = "grpc")]
+    pub async fn resolve_service_all(&self, domain_name: &str) -> Result<Vec<String>> {
         let resolver =
             TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
         let records = resolver.srv_lookup(domain_name).await?;
-        let rec = records
+        let endpoints: Vec<String> = records
             .iter()
-            .next()
-            .ok_or_else(|| anyhow!("no SRV records found for domain: {domain_name}"))?;
-        Ok(format!("dns://{}:{}", rec.target(), rec.port()))
+            .map(|rec| format!("dns://{}:{}", rec.target(), rec.port()))
+            .collect();
+        if endpoints.is_empty() {
+            return Err(anyhow!("no SRV records found for domain: {domain_name}"));
+        }
+        Ok(endpoints)
     }
 
     /// Load symbology from the given endpoint into global memory.
     #[cfg(feature = "grpc")]
     pub async fn load_symbology_from(&self, endpoint: impl AsRef<str>) -> Result<()> {
         use crate::symbology::Txn;
-        let mut client = SymbologyClient::connect(endpoint.as_ref().to_string()).await?;
+        let mut client = SymbologyClient::new(self.connect(endpoint).await?);
         let snap =
             client.symbology_snapshot(SymbologySnapshotRequest {}).await?.into_inner();
         let mut txn = Txn::begin();
@@ -63,6 +405,72 @@ impl ArchitectClient {
         Ok(())
     }
 
+    /// Resolve a universe of symbols and fetch an initial L1 snapshot for
+    /// every market they trade on, with bounded parallelism, so the first
+    /// trading decisions don't pay for on-demand lookups.  `progress` is
+    /// called as each chunk of markets completes.  A chunk whose request
+    /// fails is recorded in `failed` and the rest carry on.  Symbology must
+    /// be loaded.  Tickers aren't served here, see
+    /// `PreloadedUniverse::add_tickers`.
+    #[cfg(feature = "grpc")]
+    pub async fn preload_universe<S: AsRef<str>>(
+        &self,
+        endpoint: impl AsRef<str>,
+        symbols: impl IntoIterator<Item = S>,
+        config: PreloadConfig,
+        progress: impl Fn(PreloadProgress),
+    ) -> Result<PreloadedUniverse> {
+        use futures::StreamExt;
+        let mut universe = PreloadedUniverse::default();
+        let mut market_ids = vec![];
+        let mut seen = FxHashSet::default();
+        for symbol in symbols {
+            let symbol = symbol.as_ref();
+            match resolve_symbol(symbol) {
+                Ok(resolved) => {
+                    for m in &resolved.markets {
+                        if seen.insert(m.id) {
+                            market_ids.push(m.id);
+                        }
+                    }
+                    universe.resolved.insert(symbol.to_string(), resolved);
+                }
+                Err(e) => universe.unresolved.push((symbol.to_string(), e.to_string())),
+            }
+        }
+        let channel = self.connect(endpoint).await?;
+        let total = market_ids.len();
+        let mut done = 0;
+        let mut chunks = futures::stream::iter(
+            market_ids.chunks(config.chunk_size.max(1)).map(|chunk| {
+                let (channel, chunk) = (channel.clone(), chunk.to_vec());
+                async move {
+                    let res =
+                        first_l1_book_snapshots(channel, chunk.clone(), config.timeout)
+                            .await;
+                    (chunk, res)
+                }
+            }),
+        )
+        .buffer_unordered(config.parallelism.max(1));
+        while let Some((chunk, res)) = chunks.next().await {
+            match res {
+                Ok((snaps, timed_out)) => {
+                    universe.l1_books.extend(snaps);
+                    universe.timed_out.extend(timed_out);
+                }
+                Err(e) => {
+                    debug!("preloading {} markets failed: {e:?}", chunk.len());
+                    let e = e.to_string();
+                    universe.failed.extend(chunk.iter().map(|id| (*id, e.clone())));
+                }
+            }
+            done += chunk.len();
+            progress(PreloadProgress { markets_done: done, markets_total: total });
+        }
+        Ok(universe)
+    }
+
     #[cfg(feature = "grpc")]
     pub async fn subscribe_l1_book_snapshots_from(
         // NB alee: keeping this mut for now in case we mux clients
@@ -71,11 +479,284 @@ impl ArchitectClient {
         // if None, subscribe to all L1 books for all markets available
         market_ids: Option<Vec<MarketId>>,
     ) -> Result<Streaming<L1BookSnapshot>> {
-        let mut client = MarketdataClient::connect(endpoint.as_ref().to_string()).await?;
-        let stream = client
-            .subscribe_l1_book_snapshots(SubscribeL1BookSnapshotsRequest { market_ids })
-            .await?
-            .into_inner();
-        Ok(stream)
+        let channel = self.connect(endpoint).await?;
+        subscribe_l1_book_snapshots(channel, market_ids).await
     }
+
+    /// Like `subscribe_l1_book_snapshots_from`, but watched: if the stream
+    /// errors, ends, or receives nothing for `stall_timeout` it is torn down
+    /// and re-established.  Snapshots are forwarded until the receiver is
+    /// dropped.  Watched streams use their own connection rather than the
+    /// channel pool, so a stalled connection is never shared.
+    #[cfg(feature = "grpc")]
+    pub fn subscribe_l1_book_snapshots_watched(
+        &self,
+        endpoint: impl AsRef<str>,
+        market_ids: Option<Vec<MarketId>>,
+    ) -> mpsc::Receiver<L1BookSnapshot> {
+        let (tx, rx) = mpsc::channel(1000);
+        let endpoint = endpoint.as_ref().to_string();
+        let keepalive = self.keepalive;
+        let stats = self.stream_stats.clone();
+        tokio::task::spawn(async move {
+            let mut backoff = keepalive.backoff();
+            let mut first = true;
+            while !tx.is_closed() {
+                if !first {
+                    stats.reconnects.fetch_add(1, Ordering::Relaxed);
+                    backoff.wait().await;
+                }
+                first = false;
+                let channel = match connect(&keepalive, &endpoint).await {
+                    Ok(channel) => channel,
+                    Err(e) => {
+                        stats.errors.fetch_add(1, Ordering::Relaxed);
+                        throttled_error!("connecting to {endpoint}: {e:?}");
+                        continue;
+                    }
+                };
+                let mut stream = match subscribe_l1_book_snapshots(
+                    channel,
+                    market_ids.clone(),
+                )
+                .await
+                {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        stats.errors.fetch_add(1, Ordering::Relaxed);
+                        throttled_error!(
+                            "subscribing to l1 books from {endpoint}: {e:?}"
+                        );
+                        continue;
+                    }
+                };
+                backoff.reset();
+                loop {
+                    match tokio::time::timeout(keepalive.stall_timeout, stream.message())
+                        .await
+                    {
+                        Err(_) => {
+                            stats.stalls.fetch_add(1, Ordering::Relaxed);
+                            throttled_warn!(
+                                "l1 book stream from {endpoint} stalled, reconnecting"
+                            );
+                            break;
+                        }
+                        Ok(Err(e)) => {
+                            stats.errors.fetch_add(1, Ordering::Relaxed);
+                            throttled_warn!(
+                                "l1 book stream from {endpoint} failed: {e:?}"
+                            );
+                            break;
+                        }
+                        Ok(Ok(None)) => {
+                            throttled_warn!("l1 book stream from {endpoint} ended");
+                            break;
+                        }
+                        Ok(Ok(Some(snap))) => {
+                            if tx.send(snap).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        rx
+    }
+
+    /// Subscribe to one market's L1 book through the process wide broker,
+    /// sharing the upstream stream with any other client in this process
+    /// subscribed to the same market at the same endpoint.  Returns the
+    /// latest snapshot if the stream was already running.
+    #[cfg(feature = "grpc")]
+    pub fn subscribe_l1_book_snapshots_shared(
+        &self,
+        endpoint: impl AsRef<str>,
+        market_id: MarketId,
+    ) -> (Option<L1BookSnapshot>, tokio::sync::broadcast::Receiver<L1BookSnapshot>) {
+        crate::marketdata::broker::MarketdataBroker::global()
+            .subscribe_l1_book_snapshots(self, endpoint, market_id)
+    }
+
+    /// Run any streaming subscription resiliently.  `subscribe` is called
+    /// with a fresh connection on every (re)connect, so it should build the
+    /// whole request there, including any credentials, which are then
+    /// renewed on each reconnect.  Reconnects use the keepalive settings
+    /// and count toward `stream_stats`.
+    #[cfg(feature = "grpc")]
+    pub fn subscribe_resilient<T, F>(
+        &self,
+        endpoint: impl AsRef<str>,
+        subscribe: F,
+    ) -> ResilientStream<T>
+    where
+        T: Send + 'static,
+        F: Fn(Channel) -> futures::future::BoxFuture<'static, Result<Streaming<T>>>
+            + Send
+            + 'static,
+    {
+        let (tx, rx) = mpsc::channel(1000);
+        let resubscribe = Arc::new(tokio::sync::Notify::new());
+        let endpoint = endpoint.as_ref().to_string();
+        let keepalive = self.keepalive;
+        let stats = self.stream_stats.clone();
+        let requested = resubscribe.clone();
+        tokio::task::spawn(async move {
+            let mut backoff = keepalive.backoff();
+            let mut first = true;
+            // when the last good stream was lost
+            let mut lost: Option<tokio::time::Instant> = None;
+            while !tx.is_closed() {
+                if !first {
+                    stats.reconnects.fetch_add(1, Ordering::Relaxed);
+                    backoff.wait().await;
+                }
+                first = false;
+                let stream = match connect(&keepalive, &endpoint).await {
+                    Ok(channel) => subscribe(channel).await,
+                    Err(e) => Err(e),
+                };
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        stats.errors.fetch_add(1, Ordering::Relaxed);
+                        throttled_error!("subscribing to {endpoint}: {e:?}");
+                        continue;
+                    }
+                };
+                backoff.reset();
+                if let Some(at) = lost.take() {
+                    let gap = StreamEvent::Gap { downtime: at.elapsed() };
+                    if tx.send(gap).await.is_err() {
+                        return;
+                    }
+                }
+                loop {
+                    let msg = tokio::select! {
+                        msg = tokio::time::timeout(
+                            keepalive.stall_timeout,
+                            stream.message(),
+                        ) => msg,
+                        () = requested.notified() => {
+                            debug!("resubscribing to {endpoint}");
+                            break;
+                        }
+                    };
+                    match msg {
+                        Err(_) => {
+                            stats.stalls.fetch_add(1, Ordering::Relaxed);
+                            throttled_warn!(
+                                "stream from {endpoint} stalled, reconnecting"
+                            );
+                            break;
+                        }
+                        Ok(Err(e)) => {
+                            stats.errors.fetch_add(1, Ordering::Relaxed);
+                            throttled_warn!("stream from {endpoint} failed: {e:?}");
+                            break;
+                        }
+                        Ok(Ok(None)) => {
+                            throttled_warn!("stream from {endpoint} ended");
+                            break;
+                        }
+                        Ok(Ok(Some(t))) => {
+                            if tx.send(StreamEvent::Data(t)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+                lost = Some(tokio::time::Instant::now());
+            }
+        });
+        ResilientStream { rx, resubscribe }
+    }
+
+    /// L1 book snapshots as a `ResilientStream`; a gap is followed by fresh
+    /// snapshots of the subscribed markets
+    #[cfg(feature = "grpc")]
+    pub fn subscribe_l1_book_snapshots_resilient(
+        &self,
+        endpoint: impl AsRef<str>,
+        market_ids: Option<Vec<MarketId>>,
+    ) -> ResilientStream<L1BookSnapshot> {
+        self.subscribe_resilient(endpoint, move |channel| {
+            Box::pin(subscribe_l1_book_snapshots(channel, market_ids.clone()))
+        })
+    }
+
+    /// One market's L2 book updates as a `ResilientStream`; each
+    /// (re)subscription starts with a snapshot, followed by diffs
+    #[cfg(feature = "grpc")]
+    pub fn subscribe_l2_book_updates_resilient(
+        &self,
+        endpoint: impl AsRef<str>,
+        market_id: MarketId,
+    ) -> ResilientStream<L2BookUpdate> {
+        self.subscribe_resilient(endpoint, move |channel| {
+            Box::pin(subscribe_l2_book_updates(channel, market_id))
+        })
+    }
+}
+
+#[cfg(feature = "grpc")]
+async fn connect(keepalive: &KeepaliveConfig, endpoint: &str) -> Result<Channel> {
+    let channel = Endpoint::from_shared(endpoint.to_string())?
+        .http2_keep_alive_interval(keepalive.interval)
+        .keep_alive_timeout(keepalive.timeout)
+        .keep_alive_while_idle(true)
+        .connect()
+        .await?;
+    Ok(channel)
+}
+
+/// The first snapshot for each market, and the markets with none in time
+#[cfg(feature = "grpc")]
+async fn first_l1_book_snapshots(
+    channel: Channel,
+    market_ids: Vec<MarketId>,
+    timeout: Duration,
+) -> Result<(FxHashMap<MarketId, L1BookSnapshot>, Vec<MarketId>)> {
+    let mut snaps = FxHashMap::default();
+    let mut stream =
+        subscribe_l1_book_snapshots(channel, Some(market_ids.clone())).await?;
+    let deadline = tokio::time::Instant::now() + timeout;
+    while snaps.len() < market_ids.len() {
+        match tokio::time::timeout_at(deadline, stream.message()).await {
+            Ok(Ok(Some(snap))) => {
+                snaps.entry(snap.market_id).or_insert(snap);
+            }
+            Ok(Ok(None)) | Err(_) => break,
+            Ok(Err(e)) => return Err(e.into()),
+        }
+    }
+    let timed_out = market_ids.into_iter().filter(|id| !snaps.contains_key(id)).collect();
+    Ok((snaps, timed_out))
+}
+
+#[cfg(feature = "grpc")]
+async fn subscribe_l1_book_snapshots(
+    channel: Channel,
+    market_ids: Option<Vec<MarketId>>,
+) -> Result<Streaming<L1BookSnapshot>> {
+    let mut client = MarketdataClient::new(channel);
+    let stream = client
+        .subscribe_l1_book_snapshots(SubscribeL1BookSnapshotsRequest { market_ids })
+        .await?
+        .into_inner();
+    Ok(stream)
+}
+
+#[cfg(feature = "grpc")]
+async fn subscribe_l2_book_updates(
+    channel: Channel,
+    market_id: MarketId,
+) -> Result<Streaming<L2BookUpdate>> {
+    let mut client = MarketdataClient::new(channel);
+    let stream = client
+        .subscribe_l2_book_updates(SubscribeL2BookUpdatesRequest { market_id })
+        .await?
+        .into_inner();
+    Ok(stream)
 }
@@ -0,0 +1,13 @@
+//! Helpers for the hand written CSV exports.
+
+use std::borrow::Cow;
+
+/// Quote a field if it contains a separator, quote or newline, doubling
+/// any quotes inside
+pub fn field(s: &str) -> Cow<'_, str> {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
+}
@@ -0,0 +1,225 @@
+//! Pick the closest of several regional gateways by measured latency.
+//!
+//! When a service's SRV record lists gateways in more than one region,
+//! `ArchitectClient::resolve_service` just takes the first, which may be
+//! far away for a strategy running outside the default region.
+//! `EndpointSelector` instead probes every candidate by timing a TCP
+//! connect to it, picks the one with the lowest round trip, and, once
+//! `spawn`ed, probes again every `probe_interval`, switching only when
+//! another endpoint is faster by more than `hysteresis` so that noise
+//! doesn't flap the choice.  `pin` overrides the probing with a fixed
+//! endpoint until it is unpinned.
+
+use crate::ArchitectClient;
+use anyhow::{anyhow, bail, Result};
+use futures::future;
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use std::{sync::Arc, time::Duration};
+use tokio::{net::TcpStream, task::JoinHandle, time::Instant};
+use url::Url;
+
+#[derive(Debug, Clone, Copy)]
+pub struct EndpointSelectorConfig {
+    pub probe_interval: Duration,
+    /// endpoints that don't accept a connection in this long are
+    /// considered down
+    pub probe_timeout: Duration,
+    /// how much faster another endpoint must be to switch to it
+    pub hysteresis: Duration,
+}
+
+impl Default for EndpointSelectorConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval: Duration::from_secs(60),
+            probe_timeout: Duration::from_secs(2),
+            hysteresis: Duration::from_millis(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+    pub endpoint: String,
+    /// None if the endpoint couldn't be reached
+    pub rtt: Option<Duration>,
+}
+
+#[derive(Default)]
+struct State {
+    current: Option<String>,
+    pinned: Option<String>,
+    last_probes: Vec<Probe>,
+}
+
+pub struct EndpointSelector {
+    candidates: Vec<String>,
+    config: EndpointSelectorConfig,
+    state: Mutex<State>,
+}
+
+/// Time a TCP connect to the endpoint's host and port
+async fn probe(endpoint: &str, timeout: Duration) -> Result<Duration> {
+    let url = Url::parse(endpoint)?;
+    let host = url.host_str().ok_or_else(|| anyhow!("no host in {endpoint}"))?;
+    let port =
+        url.port_or_known_default().ok_or_else(|| anyhow!("no port in {endpoint}"))?;
+    // resolve first so the name lookup isn't counted in the round trip
+    let addr = tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("{host} has no addresses"))?;
+    let start = Instant::now();
+    tokio::time::timeout(timeout, TcpStream::connect(addr))
+        .await
+        .map_err(|_| anyhow!("connecting to {endpoint} timed out"))??;
+    Ok(start.elapsed())
+}
+
+/// The endpoint to use given the probes, keeping `current` unless another
+/// reachable endpoint is faster by more than `hysteresis`
+fn choose(
+    current: Option<&str>,
+    probes: &[Probe],
+    hysteresis: Duration,
+) -> Option<String> {
+    let (best, best_rtt) = probes
+        .iter()
+        .filter_map(|p| p.rtt.map(|rtt| (&p.endpoint, rtt)))
+        .min_by_key(|(_, rtt)| *rtt)?;
+    let current_rtt =
+        current.and_then(|c| probes.iter().find(|p| p.endpoint == c)).and_then(|p| p.rtt);
+    match (current, current_rtt) {
+        (Some(c), Some(rtt)) if rtt <= best_rtt + hysteresis => Some(c.to_string()),
+        _ => Some(best.clone()),
+    }
+}
+
+impl EndpointSelector {
+    pub fn new(candidates: Vec<String>, config: EndpointSelectorConfig) -> Result<Self> {
+        if candidates.is_empty() {
+            bail!("no candidate endpoints");
+        }
+        let candidates =
+            candidates.into_iter().map(|c| c.trim_end_matches('/').to_string()).collect();
+        Ok(Self { candidates, config, state: Mutex::new(State::default()) })
+    }
+
+    /// Select among every gateway in the SRV record for `domain_name`
+    pub async fn from_srv(
+        client: &ArchitectClient,
+        domain_name: &str,
+        config: EndpointSelectorConfig,
+    ) -> Result<Self> {
+        Self::new(client.resolve_service_all(domain_name).await?, config)
+    }
+
+    pub fn candidates(&self) -> &[String] {
+        &self.candidates
+    }
+
+    /// The selected endpoint, or the pinned one, if any selection has
+    /// been made
+    pub fn current(&self) -> Option<String> {
+        let st = self.state.lock();
+        st.pinned.clone().or_else(|| st.current.clone())
+    }
+
+    /// The results of the last probe, in candidate order
+    pub fn last_probes(&self) -> Vec<Probe> {
+        self.state.lock().last_probes.clone()
+    }
+
+    /// Use `endpoint` regardless of latency until unpinned with None; a
+    /// `spawn`ed selector applies it at its next probe
+    pub fn pin(&self, endpoint: Option<&str>) {
+        let endpoint = endpoint.map(|e| e.trim_end_matches('/').to_string());
+        info!("pinned endpoint {endpoint:?}");
+        self.state.lock().pinned = endpoint;
+    }
+
+    /// Probe every candidate
+    pub async fn probe(&self) -> Vec<Probe> {
+        let timeout = self.config.probe_timeout;
+        let probes =
+            future::join_all(self.candidates.iter().map(|endpoint| async move {
+                let rtt = match probe(endpoint, timeout).await {
+                    Ok(rtt) => Some(rtt),
+                    Err(e) => {
+                        debug!("probing {endpoint}: {e:?}");
+                        None
+                    }
+                };
+                Probe { endpoint: endpoint.clone(), rtt }
+            }))
+            .await;
+        self.state.lock().last_probes = probes.clone();
+        probes
+    }
+
+    /// Probe the candidates and return the endpoint to use, the pinned one
+    /// if pinned.  Fails if none is pinned and none can be reached.
+    pub async fn select(&self) -> Result<String> {
+        if let Some(pinned) = self.state.lock().pinned.clone() {
+            return Ok(pinned);
+        }
+        let probes = self.probe().await;
+        let mut st = self.state.lock();
+        let chosen = choose(st.current.as_deref(), &probes, self.config.hysteresis)
+            .ok_or_else(|| anyhow!("no endpoint reachable of {:?}", self.candidates))?;
+        if st.current.as_ref() != Some(&chosen) {
+            info!("selected endpoint {chosen}, probes {probes:?}");
+            st.current = Some(chosen.clone());
+        }
+        Ok(st.pinned.clone().unwrap_or(chosen))
+    }
+
+    /// Select now and every `probe_interval`, calling `apply` with the
+    /// endpoint whenever it changes, e.g. to
+    /// `ArchitectClient::set_default_marketdata_endpoint`.  Stops when the
+    /// handle is aborted.
+    pub fn spawn(
+        self: Arc<Self>,
+        apply: impl Fn(&str) + Send + Sync + 'static,
+    ) -> JoinHandle<()> {
+        tokio::task::spawn(async move {
+            let mut applied: Option<String> = None;
+            let mut interval = tokio::time::interval(self.config.probe_interval);
+            loop {
+                interval.tick().await;
+                match self.select().await {
+                    Ok(endpoint) => {
+                        if applied.as_ref() != Some(&endpoint) {
+                            apply(&endpoint);
+                            applied = Some(endpoint);
+                        }
+                    }
+                    Err(e) => warn!("selecting endpoint: {e:?}"),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose() {
+        let ms = Duration::from_millis;
+        let p = |endpoint: &str, rtt: Option<u64>| Probe {
+            endpoint: endpoint.to_string(),
+            rtt: rtt.map(ms),
+        };
+        let probes = vec![p("a", Some(40)), p("b", Some(37)), p("c", None)];
+        assert_eq!(choose(None, &probes, ms(5)), Some("b".to_string()));
+        // not enough faster to switch
+        assert_eq!(choose(Some("a"), &probes, ms(5)), Some("a".to_string()));
+        assert_eq!(choose(Some("a"), &probes, ms(1)), Some("b".to_string()));
+        // the current endpoint went down
+        assert_eq!(choose(Some("c"), &probes, ms(5)), Some("b".to_string()));
+        assert_eq!(choose(Some("a"), &[p("a", None)], ms(5)), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::reconnect::{Backoff, BackoffConfig};
 use anyhow::{anyhow, bail, Result};
 use api::external::*;
 use async_stream::try_stream;
@@ -12,6 +13,7 @@ use std::{
         atomic::{AtomicU64, Ordering},
         Arc, Mutex,
     },
+    time::Duration,
 };
 use tokio::{
     net::TcpStream,
@@ -53,14 +55,20 @@ impl ExternalDriver {
             let requests = requests.clone();
             let subscriptions = subscriptions.clone();
             tokio::spawn(async move {
+                let mut backoff = Backoff::new(
+                    "external_driver",
+                    BackoffConfig { base: Duration::from_secs(3), ..Default::default() },
+                );
                 loop {
+                    let connected_at = std::time::Instant::now();
                     if let Err(e) =
                         Self::run(url.clone(), &mut to_write, &requests, &subscriptions)
                             .await
                     {
                         error!("error in external driver ws connection: {e:?}");
-                        warn!("external driver ws reconnecting in 3s...");
-                        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+                        warn!("external driver ws reconnecting...");
+                        backoff.on_disconnect(connected_at.elapsed());
+                        backoff.wait().await;
                     }
                 }
             })
@@ -1,18 +1,35 @@
+pub mod account_history;
 #[cfg(feature = "netidx")]
 pub mod account_manager;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod admin_http;
 #[cfg(feature = "netidx")]
 pub mod admin_stats;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod bootstrap;
 #[cfg(feature = "netidx")]
 pub mod channel_driver;
 pub mod client;
 #[cfg(feature = "netidx")]
 pub mod common;
+pub mod csv;
+#[cfg(feature = "grpc")]
+pub mod endpoint_selector;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod external_driver;
+pub mod log_throttle;
 pub mod marketdata;
-#[cfg(feature = "netidx")]
 pub mod orderflow;
+pub mod params;
 #[cfg(feature = "netidx")]
 pub mod paths;
+pub mod prices;
+pub mod reconnect;
+#[cfg(feature = "netidx")]
+pub mod rpc;
+pub mod segments;
+pub mod shutdown;
 pub mod symbology;
 pub mod synced;
 #[cfg(feature = "netidx")]
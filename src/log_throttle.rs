@@ -0,0 +1,158 @@
+//! Rate limited, deduplicated logging for repetitive errors.
+//!
+//! During a venue outage every failed update on every stream logs the same
+//! error, thousands of times a minute.  `throttled_error!` and
+//! `throttled_warn!` log through the global `LogThrottle` instead: the
+//! first occurrence of a message is logged, identical messages in the
+//! following `interval` are counted and dropped, and once the interval is
+//! over a summary is logged, e.g. "suppressed 14203 identical messages in
+//! 60s: ...".
+//!
+//! Summaries are emitted as a side effect of later logging; call `flush`
+//! periodically to get them promptly after the errors stop.
+
+use fxhash::FxHashMap;
+use log::Level;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    level: Level,
+    window_start: Instant,
+    suppressed: u64,
+}
+
+struct ThrottleState {
+    interval: Duration,
+    /// by message
+    entries: FxHashMap<String, Entry>,
+    last_sweep: Instant,
+    total_suppressed: u64,
+}
+
+impl ThrottleState {
+    /// Forget messages whose window has ended, returning summaries of any
+    /// that were suppressed
+    fn sweep(&mut self, now: Instant) -> Vec<(Level, String)> {
+        let interval = self.interval;
+        let mut summaries = vec![];
+        self.entries.retain(|msg, e| {
+            if now.duration_since(e.window_start) < interval {
+                return true;
+            }
+            if e.suppressed > 0 {
+                summaries.push((e.level, summary(msg, e.suppressed, interval)));
+            }
+            false
+        });
+        self.last_sweep = now;
+        summaries
+    }
+}
+
+fn summary(msg: &str, suppressed: u64, interval: Duration) -> String {
+    format!(
+        "suppressed {suppressed} identical messages in {}s: {msg}",
+        interval.as_secs()
+    )
+}
+
+pub struct LogThrottle(Mutex<ThrottleState>);
+
+static THROTTLE: Lazy<LogThrottle> =
+    Lazy::new(|| LogThrottle::new(Duration::from_secs(60)));
+
+impl LogThrottle {
+    pub fn new(interval: Duration) -> Self {
+        Self(Mutex::new(ThrottleState {
+            interval,
+            entries: FxHashMap::default(),
+            last_sweep: Instant::now(),
+            total_suppressed: 0,
+        }))
+    }
+
+    pub fn global() -> &'static LogThrottle {
+        &THROTTLE
+    }
+
+    /// How long identical messages are suppressed after one is logged
+    pub fn set_interval(&self, interval: Duration) {
+        self.0.lock().interval = interval;
+    }
+
+    /// The number of messages suppressed since startup
+    pub fn suppressed(&self) -> u64 {
+        self.0.lock().total_suppressed
+    }
+
+    /// Log `msg` unless an identical message was logged within the interval
+    pub fn log(&self, level: Level, msg: String) {
+        let now = Instant::now();
+        let (summaries, emit) = {
+            let mut st = self.0.lock();
+            let interval = st.interval;
+            let mut summaries = if now.duration_since(st.last_sweep) >= interval {
+                st.sweep(now)
+            } else {
+                vec![]
+            };
+            let ThrottleState { entries, total_suppressed, .. } = &mut *st;
+            let emit = match entries.get_mut(&msg) {
+                Some(e) if now.duration_since(e.window_start) < interval => {
+                    e.suppressed += 1;
+                    *total_suppressed += 1;
+                    false
+                }
+                Some(e) => {
+                    if e.suppressed > 0 {
+                        summaries.push((e.level, summary(&msg, e.suppressed, interval)));
+                    }
+                    *e = Entry { level, window_start: now, suppressed: 0 };
+                    true
+                }
+                None => {
+                    entries.insert(
+                        msg.clone(),
+                        Entry { level, window_start: now, suppressed: 0 },
+                    );
+                    true
+                }
+            };
+            (summaries, emit)
+        };
+        for (level, s) in summaries {
+            log::log!(level, "{s}");
+        }
+        if emit {
+            log::log!(level, "{msg}");
+        }
+    }
+
+    /// Log summaries for messages whose interval has ended
+    pub fn flush(&self) {
+        let summaries = self.0.lock().sweep(Instant::now());
+        for (level, s) in summaries {
+            log::log!(level, "{s}");
+        }
+    }
+}
+
+/// `error!`, through the global `LogThrottle`
+#[macro_export]
+macro_rules! throttled_error {
+    ($($arg:tt)+) => {
+        $crate::log_throttle::LogThrottle::global()
+            .log(::log::Level::Error, ::std::format!($($arg)+))
+    };
+}
+
+/// `warn!`, through the global `LogThrottle`
+#[macro_export]
+macro_rules! throttled_warn {
+    ($($arg:tt)+) => {
+        $crate::log_throttle::LogThrottle::global()
+            .log(::log::Level::Warn, ::std::format!($($arg)+))
+    };
+}
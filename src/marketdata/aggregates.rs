@@ -0,0 +1,239 @@
+//! Rolled-up ticker statistics across all markets sharing an underlying, e.g.
+//! total volume and open interest across every listed BTC future and option.
+//!
+//! The set of markets is taken from `MarketIndex::by_underlying` and can be
+//! refreshed at any time to pick up newly listed contracts.
+
+#[cfg(feature = "netidx")]
+use super::managed_marketdata::{DvalHandle, ManagedMarketdata};
+use crate::symbology::{MarketIndex, MarketRef, ProductRef};
+use chrono::{DateTime, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+#[cfg(feature = "netidx")]
+use {
+    log::{debug, warn},
+    std::{sync::Arc, time::Duration},
+    tokio::{
+        sync::{mpsc, watch, Mutex},
+        task::{self, JoinHandle},
+    },
+};
+
+/// The ticker fields that participate in the rollup
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TickerFields {
+    pub volume: Option<Decimal>,
+    pub open_interest: Option<Decimal>,
+    pub last_price: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UnderlyingTotals {
+    pub timestamp: DateTime<Utc>,
+    /// number of markets currently tracked for the underlying
+    pub num_markets: usize,
+    /// number of markets that have reported at least one field
+    pub num_reporting: usize,
+    /// sum of volume, in contracts
+    pub volume: Decimal,
+    /// sum of open interest, in contracts
+    pub open_interest: Decimal,
+    /// sum of volume * multiplier * last price, in each market's quote currency
+    pub notional_volume: Decimal,
+    /// sum of open interest * multiplier * last price
+    pub notional_open_interest: Decimal,
+}
+
+pub struct UnderlyingAggregator {
+    underlying: ProductRef,
+    markets: FxHashMap<MarketRef, Option<TickerFields>>,
+    last_update: DateTime<Utc>,
+}
+
+impl UnderlyingAggregator {
+    /// Create an aggregator over all markets currently listed for `underlying`
+    pub fn new(underlying: ProductRef) -> Self {
+        let mut t =
+            Self { underlying, markets: FxHashMap::default(), last_update: Utc::now() };
+        t.refresh_markets();
+        t
+    }
+
+    pub fn underlying(&self) -> ProductRef {
+        self.underlying
+    }
+
+    pub fn markets(&self) -> impl Iterator<Item = &MarketRef> {
+        self.markets.keys()
+    }
+
+    /// Re-read the set of markets from the global index; returns the markets
+    /// that were (added, removed).
+    pub fn refresh_markets(&mut self) -> (Vec<MarketRef>, Vec<MarketRef>) {
+        let current = MarketIndex::current().by_underlying(&self.underlying);
+        let mut added = vec![];
+        let mut removed = vec![];
+        for market in &current {
+            if !self.markets.contains_key(market) {
+                self.markets.insert(*market, None);
+                added.push(*market);
+            }
+        }
+        self.markets.retain(|m, _| {
+            if current.contains(m) {
+                true
+            } else {
+                removed.push(*m);
+                false
+            }
+        });
+        (added, removed)
+    }
+
+    /// Update the ticker fields for a market; fields that are None leave
+    /// the previous value in place.  Markets not tracked are ignored.
+    pub fn update(&mut self, market: MarketRef, fields: TickerFields) -> bool {
+        match self.markets.get_mut(&market) {
+            None => false,
+            Some(cur) => {
+                let cur = cur.get_or_insert_with(TickerFields::default);
+                if fields.volume.is_some() {
+                    cur.volume = fields.volume;
+                }
+                if fields.open_interest.is_some() {
+                    cur.open_interest = fields.open_interest;
+                }
+                if fields.last_price.is_some() {
+                    cur.last_price = fields.last_price;
+                }
+                self.last_update = Utc::now();
+                true
+            }
+        }
+    }
+
+    pub fn totals(&self) -> UnderlyingTotals {
+        let mut totals = UnderlyingTotals {
+            timestamp: self.last_update,
+            num_markets: self.markets.len(),
+            ..Default::default()
+        };
+        for (market, fields) in &self.markets {
+            let fields = match fields {
+                Some(fields) => fields,
+                None => continue,
+            };
+            totals.num_reporting += 1;
+            let multiplier =
+                market.base().map(|p| p.kind.multiplier()).unwrap_or(Decimal::ONE);
+            if let Some(volume) = fields.volume {
+                totals.volume += volume;
+                if let Some(px) = fields.last_price {
+                    totals.notional_volume += volume * multiplier * px;
+                }
+            }
+            if let Some(oi) = fields.open_interest {
+                totals.open_interest += oi;
+                if let Some(px) = fields.last_price {
+                    totals.notional_open_interest += oi * multiplier * px;
+                }
+            }
+        }
+        totals
+    }
+}
+
+/// Path leaves subscribed per market by [watch_underlying]
+#[cfg(feature = "netidx")]
+pub const VOLUME_LEAF: &str = "volume_24h";
+#[cfg(feature = "netidx")]
+pub const OPEN_INTEREST_LEAF: &str = "open_interest";
+#[cfg(feature = "netidx")]
+pub const LAST_PRICE_LEAF: &str = "last_price";
+
+/// Subscribe to ticker fields for every market of the underlying through the
+/// managed marketdata, refreshing the market set every `refresh` to pick up
+/// new listings.  Totals are published on the returned watch channel.
+#[cfg(feature = "netidx")]
+pub fn watch_underlying(
+    marketdata: Arc<ManagedMarketdata>,
+    underlying: ProductRef,
+    refresh: Duration,
+    delayed: bool,
+) -> (watch::Receiver<UnderlyingTotals>, JoinHandle<()>) {
+    let (tx_totals, rx_totals) = watch::channel(UnderlyingTotals::default());
+    let task = task::spawn(async move {
+        let mut agg = UnderlyingAggregator::new(underlying);
+        let (tx, mut rx) = mpsc::unbounded_channel::<(MarketRef, &'static str)>();
+        let mut handles: FxHashMap<
+            (MarketRef, &'static str),
+            (Arc<Mutex<DvalHandle>>, JoinHandle<()>),
+        > = FxHashMap::default();
+        let mut pending: Vec<MarketRef> = agg.markets().copied().collect();
+        let mut refresh = tokio::time::interval(refresh);
+        loop {
+            for market in pending.drain(..) {
+                for leaf in [VOLUME_LEAF, OPEN_INTEREST_LEAF, LAST_PRICE_LEAF] {
+                    match marketdata
+                        .subscribe_path(market, leaf.to_string(), delayed)
+                        .await
+                    {
+                        Err(e) => {
+                            warn!("could not subscribe to {leaf} for {market}: {e}")
+                        }
+                        Ok((handle, mut synced)) => {
+                            let tx = tx.clone();
+                            let forward = task::spawn(async move {
+                                while let Ok(()) = synced.changed().await {
+                                    if tx.send((market, leaf)).is_err() {
+                                        break;
+                                    }
+                                }
+                            });
+                            handles.insert((market, leaf), (handle, forward));
+                        }
+                    }
+                }
+            }
+            tokio::select! {
+                _ = refresh.tick() => {
+                    let (added, removed) = agg.refresh_markets();
+                    for market in removed {
+                        debug!("{market} no longer listed for {underlying}");
+                        handles.retain(|(m, _), (_, forward)| {
+                            if *m == market {
+                                forward.abort();
+                                false
+                            } else {
+                                true
+                            }
+                        });
+                    }
+                    pending.extend(added);
+                }
+                Some((market, leaf)) = rx.recv() => {
+                    let value = match handles.get(&(market, leaf)) {
+                        None => continue,
+                        Some((handle, _)) => handle
+                            .lock()
+                            .await
+                            .last_value
+                            .clone()
+                            .and_then(|v| v.cast_to::<Decimal>().ok()),
+                    };
+                    let mut fields = TickerFields::default();
+                    match leaf {
+                        VOLUME_LEAF => fields.volume = value,
+                        OPEN_INTEREST_LEAF => fields.open_interest = value,
+                        _ => fields.last_price = value,
+                    }
+                    if agg.update(market, fields) {
+                        tx_totals.send_replace(agg.totals());
+                    }
+                }
+            }
+        }
+    });
+    (rx_totals, task)
+}
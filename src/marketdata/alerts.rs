@@ -0,0 +1,308 @@
+//! Price alerts over marketdata.  Register conditions (price crosses, spread
+//! thresholds, volume spikes, book imbalance) per market and feed observations
+//! from whatever marketdata source you are driving; fired alerts are delivered
+//! on a broadcast channel and to any registered callbacks.
+
+#[cfg(feature = "netidx")]
+use super::book_client::LevelBook;
+use crate::symbology::MarketRef;
+#[cfg(feature = "grpc")]
+use api::external::marketdata::L1BookSnapshot;
+use api::Dir;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+use tokio::sync::broadcast;
+
+/// A point-in-time view of a market, as much as is known
+#[derive(Debug, Clone, Default)]
+pub struct Observation {
+    pub timestamp: DateTime<Utc>,
+    /// best bid (price, size)
+    pub best_bid: Option<(Decimal, Decimal)>,
+    /// best ask (price, size)
+    pub best_ask: Option<(Decimal, Decimal)>,
+    pub last_price: Option<Decimal>,
+    /// volume traded since the last observation
+    pub volume: Option<Decimal>,
+}
+
+impl Observation {
+    pub fn mid(&self) -> Option<Decimal> {
+        let (bid, _) = self.best_bid?;
+        let (ask, _) = self.best_ask?;
+        Some((bid + ask) / Decimal::TWO)
+    }
+
+    pub fn spread(&self) -> Option<Decimal> {
+        let (bid, _) = self.best_bid?;
+        let (ask, _) = self.best_ask?;
+        Some(ask - bid)
+    }
+
+    /// Top of book imbalance in [-1, 1]; positive means more size bid
+    pub fn imbalance(&self) -> Option<Decimal> {
+        let (_, bid_size) = self.best_bid?;
+        let (_, ask_size) = self.best_ask?;
+        let total = bid_size + ask_size;
+        if total.is_zero() {
+            return None;
+        }
+        Some((bid_size - ask_size) / total)
+    }
+
+    /// The reference price for crossing conditions: last trade if known, else mid
+    pub fn price(&self) -> Option<Decimal> {
+        self.last_price.or_else(|| self.mid())
+    }
+}
+
+#[cfg(feature = "netidx")]
+impl From<&LevelBook> for Observation {
+    fn from(book: &LevelBook) -> Self {
+        Self {
+            timestamp: book.timestamp,
+            best_bid: book.best(Dir::Buy),
+            best_ask: book.best(Dir::Sell),
+            last_price: None,
+            volume: None,
+        }
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl From<&L1BookSnapshot> for Observation {
+    fn from(snap: &L1BookSnapshot) -> Self {
+        Self {
+            timestamp: Utc
+                .timestamp_opt(snap.timestamp_s, snap.timestamp_ns)
+                .single()
+                .unwrap_or_default(),
+            best_bid: snap.best_bid,
+            best_ask: snap.best_ask,
+            last_price: None,
+            volume: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AlertId(pub u64);
+
+#[derive(Debug, Clone)]
+pub enum AlertCondition {
+    /// reference price moves from below to at-or-above the level
+    PriceCrossesAbove(Decimal),
+    /// reference price moves from above to at-or-below the level
+    PriceCrossesBelow(Decimal),
+    /// absolute spread at or above the threshold
+    SpreadAbove(Decimal),
+    /// spread relative to mid, in basis points, at or above the threshold
+    SpreadBpsAbove(Decimal),
+    /// observed volume is at least `multiple` times the average of the
+    /// last `lookback` observations
+    VolumeSpike { lookback: usize, multiple: Decimal },
+    /// top of book imbalance on the given side at or above the threshold
+    Imbalance { dir: Dir, threshold: Decimal },
+}
+
+#[derive(Debug, Clone)]
+pub struct AlertEvent {
+    pub id: AlertId,
+    pub market: MarketRef,
+    pub condition: AlertCondition,
+    pub timestamp: DateTime<Utc>,
+    /// the value that triggered the alert (price, spread, volume, or imbalance)
+    pub value: Decimal,
+}
+
+struct Alert {
+    market: MarketRef,
+    condition: AlertCondition,
+    /// minimum time between successive fires
+    debounce: Duration,
+    /// whether the condition held as of the previous observation; alerts are
+    /// edge-triggered, they must go false before they can fire again
+    armed: bool,
+    last_price: Option<Decimal>,
+    last_fired: Option<DateTime<Utc>>,
+    volumes: VecDeque<Decimal>,
+}
+
+impl Alert {
+    /// Evaluate the condition, returning the triggering value if it holds
+    fn evaluate(&mut self, obs: &Observation) -> Option<Decimal> {
+        match &self.condition {
+            AlertCondition::PriceCrossesAbove(level) => {
+                let price = obs.price()?;
+                let prev = self.last_price.replace(price)?;
+                (prev < *level && price >= *level).then_some(price)
+            }
+            AlertCondition::PriceCrossesBelow(level) => {
+                let price = obs.price()?;
+                let prev = self.last_price.replace(price)?;
+                (prev > *level && price <= *level).then_some(price)
+            }
+            AlertCondition::SpreadAbove(threshold) => {
+                let spread = obs.spread()?;
+                (spread >= *threshold).then_some(spread)
+            }
+            AlertCondition::SpreadBpsAbove(threshold) => {
+                let mid = obs.mid()?;
+                if mid.is_zero() {
+                    return None;
+                }
+                let bps = obs.spread()? / mid * Decimal::from(10_000);
+                (bps >= *threshold).then_some(bps)
+            }
+            AlertCondition::VolumeSpike { lookback, multiple } => {
+                let volume = obs.volume?;
+                let fired = if self.volumes.len() >= *lookback && *lookback > 0 {
+                    let avg = self.volumes.iter().sum::<Decimal>()
+                        / Decimal::from(self.volumes.len());
+                    !avg.is_zero() && volume >= avg * *multiple
+                } else {
+                    false
+                };
+                self.volumes.push_back(volume);
+                while self.volumes.len() > *lookback {
+                    self.volumes.pop_front();
+                }
+                fired.then_some(volume)
+            }
+            AlertCondition::Imbalance { dir, threshold } => {
+                let imbalance = match dir {
+                    Dir::Buy => obs.imbalance()?,
+                    Dir::Sell => -obs.imbalance()?,
+                };
+                (imbalance >= *threshold).then_some(imbalance)
+            }
+        }
+    }
+}
+
+/// Alerts engine; call `observe` for each marketdata update you receive.
+pub struct AlertsEngine {
+    next_id: u64,
+    alerts: FxHashMap<AlertId, Alert>,
+    by_market: FxHashMap<MarketRef, Vec<AlertId>>,
+    callbacks: Vec<Box<dyn Fn(&AlertEvent) + Send + Sync>>,
+    tx: broadcast::Sender<AlertEvent>,
+}
+
+impl Default for AlertsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AlertsEngine {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self {
+            next_id: 0,
+            alerts: FxHashMap::default(),
+            by_market: FxHashMap::default(),
+            callbacks: vec![],
+            tx,
+        }
+    }
+
+    /// Register an alert; it will fire at most once per `debounce`.
+    pub fn add(
+        &mut self,
+        market: MarketRef,
+        condition: AlertCondition,
+        debounce: Duration,
+    ) -> AlertId {
+        let id = AlertId(self.next_id);
+        self.next_id += 1;
+        self.alerts.insert(
+            id,
+            Alert {
+                market,
+                condition,
+                debounce,
+                armed: true,
+                last_price: None,
+                last_fired: None,
+                volumes: VecDeque::new(),
+            },
+        );
+        self.by_market.entry(market).or_default().push(id);
+        id
+    }
+
+    pub fn remove(&mut self, id: AlertId) -> bool {
+        match self.alerts.remove(&id) {
+            None => false,
+            Some(alert) => {
+                if let Some(ids) = self.by_market.get_mut(&alert.market) {
+                    ids.retain(|i| *i != id);
+                    if ids.is_empty() {
+                        self.by_market.remove(&alert.market);
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    /// Markets that have at least one alert registered
+    pub fn markets(&self) -> impl Iterator<Item = &MarketRef> {
+        self.by_market.keys()
+    }
+
+    /// Register a callback to be invoked synchronously for each fired alert
+    pub fn on_alert(&mut self, f: impl Fn(&AlertEvent) + Send + Sync + 'static) {
+        self.callbacks.push(Box::new(f));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AlertEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Evaluate all alerts for the market against the observation, returning
+    /// the alerts that fired.
+    pub fn observe(&mut self, market: MarketRef, obs: &Observation) -> Vec<AlertEvent> {
+        let mut fired = vec![];
+        let ids = match self.by_market.get(&market) {
+            Some(ids) => ids,
+            None => return fired,
+        };
+        for id in ids {
+            let alert = match self.alerts.get_mut(id) {
+                Some(alert) => alert,
+                None => continue,
+            };
+            match alert.evaluate(obs) {
+                None => alert.armed = true,
+                Some(value) => {
+                    let debounced = alert
+                        .last_fired
+                        .map(|t| obs.timestamp - t < alert.debounce)
+                        .unwrap_or(false);
+                    if alert.armed && !debounced {
+                        alert.armed = false;
+                        alert.last_fired = Some(obs.timestamp);
+                        fired.push(AlertEvent {
+                            id: *id,
+                            market,
+                            condition: alert.condition.clone(),
+                            timestamp: obs.timestamp,
+                            value,
+                        });
+                    }
+                }
+            }
+        }
+        for ev in &fired {
+            for f in &self.callbacks {
+                f(ev);
+            }
+            let _ = self.tx.send(ev.clone());
+        }
+        fired
+    }
+}
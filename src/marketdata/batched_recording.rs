@@ -0,0 +1,597 @@
+//! A compact segment format for marketdata recordings.
+//!
+//! Compressing each record on its own, or a whole JSON lines segment after
+//! the fact, leaves most of the redundancy between L2 updates on the
+//! table.  A batched recording instead groups records by stream type (L1,
+//! L2, trades, candles) into batches that are compressed with zstd as a
+//! unit once the `FlushPolicy` says so, and, once enough records of a type
+//! have been seen, compresses that type's batches with a dictionary
+//! trained on them, which is where small batches gain the most.
+//! Dictionaries are written into every segment ahead of the batches that
+//! use them, so each segment can be read on its own.
+//!
+//! Records of one stream type keep their order, and gaps are written in
+//! order with everything else: every pending batch is flushed before a
+//! `Gap`.  Between stream types, records are ordered only to within a
+//! batch.
+//!
+//! A segment is `BATCHED_MAGIC` followed by frames, each a header of kind,
+//! stream type, dictionary flag, uncompressed length, and length, then the
+//! payload.  `RecordingReader` recognizes batched segments by the magic.
+//! Opening a segment to append to it first cuts off any partly written
+//! frame left at its end, e.g. by a crash.
+
+use super::recorder::{Record, RecordedData, BATCHED_MAGIC};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use fxhash::FxHashMap;
+use log::{debug, warn};
+use std::{
+    collections::{hash_map::Entry, VecDeque},
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const FRAME_DICT: u8 = 0;
+const FRAME_BATCH: u8 = 1;
+const HEADER_LEN: usize = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StreamType {
+    L1,
+    L2,
+    Trade,
+    Candle,
+    Gap,
+}
+
+impl StreamType {
+    pub fn of(data: &RecordedData) -> Self {
+        match data {
+            RecordedData::L1(_) => Self::L1,
+            RecordedData::L2(_) => Self::L2,
+            RecordedData::Trade(_) => Self::Trade,
+            RecordedData::Candle(_) => Self::Candle,
+            RecordedData::Gap { .. } => Self::Gap,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::L1 => 0,
+            Self::L2 => 1,
+            Self::Trade => 2,
+            Self::Candle => 3,
+            Self::Gap => 4,
+        }
+    }
+
+    fn from_u8(b: u8) -> Result<Self> {
+        Ok(match b {
+            0 => Self::L1,
+            1 => Self::L2,
+            2 => Self::Trade,
+            3 => Self::Candle,
+            4 => Self::Gap,
+            b => bail!("invalid stream type {b}"),
+        })
+    }
+}
+
+/// When a stream type's pending batch is compressed and written; the first
+/// limit reached wins
+#[derive(Debug, Clone, Copy)]
+pub struct FlushPolicy {
+    pub max_records: usize,
+    /// uncompressed bytes
+    pub max_bytes: usize,
+    /// checked by `flush_due`
+    pub max_age: Duration,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self { max_records: 4096, max_bytes: 1024 * 1024, max_age: Duration::seconds(5) }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DictionaryConfig {
+    /// records of a stream type to train its dictionary on
+    pub samples: usize,
+    pub max_size: usize,
+}
+
+impl Default for DictionaryConfig {
+    fn default() -> Self {
+        Self { samples: 2000, max_size: 64 * 1024 }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// zstd level, up to 22
+    pub level: i32,
+    pub flush: FlushPolicy,
+    /// None to compress without dictionaries
+    pub dictionary: Option<DictionaryConfig>,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            level: 9,
+            flush: FlushPolicy::default(),
+            dictionary: Some(DictionaryConfig::default()),
+        }
+    }
+}
+
+/// Fill `buf`, returning false at the end of the input
+fn read_full(rd: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match rd.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Walk the frames of a segment, calling `f` with the kind and stream type
+/// of each, and return the length of the segment up to the end of its last
+/// complete frame
+fn scan_frames(rd: &mut impl Read, mut f: impl FnMut(u8, StreamType)) -> Result<u64> {
+    let mut magic = [0u8; BATCHED_MAGIC.len()];
+    rd.read_exact(&mut magic)?;
+    if magic != *BATCHED_MAGIC {
+        bail!("not a batched recording");
+    }
+    let mut len = magic.len() as u64;
+    let mut header = [0u8; HEADER_LEN];
+    loop {
+        if !read_full(rd, &mut header)? {
+            return Ok(len);
+        }
+        let (FRAME_DICT | FRAME_BATCH, Ok(stream)) =
+            (header[0], StreamType::from_u8(header[1]))
+        else {
+            return Ok(len);
+        };
+        let payload_len = u32::from_le_bytes(header[7..11].try_into()?) as u64;
+        if io::copy(&mut rd.by_ref().take(payload_len), &mut io::sink())? < payload_len {
+            return Ok(len);
+        }
+        f(header[0], stream);
+        len += HEADER_LEN as u64 + payload_len;
+    }
+}
+
+struct Frames {
+    out: BufWriter<File>,
+    len: u64,
+}
+
+impl Frames {
+    fn open(path: &Path) -> Result<Self> {
+        let mut file =
+            OpenOptions::new().create(true).read(true).write(true).open(path)?;
+        let file_len = file.metadata()?.len();
+        // anything appended after a partial frame would be unreadable
+        let len = if file_len < BATCHED_MAGIC.len() as u64 {
+            0
+        } else {
+            scan_frames(&mut BufReader::new(&mut file), |_, _| ())?
+        };
+        if len < file_len {
+            warn!(
+                "truncating {} bytes of partial frame from {}",
+                file_len - len,
+                path.display()
+            );
+            file.set_len(len)?;
+        }
+        file.seek(SeekFrom::Start(len))?;
+        let mut frames = Self { out: BufWriter::new(file), len };
+        if len == 0 {
+            frames.out.write_all(BATCHED_MAGIC)?;
+            frames.len += BATCHED_MAGIC.len() as u64;
+        }
+        Ok(frames)
+    }
+
+    fn write(
+        &mut self,
+        kind: u8,
+        stream: StreamType,
+        dict: bool,
+        raw_len: usize,
+        payload: &[u8],
+    ) -> Result<()> {
+        let mut header = [0u8; HEADER_LEN];
+        header[0] = kind;
+        header[1] = stream.to_u8();
+        header[2] = dict as u8;
+        header[3..7].copy_from_slice(&u32::try_from(raw_len)?.to_le_bytes());
+        header[7..11].copy_from_slice(&u32::try_from(payload.len())?.to_le_bytes());
+        self.out.write_all(&header)?;
+        self.out.write_all(payload)?;
+        self.len += (HEADER_LEN + payload.len()) as u64;
+        Ok(())
+    }
+}
+
+struct StreamState {
+    /// pending records as JSON lines
+    batch: Vec<u8>,
+    count: usize,
+    started: Option<DateTime<Utc>>,
+    /// records kept for training, None once trained or given up on
+    samples: Option<Vec<Vec<u8>>>,
+    dict: Option<Vec<u8>>,
+    compressor: zstd::bulk::Compressor<'static>,
+}
+
+impl StreamState {
+    fn new(config: &BatchConfig) -> Result<Self> {
+        Ok(Self {
+            batch: vec![],
+            count: 0,
+            started: None,
+            samples: config.dictionary.map(|_| vec![]),
+            dict: None,
+            compressor: zstd::bulk::Compressor::new(config.level)?,
+        })
+    }
+
+    /// Train a dictionary once there are enough samples, returning it if
+    /// one was trained
+    fn train(
+        &mut self,
+        stream: StreamType,
+        config: &BatchConfig,
+    ) -> Result<Option<&[u8]>> {
+        let (Some(dc), Some(samples)) = (config.dictionary, &self.samples) else {
+            return Ok(None);
+        };
+        if samples.len() < dc.samples {
+            return Ok(None);
+        }
+        let samples = self.samples.take().unwrap_or_default();
+        match zstd::dict::from_samples(&samples, dc.max_size) {
+            Ok(dict) => {
+                debug!("trained {} byte {stream:?} recording dictionary", dict.len());
+                self.compressor =
+                    zstd::bulk::Compressor::with_dictionary(config.level, &dict)?;
+                Ok(Some(self.dict.insert(dict).as_slice()))
+            }
+            Err(e) => {
+                warn!(
+                    "training {stream:?} recording dictionary, continuing without: {e}"
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Writes a batched recording segment, see the module docs
+pub struct BatchedRecordingWriter {
+    frames: Frames,
+    config: BatchConfig,
+    streams: FxHashMap<StreamType, StreamState>,
+}
+
+impl BatchedRecordingWriter {
+    /// Open the segment at `path` for appending, creating it if needed
+    pub fn open(path: impl AsRef<Path>, config: BatchConfig) -> Result<Self> {
+        let frames = Frames::open(path.as_ref())?;
+        Ok(Self { frames, config, streams: FxHashMap::default() })
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<()> {
+        let stream = StreamType::of(&record.data);
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        if stream == StreamType::Gap {
+            self.flush_batches(|_| true)?;
+        }
+        let config = self.config;
+        let st = match self.streams.entry(stream) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(StreamState::new(&config)?),
+        };
+        if let Some(samples) = &mut st.samples {
+            samples.push(line.clone());
+        }
+        if let Some(dict) = st.train(stream, &config)? {
+            self.frames.write(FRAME_DICT, stream, false, dict.len(), dict)?;
+        }
+        st.batch.extend_from_slice(&line);
+        st.count += 1;
+        st.started.get_or_insert(record.recv_time);
+        if stream == StreamType::Gap
+            || st.count >= config.flush.max_records
+            || st.batch.len() >= config.flush.max_bytes
+        {
+            self.flush_batches(|s| s == stream)?;
+        }
+        Ok(())
+    }
+
+    fn flush_batches(&mut self, which: impl Fn(StreamType) -> bool) -> Result<()> {
+        for (stream, st) in self.streams.iter_mut() {
+            if st.count == 0 || !which(*stream) {
+                continue;
+            }
+            let payload = st.compressor.compress(&st.batch)?;
+            self.frames.write(
+                FRAME_BATCH,
+                *stream,
+                st.dict.is_some(),
+                st.batch.len(),
+                &payload,
+            )?;
+            st.batch.clear();
+            st.count = 0;
+            st.started = None;
+        }
+        Ok(())
+    }
+
+    /// Write the batches that have been pending longer than the flush
+    /// policy's `max_age` as of `now`, then flush the file
+    pub fn flush_due(&mut self, now: DateTime<Utc>) -> Result<()> {
+        let cutoff = now - self.config.flush.max_age;
+        let due: Vec<StreamType> = self
+            .streams
+            .iter()
+            .filter(|(_, st)| st.started.is_some_and(|t| t <= cutoff))
+            .map(|(s, _)| *s)
+            .collect();
+        self.flush_batches(|s| due.contains(&s))?;
+        Ok(self.frames.out.flush()?)
+    }
+
+    /// Write every pending batch and flush the file
+    pub fn flush(&mut self) -> Result<()> {
+        self.flush_batches(|_| true)?;
+        Ok(self.frames.out.flush()?)
+    }
+
+    /// The size of the segment written so far, not counting pending
+    /// batches
+    pub fn bytes_written(&self) -> u64 {
+        self.frames.len
+    }
+
+    /// Finish this segment and continue in a new one at `path`, carrying
+    /// over the dictionaries trained so far
+    pub fn roll(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.flush()?;
+        self.frames = Frames::open(path.as_ref())?;
+        for (stream, st) in &self.streams {
+            if let Some(dict) = &st.dict {
+                self.frames.write(FRAME_DICT, *stream, false, dict.len(), dict)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BatchedRecordingWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Reads the records of a batched recording segment in the order written
+pub struct BatchedRecordingReader<R> {
+    rd: R,
+    dicts: FxHashMap<StreamType, Vec<u8>>,
+    pending: VecDeque<Record>,
+    done: bool,
+}
+
+impl<R: Read> BatchedRecordingReader<R> {
+    pub fn new(mut rd: R) -> Result<Self> {
+        let mut magic = [0u8; BATCHED_MAGIC.len()];
+        rd.read_exact(&mut magic)?;
+        if magic != *BATCHED_MAGIC {
+            bail!("not a batched recording");
+        }
+        Ok(Self {
+            rd,
+            dicts: FxHashMap::default(),
+            pending: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Read the next frame, returning false at the end of the segment
+    fn read_frame(&mut self) -> Result<bool> {
+        let mut header = [0u8; HEADER_LEN];
+        match self.rd.read(&mut header[..1])? {
+            0 => return Ok(false),
+            _ => self.rd.read_exact(&mut header[1..]).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => anyhow!("truncated frame header"),
+                _ => e.into(),
+            })?,
+        }
+        let stream = StreamType::from_u8(header[1])?;
+        let dict = header[2] != 0;
+        let raw_len = u32::from_le_bytes(header[3..7].try_into()?) as usize;
+        let len = u32::from_le_bytes(header[7..11].try_into()?) as usize;
+        let mut payload = vec![0u8; len];
+        self.rd.read_exact(&mut payload)?;
+        match header[0] {
+            FRAME_DICT => {
+                self.dicts.insert(stream, payload);
+            }
+            FRAME_BATCH => {
+                let batch = if dict {
+                    let dict = self.dicts.get(&stream).ok_or_else(|| {
+                        anyhow!("{stream:?} batch before its dictionary")
+                    })?;
+                    zstd::bulk::Decompressor::with_dictionary(dict)?
+                        .decompress(&payload, raw_len)?
+                } else {
+                    zstd::bulk::decompress(&payload, raw_len)?
+                };
+                for line in batch.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+                    let record = serde_json::from_slice(line)
+                        .map_err(|e| anyhow!("invalid record: {e}"))?;
+                    self.pending.push_back(record);
+                }
+            }
+            k => bail!("invalid frame kind {k}"),
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for BatchedRecordingReader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Some(Ok(record));
+            }
+            if self.done {
+                return None;
+            }
+            match self.read_frame() {
+                Ok(true) => (),
+                Ok(false) => self.done = true,
+                // frames can't be resynced after a bad one
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{marketdata::recorder::RecordedTrade, symbology::test_market};
+    use api::{symbology::MarketId, Dir};
+    use chrono::TimeZone;
+    use rust_decimal::Decimal;
+    use std::path::PathBuf;
+
+    fn trades(market: MarketId, from: i64, n: i64) -> Vec<Record> {
+        let start = Utc.with_ymd_and_hms(2024, 6, 3, 14, 0, 0).unwrap();
+        (from..from + n)
+            .map(|i| {
+                let timestamp = start + Duration::milliseconds(i * 37);
+                let trade = RecordedTrade {
+                    timestamp,
+                    price: Decimal::new(108_000 + i % 50, 5),
+                    size: Decimal::from(i % 7 + 1) * Decimal::new(1000, 0),
+                    dir: Some(if i % 3 == 0 { Dir::Sell } else { Dir::Buy }),
+                };
+                Record { recv_time: timestamp, market, data: RecordedData::Trade(trade) }
+            })
+            .collect()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("batched_recording_{}_{name}", std::process::id()))
+    }
+
+    fn read_trades(path: &Path) -> Result<Vec<RecordedTrade>> {
+        BatchedRecordingReader::new(File::open(path)?)?
+            .map(|r| match r?.data {
+                RecordedData::Trade(t) => Ok(t),
+                d => bail!("unexpected record {d:?}"),
+            })
+            .collect()
+    }
+
+    fn count_dicts(path: &Path) -> Result<usize> {
+        let mut n = 0;
+        scan_frames(&mut BufReader::new(File::open(path)?), |kind, _| {
+            n += (kind == FRAME_DICT) as usize
+        })?;
+        Ok(n)
+    }
+
+    #[test]
+    fn test_round_trip_with_roll() -> Result<()> {
+        let market = test_market("EURUSD", "EUR", "USD")?.id;
+        let config = BatchConfig {
+            level: 9,
+            flush: FlushPolicy { max_records: 64, ..Default::default() },
+            dictionary: Some(DictionaryConfig { samples: 1000, max_size: 4096 }),
+        };
+        let (first, second) = (temp_path("first"), temp_path("second"));
+        let (before, after) = (trades(market, 0, 3000), trades(market, 3000, 1000));
+        {
+            let mut writer = BatchedRecordingWriter::open(&first, config)?;
+            for r in &before {
+                writer.write(r)?;
+            }
+            writer.roll(&second)?;
+            for r in &after {
+                writer.write(r)?;
+            }
+        }
+        let trade = |r: &Record| match r.data {
+            RecordedData::Trade(t) => t,
+            _ => unreachable!(),
+        };
+        assert_eq!(read_trades(&first)?, before.iter().map(trade).collect::<Vec<_>>());
+        assert_eq!(read_trades(&second)?, after.iter().map(trade).collect::<Vec<_>>());
+        // the dictionary trained in the first segment is carried into the
+        // second, which is readable on its own
+        assert_eq!(count_dicts(&first)?, 1);
+        assert_eq!(count_dicts(&second)?, 1);
+        // against compressing every record on its own at the same level
+        let mut per_message = 0;
+        for r in before.iter().chain(after.iter()) {
+            per_message += zstd::bulk::compress(&serde_json::to_vec(r)?, 9)?.len();
+        }
+        let batched =
+            std::fs::metadata(&first)?.len() + std::fs::metadata(&second)?.len();
+        assert!(
+            (batched as usize) * 2 < per_message,
+            "batched {batched} bytes, per message {per_message}"
+        );
+        std::fs::remove_file(&first)?;
+        std::fs::remove_file(&second)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_truncates_partial_frame() -> Result<()> {
+        let market = test_market("EURUSD", "EUR", "USD")?.id;
+        let config = BatchConfig { dictionary: None, ..Default::default() };
+        let path = temp_path("reopen");
+        let (before, after) = (trades(market, 0, 10), trades(market, 10, 10));
+        {
+            let mut writer = BatchedRecordingWriter::open(&path, config)?;
+            for r in &before {
+                writer.write(r)?;
+            }
+        }
+        // a crash partway through writing another frame
+        let complete = std::fs::metadata(&path)?.len();
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&[FRAME_BATCH, 2, 0, 100, 0, 0, 0, 100, 0, 0, 0, 1, 2, 3])?;
+        drop(file);
+        {
+            let mut writer = BatchedRecordingWriter::open(&path, config)?;
+            assert_eq!(writer.bytes_written(), complete);
+            for r in &after {
+                writer.write(r)?;
+            }
+        }
+        assert_eq!(read_trades(&path)?.len(), 20);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
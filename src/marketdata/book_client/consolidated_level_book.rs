@@ -120,35 +120,28 @@ impl ConsolidatedLevelBook {
     pub(super) fn update_from_snapshot(
         &mut self,
         market: MarketRef,
-        mut snapshot: Snapshot,
+        snapshot: &Snapshot,
     ) {
         self.clear_one(market);
-        for (price, size) in snapshot.book.buy.drain(..) {
-            self.upsert(market, Dir::Buy, price, size);
+        for (price, size) in snapshot.book.buy.iter() {
+            self.upsert(market, Dir::Buy, *price, *size);
         }
-        for (price, size) in snapshot.book.sell.drain(..) {
-            self.upsert(market, Dir::Sell, price, size);
+        for (price, size) in snapshot.book.sell.iter() {
+            self.upsert(market, Dir::Sell, *price, *size);
         }
     }
 
-    pub(super) fn update(&mut self, market: MarketRef, mut updates: Updates) {
-        for up in updates.book.buy.drain(..) {
-            match up {
-                Update::Change { price, size } => {
-                    self.upsert(market, Dir::Buy, price, size);
-                }
-                Update::Remove { price } => {
-                    self.remove(market, Dir::Buy, price);
-                }
-            }
-        }
-        for up in updates.book.sell.drain(..) {
-            match up {
-                Update::Change { price, size } => {
-                    self.upsert(market, Dir::Sell, price, size);
-                }
-                Update::Remove { price } => {
-                    self.remove(market, Dir::Sell, price);
+    pub(super) fn update(&mut self, market: MarketRef, updates: &Updates) {
+        for (dir, ups) in [(Dir::Buy, &updates.book.buy), (Dir::Sell, &updates.book.sell)]
+        {
+            for up in ups.iter() {
+                match up {
+                    Update::Change { price, size } => {
+                        self.upsert(market, dir, *price, *size);
+                    }
+                    Update::Remove { price } => {
+                        self.remove(market, dir, *price);
+                    }
                 }
             }
         }
@@ -1,8 +1,15 @@
 //! Subscribe to book data
 
+use super::{
+    health::MarketdataMonitor,
+    journal::{JournalHandle, RecvStamp},
+    published::{BookTop, DepthViews, Published},
+};
 use crate::{symbology::MarketRef, synced::Synced};
 use anyhow::{anyhow, bail, Result};
 use api::marketdata::{MessageHeader, NetidxFeedPaths, Snapshot, Updates};
+use bytes::{Buf, Bytes};
+use chrono::Utc;
 use consolidated_level_book::ConsolidatedLevelBook;
 use futures::channel::mpsc;
 use fxhash::FxHashMap;
@@ -13,12 +20,44 @@ use netidx::{
     pool::Pooled,
     subscriber::{Dval, Event, SubId, Subscriber, UpdatesFlags, Value},
 };
-use std::ops::Deref;
+use std::{ops::Deref, sync::Arc};
 use tokio::sync::watch;
 
 pub mod consolidated_level_book;
-pub mod level_book;
-pub use level_book::*;
+pub use super::level_book::{self, *};
+
+/// How many levels of a book a consumer sees.  Feeds that publish depth
+/// limited books (see `ManagedMarketdata::set_depth_limited_cptys`) send
+/// only that many levels; other feeds send the full book, which is needed
+/// to keep even the top levels right as deeper ones move up, and the depth
+/// limits the consumer's view of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BookDepth {
+    Top10,
+    Top50,
+    #[default]
+    Full,
+}
+
+impl BookDepth {
+    pub fn levels(&self) -> Option<usize> {
+        match self {
+            BookDepth::Top10 => Some(10),
+            BookDepth::Top50 => Some(50),
+            BookDepth::Full => None,
+        }
+    }
+
+    /// The leaf under the market's feed path where books of this depth are
+    /// published, for feeds that publish depth limited books
+    pub fn path_leaf(&self) -> &'static str {
+        match self {
+            BookDepth::Top10 => "book-top10",
+            BookDepth::Top50 => "book-top50",
+            BookDepth::Full => "book",
+        }
+    }
+}
 
 /// A subscription to book data
 pub struct BookClient {
@@ -27,6 +66,12 @@ pub struct BookClient {
     subscription: Dval,
     synced: u64,
     tx_updates: watch::Sender<u64>,
+    published: Published<BookTop>,
+    /// None publishes the whole book
+    published_depth: Option<usize>,
+    views: DepthViews,
+    journal: Option<JournalHandle>,
+    health: Option<Arc<MarketdataMonitor>>,
 }
 
 impl Deref for BookClient {
@@ -67,7 +112,18 @@ impl BookClient {
         }
         let synced = 0;
         let (tx_updates, _) = watch::channel(synced);
-        Self { book: LevelBook::default(), market, subscription, synced, tx_updates }
+        Self {
+            book: LevelBook::default(),
+            market,
+            subscription,
+            synced,
+            tx_updates,
+            published: Published::default(),
+            published_depth: None,
+            views: DepthViews::default(),
+            journal: None,
+            health: None,
+        }
     }
 
     /// Return the id of this subscription
@@ -91,35 +147,126 @@ impl BookClient {
         Synced(self.tx_updates.subscribe())
     }
 
+    /// A lock-free handle to the top of this book, updated after every
+    /// message.  Clone it out to reader threads once; reading it doesn't
+    /// touch the lock guarding the client.
+    pub fn published(&self) -> Published<BookTop> {
+        self.published.clone()
+    }
+
+    /// Limit the published snapshot to `depth` levels per side; it holds
+    /// the whole book unless set.  `Some(0)` disables publishing.
+    pub fn set_published_depth(&mut self, depth: Option<usize>) {
+        self.published_depth = depth;
+    }
+
+    /// A lock-free view of the top `depth` levels of this book, shared by
+    /// every consumer asking for the same depth.  Consumers of one
+    /// subscription that want different depths each take their own view.
+    pub fn published_at(&mut self, depth: usize) -> Published<BookTop> {
+        self.views.get(&self.book, depth, self.synced)
+    }
+
+    /// Journal every message received by this client, see `journal`
+    pub fn set_journal(&mut self, journal: Option<JournalHandle>) {
+        self.journal = journal;
+    }
+
+    /// Record the latency and rate of applied messages, see `health`
+    pub fn set_health_monitor(&mut self, health: Option<Arc<MarketdataMonitor>>) {
+        self.health = health;
+    }
+
     /// Process the specified book event, updating the book with its contents.
     pub fn process_event(&mut self, ev: Event) -> Result<()> {
+        self.process_event_at(ev, RecvStamp::now(), None)
+    }
+
+    /// Process an event received at `stamp`, with the venue's sequence
+    /// number of the message if the caller has one; netidx book messages
+    /// don't carry it.  Both are only used for the journal.
+    pub fn process_event_at(
+        &mut self,
+        ev: Event,
+        stamp: RecvStamp,
+        venue_sequence: Option<u64>,
+    ) -> Result<()> {
+        self.journal_event(&ev, stamp, venue_sequence);
+        if let Some(msg) = BookMessage::decode(ev)? {
+            self.apply(&msg);
+        }
+        Ok(())
+    }
+
+    fn journal_event(&self, ev: &Event, stamp: RecvStamp, venue_sequence: Option<u64>) {
+        if let (Some(journal), Event::Update(Value::Bytes(buf))) = (&self.journal, ev) {
+            let msg = Bytes::copy_from_slice(&buf[..]);
+            journal.write(self.market.id, stamp, venue_sequence, msg);
+        }
+    }
+
+    /// Apply an already decoded message to the book, returns false if the
+    /// message was ignored because the book isn't synced yet
+    pub fn apply(&mut self, msg: &BookMessage) -> bool {
+        match msg {
+            BookMessage::Updates(updates) => {
+                if self.synced == 0 {
+                    return false;
+                }
+                trace!("book updates: {:?}", updates);
+                self.book.apply_updates(updates);
+                self.synced += 1;
+            }
+            BookMessage::Snapshot(snap) => {
+                trace!("book snap: {:?}", snap);
+                self.book.apply_snapshot(snap);
+                self.synced = 1;
+            }
+        }
+        if let Some(health) = &self.health {
+            health.record(self.market, self.book.timestamp, Utc::now());
+        }
+        if self.published_depth != Some(0) {
+            self.published.store(BookTop::new(
+                &self.book,
+                self.published_depth.unwrap_or(usize::MAX),
+                self.synced,
+            ));
+        }
+        self.views.publish(&self.book, self.synced);
+        self.tx_updates.send_replace(self.synced);
+        true
+    }
+}
+
+/// A decoded book message
+#[derive(Debug)]
+pub enum BookMessage {
+    Updates(Updates),
+    Snapshot(Snapshot),
+}
+
+impl BookMessage {
+    /// Decode a book event once so it can be applied to any number of
+    /// books by reference. Returns None for events that carry no book data.
+    pub fn decode(ev: Event) -> Result<Option<Self>> {
         match ev {
             Event::Update(Value::Bytes(mut buf)) => {
-                let typ: MessageHeader = Pack::decode(&mut buf)?;
-                match typ {
-                    MessageHeader::Updates => {
-                        if self.synced > 0 {
-                            let updates: Updates = Pack::decode(&mut buf)?;
-                            trace!("book updates: {:?}", updates);
-                            self.book.update(updates);
-                            self.synced += 1;
-                            self.tx_updates.send_replace(self.synced);
-                        }
-                    }
-                    MessageHeader::Snapshot => {
-                        let snap: Snapshot = Pack::decode(&mut buf)?;
-                        trace!("book snap: {:?}", snap);
-                        self.book.update_from_snapshot(snap);
-                        self.synced = 1;
-                        self.tx_updates.send_replace(self.synced);
-                    }
-                }
+                Ok(Some(Self::decode_bytes(&mut buf)?))
             }
             // this is the default value before the book subscribes on the qf side
-            Event::Update(Value::Null) | Event::Unsubscribed => (),
+            Event::Update(Value::Null) | Event::Unsubscribed => Ok(None),
             e => bail!("book protocol error, invalid event {:?}", e),
         }
-        Ok(())
+    }
+
+    /// Decode the bytes of a book event, e.g. from a journal
+    pub fn decode_bytes(buf: &mut impl Buf) -> Result<Self> {
+        let typ: MessageHeader = Pack::decode(buf)?;
+        Ok(match typ {
+            MessageHeader::Updates => Self::Updates(Pack::decode(buf)?),
+            MessageHeader::Snapshot => Self::Snapshot(Pack::decode(buf)?),
+        })
     }
 }
 
@@ -157,6 +304,14 @@ impl ConsolidatedBookClient {
         Self { consolidated_book: ConsolidatedLevelBook::default(), books }
     }
 
+    /// Record the latency and rate of messages applied to each of the
+    /// books, see `health`
+    pub fn set_health_monitor(&mut self, health: Option<Arc<MarketdataMonitor>>) {
+        for (_, (_, b)) in &mut self.books {
+            b.set_health_monitor(health.clone())
+        }
+    }
+
     /// Process the specified book event, updating the indivudal book and
     /// consolidated book with it contents.
     pub fn process_event(&mut self, sub_id: SubId, ev: Event) -> Result<()> {
@@ -164,26 +319,18 @@ impl ConsolidatedBookClient {
             .books
             .get_mut(&sub_id)
             .ok_or_else(|| anyhow!("missing book for sub_id: {:?}", sub_id))?;
-        book_client.process_event(ev.clone())?;
-        match ev {
-            Event::Update(Value::Bytes(mut buf)) => {
-                let typ: MessageHeader = Pack::decode(&mut buf)?;
-                match typ {
-                    MessageHeader::Updates => {
-                        if book_client.synced() {
-                            let updates: Updates = Pack::decode(&mut buf)?;
-                            self.consolidated_book.update(*tp, updates)
-                        }
+        book_client.journal_event(&ev, RecvStamp::now(), None);
+        if let Some(msg) = BookMessage::decode(ev)? {
+            if book_client.apply(&msg) {
+                match &msg {
+                    BookMessage::Updates(updates) => {
+                        self.consolidated_book.update(*tp, updates)
                     }
-                    MessageHeader::Snapshot => {
-                        let snap: Snapshot = Pack::decode(&mut buf)?;
+                    BookMessage::Snapshot(snap) => {
                         self.consolidated_book.update_from_snapshot(*tp, snap)
                     }
                 }
             }
-            // this is the default value before the book subscribes on the qf side
-            Event::Update(Value::Null) | Event::Unsubscribed => (),
-            e => bail!("book protocol error, invalid event {:?}", e),
         }
         Ok(())
     }
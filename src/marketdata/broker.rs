@@ -0,0 +1,98 @@
+//! A process wide marketdata broker shared by every `ArchitectClient`.
+//!
+//! Clients in one process (e.g. trading under different keys) that ask for
+//! the same stream attach to a single upstream subscription keyed by
+//! endpoint, market, and stream kind, instead of each opening their own.
+//! The upstream is a watched stream (see
+//! `ArchitectClient::subscribe_l1_book_snapshots_watched`) and is torn
+//! down once its last subscriber is dropped.
+
+use crate::ArchitectClient;
+use api::{external::marketdata::L1BookSnapshot, symbology::MarketId};
+use fxhash::FxHashMap;
+use log::debug;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StreamKind {
+    L1BookSnapshots,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct StreamKey {
+    endpoint: String,
+    market: MarketId,
+    kind: StreamKind,
+}
+
+struct Upstream {
+    tx: broadcast::Sender<L1BookSnapshot>,
+    last: Arc<Mutex<Option<L1BookSnapshot>>>,
+}
+
+#[derive(Default)]
+pub struct MarketdataBroker {
+    upstreams: Mutex<FxHashMap<StreamKey, Upstream>>,
+}
+
+static BROKER: Lazy<MarketdataBroker> = Lazy::new(MarketdataBroker::default);
+
+impl MarketdataBroker {
+    pub fn global() -> &'static MarketdataBroker {
+        &BROKER
+    }
+
+    /// Attach to the L1 book stream for the market, starting the upstream
+    /// with `client`'s keepalive settings if nobody else is subscribed.
+    /// Returns the most recent snapshot, if any, so late joiners don't have
+    /// to wait for the next update.
+    pub fn subscribe_l1_book_snapshots(
+        &self,
+        client: &ArchitectClient,
+        endpoint: impl AsRef<str>,
+        market: MarketId,
+    ) -> (Option<L1BookSnapshot>, broadcast::Receiver<L1BookSnapshot>) {
+        let key = StreamKey {
+            endpoint: endpoint.as_ref().trim_end_matches('/').to_string(),
+            market,
+            kind: StreamKind::L1BookSnapshots,
+        };
+        let mut upstreams = self.upstreams.lock();
+        if let Some(up) = upstreams.get(&key) {
+            // an upstream with no receivers is winding down, replace it
+            if up.tx.receiver_count() > 0 {
+                return (up.last.lock().clone(), up.tx.subscribe());
+            }
+        }
+        debug!("starting shared upstream {key:?}");
+        let (tx, rx) = broadcast::channel(1000);
+        let last = Arc::new(Mutex::new(None));
+        let mut snaps =
+            client.subscribe_l1_book_snapshots_watched(&key.endpoint, Some(vec![market]));
+        {
+            let tx = tx.clone();
+            let last = last.clone();
+            tokio::task::spawn(async move {
+                while let Some(snap) = snaps.recv().await {
+                    *last.lock() = Some(snap.clone());
+                    // errors only when every subscriber is gone; dropping
+                    // snaps then stops the watched stream
+                    if tx.send(snap).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        upstreams.retain(|_, up| up.tx.receiver_count() > 0);
+        upstreams.insert(key, Upstream { tx, last });
+        (None, rx)
+    }
+
+    /// The number of upstream subscriptions with at least one subscriber
+    pub fn upstreams(&self) -> usize {
+        self.upstreams.lock().values().filter(|up| up.tx.receiver_count() > 0).count()
+    }
+}
@@ -0,0 +1,285 @@
+//! Build candles from trades on the client.
+//!
+//! `CandleBuilder` aggregates trades into OHLCV candles of any width,
+//! including widths the server doesn't offer.  Candles are aligned to
+//! multiples of the width since the epoch (plus an offset), since the
+//! session open, or since the first trade.  As trades arrive it emits the
+//! candle in progress as a `CandleEvent::Partial`, and each candle once its
+//! interval is over as a `CandleEvent::Final`; `tick` finalizes candles
+//! when time passes without trades.  With `GapFill::Flat`, intervals
+//! without trades get a flat, zero volume candle at the previous close, so
+//! the series has no holes; gaps are not filled across session breaks.
+//!
+//! `build_candles` drives a builder from a trade stream.
+
+use super::{
+    resample::{Bar, Session},
+    time_and_sales::Print,
+};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use futures::{Stream, StreamExt};
+use log::debug;
+use rust_decimal::Decimal;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    /// at multiples of the width since the unix epoch, shifted by `offset`,
+    /// e.g. 5 minute candles starting at :00, :05, ...
+    Epoch { offset: Duration },
+    /// at multiples of the width since the session open; candles don't
+    /// span the close and trades outside the session are dropped
+    Session(Session),
+    /// at multiples of the width since the first trade
+    FirstTrade,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapFill {
+    /// intervals without trades have no candle
+    Skip,
+    /// intervals without trades get a flat candle at the previous close
+    Flat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CandleConfig {
+    pub width: Duration,
+    pub alignment: Alignment,
+    pub gap_fill: GapFill,
+    /// emit the candle in progress after each trade
+    pub partials: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandleEvent {
+    /// the candle in progress, updated
+    Partial(Bar),
+    /// a finished candle; it won't change
+    Final(Bar),
+}
+
+pub struct CandleBuilder {
+    config: CandleConfig,
+    /// the alignment origin, set by the first trade for `FirstTrade`
+    anchor: Option<DateTime<Utc>>,
+    current: Option<Bar>,
+    /// the end of the last final candle, and its close
+    last: Option<(DateTime<Utc>, Decimal)>,
+}
+
+impl CandleBuilder {
+    pub fn new(config: CandleConfig) -> Result<Self> {
+        if config.width <= Duration::zero() {
+            bail!("invalid candle width {}", config.width);
+        }
+        Ok(Self { config, anchor: None, current: None, last: None })
+    }
+
+    pub fn config(&self) -> &CandleConfig {
+        &self.config
+    }
+
+    /// The candle in progress
+    pub fn current(&self) -> Option<&Bar> {
+        self.current.as_ref()
+    }
+
+    /// The [start, end) of the candle containing `t`, if any
+    fn interval(&self, t: DateTime<Utc>) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let (origin, limit) = match self.config.alignment {
+            // the default is the unix epoch
+            Alignment::Epoch { offset } => (DateTime::<Utc>::default() + offset, None),
+            Alignment::Session(session) => {
+                let start = session.start_of(t)?;
+                (start, Some(start + session.length))
+            }
+            Alignment::FirstTrade => (self.anchor?, None),
+        };
+        let width = self.config.width.num_milliseconds();
+        let n = (t - origin).num_milliseconds().div_euclid(width);
+        let start = origin + Duration::milliseconds(width * n);
+        let end = start + self.config.width;
+        Some((start, limit.map_or(end, |l| end.min(l))))
+    }
+
+    /// Finalize the candle in progress, and fill gaps, for intervals over
+    /// by `t`
+    fn finalize_through(&mut self, t: DateTime<Utc>, events: &mut Vec<CandleEvent>) {
+        if let Some(cur) = self.current {
+            if t < cur.end {
+                return;
+            }
+            events.push(CandleEvent::Final(cur));
+            self.last = Some((cur.end, cur.close));
+            self.current = None;
+        }
+        if self.config.gap_fill != GapFill::Flat {
+            return;
+        }
+        while let Some((from, close)) = self.last {
+            let Some((start, end)) = self.interval(from) else { break };
+            if end > t {
+                break;
+            }
+            events.push(CandleEvent::Final(Bar {
+                start,
+                end,
+                open: close,
+                high: close,
+                low: close,
+                close,
+                volume: Decimal::ZERO,
+                notional: Decimal::ZERO,
+                trades: 0,
+            }));
+            self.last = Some((end, close));
+        }
+    }
+
+    /// Add a trade, returning the candles it finalized and, if partials
+    /// are on, the candle in progress.  Trades must be time ordered; a
+    /// trade before the candle in progress is dropped.
+    pub fn push(&mut self, timestamp: DateTime<Utc>, print: &Print) -> Vec<CandleEvent> {
+        let mut events = vec![];
+        if self.config.alignment == Alignment::FirstTrade && self.anchor.is_none() {
+            self.anchor = Some(timestamp);
+        }
+        let Some((start, end)) = self.interval(timestamp) else {
+            debug!("dropping trade at {timestamp} outside the session");
+            return events;
+        };
+        self.finalize_through(start, &mut events);
+        match &mut self.current {
+            Some(cur) if timestamp < cur.start => {
+                debug!("dropping late trade at {timestamp}");
+                return events;
+            }
+            Some(cur) => {
+                cur.high = cur.high.max(print.price);
+                cur.low = cur.low.min(print.price);
+                cur.close = print.price;
+                cur.volume += print.size;
+                cur.notional += print.price * print.size;
+                cur.trades += 1;
+            }
+            None => {
+                if self.last.is_some_and(|(last_end, _)| start < last_end) {
+                    debug!("dropping late trade at {timestamp}");
+                    return events;
+                }
+                self.current = Some(Bar {
+                    start,
+                    end,
+                    open: print.price,
+                    high: print.price,
+                    low: print.price,
+                    close: print.price,
+                    volume: print.size,
+                    notional: print.price * print.size,
+                    trades: 1,
+                });
+            }
+        }
+        if self.config.partials {
+            events.extend(self.current.map(CandleEvent::Partial));
+        }
+        events
+    }
+
+    /// Finalize candles whose interval is over by `now`, without a trade
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Vec<CandleEvent> {
+        let mut events = vec![];
+        self.finalize_through(now, &mut events);
+        events
+    }
+
+    /// Finalize and return the candle in progress, e.g. at the end of the
+    /// data
+    pub fn flush(&mut self) -> Option<Bar> {
+        let cur = self.current.take()?;
+        self.last = Some((cur.end, cur.close));
+        Some(cur)
+    }
+}
+
+/// Build candles from a stream of trades, finalizing candles on time as
+/// well as on trades.  Ends when the trade stream ends, after flushing the
+/// candle in progress, or when the receiver is dropped.
+pub fn build_candles<S>(
+    trades: S,
+    mut builder: CandleBuilder,
+) -> mpsc::Receiver<CandleEvent>
+where
+    S: Stream<Item = (DateTime<Utc>, Print)> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(1000);
+    tokio::task::spawn(async move {
+        let mut trades = Box::pin(trades);
+        let tick = builder.config.width.to_std().unwrap_or_default();
+        let mut ticks =
+            tokio::time::interval(tick.min(std::time::Duration::from_secs(1)));
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            let events = tokio::select! {
+                trade = trades.next() => match trade {
+                    Some((timestamp, print)) => builder.push(timestamp, &print),
+                    None => {
+                        if let Some(bar) = builder.flush() {
+                            let _ = tx.send(CandleEvent::Final(bar)).await;
+                        }
+                        break;
+                    }
+                },
+                _ = ticks.tick() => builder.tick(Utc::now()),
+            };
+            for ev in events {
+                if tx.send(ev).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_candle_builder() -> Result<()> {
+        let t = |m, s| Utc.with_ymd_and_hms(2024, 3, 5, 12, m, s).unwrap();
+        let print = |price, size| Print { price, size, dir: None };
+        let mut b = CandleBuilder::new(CandleConfig {
+            width: Duration::minutes(7),
+            alignment: Alignment::Epoch { offset: Duration::zero() },
+            gap_fill: GapFill::Flat,
+            partials: true,
+        })?;
+        // 12:00 isn't a multiple of 7 minutes since the epoch; 11:57 is
+        let ev = b.push(t(0, 0), &print(dec!(100), dec!(1)));
+        assert!(matches!(ev[..], [CandleEvent::Partial(bar)]
+            if bar.start == Utc.with_ymd_and_hms(2024, 3, 5, 11, 57, 0).unwrap()));
+        b.push(t(1, 0), &print(dec!(101), dec!(2)));
+        // two intervals later: one final, one flat, then the new partial
+        let ev = b.push(t(12, 0), &print(dec!(99), dec!(1)));
+        assert_eq!(ev.len(), 3);
+        let CandleEvent::Final(first) = ev[0] else { panic!("expected a final candle") };
+        assert_eq!(
+            (first.open, first.high, first.close),
+            (dec!(100), dec!(101), dec!(101))
+        );
+        assert_eq!(first.volume, dec!(3));
+        let CandleEvent::Final(flat) = ev[1] else { panic!("expected a flat candle") };
+        assert_eq!((flat.start, flat.end), (t(4, 0), t(11, 0)));
+        assert_eq!((flat.close, flat.volume), (dec!(101), dec!(0)));
+        assert!(matches!(ev[2], CandleEvent::Partial(bar) if bar.start == t(11, 0)));
+        assert_eq!(b.tick(t(17, 59)), vec![]);
+        assert_eq!(b.tick(t(18, 0)).len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+//! Check candles against the trades they were built from.
+//!
+//! `check_candles` rebuilds time bars from a trade stream with the
+//! `Resampler` and compares them bar by bar with candles from a server
+//! (converted to `Bar`), for data quality monitoring.  A candle with more
+//! volume than its trades usually means prints are missing from the trade
+//! stream; less, or a candle missing altogether, that the candle is stale
+//! or was built from partial data.
+
+use super::{
+    resample::{resample_trades, Bar, BarSpec, Session},
+    time_and_sales::Print,
+};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CandleCheckConfig {
+    pub width: Duration,
+    pub session: Session,
+    /// largest absolute price difference not flagged
+    pub price_tolerance: Decimal,
+    /// largest volume difference not flagged, as a fraction of the
+    /// recomputed volume
+    pub volume_tolerance: Decimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Open,
+    High,
+    Low,
+    Close,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// trades in the interval but no candle
+    MissingCandle {
+        computed: Bar,
+    },
+    /// a candle with volume but no trades in the interval
+    NoTrades {
+        server: Bar,
+    },
+    Price {
+        field: Field,
+        server: Decimal,
+        computed: Decimal,
+    },
+    Volume {
+        server: Decimal,
+        computed: Decimal,
+    },
+}
+
+impl Discrepancy {
+    /// The candle has more volume than the trades, so prints are likely
+    /// missing from the trade stream
+    pub fn is_missing_prints(&self) -> bool {
+        match self {
+            Discrepancy::NoTrades { .. } => true,
+            Discrepancy::Volume { server, computed } => server > computed,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CandleReport {
+    /// the number of intervals compared
+    pub checked: usize,
+    /// by candle start time
+    pub discrepancies: Vec<(DateTime<Utc>, Discrepancy)>,
+}
+
+impl CandleReport {
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// Compare `server` candles with candles recomputed from `trades`, both
+/// time ordered and covering the same period
+pub fn check_candles<'a>(
+    trades: impl IntoIterator<Item = &'a (DateTime<Utc>, Print)>,
+    server: &[Bar],
+    config: &CandleCheckConfig,
+) -> Result<CandleReport> {
+    let computed = resample_trades(trades, BarSpec::Time(config.width), config.session)?;
+    let mut by_start: BTreeMap<DateTime<Utc>, (Option<&Bar>, Option<&Bar>)> =
+        BTreeMap::new();
+    for bar in server {
+        by_start.entry(bar.start).or_default().0 = Some(bar);
+    }
+    for bar in &computed {
+        by_start.entry(bar.start).or_default().1 = Some(bar);
+    }
+    let mut report = CandleReport { checked: by_start.len(), discrepancies: vec![] };
+    for (start, pair) in by_start {
+        let mut flag = |d| report.discrepancies.push((start, d));
+        match pair {
+            (None, Some(computed)) => {
+                flag(Discrepancy::MissingCandle { computed: *computed })
+            }
+            (Some(server), None) => {
+                if !server.volume.is_zero() {
+                    flag(Discrepancy::NoTrades { server: *server })
+                }
+            }
+            (Some(server), Some(computed)) => {
+                for (field, s, c) in [
+                    (Field::Open, server.open, computed.open),
+                    (Field::High, server.high, computed.high),
+                    (Field::Low, server.low, computed.low),
+                    (Field::Close, server.close, computed.close),
+                ] {
+                    if (s - c).abs() > config.price_tolerance {
+                        flag(Discrepancy::Price { field, server: s, computed: c });
+                    }
+                }
+                let diff = (server.volume - computed.volume).abs();
+                if diff > computed.volume * config.volume_tolerance {
+                    flag(Discrepancy::Volume {
+                        server: server.volume,
+                        computed: computed.volume,
+                    });
+                }
+            }
+            (None, None) => (),
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_check_candles() -> Result<()> {
+        let t = |m| Utc.with_ymd_and_hms(2024, 3, 5, 12, m, 0).unwrap();
+        let print = |price, size| Print { price, size, dir: None };
+        let trades = vec![
+            (t(0), print(dec!(100), dec!(1))),
+            (t(0), print(dec!(101), dec!(1))),
+            (t(1), print(dec!(102), dec!(3))),
+        ];
+        let bar = |m, o, h, l, c, v| Bar {
+            start: t(m),
+            end: t(m + 1),
+            open: o,
+            high: h,
+            low: l,
+            close: c,
+            volume: v,
+            notional: Decimal::ZERO,
+            trades: 0,
+        };
+        let server = vec![
+            bar(0, dec!(100), dec!(101), dec!(100), dec!(101), dec!(2)),
+            // a print of 2 missing from the trades
+            bar(1, dec!(102), dec!(102), dec!(102), dec!(102), dec!(5)),
+            bar(2, dec!(103), dec!(103), dec!(103), dec!(103), dec!(1)),
+        ];
+        let config = CandleCheckConfig {
+            width: Duration::minutes(1),
+            session: Session::utc_day(),
+            price_tolerance: Decimal::ZERO,
+            volume_tolerance: dec!(0.01),
+        };
+        let report = check_candles(&trades, &server, &config)?;
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.discrepancies.len(), 2);
+        assert_eq!(
+            report.discrepancies[0],
+            (t(1), Discrepancy::Volume { server: dec!(5), computed: dec!(3) })
+        );
+        assert!(matches!(report.discrepancies[1], (_, Discrepancy::NoTrades { .. })));
+        assert!(report.discrepancies.iter().all(|(_, d)| d.is_missing_prints()));
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Conflate marketdata to a maximum update rate per key.
+//!
+//! A `Conflator` passes through the first update for a key, then at most
+//! one update per `interval`: updates arriving sooner replace each other,
+//! and the latest is delivered when the interval is up.  Consumers that
+//! can't keep up with the raw feed, like UIs, see at most e.g. 10 updates
+//! per second per market, always ending at the latest state.
+//!
+//! Only conflate updates that are complete states, like L1 snapshots or
+//! tickers; dropping an L2 diff corrupts the book built from the rest.
+//!
+//! `conflate` applies a conflator to any stream, e.g. with the interval
+//! from `venue_config::ResolvedMarketdata::conflation`.
+
+use anyhow::{bail, Result};
+use futures::{Stream, StreamExt};
+use fxhash::FxHashMap;
+use std::{hash::Hash, time::Duration};
+use tokio::{sync::mpsc, time::Instant};
+
+struct KeyState<T> {
+    last_sent: Instant,
+    pending: Option<T>,
+}
+
+pub struct Conflator<K, T> {
+    interval: Duration,
+    keys: FxHashMap<K, KeyState<T>>,
+    conflated: u64,
+}
+
+impl<K: Hash + Eq, T> Conflator<K, T> {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, keys: FxHashMap::default(), conflated: 0 }
+    }
+
+    /// At most `rate` updates per second per key
+    pub fn per_second(rate: u32) -> Result<Self> {
+        if rate == 0 {
+            bail!("invalid conflation rate 0");
+        }
+        Ok(Self::new(Duration::from_secs(1) / rate))
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The number of updates replaced by a later one and never delivered
+    pub fn conflated(&self) -> u64 {
+        self.conflated
+    }
+
+    /// Add an update, returning it if it should be delivered now; otherwise
+    /// it is held until `due`
+    pub fn push(&mut self, key: K, item: T, now: Instant) -> Option<T> {
+        match self.keys.get_mut(&key) {
+            Some(st) if now < st.last_sent + self.interval => {
+                if st.pending.replace(item).is_some() {
+                    self.conflated += 1;
+                }
+                None
+            }
+            Some(st) => {
+                // anything pending is older than this
+                if st.pending.take().is_some() {
+                    self.conflated += 1;
+                }
+                st.last_sent = now;
+                Some(item)
+            }
+            None => {
+                self.keys.insert(key, KeyState { last_sent: now, pending: None });
+                Some(item)
+            }
+        }
+    }
+
+    /// Take the held updates whose interval is up
+    pub fn due(&mut self, now: Instant) -> Vec<T> {
+        let interval = self.interval;
+        let mut res = vec![];
+        for st in self.keys.values_mut() {
+            if st.pending.is_some() && now >= st.last_sent + interval {
+                res.extend(st.pending.take());
+                st.last_sent = now;
+            }
+        }
+        res
+    }
+
+    /// When the next held update is due, if any are held
+    pub fn next_due(&self) -> Option<Instant> {
+        self.keys
+            .values()
+            .filter(|st| st.pending.is_some())
+            .map(|st| st.last_sent + self.interval)
+            .min()
+    }
+
+    /// Take every held update regardless of the rate, e.g. at the end of
+    /// the stream
+    pub fn flush(&mut self) -> Vec<T> {
+        self.keys.values_mut().filter_map(|st| st.pending.take()).collect()
+    }
+}
+
+/// Conflate `updates` by the key `key` returns for each.  Ends when the
+/// stream ends, after delivering anything held, or when the receiver is
+/// dropped.
+pub fn conflate<S, T, K>(
+    updates: S,
+    mut conflator: Conflator<K, T>,
+    key: impl Fn(&T) -> K + Send + 'static,
+) -> mpsc::Receiver<T>
+where
+    S: Stream<Item = T> + Send + 'static,
+    T: Send + 'static,
+    K: Hash + Eq + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(1000);
+    tokio::task::spawn(async move {
+        let mut updates = Box::pin(updates);
+        loop {
+            let next_due = conflator.next_due();
+            let ready: Vec<T> = tokio::select! {
+                update = updates.next() => match update {
+                    Some(update) => {
+                        let k = key(&update);
+                        conflator.push(k, update, Instant::now()).into_iter().collect()
+                    }
+                    None => {
+                        for update in conflator.flush() {
+                            if tx.send(update).await.is_err() {
+                                return;
+                            }
+                        }
+                        break;
+                    }
+                },
+                () = async {
+                    match next_due {
+                        Some(at) => tokio::time::sleep_until(at).await,
+                        None => std::future::pending().await,
+                    }
+                } => conflator.due(Instant::now()),
+            };
+            for update in ready {
+                if tx.send(update).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflator() -> Result<()> {
+        let mut c = Conflator::per_second(10)?;
+        let t0 = Instant::now();
+        let ms = |n| t0 + Duration::from_millis(n);
+        assert_eq!(c.push("a", 1, ms(0)), Some(1));
+        assert_eq!(c.push("b", 1, ms(10)), Some(1));
+        assert_eq!(c.push("a", 2, ms(20)), None);
+        assert_eq!(c.push("a", 3, ms(30)), None);
+        assert_eq!(c.next_due(), Some(ms(100)));
+        assert_eq!(c.due(ms(99)), Vec::<i32>::new());
+        // the latest state, not the first held
+        assert_eq!(c.due(ms(100)), vec![3]);
+        assert_eq!(c.conflated(), 1);
+        assert_eq!(c.next_due(), None);
+        assert_eq!(c.push("a", 4, ms(150)), None);
+        assert_eq!(c.push("a", 5, ms(250)), Some(5));
+        assert_eq!(c.conflated(), 2);
+        assert_eq!(c.flush(), Vec::<i32>::new());
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+//! A national best bid and offer across venues.
+//!
+//! `ConsolidatedL1` subscribes to the L1 book of the same instrument on
+//! several venues through the process wide `MarketdataBroker`, and keeps the
+//! best bid and best offer across them, attributed to the venue quoting
+//! it.  Ties on price go to the larger size, then to the venue listed
+//! first.  `nbbo` gets the current NBBO; `subscribe` streams it, one update
+//! per change.
+
+use super::broker::MarketdataBroker;
+use crate::ArchitectClient;
+use api::{external::marketdata::L1BookSnapshot, symbology::MarketId};
+use chrono::{DateTime, TimeZone, Utc};
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use tokio::{
+    sync::{broadcast, watch},
+    task::JoinHandle,
+};
+
+/// The instrument's market on one venue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct L1Source {
+    pub venue: String,
+    pub endpoint: String,
+    pub market: MarketId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VenueQuote {
+    pub venue: String,
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Nbbo {
+    pub bid: Option<VenueQuote>,
+    pub ask: Option<VenueQuote>,
+    /// the latest venue update that went into it
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl Nbbo {
+    /// True if the best bid is at or above the best offer across venues
+    pub fn is_crossed(&self) -> bool {
+        match (&self.bid, &self.ask) {
+            (Some(bid), Some(ask)) => bid.price >= ask.price,
+            _ => false,
+        }
+    }
+}
+
+/// One venue's best bid and offer (price, size)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VenueBbo {
+    pub bid: Option<(Decimal, Decimal)>,
+    pub ask: Option<(Decimal, Decimal)>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// The NBBO of per venue quotes, in source order
+fn consolidate<'a>(quotes: impl IntoIterator<Item = (&'a str, &'a VenueBbo)>) -> Nbbo {
+    let mut nbbo = Nbbo::default();
+    let better = |cur: &Option<VenueQuote>, price: Decimal, size: Decimal, bid: bool| {
+        cur.as_ref().map_or(true, |c| {
+            let improves = if bid { price > c.price } else { price < c.price };
+            improves || (price == c.price && size > c.size)
+        })
+    };
+    for (venue, q) in quotes {
+        if let Some((price, size)) = q.bid {
+            if better(&nbbo.bid, price, size, true) {
+                nbbo.bid = Some(VenueQuote { venue: venue.to_string(), price, size });
+            }
+        }
+        if let Some((price, size)) = q.ask {
+            if better(&nbbo.ask, price, size, false) {
+                nbbo.ask = Some(VenueQuote { venue: venue.to_string(), price, size });
+            }
+        }
+        nbbo.timestamp = nbbo.timestamp.max(q.timestamp);
+    }
+    nbbo
+}
+
+pub struct ConsolidatedL1 {
+    sources: Vec<L1Source>,
+    quotes: Arc<Mutex<Vec<VenueBbo>>>,
+    nbbo: watch::Receiver<Nbbo>,
+    tx: broadcast::Sender<Nbbo>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for ConsolidatedL1 {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl ConsolidatedL1 {
+    pub fn subscribe_sources(client: &ArchitectClient, sources: Vec<L1Source>) -> Self {
+        let quotes = Arc::new(Mutex::new(vec![VenueBbo::default(); sources.len()]));
+        let (nbbo_tx, nbbo) = watch::channel(Nbbo::default());
+        let nbbo_tx = Arc::new(nbbo_tx);
+        let (tx, _) = broadcast::channel(1000);
+        let names: Arc<Vec<String>> =
+            Arc::new(sources.iter().map(|s| s.venue.clone()).collect());
+        let mut tasks = vec![];
+        for (i, source) in sources.iter().enumerate() {
+            let (last, mut rx) = MarketdataBroker::global().subscribe_l1_book_snapshots(
+                client,
+                &source.endpoint,
+                source.market,
+            );
+            let quotes = quotes.clone();
+            let nbbo_tx = nbbo_tx.clone();
+            let tx = tx.clone();
+            let names = names.clone();
+            let update = move |snap: &L1BookSnapshot| {
+                let nbbo = {
+                    let mut quotes = quotes.lock();
+                    quotes[i] = VenueBbo {
+                        bid: snap.best_bid,
+                        ask: snap.best_ask,
+                        timestamp: Utc
+                            .timestamp_opt(snap.timestamp_s, snap.timestamp_ns)
+                            .single(),
+                    };
+                    consolidate(names.iter().map(|n| n.as_str()).zip(quotes.iter()))
+                };
+                let changed = nbbo_tx.send_if_modified(|cur| {
+                    let changed = cur.bid != nbbo.bid || cur.ask != nbbo.ask;
+                    *cur = nbbo.clone();
+                    changed
+                });
+                if changed {
+                    let _ = tx.send(nbbo);
+                }
+            };
+            if let Some(snap) = &last {
+                update(snap);
+            }
+            tasks.push(tokio::task::spawn(async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(snap) => update(&snap),
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            }));
+        }
+        Self { sources, quotes, nbbo, tx, tasks }
+    }
+
+    pub fn sources(&self) -> &[L1Source] {
+        &self.sources
+    }
+
+    pub fn nbbo(&self) -> Nbbo {
+        self.nbbo.borrow().clone()
+    }
+
+    /// Each venue's best bid and offer, in source order
+    pub fn venue_quotes(&self) -> Vec<(&str, VenueBbo)> {
+        let quotes = self.quotes.lock();
+        self.sources
+            .iter()
+            .zip(quotes.iter())
+            .map(|(s, q)| (s.venue.as_str(), *q))
+            .collect()
+    }
+
+    /// The NBBO, each time it changes
+    pub fn subscribe(&self) -> broadcast::Receiver<Nbbo> {
+        self.tx.subscribe()
+    }
+
+    /// The NBBO as a watch, holding the latest
+    pub fn watch(&self) -> watch::Receiver<Nbbo> {
+        self.nbbo.clone()
+    }
+}
@@ -0,0 +1,222 @@
+//! A consolidated L2 book across venues over gRPC.
+//!
+//! `ConsolidatedL2Client` is the gRPC counterpart of the netidx
+//! `ConsolidatedBookClient`: it subscribes to the L2 book updates of the
+//! same instrument on several venues and merges them into one
+//! `ConsolidatedLevelBook`, each level attributed to the markets quoting
+//! it.  Every venue is tracked by sequence number; when a diff is missed,
+//! or a venue's stream is lost, that venue's levels are pulled from the
+//! book until a fresh snapshot arrives, so the book never shows levels a
+//! venue may no longer have.
+
+use super::book_client::consolidated_level_book::ConsolidatedLevelBook;
+use crate::{
+    client::{ResilientStream, StreamEvent},
+    symbology::MarketRef,
+    ArchitectClient,
+};
+use api::{
+    external::marketdata::{L2BookDiff, L2BookSnapshot, L2BookUpdate},
+    Dir,
+};
+use log::{info, warn};
+use parking_lot::{Mutex, MutexGuard};
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use tokio::{sync::broadcast, task::JoinHandle};
+
+/// The instrument's market and the endpoint serving its book
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct L2Source {
+    pub endpoint: String,
+    pub market: MarketRef,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VenueStatus {
+    /// waiting for the first snapshot
+    Syncing,
+    Live,
+    /// the stream was lost or missed a diff; waiting for a fresh snapshot
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsolidatedL2Event {
+    Updated(MarketRef),
+    /// the market's levels were removed from the book
+    Dropped(MarketRef),
+    /// the market is back in the book after being dropped
+    Rejoined(MarketRef),
+}
+
+struct Shared {
+    book: Mutex<ConsolidatedLevelBook>,
+    status: Mutex<Vec<VenueStatus>>,
+    tx: broadcast::Sender<ConsolidatedL2Event>,
+}
+
+impl Shared {
+    fn set_status(&self, i: usize, status: VenueStatus) -> VenueStatus {
+        std::mem::replace(&mut self.status.lock()[i], status)
+    }
+
+    fn apply_snapshot(&self, i: usize, market: MarketRef, snap: &L2BookSnapshot) {
+        {
+            let mut book = self.book.lock();
+            book.clear_one(market);
+            for (dir, levels) in [(Dir::Buy, &snap.bids), (Dir::Sell, &snap.asks)] {
+                for (price, size) in levels {
+                    book.upsert(market, dir, *price, *size);
+                }
+            }
+            book.timestamp = book.timestamp.max(snap.timestamp);
+        }
+        let ev = match self.set_status(i, VenueStatus::Live) {
+            VenueStatus::Down => {
+                info!("{market} rejoined the consolidated book");
+                ConsolidatedL2Event::Rejoined(market)
+            }
+            VenueStatus::Syncing | VenueStatus::Live => {
+                ConsolidatedL2Event::Updated(market)
+            }
+        };
+        let _ = self.tx.send(ev);
+    }
+
+    fn apply_diff(&self, market: MarketRef, diff: &L2BookDiff) {
+        {
+            let mut book = self.book.lock();
+            for (dir, levels) in [(Dir::Buy, &diff.bids), (Dir::Sell, &diff.asks)] {
+                for (price, size) in levels {
+                    if *size == Decimal::ZERO {
+                        book.remove(market, dir, *price);
+                    } else {
+                        book.upsert(market, dir, *price, *size);
+                    }
+                }
+            }
+            book.timestamp = book.timestamp.max(diff.timestamp);
+        }
+        let _ = self.tx.send(ConsolidatedL2Event::Updated(market));
+    }
+
+    fn drop_venue(&self, i: usize, market: MarketRef) {
+        self.book.lock().clear_one(market);
+        if self.set_status(i, VenueStatus::Down) != VenueStatus::Down {
+            let _ = self.tx.send(ConsolidatedL2Event::Dropped(market));
+        }
+    }
+
+    async fn run(
+        &self,
+        i: usize,
+        market: MarketRef,
+        mut stream: ResilientStream<L2BookUpdate>,
+    ) {
+        // (sequence id, sequence number) of the last applied update; None
+        // until a snapshot is applied
+        let mut seq: Option<(u64, u64)> = None;
+        while let Some(ev) = stream.next().await {
+            match ev {
+                StreamEvent::Gap { downtime } => {
+                    warn!("{market} book stream was down for {downtime:?}");
+                    seq = None;
+                    self.drop_venue(i, market);
+                }
+                StreamEvent::Data(L2BookUpdate::Snapshot(snap)) => {
+                    seq = Some((snap.sequence_id, snap.sequence_number));
+                    self.apply_snapshot(i, market, &snap);
+                }
+                StreamEvent::Data(L2BookUpdate::Diff(diff)) => match seq {
+                    // waiting for a snapshot
+                    None => (),
+                    Some((id, n))
+                        if diff.sequence_id == id && diff.sequence_number == n + 1 =>
+                    {
+                        seq = Some((id, diff.sequence_number));
+                        self.apply_diff(market, &diff);
+                    }
+                    Some((id, n)) => {
+                        warn!(
+                            "{market} book sequence gap, expected {id}:{} got {}:{}, resyncing",
+                            n + 1,
+                            diff.sequence_id,
+                            diff.sequence_number
+                        );
+                        seq = None;
+                        self.drop_venue(i, market);
+                        stream.resubscribe();
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Subscriptions to one instrument's L2 book on several venues,
+/// consolidated into one
+pub struct ConsolidatedL2Client {
+    sources: Vec<L2Source>,
+    shared: Arc<Shared>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for ConsolidatedL2Client {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl ConsolidatedL2Client {
+    pub fn subscribe_sources(client: &ArchitectClient, sources: Vec<L2Source>) -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        let shared = Arc::new(Shared {
+            book: Mutex::new(ConsolidatedLevelBook::default()),
+            status: Mutex::new(vec![VenueStatus::Syncing; sources.len()]),
+            tx,
+        });
+        let tasks = sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| {
+                let stream = client.subscribe_l2_book_updates_resilient(
+                    &source.endpoint,
+                    source.market.id,
+                );
+                let shared = shared.clone();
+                let market = source.market;
+                tokio::task::spawn(async move { shared.run(i, market, stream).await })
+            })
+            .collect();
+        Self { sources, shared, tasks }
+    }
+
+    pub fn sources(&self) -> &[L2Source] {
+        &self.sources
+    }
+
+    /// The consolidated book; don't hold the lock across awaits, it blocks
+    /// updates from every venue
+    pub fn book(&self) -> MutexGuard<'_, ConsolidatedLevelBook> {
+        self.shared.book.lock()
+    }
+
+    /// Each market's status, in source order
+    pub fn status(&self) -> Vec<(MarketRef, VenueStatus)> {
+        let status = self.shared.status.lock();
+        self.sources.iter().zip(status.iter()).map(|(s, st)| (s.market, *st)).collect()
+    }
+
+    /// True when every venue is in the book
+    pub fn is_synced(&self) -> bool {
+        self.shared.status.lock().iter().all(|s| *s == VenueStatus::Live)
+    }
+
+    /// A notification per applied update, drop or rejoin
+    pub fn subscribe(&self) -> broadcast::Receiver<ConsolidatedL2Event> {
+        self.shared.tx.subscribe()
+    }
+}
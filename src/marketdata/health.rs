@@ -0,0 +1,233 @@
+//! Per market latency and staleness of marketdata streams.
+//!
+//! A `MarketdataMonitor` is handed to the managed clients
+//! (`ManagedMarketdata::set_health_monitor`,
+//! `ManagedL1Streams::set_health_monitor`), which record the exchange
+//! timestamp of every update they apply along with when it arrived.
+//! `health` summarizes, per market over a sliding window, the exchange to
+//! client latency, the update rate, and the time since the last update,
+//! the last being the one to alert on for a feed that has silently
+//! stopped.  With netidx, `MarketdataMonitor::publish_stats` publishes
+//! the summary through the admin stats API on an interval.
+//!
+//! Latency is only as good as the clocks involved; with skew between the
+//! exchange and this host it can even be negative.
+
+use crate::symbology::MarketRef;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamHealth {
+    /// updates since the stream was first seen
+    pub updates: u64,
+    /// updates per second over the window
+    pub update_rate: Decimal,
+    pub last_update: DateTime<Utc>,
+    pub since_last_update: Duration,
+    /// exchange to client latency of the last update
+    pub last_latency: Duration,
+    /// None if no update arrived in the window
+    pub mean_latency: Option<Duration>,
+    pub max_latency: Option<Duration>,
+}
+
+/// A snapshot of every monitored stream's health
+#[derive(Debug, Clone, Default)]
+pub struct MarketdataHealth {
+    pub timestamp: DateTime<Utc>,
+    pub streams: FxHashMap<MarketRef, StreamHealth>,
+}
+
+impl MarketdataHealth {
+    /// Streams that haven't updated for longer than `max_age`
+    pub fn stale(&self, max_age: Duration) -> Vec<MarketRef> {
+        self.streams
+            .iter()
+            .filter(|(_, h)| h.since_last_update > max_age)
+            .map(|(m, _)| *m)
+            .collect()
+    }
+
+    /// Publish through the admin stats API under
+    /// marketdata/health/$market, latencies in milliseconds
+    #[cfg(feature = "netidx")]
+    pub fn publish(&self, common: &crate::Common) {
+        use netidx::path::Path;
+        use rust_decimal::prelude::ToPrimitive;
+        let ms = |d: Duration| d.num_microseconds().unwrap_or(i64::MAX) as f64 / 1000.;
+        for (market, h) in &self.streams {
+            let base = Path::from("marketdata/health").append(market.name.as_str());
+            common.stat_set(base.append("updates"), h.updates);
+            common.stat_set(
+                base.append("update-rate"),
+                h.update_rate.to_f64().unwrap_or(0.),
+            );
+            common.stat_set(base.append("since-last-update"), ms(h.since_last_update));
+            common.stat_set(base.append("last-latency"), ms(h.last_latency));
+            if let Some(l) = h.mean_latency {
+                common.stat_set(base.append("mean-latency"), ms(l));
+            }
+            if let Some(l) = h.max_latency {
+                common.stat_set(base.append("max-latency"), ms(l));
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct StreamState {
+    updates: u64,
+    last_update: DateTime<Utc>,
+    last_latency: Duration,
+    /// arrival times and latencies of updates in the window
+    recent: VecDeque<(DateTime<Utc>, Duration)>,
+}
+
+impl StreamState {
+    fn new(recv_time: DateTime<Utc>) -> Self {
+        Self {
+            updates: 0,
+            last_update: recv_time,
+            last_latency: Duration::zero(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, window: Duration, recv_time: DateTime<Utc>, latency: Duration) {
+        self.updates += 1;
+        self.last_update = recv_time;
+        self.last_latency = latency;
+        self.recent.push_back((recv_time, latency));
+        // bound memory for streams nobody asks about
+        let cutoff = recv_time - window;
+        while self.recent.front().is_some_and(|(t, _)| *t < cutoff) {
+            self.recent.pop_front();
+        }
+    }
+
+    fn health(&mut self, window: Duration, now: DateTime<Utc>) -> StreamHealth {
+        let cutoff = now - window;
+        while self.recent.front().is_some_and(|(t, _)| *t < cutoff) {
+            self.recent.pop_front();
+        }
+        let secs = Decimal::from(window.num_milliseconds()) / Decimal::ONE_THOUSAND;
+        let n = self.recent.len();
+        let sum = self.recent.iter().fold(Duration::zero(), |acc, (_, l)| acc + *l);
+        StreamHealth {
+            updates: self.updates,
+            update_rate: Decimal::from(n) / secs,
+            last_update: self.last_update,
+            since_last_update: now - self.last_update,
+            last_latency: self.last_latency,
+            mean_latency: (n > 0).then(|| sum / n as i32),
+            max_latency: self.recent.iter().map(|(_, l)| *l).max(),
+        }
+    }
+}
+
+/// Collects update timing from marketdata clients, share it in an `Arc`
+#[derive(Debug)]
+pub struct MarketdataMonitor {
+    window: Duration,
+    streams: Mutex<FxHashMap<MarketRef, StreamState>>,
+}
+
+impl MarketdataMonitor {
+    /// Rates and latency statistics are over the last `window`
+    pub fn new(window: Duration) -> Result<Self> {
+        if window <= Duration::zero() {
+            bail!("invalid health window {window}");
+        }
+        Ok(Self { window, streams: Mutex::new(FxHashMap::default()) })
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Record an update to `market` stamped `exchange_time` by the
+    /// exchange that arrived at `recv_time`
+    pub fn record(
+        &self,
+        market: MarketRef,
+        exchange_time: DateTime<Utc>,
+        recv_time: DateTime<Utc>,
+    ) {
+        self.streams
+            .lock()
+            .entry(market)
+            .or_insert_with(|| StreamState::new(recv_time))
+            .push(self.window, recv_time, recv_time - exchange_time)
+    }
+
+    pub fn stream(&self, market: &MarketRef, now: DateTime<Utc>) -> Option<StreamHealth> {
+        self.streams.lock().get_mut(market).map(|st| st.health(self.window, now))
+    }
+
+    pub fn health(&self, now: DateTime<Utc>) -> MarketdataHealth {
+        let streams = self
+            .streams
+            .lock()
+            .iter_mut()
+            .map(|(m, st)| (*m, st.health(self.window, now)))
+            .collect();
+        MarketdataHealth { timestamp: now, streams }
+    }
+
+    /// Stop monitoring `market`, e.g. after unsubscribing
+    pub fn remove(&self, market: &MarketRef) {
+        self.streams.lock().remove(market);
+    }
+
+    /// Publish the health of every stream every `interval`, see
+    /// `MarketdataHealth::publish`.  `Common::init_stats` must have been
+    /// called for the stats to go anywhere.
+    #[cfg(feature = "netidx")]
+    pub fn publish_stats(
+        self: &std::sync::Arc<Self>,
+        common: crate::Common,
+        interval: std::time::Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                monitor.health(Utc::now()).publish(&common);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_stream_health() {
+        let window = Duration::seconds(10);
+        let t = |s| Utc.with_ymd_and_hms(2024, 3, 5, 12, 0, s).unwrap();
+        let mut st = StreamState::new(t(0));
+        for (s, latency) in [(0, 10), (4, 30), (8, 20)] {
+            st.push(window, t(s), Duration::milliseconds(latency));
+        }
+        let h = st.health(window, t(12));
+        assert_eq!(h.updates, 3);
+        assert_eq!(h.update_rate, dec!(0.2));
+        assert_eq!(h.since_last_update, Duration::seconds(4));
+        assert_eq!(h.last_latency, Duration::milliseconds(20));
+        assert_eq!(h.mean_latency, Some(Duration::milliseconds(25)));
+        assert_eq!(h.max_latency, Some(Duration::milliseconds(30)));
+        let h = st.health(window, t(30));
+        assert_eq!(h.update_rate, Decimal::ZERO);
+        assert_eq!(h.mean_latency, None);
+        assert_eq!(h.updates, 3);
+    }
+}
@@ -2,10 +2,12 @@ use super::utils::apply_oneshot;
 use crate::{symbology, Common};
 use anyhow::{bail, Result};
 use api::marketdata::{CandleV1, CandleWidth, HistoricalCandlesV1};
-use chrono::{DateTime, Utc};
-use log::debug;
+use bytes::BytesMut;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use log::{debug, warn};
 use netidx::{
     chars::Chars,
+    pack::{encode_varint, Pack},
     path::Path,
     publisher::FromValue,
     resolver_client::{Glob, GlobSet},
@@ -13,6 +15,8 @@ use netidx::{
 };
 use netidx_archive::recorder_client;
 use netidx_protocols::{call_rpc, rpc::client::Proc};
+use std::path::PathBuf;
+use tokio::fs;
 
 pub async fn get(
     common: &Common,
@@ -74,3 +78,121 @@ pub async fn get_from_recorder(
     .await?;
     Ok(candles)
 }
+
+/// A disk backed cache in front of `get`, keyed by venue, market, candle
+/// width, and UTC day.  Completed days are immutable and served from disk;
+/// the current day, and the previous one until it has settled, is always
+/// fetched.
+pub struct CandleCache {
+    dir: PathBuf,
+    /// days ending less than this long ago are treated as still mutable,
+    /// to allow late finalized candles to arrive
+    settle: Duration,
+}
+
+impl CandleCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), settle: Duration::minutes(10) }
+    }
+
+    pub fn with_settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    fn day_path(
+        &self,
+        market: symbology::MarketRef,
+        width: CandleWidth,
+        day: NaiveDate,
+    ) -> PathBuf {
+        self.dir
+            .join(market.venue.name.as_str())
+            .join(market.id.to_string())
+            .join(width.as_str())
+            .join(format!("{day}.pack"))
+    }
+
+    pub async fn get(
+        &self,
+        common: &Common,
+        market: symbology::MarketRef,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        width: CandleWidth,
+    ) -> Result<Vec<CandleV1>> {
+        let mut candles = vec![];
+        if start >= end {
+            return Ok(candles);
+        }
+        let now = Utc::now();
+        let mut day = start.date_naive();
+        while day <= end.date_naive() {
+            let day_start = day.and_time(NaiveTime::MIN).and_utc();
+            let day_end = day_start + Duration::days(1);
+            if day_start >= end {
+                break;
+            }
+            let path = self.day_path(market, width, day);
+            let immutable = day_end + self.settle <= now;
+            let cached = if immutable { read_day(&path).await } else { None };
+            let mut day_candles = match cached {
+                Some(c) => c,
+                None => {
+                    let c = get(common, market, day_start, day_end, width).await?;
+                    if immutable {
+                        if let Err(e) = write_day(&path, &c).await {
+                            warn!("failed to cache candles at {}: {e:?}", path.display());
+                        }
+                    }
+                    c
+                }
+            };
+            day_candles.retain(|c| c.time >= start && c.time < end);
+            candles.extend(day_candles);
+            day = day + Duration::days(1);
+        }
+        Ok(candles)
+    }
+
+    /// Remove the cached day, if any
+    pub async fn invalidate(
+        &self,
+        market: symbology::MarketRef,
+        width: CandleWidth,
+        day: NaiveDate,
+    ) -> Result<()> {
+        match fs::remove_file(self.day_path(market, width, day)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+async fn read_day(path: &std::path::Path) -> Option<Vec<CandleV1>> {
+    let buf = fs::read(path).await.ok()?;
+    match Pack::decode(&mut &buf[..]) {
+        Ok(c) => Some(c),
+        Err(e) => {
+            warn!("discarding corrupt candle cache {}: {e:?}", path.display());
+            None
+        }
+    }
+}
+
+async fn write_day(path: &std::path::Path, candles: &[CandleV1]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    // encoded as a Vec<CandleV1>, which is what read_day decodes
+    let mut buf = BytesMut::new();
+    encode_varint(candles.len() as u64, &mut buf);
+    for c in candles {
+        Pack::encode(c, &mut buf)?;
+    }
+    // write then rename so readers never see a partial file
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, &buf).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
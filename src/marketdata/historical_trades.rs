@@ -0,0 +1,104 @@
+//! Tick level historical trades from the historical marketdata service.
+//!
+//! `get` pages through long ranges in chunks of `HistoricalTradesConfig`'s
+//! `chunk`, one `get-historical-trades` call each, and streams the trades
+//! in time order, so a month of ticks never has to fit in memory.  Calls
+//! that fail in transit are retried with backoff; an error reported by the
+//! service is not retried.
+
+use crate::{
+    reconnect::{Backoff, BackoffConfig},
+    symbology, Common,
+};
+use anyhow::{anyhow, Result};
+use api::marketdata::TradeV1;
+use async_stream::try_stream;
+use chrono::{DateTime, Duration, Utc};
+use futures::Stream;
+use log::{debug, warn};
+use netidx::{pack::Pack, publisher::Value};
+use netidx_protocols::{call_rpc, rpc::client::Proc};
+
+#[derive(Debug, Clone, Copy)]
+pub struct HistoricalTradesConfig {
+    /// the range fetched per call
+    pub chunk: Duration,
+    /// attempts per chunk before giving up
+    pub max_attempts: u32,
+    pub backoff: BackoffConfig,
+}
+
+impl Default for HistoricalTradesConfig {
+    fn default() -> Self {
+        Self {
+            chunk: Duration::hours(1),
+            max_attempts: 5,
+            backoff: BackoffConfig::default(),
+        }
+    }
+}
+
+/// Fetch one chunk; the outer error is transient, the inner one isn't
+async fn get_chunk(
+    proc: &Proc,
+    market: symbology::MarketRef,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Result<Vec<TradeV1>>> {
+    let value =
+        call_rpc!(proc, market: market.name.to_string(), start: start, end: end).await?;
+    Ok(match value {
+        Value::Error(e) => Err(anyhow!("{e}")),
+        Value::Bytes(mut buf) => Pack::decode(&mut buf).map_err(anyhow::Error::from),
+        v => Err(anyhow!("unexpected reply {v}")),
+    })
+}
+
+/// The trades of `market` in [start, end), oldest first.  The stream ends
+/// after the first error.
+pub fn get(
+    common: &Common,
+    market: symbology::MarketRef,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    config: HistoricalTradesConfig,
+) -> impl Stream<Item = Result<TradeV1>> {
+    let common = common.clone();
+    try_stream! {
+        if config.chunk <= Duration::zero() {
+            Err(anyhow!("invalid chunk {}", config.chunk))?;
+        }
+        let path =
+            common.paths.historical_marketdata_api().append("get-historical-trades");
+        let proc = Proc::new(&common.subscriber, path)?;
+        let mut backoff = Backoff::new("historical_trades", config.backoff);
+        let mut from = start;
+        while from < end {
+            let to = (from + config.chunk).min(end);
+            let mut attempts = 0;
+            let trades = loop {
+                attempts += 1;
+                match get_chunk(&proc, market, from, to).await {
+                    Ok(res) => break res?,
+                    Err(e) if attempts < config.max_attempts => {
+                        warn!(
+                            "fetching {} trades from {from} to {to}, attempt {attempts}: {e:?}",
+                            market.name
+                        );
+                        backoff.wait().await;
+                    }
+                    Err(e) => Err(e.context(format!(
+                        "fetching {} trades from {from} to {to}, giving up after {attempts} attempts",
+                        market.name
+                    )))?,
+                }
+            };
+            backoff.reset();
+            debug!("fetched {} {} trades from {from} to {to}", trades.len(), market.name);
+            for trade in trades {
+                yield trade;
+            }
+            from = to;
+        }
+    }
+}
@@ -0,0 +1,381 @@
+//! Incremental technical indicators and candlestick patterns over the
+//! SDK's own bar and trade types, so simple signal strategies don't need a
+//! TA crate with its own float types.
+//!
+//! Every indicator takes one input at a time and returns its new value, or
+//! None while it is still warming up.  The cores are plain `Decimal`
+//! arithmetic over `core` (Bollinger bands also keep a window in a
+//! `VecDeque`); only the `on_bar` and `on_print` conveniences know about
+//! the SDK types.
+
+use super::{
+    resample::{Bar, Session},
+    time_and_sales::Print,
+};
+use anyhow::{bail, Result};
+use api::Dir;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+const HUNDRED: Decimal = Decimal::ONE_HUNDRED;
+
+fn check_period(period: usize) -> Result<Decimal> {
+    if period == 0 {
+        bail!("indicator period must be positive");
+    }
+    Ok(Decimal::from(period))
+}
+
+/// Exponential moving average, seeded with the simple average of the first
+/// `period` inputs
+#[derive(Debug, Clone)]
+pub struct Ema {
+    period: Decimal,
+    alpha: Decimal,
+    seen: usize,
+    sum: Decimal,
+    value: Option<Decimal>,
+}
+
+impl Ema {
+    pub fn new(period: usize) -> Result<Self> {
+        let period = check_period(period)?;
+        Ok(Self {
+            period,
+            alpha: Decimal::TWO / (period + Decimal::ONE),
+            seen: 0,
+            sum: Decimal::ZERO,
+            value: None,
+        })
+    }
+
+    pub fn update(&mut self, x: Decimal) -> Option<Decimal> {
+        self.value = match self.value {
+            Some(v) => Some(v + self.alpha * (x - v)),
+            None => {
+                self.seen += 1;
+                self.sum += x;
+                (Decimal::from(self.seen) == self.period).then(|| self.sum / self.period)
+            }
+        };
+        self.value
+    }
+
+    pub fn on_bar(&mut self, bar: &Bar) -> Option<Decimal> {
+        self.update(bar.close)
+    }
+
+    pub fn value(&self) -> Option<Decimal> {
+        self.value
+    }
+}
+
+/// Wilder's smoothing, shared by RSI and ATR: the simple average of the
+/// first `period` inputs, then `avg + (x - avg) / period`
+#[derive(Debug, Clone)]
+struct Wilder {
+    period: Decimal,
+    seen: usize,
+    sum: Decimal,
+    value: Option<Decimal>,
+}
+
+impl Wilder {
+    fn new(period: Decimal) -> Self {
+        Self { period, seen: 0, sum: Decimal::ZERO, value: None }
+    }
+
+    fn update(&mut self, x: Decimal) -> Option<Decimal> {
+        self.value = match self.value {
+            Some(v) => Some(v + (x - v) / self.period),
+            None => {
+                self.seen += 1;
+                self.sum += x;
+                (Decimal::from(self.seen) == self.period).then(|| self.sum / self.period)
+            }
+        };
+        self.value
+    }
+}
+
+/// Relative strength index in [0, 100], over closes
+#[derive(Debug, Clone)]
+pub struct Rsi {
+    prev: Option<Decimal>,
+    gain: Wilder,
+    loss: Wilder,
+}
+
+impl Rsi {
+    pub fn new(period: usize) -> Result<Self> {
+        let period = check_period(period)?;
+        Ok(Self { prev: None, gain: Wilder::new(period), loss: Wilder::new(period) })
+    }
+
+    pub fn update(&mut self, x: Decimal) -> Option<Decimal> {
+        let prev = self.prev.replace(x)?;
+        let change = x - prev;
+        let gain = self.gain.update(change.max(Decimal::ZERO));
+        let loss = self.loss.update((-change).max(Decimal::ZERO));
+        Self::rsi(gain?, loss?)
+    }
+
+    fn rsi(gain: Decimal, loss: Decimal) -> Option<Decimal> {
+        if loss.is_zero() {
+            return Some(if gain.is_zero() { HUNDRED / Decimal::TWO } else { HUNDRED });
+        }
+        Some(HUNDRED - HUNDRED / (Decimal::ONE + gain / loss))
+    }
+
+    pub fn on_bar(&mut self, bar: &Bar) -> Option<Decimal> {
+        self.update(bar.close)
+    }
+
+    pub fn value(&self) -> Option<Decimal> {
+        Self::rsi(self.gain.value?, self.loss.value?)
+    }
+}
+
+/// Average true range
+#[derive(Debug, Clone)]
+pub struct Atr {
+    prev_close: Option<Decimal>,
+    tr: Wilder,
+}
+
+impl Atr {
+    pub fn new(period: usize) -> Result<Self> {
+        Ok(Self { prev_close: None, tr: Wilder::new(check_period(period)?) })
+    }
+
+    pub fn update(
+        &mut self,
+        high: Decimal,
+        low: Decimal,
+        close: Decimal,
+    ) -> Option<Decimal> {
+        let tr = match self.prev_close.replace(close) {
+            None => high - low,
+            Some(pc) => (high - low).max((high - pc).abs()).max((low - pc).abs()),
+        };
+        self.tr.update(tr)
+    }
+
+    pub fn on_bar(&mut self, bar: &Bar) -> Option<Decimal> {
+        self.update(bar.high, bar.low, bar.close)
+    }
+
+    pub fn value(&self) -> Option<Decimal> {
+        self.tr.value
+    }
+}
+
+/// Volume weighted average price since the session open, reset at each new
+/// session
+#[derive(Debug, Clone)]
+pub struct SessionVwap {
+    session: Session,
+    start: Option<DateTime<Utc>>,
+    notional: Decimal,
+    volume: Decimal,
+}
+
+impl SessionVwap {
+    pub fn new(session: Session) -> Self {
+        Self { session, start: None, notional: Decimal::ZERO, volume: Decimal::ZERO }
+    }
+
+    /// Add traded notional and volume at `timestamp`; inputs outside the
+    /// session are ignored
+    pub fn update(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        notional: Decimal,
+        volume: Decimal,
+    ) -> Option<Decimal> {
+        if let Some(start) = self.session.start_of(timestamp) {
+            if self.start != Some(start) {
+                self.start = Some(start);
+                self.notional = Decimal::ZERO;
+                self.volume = Decimal::ZERO;
+            }
+            self.notional += notional;
+            self.volume += volume;
+        }
+        self.value()
+    }
+
+    pub fn on_print(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        print: &Print,
+    ) -> Option<Decimal> {
+        self.update(timestamp, print.price * print.size, print.size)
+    }
+
+    pub fn on_bar(&mut self, bar: &Bar) -> Option<Decimal> {
+        self.update(bar.start, bar.notional, bar.volume)
+    }
+
+    pub fn value(&self) -> Option<Decimal> {
+        (!self.volume.is_zero()).then(|| self.notional / self.volume)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bands {
+    pub lower: Decimal,
+    pub middle: Decimal,
+    pub upper: Decimal,
+}
+
+/// Bollinger bands: the simple moving average plus and minus `k`
+/// population standard deviations
+#[derive(Debug, Clone)]
+pub struct Bollinger {
+    period: usize,
+    k: Decimal,
+    window: VecDeque<Decimal>,
+    sum: Decimal,
+}
+
+impl Bollinger {
+    pub fn new(period: usize, k: Decimal) -> Result<Self> {
+        check_period(period)?;
+        if k < Decimal::ZERO {
+            bail!("band width must not be negative, got {k}");
+        }
+        Ok(Self {
+            period,
+            k,
+            window: VecDeque::with_capacity(period),
+            sum: Decimal::ZERO,
+        })
+    }
+
+    pub fn update(&mut self, x: Decimal) -> Option<Bands> {
+        self.window.push_back(x);
+        self.sum += x;
+        if self.window.len() > self.period {
+            self.sum -= self.window.pop_front().unwrap_or_default();
+        }
+        self.value()
+    }
+
+    pub fn on_bar(&mut self, bar: &Bar) -> Option<Bands> {
+        self.update(bar.close)
+    }
+
+    pub fn value(&self) -> Option<Bands> {
+        if self.window.len() < self.period {
+            return None;
+        }
+        let n = Decimal::from(self.period);
+        let middle = self.sum / n;
+        let var =
+            self.window.iter().map(|x| (x - middle) * (x - middle)).sum::<Decimal>() / n;
+        let width = self.k * sqrt(var);
+        Some(Bands { lower: middle - width, middle, upper: middle + width })
+    }
+}
+
+/// Newton's method square root, to the precision `Decimal` can hold
+pub(crate) fn sqrt(x: Decimal) -> Decimal {
+    if x <= Decimal::ZERO {
+        return Decimal::ZERO;
+    }
+    let mut r = if x > Decimal::ONE { x / Decimal::TWO } else { Decimal::ONE };
+    for _ in 0..100 {
+        let next = (r + x / r) / Decimal::TWO;
+        if next == r {
+            break;
+        }
+        r = next;
+    }
+    r
+}
+
+/// A bar whose body is at most `max_body` (e.g. 0.1) of its range
+pub fn is_doji(bar: &Bar, max_body: Decimal) -> bool {
+    let range = bar.high - bar.low;
+    !range.is_zero() && (bar.close - bar.open).abs() <= range * max_body
+}
+
+/// A hammer (Buy) or shooting star (Sell): a small body at one end of the
+/// range with a shadow at least twice the body on the other
+pub fn hammer(bar: &Bar) -> Option<Dir> {
+    let body = (bar.close - bar.open).abs();
+    let top = bar.high - bar.open.max(bar.close);
+    let bottom = bar.open.min(bar.close) - bar.low;
+    if body.is_zero() {
+        return None;
+    }
+    if bottom >= body * Decimal::TWO && top <= body {
+        Some(Dir::Buy)
+    } else if top >= body * Decimal::TWO && bottom <= body {
+        Some(Dir::Sell)
+    } else {
+        None
+    }
+}
+
+/// A bullish (Buy) or bearish (Sell) engulfing: `bar`'s body covers the
+/// opposite colored body of `prev`
+pub fn engulfing(prev: &Bar, bar: &Bar) -> Option<Dir> {
+    let prev_up = prev.close > prev.open;
+    let prev_down = prev.close < prev.open;
+    if prev_down
+        && bar.close > bar.open
+        && bar.open <= prev.close
+        && bar.close >= prev.open
+    {
+        Some(Dir::Buy)
+    } else if prev_up
+        && bar.close < bar.open
+        && bar.open >= prev.close
+        && bar.close <= prev.open
+    {
+        Some(Dir::Sell)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_averages() -> Result<()> {
+        let mut ema = Ema::new(3)?;
+        assert_eq!(ema.update(dec!(1)), None);
+        assert_eq!(ema.update(dec!(2)), None);
+        assert_eq!(ema.update(dec!(3)), Some(dec!(2)));
+        assert_eq!(ema.update(dec!(6)), Some(dec!(4)));
+        let mut rsi = Rsi::new(2)?;
+        assert_eq!(rsi.update(dec!(10)), None);
+        assert_eq!(rsi.update(dec!(11)), None);
+        // average gain 0.5, average loss 0.5
+        assert_eq!(rsi.update(dec!(10)), Some(dec!(50)));
+        // mean 5, standard deviation 2
+        let mut bb = Bollinger::new(8, dec!(2))?;
+        for x in [2, 4, 4, 4, 5, 5, 7] {
+            assert_eq!(bb.update(Decimal::from(x)), None);
+        }
+        let bands = Bands { lower: dec!(1), middle: dec!(5), upper: dec!(9) };
+        assert_eq!(bb.update(dec!(9)), Some(bands));
+        Ok(())
+    }
+
+    #[test]
+    fn test_atr() -> Result<()> {
+        let mut atr = Atr::new(2)?;
+        assert_eq!(atr.update(dec!(11), dec!(9), dec!(10)), None);
+        // true range from the previous close, 14 - 10
+        assert_eq!(atr.update(dec!(14), dec!(12), dec!(13)), Some(dec!(3)));
+        assert_eq!(atr.update(dec!(14), dec!(13), dec!(14)), Some(dec!(2)));
+        Ok(())
+    }
+}
@@ -0,0 +1,469 @@
+//! Share managed marketdata subscriptions between processes on one box.
+//!
+//! One process runs `serve` on a unix socket, backed by its `ManagedMarketdata`;
+//! sibling processes connect with `IpcMarketdataClient` instead of subscribing
+//! to the venue feeds themselves.  Since `ManagedMarketdata` dedups by market,
+//! N clients asking for the same book share a single upstream subscription.
+//! Trades are served too if the server is given a `TradeFeed`; each
+//! market's feed is opened once and shared by every client of it.
+//!
+//! The wire protocol is a stream of frames, each a big endian u32 length
+//! followed by a netidx `Pack` encoded `IpcRequest` or `IpcResponse`.  A
+//! book subscription starts with a full `Snapshot` of the book, followed by
+//! a `BookPatch` per update with only the levels that changed.
+
+use super::{
+    book_client::{BookPatch, LevelBook},
+    time_and_sales::Print,
+};
+use crate::{
+    symbology::{MarketRef, StaticRef},
+    synced::Synced,
+    throttled_warn, ManagedMarketdata,
+};
+use anyhow::{anyhow, bail, Result};
+use api::{symbology::market::MarketId, Dir};
+use bytes::{Buf, BytesMut};
+use chrono::{DateTime, Utc};
+use futures::{stream::BoxStream, StreamExt};
+use fxhash::FxHashMap;
+use log::{debug, error};
+use netidx::pack::Pack;
+use netidx_derive::Pack;
+use rust_decimal::Decimal;
+use std::{os::unix::fs::FileTypeExt, path::Path, sync::Arc};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::{broadcast, mpsc, watch, Mutex},
+    task::{self, JoinHandle},
+};
+
+/// frames larger than this are treated as a protocol error
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Pack)]
+pub enum IpcRequest {
+    Subscribe { market: MarketId, delayed: bool },
+    Unsubscribe { market: MarketId },
+    SubscribeTrades { market: MarketId },
+    UnsubscribeTrades { market: MarketId },
+}
+
+#[derive(Debug, Clone, Pack)]
+pub enum IpcResponse {
+    /// the whole book, the first message of a subscription
+    Snapshot {
+        market: MarketId,
+        book: LevelBook,
+    },
+    /// the changes since the previous snapshot or patch
+    Patch {
+        market: MarketId,
+        patch: BookPatch,
+    },
+    Trade {
+        market: MarketId,
+        trade: IpcTrade,
+    },
+    Error {
+        market: MarketId,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Pack)]
+pub struct IpcTrade {
+    pub timestamp: DateTime<Utc>,
+    pub price: Decimal,
+    pub size: Decimal,
+    /// aggressor side, if known
+    pub dir: Option<Dir>,
+}
+
+impl IpcTrade {
+    pub fn new(timestamp: DateTime<Utc>, print: &Print) -> Self {
+        Self { timestamp, price: print.price, size: print.size, dir: print.dir }
+    }
+
+    pub fn print(&self) -> Print {
+        Print { price: self.price, size: self.size, dir: self.dir }
+    }
+}
+
+/// Opens a stream of a market's timestamped trades, e.g. the input of
+/// `build_candles`
+pub type TradeFeed =
+    Arc<dyn Fn(MarketRef) -> BoxStream<'static, (DateTime<Utc>, Print)> + Send + Sync>;
+
+/// One upstream trade stream per market, shared by all connections
+struct SharedTrades {
+    feed: TradeFeed,
+    markets: parking_lot::Mutex<FxHashMap<MarketId, broadcast::Sender<IpcTrade>>>,
+}
+
+impl SharedTrades {
+    fn subscribe(self: &Arc<Self>, market: MarketRef) -> broadcast::Receiver<IpcTrade> {
+        let mut markets = self.markets.lock();
+        if let Some(tx) = markets.get(&market.id) {
+            return tx.subscribe();
+        }
+        let (tx, rx) = broadcast::channel(1000);
+        markets.insert(market.id, tx.clone());
+        let mut trades = (self.feed)(market);
+        let t = self.clone();
+        task::spawn(async move {
+            while let Some((timestamp, print)) = trades.next().await {
+                if tx.send(IpcTrade::new(timestamp, &print)).is_err() {
+                    // checked under the lock so a concurrent subscribe
+                    // either sees the entry gone or keeps us running
+                    let mut markets = t.markets.lock();
+                    if tx.receiver_count() == 0 {
+                        markets.remove(&market.id);
+                        return;
+                    }
+                }
+            }
+            debug!("ipc trade feed for {market} ended");
+            t.markets.lock().remove(&market.id);
+        });
+        rx
+    }
+}
+
+async fn read_frame<T: Pack, R: AsyncRead + Unpin>(rd: &mut R) -> Result<T> {
+    let len = rd.read_u32().await? as usize;
+    if len > MAX_FRAME_LEN {
+        bail!("frame too large {len}");
+    }
+    let mut buf = BytesMut::zeroed(len);
+    rd.read_exact(&mut buf).await?;
+    let mut buf = buf.freeze();
+    let t = Pack::decode(&mut buf)?;
+    if buf.has_remaining() {
+        bail!("trailing bytes in frame");
+    }
+    Ok(t)
+}
+
+async fn write_frame<T: Pack, W: AsyncWrite + Unpin>(
+    wr: &mut W,
+    buf: &mut BytesMut,
+    t: &T,
+) -> Result<()> {
+    buf.clear();
+    Pack::encode(t, buf)?;
+    wr.write_u32(buf.len() as u32).await?;
+    wr.write_all(&buf).await?;
+    Ok(())
+}
+
+/// Serve marketdata on the unix socket at `path`, replacing any stale socket
+/// file left behind by a previous run; fails if anything else is there.
+/// Trade subscriptions are refused unless `trades` is given.
+pub async fn serve(
+    marketdata: Arc<ManagedMarketdata>,
+    trades: Option<TradeFeed>,
+    path: impl AsRef<Path>,
+) -> Result<()> {
+    let path = path.as_ref();
+    match std::fs::symlink_metadata(path) {
+        Ok(md) if md.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => bail!("{} exists and is not a socket", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e.into()),
+    }
+    let listener = UnixListener::bind(path)?;
+    let trades = trades.map(|feed| {
+        Arc::new(SharedTrades {
+            feed,
+            markets: parking_lot::Mutex::new(Default::default()),
+        })
+    });
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let marketdata = marketdata.clone();
+        let trades = trades.clone();
+        task::spawn(async move {
+            if let Err(e) = serve_connection(marketdata, trades, stream).await {
+                debug!("ipc marketdata connection closed: {e:?}");
+            }
+        });
+    }
+}
+
+async fn serve_connection(
+    marketdata: Arc<ManagedMarketdata>,
+    trades: Option<Arc<SharedTrades>>,
+    stream: UnixStream,
+) -> Result<()> {
+    let (mut rd, mut wr) = stream.into_split();
+    let (tx, mut rx) = mpsc::channel::<IpcResponse>(1000);
+    let writer = task::spawn(async move {
+        let mut buf = BytesMut::new();
+        while let Some(msg) = rx.recv().await {
+            if let Err(e) = write_frame(&mut wr, &mut buf, &msg).await {
+                debug!("ipc marketdata write failed: {e:?}");
+                break;
+            }
+        }
+    });
+    let mut forwarders: FxHashMap<MarketId, JoinHandle<()>> = FxHashMap::default();
+    let mut trade_forwarders: FxHashMap<MarketId, JoinHandle<()>> = FxHashMap::default();
+    let res = loop {
+        let req = match read_frame::<IpcRequest, _>(&mut rd).await {
+            Ok(req) => req,
+            Err(e) => break Err(e),
+        };
+        match req {
+            IpcRequest::Subscribe { market: id, delayed } => {
+                let market = match MarketRef::get_by_id(&id) {
+                    Some(market) => market,
+                    None => {
+                        let message = format!("unknown market {id}");
+                        let _ = tx.send(IpcResponse::Error { market: id, message }).await;
+                        continue;
+                    }
+                };
+                if forwarders.contains_key(&id) {
+                    continue;
+                }
+                let (book, mut synced) = marketdata.subscribe(market, delayed).await;
+                let tx = tx.clone();
+                let forward = task::spawn(async move {
+                    // the book as last sent to the client
+                    let mut sent: Option<LevelBook> = None;
+                    // another consumer may already hold the book synced, and on
+                    // a quiet market it might not change for a long time
+                    let mut ready = *synced.0.borrow() > 0;
+                    loop {
+                        if !ready && synced.changed().await.is_err() {
+                            break;
+                        }
+                        ready = false;
+                        // copy or diff under the lock, encode outside of it
+                        let msg = match &mut sent {
+                            None => {
+                                let book = book.lock().await.book().clone();
+                                sent = Some(book.clone());
+                                IpcResponse::Snapshot { market: id, book }
+                            }
+                            Some(sent) => {
+                                let patch = book.lock().await.book().diff(sent);
+                                if patch.is_empty() && patch.timestamp == sent.timestamp {
+                                    continue;
+                                }
+                                sent.apply_patch(&patch);
+                                IpcResponse::Patch { market: id, patch }
+                            }
+                        };
+                        if tx.send(msg).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                forwarders.insert(id, forward);
+            }
+            IpcRequest::Unsubscribe { market } => {
+                if let Some(forward) = forwarders.remove(&market) {
+                    forward.abort();
+                }
+            }
+            IpcRequest::SubscribeTrades { market: id } => {
+                let (Some(market), Some(trades)) = (MarketRef::get_by_id(&id), &trades)
+                else {
+                    let message = match trades {
+                        None => "trades are not served".to_string(),
+                        Some(_) => format!("unknown market {id}"),
+                    };
+                    let _ = tx.send(IpcResponse::Error { market: id, message }).await;
+                    continue;
+                };
+                if trade_forwarders.contains_key(&id) {
+                    continue;
+                }
+                let mut rx = trades.subscribe(market);
+                let tx = tx.clone();
+                let forward = task::spawn(async move {
+                    loop {
+                        let trade = match rx.recv().await {
+                            Ok(trade) => trade,
+                            Err(broadcast::error::RecvError::Lagged(n)) => {
+                                throttled_warn!("ipc dropped {n} trades for {market}");
+                                continue;
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
+                        if tx
+                            .send(IpcResponse::Trade { market: id, trade })
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+                trade_forwarders.insert(id, forward);
+            }
+            IpcRequest::UnsubscribeTrades { market } => {
+                if let Some(forward) = trade_forwarders.remove(&market) {
+                    forward.abort();
+                }
+            }
+        }
+    };
+    for (_, forward) in forwarders.into_iter().chain(trade_forwarders) {
+        forward.abort();
+    }
+    writer.abort();
+    res
+}
+
+struct ClientBook {
+    book: LevelBook,
+    /// patches are only applied once the snapshot has arrived
+    snapshot: bool,
+    tx_updates: watch::Sender<u64>,
+}
+
+/// A client of marketdata served by another process with `serve`
+pub struct IpcMarketdataClient {
+    tx: mpsc::Sender<IpcRequest>,
+    books: Arc<Mutex<FxHashMap<MarketId, ClientBook>>>,
+    trades: Arc<Mutex<FxHashMap<MarketId, broadcast::Sender<IpcTrade>>>>,
+    _reader: JoinHandle<()>,
+    _writer: JoinHandle<()>,
+}
+
+impl IpcMarketdataClient {
+    pub async fn connect(path: impl AsRef<Path>) -> Result<Self> {
+        let stream = UnixStream::connect(path).await?;
+        let (mut rd, mut wr) = stream.into_split();
+        let books: Arc<Mutex<FxHashMap<MarketId, ClientBook>>> =
+            Arc::new(Mutex::new(FxHashMap::default()));
+        let trades: Arc<Mutex<FxHashMap<MarketId, broadcast::Sender<IpcTrade>>>> =
+            Arc::new(Mutex::new(FxHashMap::default()));
+        let (tx, mut rx) = mpsc::channel::<IpcRequest>(1000);
+        let writer = task::spawn(async move {
+            let mut buf = BytesMut::new();
+            while let Some(req) = rx.recv().await {
+                if let Err(e) = write_frame(&mut wr, &mut buf, &req).await {
+                    error!("ipc marketdata write failed: {e:?}");
+                    break;
+                }
+            }
+        });
+        let reader = {
+            let books = books.clone();
+            let trades = trades.clone();
+            task::spawn(async move {
+                loop {
+                    match read_frame::<IpcResponse, _>(&mut rd).await {
+                        Err(e) => {
+                            error!("ipc marketdata connection lost: {e:?}");
+                            break;
+                        }
+                        Ok(IpcResponse::Error { market, message }) => {
+                            throttled_warn!(
+                                "ipc marketdata error for {market}: {message}"
+                            )
+                        }
+                        Ok(IpcResponse::Snapshot { market, book }) => {
+                            let mut books = books.lock().await;
+                            if let Some(cb) = books.get_mut(&market) {
+                                cb.book = book;
+                                cb.snapshot = true;
+                                cb.tx_updates.send_modify(|n| *n += 1);
+                            }
+                        }
+                        Ok(IpcResponse::Patch { market, patch }) => {
+                            let mut books = books.lock().await;
+                            if let Some(cb) = books.get_mut(&market) {
+                                if cb.snapshot {
+                                    cb.book.apply_patch(&patch);
+                                    cb.tx_updates.send_modify(|n| *n += 1);
+                                }
+                            }
+                        }
+                        Ok(IpcResponse::Trade { market, trade }) => {
+                            if let Some(tx) = trades.lock().await.get(&market) {
+                                let _ = tx.send(trade);
+                            }
+                        }
+                    }
+                }
+                // close the trade receivers
+                trades.lock().await.clear();
+            })
+        };
+        Ok(Self { tx, books, trades, _reader: reader, _writer: writer })
+    }
+
+    /// Subscribe to the book for a market; the returned `Synced` ticks on
+    /// every update received from the server.
+    pub async fn subscribe(
+        &self,
+        market: MarketRef,
+        delayed: bool,
+    ) -> Result<Synced<u64>> {
+        let synced = {
+            let mut books = self.books.lock().await;
+            let cb = books.entry(market.id).or_insert_with(|| ClientBook {
+                book: LevelBook::default(),
+                snapshot: false,
+                tx_updates: watch::channel(0).0,
+            });
+            Synced(cb.tx_updates.subscribe())
+        };
+        self.tx
+            .send(IpcRequest::Subscribe { market: market.id, delayed })
+            .await
+            .map_err(|_| anyhow!("ipc marketdata connection closed"))?;
+        Ok(synced)
+    }
+
+    pub async fn unsubscribe(&self, market: MarketRef) -> Result<()> {
+        self.books.lock().await.remove(&market.id);
+        self.tx
+            .send(IpcRequest::Unsubscribe { market: market.id })
+            .await
+            .map_err(|_| anyhow!("ipc marketdata connection closed"))
+    }
+
+    /// Subscribe to the trades of a market; fails on the receiver's side
+    /// with `Closed` if the connection is lost or the market unsubscribed
+    pub async fn subscribe_trades(
+        &self,
+        market: MarketRef,
+    ) -> Result<broadcast::Receiver<IpcTrade>> {
+        let rx = {
+            let mut trades = self.trades.lock().await;
+            trades
+                .entry(market.id)
+                .or_insert_with(|| broadcast::channel(1000).0)
+                .subscribe()
+        };
+        self.tx
+            .send(IpcRequest::SubscribeTrades { market: market.id })
+            .await
+            .map_err(|_| anyhow!("ipc marketdata connection closed"))?;
+        Ok(rx)
+    }
+
+    pub async fn unsubscribe_trades(&self, market: MarketRef) -> Result<()> {
+        self.trades.lock().await.remove(&market.id);
+        self.tx
+            .send(IpcRequest::UnsubscribeTrades { market: market.id })
+            .await
+            .map_err(|_| anyhow!("ipc marketdata connection closed"))
+    }
+
+    /// Call `f` with the latest book for the market, if subscribed
+    pub async fn with_book<R>(
+        &self,
+        market: MarketRef,
+        f: impl FnOnce(&LevelBook) -> R,
+    ) -> Option<R> {
+        self.books.lock().await.get(&market.id).map(|cb| f(&cb.book))
+    }
+}
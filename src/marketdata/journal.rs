@@ -0,0 +1,417 @@
+//! Journal raw book messages with nanosecond receive timestamps, for
+//! latency and microstructure research.
+//!
+//! Each record carries the wall clock and monotonic receive time (the
+//! monotonic clock is immune to NTP steps, so it gives the true order and
+//! spacing of messages within a journal), a local sequence number, the
+//! venue's sequence number when the feed provides one, and the message
+//! bytes exactly as received.
+//!
+//! The file is a header frame followed by record frames, each a big endian
+//! u32 length and a netidx `Pack` encoded value, like the ipc protocol.
+//!
+//! For unattended recording, `RollingJournalWriter` writes a directory of
+//! journal segments that can be compacted and pruned, see `crate::segments`.
+//!
+//! Writers do blocking file io; on the marketdata path hand them to a
+//! `JournalHandle`, which writes on a dedicated thread.  Take the
+//! `RecvStamp` of a message as soon as it is received, it is carried to the
+//! writer with the message.  Records are in the order they reached the
+//! writer; a handle shared by several producers can write a message ahead
+//! of one received earlier, so sort by `recv_mono_ns` for receive order.
+
+use super::book_client::BookMessage;
+use crate::{
+    segments::{open_segment, RollPolicy, SegmentDir},
+    throttled_warn,
+};
+use anyhow::{bail, Result};
+use api::symbology::market::MarketId;
+use bytes::{Buf, Bytes, BytesMut};
+use chrono::{DateTime, TimeZone, Utc};
+use log::debug;
+use netidx::pack::Pack;
+use netidx_derive::Pack;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const MAGIC: u64 = 0x4158_424f_4f4b_4a31; // "AXBOOKJ1"
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Pack)]
+pub struct JournalHeader {
+    pub magic: u64,
+    /// wall clock nanoseconds at the monotonic zero of the journal
+    pub start_wall_ns: i64,
+}
+
+/// When a message was received; take it as early as possible, before any
+/// decoding or queueing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RecvStamp {
+    pub wall_ns: i64,
+    /// written relative to the monotonic zero of the journal
+    pub instant: Instant,
+}
+
+impl RecvStamp {
+    pub fn now() -> Self {
+        Self { wall_ns: wall_ns(), instant: Instant::now() }
+    }
+}
+
+#[derive(Debug, Clone, Pack)]
+pub struct JournalRecord {
+    pub market: MarketId,
+    pub recv_wall_ns: i64,
+    pub recv_mono_ns: u64,
+    /// assigned by the writer, strictly increasing within a journal
+    pub sequence: u64,
+    pub venue_sequence: Option<u64>,
+    /// the encoded book message as received
+    pub message: Bytes,
+}
+
+impl JournalRecord {
+    pub fn recv_time(&self) -> DateTime<Utc> {
+        Utc.timestamp_nanos(self.recv_wall_ns)
+    }
+
+    pub fn decode(&self) -> Result<BookMessage> {
+        BookMessage::decode_bytes(&mut self.message.clone())
+    }
+}
+
+fn wall_ns() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i64).unwrap_or(0)
+}
+
+/// Returns the number of bytes written
+fn write_frame<T: Pack>(wr: &mut impl Write, buf: &mut BytesMut, t: &T) -> Result<u64> {
+    buf.clear();
+    Pack::encode(t, buf)?;
+    wr.write_all(&(buf.len() as u32).to_be_bytes())?;
+    wr.write_all(buf)?;
+    Ok(4 + buf.len() as u64)
+}
+
+/// Returns None at a clean end of file
+fn read_frame<T: Pack>(rd: &mut impl Read) -> Result<Option<T>> {
+    let mut len = [0u8; 4];
+    match rd.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        bail!("journal frame too large {len}");
+    }
+    let mut buf = BytesMut::zeroed(len);
+    rd.read_exact(&mut buf)?;
+    let mut buf = buf.freeze();
+    let t = Pack::decode(&mut buf)?;
+    if buf.has_remaining() {
+        bail!("trailing bytes in journal frame");
+    }
+    Ok(Some(t))
+}
+
+pub struct JournalWriter {
+    out: BufWriter<File>,
+    buf: BytesMut,
+    start: Instant,
+    sequence: u64,
+    len: u64,
+}
+
+impl JournalWriter {
+    /// Create a new journal at `path`, truncating any existing file
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let mut buf = BytesMut::new();
+        let start = Instant::now();
+        let header = JournalHeader { magic: MAGIC, start_wall_ns: wall_ns() };
+        let len = write_frame(&mut out, &mut buf, &header)?;
+        Ok(Self { out, buf, start, sequence: 0, len })
+    }
+
+    /// The bytes written so far, including any still buffered
+    pub fn bytes_written(&self) -> u64 {
+        self.len
+    }
+
+    pub fn write(
+        &mut self,
+        market: MarketId,
+        stamp: RecvStamp,
+        venue_sequence: Option<u64>,
+        message: Bytes,
+    ) -> Result<()> {
+        self.sequence += 1;
+        let record = JournalRecord {
+            market,
+            recv_wall_ns: stamp.wall_ns,
+            recv_mono_ns: stamp.instant.saturating_duration_since(self.start).as_nanos()
+                as u64,
+            sequence: self.sequence,
+            venue_sequence,
+            message,
+        };
+        self.len += write_frame(&mut self.out, &mut self.buf, &record)?;
+        Ok(())
+    }
+
+    /// Records are buffered; flush periodically and before dropping
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.out.flush()?)
+    }
+}
+
+impl Drop for JournalWriter {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+/// A journal that rolls to a new segment in a `SegmentDir` according to a
+/// `RollPolicy`.  Sequence numbers and the monotonic clock restart with
+/// each segment.
+pub struct RollingJournalWriter {
+    dir: SegmentDir,
+    roll: RollPolicy,
+    current: JournalWriter,
+    started: DateTime<Utc>,
+}
+
+impl RollingJournalWriter {
+    pub fn new(dir: SegmentDir, roll: RollPolicy) -> Result<Self> {
+        let started = Utc::now();
+        let current = JournalWriter::create(dir.segment_path(started))?;
+        Ok(Self { dir, roll, current, started })
+    }
+
+    pub fn segments(&self) -> &SegmentDir {
+        &self.dir
+    }
+
+    /// Start a new segment if the current one is due, returning the path of
+    /// the segment that was closed.  Called by `write`; messages received
+    /// before a roll and written after it are at the new segment's
+    /// monotonic zero.
+    pub fn roll_if_due(&mut self) -> Result<Option<PathBuf>> {
+        let now = Utc::now();
+        if !self.roll.due(self.current.bytes_written(), self.started, now) {
+            return Ok(None);
+        }
+        let closed = self.dir.segment_path(self.started);
+        self.current.flush()?;
+        self.current = JournalWriter::create(self.dir.segment_path(now))?;
+        self.started = now;
+        Ok(Some(closed))
+    }
+
+    pub fn write(
+        &mut self,
+        market: MarketId,
+        stamp: RecvStamp,
+        venue_sequence: Option<u64>,
+        message: Bytes,
+    ) -> Result<()> {
+        self.roll_if_due()?;
+        self.current.write(market, stamp, venue_sequence, message)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.current.flush()
+    }
+}
+
+/// A journal writer that can be moved to a `JournalHandle`
+pub trait WriteJournal: Send + 'static {
+    fn write(
+        &mut self,
+        market: MarketId,
+        stamp: RecvStamp,
+        venue_sequence: Option<u64>,
+        message: Bytes,
+    ) -> Result<()>;
+
+    fn flush(&mut self) -> Result<()>;
+}
+
+impl WriteJournal for JournalWriter {
+    fn write(
+        &mut self,
+        market: MarketId,
+        stamp: RecvStamp,
+        venue_sequence: Option<u64>,
+        message: Bytes,
+    ) -> Result<()> {
+        JournalWriter::write(self, market, stamp, venue_sequence, message)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        JournalWriter::flush(self)
+    }
+}
+
+impl WriteJournal for RollingJournalWriter {
+    fn write(
+        &mut self,
+        market: MarketId,
+        stamp: RecvStamp,
+        venue_sequence: Option<u64>,
+        message: Bytes,
+    ) -> Result<()> {
+        RollingJournalWriter::write(self, market, stamp, venue_sequence, message)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        RollingJournalWriter::flush(self)
+    }
+}
+
+struct JournalEntry {
+    market: MarketId,
+    stamp: RecvStamp,
+    venue_sequence: Option<u64>,
+    message: Bytes,
+}
+
+/// Writes a journal on a dedicated thread, so journaling a message costs
+/// the caller a channel send.  The journal is flushed whenever it has been
+/// idle for a second, and when the last handle is dropped.
+#[derive(Clone)]
+pub struct JournalHandle {
+    tx: mpsc::Sender<JournalEntry>,
+}
+
+impl JournalHandle {
+    pub fn spawn(mut journal: impl WriteJournal) -> Result<Self> {
+        let (tx, rx) = mpsc::channel::<JournalEntry>();
+        thread::Builder::new().name("book-journal".into()).spawn(move || loop {
+            match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(e) => {
+                    let JournalEntry { market, stamp, venue_sequence, message } = e;
+                    if let Err(e) = journal.write(market, stamp, venue_sequence, message)
+                    {
+                        throttled_warn!("failed to journal book message: {e:?}");
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if let Err(e) = journal.flush() {
+                        throttled_warn!("failed to flush book journal: {e:?}");
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    if let Err(e) = journal.flush() {
+                        throttled_warn!("failed to flush book journal: {e:?}");
+                    }
+                    break;
+                }
+            }
+        })?;
+        Ok(Self { tx })
+    }
+
+    /// Queue a message for the journal; `stamp` is when it was received
+    pub fn write(
+        &self,
+        market: MarketId,
+        stamp: RecvStamp,
+        venue_sequence: Option<u64>,
+        message: Bytes,
+    ) {
+        let e = JournalEntry { market, stamp, venue_sequence, message };
+        if self.tx.send(e).is_err() {
+            throttled_warn!("book journal writer is gone, dropping message");
+        }
+    }
+}
+
+/// The result of `JournalReader::check`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JournalCheck {
+    pub records: u64,
+    /// records received before the record written ahead of them, by the
+    /// monotonic clock; expected when several producers share a handle
+    pub mono_inversions: u64,
+}
+
+/// Iterate over the records of a journal in the order they were written
+pub struct JournalReader {
+    rd: Box<dyn Read + Send>,
+    header: JournalHeader,
+}
+
+impl JournalReader {
+    /// Open a journal file or segment, compacted or not
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_reader(open_segment(path)?)
+    }
+
+    pub fn from_reader(mut rd: Box<dyn Read + Send>) -> Result<Self> {
+        let header: JournalHeader = match read_frame(&mut rd)? {
+            Some(h) => h,
+            None => bail!("empty journal"),
+        };
+        if header.magic != MAGIC {
+            bail!("not a book journal");
+        }
+        Ok(Self { rd, header })
+    }
+
+    /// Read the whole journal, checking every frame decodes and that
+    /// sequence numbers always increase, and counting receive times that
+    /// go backwards
+    pub fn check(rd: Box<dyn Read + Send>) -> Result<JournalCheck> {
+        let mut res = JournalCheck::default();
+        let mut last: Option<(u64, u64)> = None;
+        for record in Self::from_reader(rd)? {
+            let record = record?;
+            if let Some((sequence, mono_ns)) = last {
+                if record.sequence <= sequence {
+                    bail!("sequence {} after {sequence}", record.sequence);
+                }
+                if record.recv_mono_ns < mono_ns {
+                    res.mono_inversions += 1;
+                }
+            }
+            last = Some((record.sequence, record.recv_mono_ns));
+            res.records += 1;
+        }
+        Ok(res)
+    }
+
+    /// `check`, returning the number of records.  Suitable for
+    /// `SegmentDir::verify`.
+    pub fn verify(rd: Box<dyn Read + Send>) -> Result<u64> {
+        let check = Self::check(rd)?;
+        if check.mono_inversions > 0 {
+            debug!(
+                "{} of {} journal records were written out of receive order",
+                check.mono_inversions, check.records
+            );
+        }
+        Ok(check.records)
+    }
+
+    pub fn header(&self) -> &JournalHeader {
+        &self.header
+    }
+}
+
+impl Iterator for JournalReader {
+    type Item = Result<JournalRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        read_frame(&mut self.rd).transpose()
+    }
+}
@@ -0,0 +1,544 @@
+/// Order book representation
+#[cfg(feature = "netidx")]
+use api::{
+    marketdata::{Snapshot, Update, Updates},
+    pool,
+};
+use api::{Dir, DirPair};
+use chrono::prelude::*;
+use itertools::Itertools;
+#[cfg(feature = "netidx")]
+use netidx::pool::Pooled;
+#[cfg(feature = "netidx")]
+use netidx_derive::Pack;
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{btree_map::Iter, BTreeMap},
+    iter::Rev,
+    ops::{Deref, DerefMut},
+};
+
+// CR alee: probably want to rethink where to put these
+pub trait LevelLike {
+    fn price(&self) -> Decimal;
+    fn size(&self) -> Decimal;
+    fn total(&self) -> Option<Decimal>;
+}
+
+impl<'a> LevelLike for (&Decimal, &Decimal) {
+    fn price(&self) -> Decimal {
+        *self.0
+    }
+
+    fn size(&self) -> Decimal {
+        *self.1
+    }
+
+    fn total(&self) -> Option<Decimal> {
+        None
+    }
+}
+
+impl LevelLike for (Decimal, Decimal) {
+    fn price(&self) -> Decimal {
+        self.0
+    }
+
+    fn size(&self) -> Decimal {
+        self.1
+    }
+
+    fn total(&self) -> Option<Decimal> {
+        None
+    }
+}
+
+impl LevelLike for &(Decimal, Decimal) {
+    fn price(&self) -> Decimal {
+        self.0
+    }
+
+    fn size(&self) -> Decimal {
+        self.1
+    }
+
+    fn total(&self) -> Option<Decimal> {
+        None
+    }
+}
+
+pub enum LevelIterator<'a> {
+    Forward(Iter<'a, Decimal, Decimal>),
+    Reverse(Rev<Iter<'a, Decimal, Decimal>>),
+}
+
+impl<'a> Iterator for LevelIterator<'a> {
+    type Item = (&'a Decimal, &'a Decimal);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            LevelIterator::Forward(i) => i.next(),
+            LevelIterator::Reverse(i) => i.next(),
+        }
+    }
+}
+
+/// An order book
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "netidx", derive(Pack))]
+pub struct LevelBook {
+    pub book: DirPair<BTreeMap<Decimal, Decimal>>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Deref for LevelBook {
+    type Target = DirPair<BTreeMap<Decimal, Decimal>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.book
+    }
+}
+
+impl DerefMut for LevelBook {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.book
+    }
+}
+
+impl Default for LevelBook {
+    fn default() -> Self {
+        LevelBook { book: DirPair::default(), timestamp: DateTime::<Utc>::default() }
+    }
+}
+
+impl LevelBook {
+    /// clear the order book
+    pub fn clear(&mut self) {
+        self.buy.clear();
+        self.sell.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buy.is_empty() && self.sell.is_empty()
+    }
+
+    /// Replace the contents of the book with the snapshot
+    #[cfg(feature = "netidx")]
+    pub fn apply_snapshot(&mut self, snapshot: &Snapshot) {
+        self.buy.clear();
+        self.sell.clear();
+        for (price, size) in snapshot.book.buy.iter() {
+            self.buy.insert(*price, *size);
+        }
+        for (price, size) in snapshot.book.sell.iter() {
+            self.sell.insert(*price, *size);
+        }
+        self.timestamp = snapshot.timestamp;
+    }
+
+    /// Apply updates in place without taking ownership, so one decoded
+    /// message can be applied to several books
+    #[cfg(feature = "netidx")]
+    pub fn apply_updates(&mut self, updates: &Updates) {
+        fn apply(side: &mut BTreeMap<Decimal, Decimal>, up: &Update) {
+            match up {
+                Update::Change { price, size } => match side.get_mut(price) {
+                    Some(cur) => *cur = *size,
+                    None => {
+                        side.insert(*price, *size);
+                    }
+                },
+                Update::Remove { price } => {
+                    side.remove(price);
+                }
+            }
+        }
+        for up in updates.book.buy.iter() {
+            apply(&mut self.buy, up);
+        }
+        for up in updates.book.sell.iter() {
+            apply(&mut self.sell, up);
+        }
+        self.timestamp = updates.timestamp;
+    }
+
+    /// return the best price and quantity given a direction
+    pub fn best(&self, dir: Dir) -> Option<(Decimal, Decimal)> {
+        match dir {
+            Dir::Buy => match self.buy.iter().next_back() {
+                None => return None,
+                Some((price, size)) => return Some((*price, *size)),
+            },
+            Dir::Sell => match self.sell.iter().next() {
+                None => return None,
+                Some((price, size)) => return Some((*price, *size)),
+            },
+        }
+    }
+
+    /// return an iterator traversing the levels in best price order
+    /// for a given direction
+    pub fn iter_levels(&self, dir: Dir) -> LevelIterator {
+        match dir {
+            Dir::Buy => LevelIterator::Reverse(self.buy.iter().rev()),
+            Dir::Sell => LevelIterator::Forward(self.sell.iter()),
+        }
+    }
+
+    pub fn mid(&self) -> Option<Decimal> {
+        let (bid, _) = self.best(Dir::Buy)?;
+        let (ask, _) = self.best(Dir::Sell)?;
+        Some((bid + ask) / Decimal::TWO)
+    }
+
+    pub fn spread(&self) -> Option<Decimal> {
+        let (bid, _) = self.best(Dir::Buy)?;
+        let (ask, _) = self.best(Dir::Sell)?;
+        Some(ask - bid)
+    }
+
+    /// The mid weighted by the size at the touch, leaning toward the side
+    /// with less size
+    pub fn microprice(&self) -> Option<Decimal> {
+        let (bid, bid_size) = self.best(Dir::Buy)?;
+        let (ask, ask_size) = self.best(Dir::Sell)?;
+        let total = bid_size + ask_size;
+        if total.is_zero() {
+            return None;
+        }
+        Some((bid * ask_size + ask * bid_size) / total)
+    }
+
+    /// Imbalance of the size in the top `depth` levels, in [-1, 1];
+    /// positive means more size bid
+    pub fn imbalance(&self, depth: usize) -> Option<Decimal> {
+        let size = |dir| -> Decimal {
+            self.iter_levels(dir).take(depth).map(|(_, size)| *size).sum()
+        };
+        let (bid_size, ask_size) = (size(Dir::Buy), size(Dir::Sell));
+        let total = bid_size + ask_size;
+        if total.is_zero() {
+            return None;
+        }
+        Some((bid_size - ask_size) / total)
+    }
+
+    /// Walk the side an order in `dir` would trade against until `of` the
+    /// (size, notional) taken reaches `want`; returns what was taken, or
+    /// None if the side runs out first
+    fn take(
+        &self,
+        dir: Dir,
+        want: Decimal,
+        of: impl Fn(Decimal, Decimal) -> Decimal,
+    ) -> Option<(Decimal, Decimal)> {
+        if want <= Decimal::ZERO {
+            return None;
+        }
+        let opposite = match dir {
+            Dir::Buy => Dir::Sell,
+            Dir::Sell => Dir::Buy,
+        };
+        let (mut size, mut notional) = (Decimal::ZERO, Decimal::ZERO);
+        for (price, level) in self.iter_levels(opposite) {
+            let remaining = want - of(size, notional);
+            let level_want = of(*level, *price * *level);
+            if level_want >= remaining {
+                let take = *level * remaining / level_want;
+                return Some((size + take, notional + take * *price));
+            }
+            size += *level;
+            notional += *price * *level;
+        }
+        None
+    }
+
+    /// The average price of trading `size` in `dir` against the book, or
+    /// None if there isn't that much
+    pub fn vwap_to_size(&self, dir: Dir, size: Decimal) -> Option<Decimal> {
+        let (size, notional) = self.take(dir, size, |size, _| size)?;
+        Some(notional / size)
+    }
+
+    /// How far the average price of trading `notional` in `dir` against
+    /// the book is from the mid, positive when worse than the mid; None if
+    /// there isn't that much
+    pub fn price_impact(&self, dir: Dir, notional: Decimal) -> Option<Decimal> {
+        let mid = self.mid()?;
+        let (size, notional) = self.take(dir, notional, |_, notional| notional)?;
+        let avg = notional / size;
+        Some(match dir {
+            Dir::Buy => avg - mid,
+            Dir::Sell => mid - avg,
+        })
+    }
+
+    /// return true if the book is crossed, false otherwise. Depending
+    /// on the exchange a crossed book may mean different things. On
+    /// many (most?) exchanges a crossed book would only occurr if
+    /// there were an error of some kind.
+    pub fn is_crossed(&self) -> bool {
+        self.book
+            .buy
+            .last_key_value()
+            .and_then(|(e0, _)| self.book.sell.first_key_value().map(|(e1, _)| (e0, e1)))
+            .map(|(e0, e1)| e0 >= e1)
+            .unwrap_or(false)
+    }
+
+    /// Condense the order book grouping prices by `precision` and
+    /// summing the size of condensed levels. Output a maximum of
+    /// `num_levels` condensed levels from the top of the book
+    #[cfg(feature = "netidx")]
+    pub fn condense(
+        &self,
+        num_levels: usize,
+        precision: Decimal,
+    ) -> DirPair<Pooled<Vec<CondensedLevel>>> {
+        pool!(pool_levels, Vec<CondensedLevel>, 1000, 100);
+        let mut dst = DirPair { buy: pool_levels().take(), sell: pool_levels().take() };
+        self.condense_side(num_levels, precision, Dir::Buy, &mut dst.buy);
+        self.condense_side(num_levels, precision, Dir::Sell, &mut dst.sell);
+        dst
+    }
+
+    /// Like `condense`, but writes into caller owned vectors
+    pub fn condense_into(
+        &self,
+        num_levels: usize,
+        precision: Decimal,
+        dst: &mut DirPair<Vec<CondensedLevel>>,
+    ) {
+        self.condense_side(num_levels, precision, Dir::Buy, &mut dst.buy);
+        self.condense_side(num_levels, precision, Dir::Sell, &mut dst.sell);
+    }
+
+    fn condense_side(
+        &self,
+        num_levels: usize,
+        precision: Decimal,
+        dir: Dir,
+        dst: &mut Vec<CondensedLevel>,
+    ) {
+        match dir {
+            Dir::Buy => condense_from_levels(
+                num_levels,
+                dst,
+                self.buy.iter().rev(),
+                precision,
+                dir,
+            ),
+            Dir::Sell => {
+                condense_from_levels(num_levels, dst, self.sell.iter(), precision, dir)
+            }
+        }
+    }
+
+    /// Compute the minimal set of level changes that transforms `previous`
+    /// into `self`, suitable for pushing incremental updates to a UI.
+    pub fn diff(&self, previous: &LevelBook) -> BookPatch {
+        BookPatch {
+            buy: diff_side(&previous.buy, &self.buy),
+            sell: diff_side(&previous.sell, &self.sell),
+            timestamp: self.timestamp,
+        }
+    }
+
+    /// Apply a patch produced by `diff`
+    pub fn apply_patch(&mut self, patch: &BookPatch) {
+        for l in &patch.buy {
+            l.apply(&mut self.book.buy);
+        }
+        for l in &patch.sell {
+            l.apply(&mut self.book.sell);
+        }
+        self.timestamp = patch.timestamp;
+    }
+}
+
+/// A single level change; a size of zero removes the level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "netidx", derive(Pack))]
+pub struct LevelPatch {
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+impl LevelPatch {
+    fn apply(&self, side: &mut BTreeMap<Decimal, Decimal>) {
+        if self.size.is_zero() {
+            side.remove(&self.price);
+        } else {
+            side.insert(self.price, self.size);
+        }
+    }
+}
+
+/// The difference between two books, see `LevelBook::diff`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "netidx", derive(Pack))]
+pub struct BookPatch {
+    pub buy: Vec<LevelPatch>,
+    pub sell: Vec<LevelPatch>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl BookPatch {
+    pub fn is_empty(&self) -> bool {
+        self.buy.is_empty() && self.sell.is_empty()
+    }
+}
+
+fn diff_side(
+    prev: &BTreeMap<Decimal, Decimal>,
+    cur: &BTreeMap<Decimal, Decimal>,
+) -> Vec<LevelPatch> {
+    let mut res = vec![];
+    let mut prev = prev.iter().peekable();
+    let mut cur = cur.iter().peekable();
+    loop {
+        match (prev.peek(), cur.peek()) {
+            (None, None) => break,
+            (Some((price, _)), None) => {
+                res.push(LevelPatch { price: **price, size: Decimal::ZERO });
+                prev.next();
+            }
+            (None, Some((price, size))) => {
+                res.push(LevelPatch { price: **price, size: **size });
+                cur.next();
+            }
+            (Some((p0, s0)), Some((p1, s1))) => match p0.cmp(p1) {
+                Ordering::Less => {
+                    res.push(LevelPatch { price: **p0, size: Decimal::ZERO });
+                    prev.next();
+                }
+                Ordering::Greater => {
+                    res.push(LevelPatch { price: **p1, size: **s1 });
+                    cur.next();
+                }
+                Ordering::Equal => {
+                    if s0 != s1 {
+                        res.push(LevelPatch { price: **p1, size: **s1 });
+                    }
+                    prev.next();
+                    cur.next();
+                }
+            },
+        }
+    }
+    res
+}
+
+#[derive(Debug)]
+pub struct CondensedLevel {
+    /// price of this level grouped by precision
+    pub price: Decimal,
+    /// size at this level
+    pub size: Decimal,
+    /// total size available at this level and all the levels above
+    pub total: Decimal,
+}
+
+impl LevelLike for CondensedLevel {
+    fn price(&self) -> Decimal {
+        self.price
+    }
+
+    fn size(&self) -> Decimal {
+        self.size
+    }
+
+    fn total(&self) -> Option<Decimal> {
+        Some(self.total)
+    }
+}
+
+impl<'a> LevelLike for &'a CondensedLevel {
+    fn price(&self) -> Decimal {
+        self.price
+    }
+
+    fn size(&self) -> Decimal {
+        self.size
+    }
+
+    fn total(&self) -> Option<Decimal> {
+        Some(self.total)
+    }
+}
+
+fn condense_from_levels<'a>(
+    num_levels: usize,
+    dst: &mut Vec<CondensedLevel>,
+    levels: impl Iterator<Item = (&'a Decimal, &'a Decimal)>,
+    precision: Decimal,
+    dir: Dir,
+) {
+    let mut total = Decimal::ZERO;
+    let group = |price: Decimal| {
+        let n = price / precision;
+        let n = if dir == Dir::Buy { n.floor() } else { n.ceil() };
+        n * precision
+    };
+    dst.clear();
+    dst.extend(
+        levels
+            .map(|(price, size)| (group(*price), *size))
+            .group_by(|(p, _)| *p)
+            .into_iter()
+            .take(num_levels)
+            .map(|(price, sizes)| {
+                let size = sizes.map(|(_, s)| s).sum();
+                total += size;
+                CondensedLevel { price, size, total }
+            }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_diff_apply_patch() {
+        let mut prev = LevelBook::default();
+        prev.buy.insert(dec!(99), dec!(1));
+        prev.buy.insert(dec!(98), dec!(2));
+        prev.sell.insert(dec!(101), dec!(1));
+        let mut cur = LevelBook::default();
+        cur.buy.insert(dec!(99), dec!(3));
+        cur.buy.insert(dec!(97), dec!(1));
+        cur.sell.insert(dec!(101), dec!(1));
+        cur.sell.insert(dec!(102), dec!(5));
+        let patch = cur.diff(&prev);
+        assert_eq!(patch.buy.len(), 3);
+        assert_eq!(patch.sell, vec![LevelPatch { price: dec!(102), size: dec!(5) }]);
+        prev.apply_patch(&patch);
+        assert_eq!(prev.book.buy, cur.book.buy);
+        assert_eq!(prev.book.sell, cur.book.sell);
+        assert!(cur.diff(&prev).is_empty());
+    }
+
+    #[test]
+    fn test_analytics() {
+        let mut book = LevelBook::default();
+        book.buy.insert(dec!(99), dec!(1));
+        book.buy.insert(dec!(98), dec!(2));
+        book.sell.insert(dec!(101), dec!(3));
+        book.sell.insert(dec!(102), dec!(1));
+        assert_eq!(book.mid(), Some(dec!(100)));
+        assert_eq!(book.spread(), Some(dec!(2)));
+        assert_eq!(book.microprice(), Some(dec!(99.5)));
+        assert_eq!(book.imbalance(1), Some(dec!(-0.5)));
+        assert_eq!(book.imbalance(2), Some(dec!(-1) / dec!(7)));
+        assert_eq!(book.vwap_to_size(Dir::Buy, dec!(4)), Some(dec!(101.25)));
+        assert_eq!(book.vwap_to_size(Dir::Sell, dec!(2)), Some(dec!(98.5)));
+        assert_eq!(book.vwap_to_size(Dir::Buy, dec!(5)), None);
+        assert_eq!(book.price_impact(Dir::Buy, dec!(202)), Some(dec!(1)));
+        assert_eq!(book.price_impact(Dir::Sell, dec!(197)), Some(dec!(1.5)));
+        assert_eq!(book.price_impact(Dir::Sell, dec!(1000)), None);
+    }
+}
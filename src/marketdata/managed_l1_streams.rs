@@ -0,0 +1,240 @@
+//! L1 book subscriptions managed as named groups of markets.
+//!
+//! `ManagedL1Streams` keeps one subscription per market, through the
+//! process wide `MarketdataBroker`, for as long as any group wants it.
+//! Setting a group diffs the desired markets against the group's current
+//! ones, subscribing what's new and unsubscribing what no group wants any
+//! more; a group's symbols are all resolved before anything is subscribed,
+//! so a bad symbol leaves the subscriptions as they were.
+//! `set_watchlist` keeps one group per venue, by symbol.
+//!
+//! Snapshots from every subscribed market are broadcast to `subscribe`rs,
+//! and the latest per market is kept for `latest`.
+
+use super::{broker::MarketdataBroker, health::MarketdataMonitor};
+use crate::{
+    symbology::{resolve::resolve_symbol, MarketRef},
+    ArchitectClient,
+};
+use anyhow::{anyhow, Result};
+use api::{external::marketdata::L1BookSnapshot, symbology::MarketId};
+use chrono::{TimeZone, Utc};
+use fxhash::{FxHashMap, FxHashSet};
+use log::debug;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+
+/// The subscription changes made by setting a group
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupDiff {
+    /// markets newly subscribed
+    pub subscribed: Vec<MarketRef>,
+    /// markets no group wants any more, unsubscribed
+    pub unsubscribed: Vec<MarketRef>,
+}
+
+#[derive(Default)]
+struct State {
+    groups: FxHashMap<String, FxHashSet<MarketRef>>,
+    tasks: FxHashMap<MarketRef, JoinHandle<()>>,
+}
+
+impl State {
+    fn wanted(&self, market: &MarketRef) -> bool {
+        self.groups.values().any(|g| g.contains(market))
+    }
+}
+
+pub struct ManagedL1Streams {
+    client: Arc<ArchitectClient>,
+    state: Mutex<State>,
+    latest: Arc<Mutex<FxHashMap<MarketId, L1BookSnapshot>>>,
+    tx: broadcast::Sender<L1BookSnapshot>,
+    health: Option<Arc<MarketdataMonitor>>,
+}
+
+impl Drop for ManagedL1Streams {
+    fn drop(&mut self) {
+        for (_, task) in self.state.get_mut().tasks.drain() {
+            task.abort();
+        }
+    }
+}
+
+impl ManagedL1Streams {
+    pub fn new(client: Arc<ArchitectClient>) -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self {
+            client,
+            state: Mutex::new(State::default()),
+            latest: Arc::new(Mutex::new(FxHashMap::default())),
+            tx,
+            health: None,
+        }
+    }
+
+    /// Record the latency and rate of snapshots from markets subscribed
+    /// from now on, see `health`
+    pub fn set_health_monitor(&mut self, health: Option<Arc<MarketdataMonitor>>) {
+        self.health = health;
+    }
+
+    /// Snapshots from every subscribed market
+    pub fn subscribe(&self) -> broadcast::Receiver<L1BookSnapshot> {
+        self.tx.subscribe()
+    }
+
+    pub fn latest(&self, market: MarketId) -> Option<L1BookSnapshot> {
+        self.latest.lock().get(&market).cloned()
+    }
+
+    /// Every subscribed market
+    pub fn markets(&self) -> Vec<MarketRef> {
+        self.state.lock().tasks.keys().copied().collect()
+    }
+
+    pub fn group(&self, name: &str) -> Option<Vec<MarketRef>> {
+        self.state.lock().groups.get(name).map(|g| g.iter().copied().collect())
+    }
+
+    fn start(&self, market: MarketRef, endpoint: &str) -> JoinHandle<()> {
+        let (last, mut rx) = MarketdataBroker::global().subscribe_l1_book_snapshots(
+            &self.client,
+            endpoint,
+            market.id,
+        );
+        let latest = self.latest.clone();
+        let tx = self.tx.clone();
+        let health = self.health.clone();
+        tokio::task::spawn(async move {
+            let mut next = last;
+            loop {
+                if let Some(snap) = next.take() {
+                    latest.lock().insert(snap.market_id, snap.clone());
+                    let _ = tx.send(snap);
+                }
+                next = match rx.recv().await {
+                    Ok(snap) => {
+                        // not the broker's cached snapshot, which may be old
+                        if let Some(health) = &health {
+                            let exchange_time = Utc
+                                .timestamp_opt(snap.timestamp_s, snap.timestamp_ns)
+                                .single()
+                                .unwrap_or_default();
+                            health.record(market, exchange_time, Utc::now());
+                        }
+                        Some(snap)
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        debug!("{} l1 subscription lagged by {n}", market.name);
+                        None
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+            }
+        })
+    }
+
+    /// Make `name` the group of exactly `markets`.  Fails without changing
+    /// anything if a market has no marketdata endpoint.
+    pub fn set_group(
+        &self,
+        name: impl Into<String>,
+        markets: impl IntoIterator<Item = MarketRef>,
+    ) -> Result<GroupDiff> {
+        self.replace_group(name.into(), Some(markets.into_iter().collect()))
+    }
+
+    /// Replace or, with None, remove the group `name`
+    fn replace_group(
+        &self,
+        name: String,
+        markets: Option<FxHashSet<MarketRef>>,
+    ) -> Result<GroupDiff> {
+        let mut st = self.state.lock();
+        let mut endpoints = vec![];
+        for market in markets.iter().flatten() {
+            if !st.tasks.contains_key(market) {
+                endpoints.push((*market, self.client.marketdata_endpoint(market)?));
+            }
+        }
+        let old = match markets {
+            Some(markets) => st.groups.insert(name, markets),
+            None => st.groups.remove(&name),
+        };
+        let mut diff = GroupDiff::default();
+        for (market, endpoint) in endpoints {
+            st.tasks.insert(market, self.start(market, &endpoint));
+            diff.subscribed.push(market);
+        }
+        for market in old.into_iter().flatten() {
+            if !st.wanted(&market) {
+                if let Some(task) = st.tasks.remove(&market) {
+                    task.abort();
+                }
+                self.latest.lock().remove(&market.id);
+                if let Some(health) = &self.health {
+                    health.remove(&market);
+                }
+                diff.unsubscribed.push(market);
+            }
+        }
+        debug!(
+            "l1 groups: subscribed {}, unsubscribed {}",
+            diff.subscribed.len(),
+            diff.unsubscribed.len()
+        );
+        Ok(diff)
+    }
+
+    /// Resolve `symbols` and add their markets to the group `name`, all or
+    /// none.  A symbol resolves to its first market, see `resolve_symbol`.
+    pub fn subscribe_group<S: AsRef<str>>(
+        &self,
+        name: impl Into<String>,
+        symbols: impl IntoIterator<Item = S>,
+    ) -> Result<GroupDiff> {
+        let name = name.into();
+        let mut markets = self.group(&name).unwrap_or_default();
+        for symbol in symbols {
+            let symbol = symbol.as_ref();
+            let resolved = resolve_symbol(symbol)?;
+            let market = resolved
+                .markets
+                .first()
+                .ok_or_else(|| anyhow!("{symbol} doesn't trade on any market"))?;
+            markets.push(*market);
+        }
+        self.set_group(name, markets)
+    }
+
+    /// Drop the group `name`, unsubscribing markets no other group wants
+    pub fn unsubscribe_group(&self, name: &str) -> GroupDiff {
+        // removing needs no endpoints, so can't fail
+        self.replace_group(name.to_string(), None).unwrap_or_default()
+    }
+
+    /// Make the watchlist for `venue` exactly the markets of `symbols` on
+    /// that venue, all or none
+    pub fn set_watchlist<S: AsRef<str>>(
+        &self,
+        venue: &str,
+        symbols: impl IntoIterator<Item = S>,
+    ) -> Result<GroupDiff> {
+        let mut markets = vec![];
+        for symbol in symbols {
+            let symbol = symbol.as_ref();
+            let market = resolve_symbol(symbol)?
+                .markets
+                .into_iter()
+                .find(|m| m.venue.name.as_str() == venue)
+                .ok_or_else(|| anyhow!("{symbol} doesn't trade on {venue}"))?;
+            markets.push(market);
+        }
+        self.set_group(format!("watchlist/{venue}"), markets)
+    }
+}
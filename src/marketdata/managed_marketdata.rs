@@ -4,23 +4,36 @@
 //! easier, more efficient interface than trying to manually juggle a bunch of
 //! `BookClient`s.
 
-use super::book_client::BookClient;
+use super::{
+    book_client::{BookClient, BookDepth},
+    health::MarketdataMonitor,
+    journal::RecvStamp,
+    published::{BookTop, Published},
+    rfq_client::SubscribeRfq,
+    venue_config::MarketdataConfig,
+    warm_up::{SubscriptionPriority, WarmUp, WarmUpConfig},
+};
 use crate::{
+    rpc::RpcClient,
     symbology::{Cpty, MarketKind, MarketRef},
     synced::Synced,
-    Common,
+    throttled_error, Common,
 };
 use anyhow::{bail, Result};
-use api::marketdata::{RfqRequest, RfqResponse};
+use api::{
+    marketdata::{RfqRequest, RfqResponse},
+    symbology::CptyId,
+};
+use chrono::{DateTime, Utc};
 use futures::channel::mpsc;
 use futures_util::StreamExt;
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
 use log::{debug, error, warn};
 use netidx::{
+    path::Path,
     pool::Pooled,
     subscriber::{Dval, Event, SubId, UpdatesFlags, Value},
 };
-use netidx_protocols::{call_rpc, rpc::client::Proc};
 use rust_decimal::Decimal;
 use std::{
     sync::{Arc, Weak},
@@ -36,16 +49,43 @@ pub struct ManagedMarketdata {
     rfq_handles: Arc<Mutex<RfqHandles>>,
     dval_handles: Arc<Mutex<DvalHandles>>,
     common: Common,
+    /// cptys whose feeds publish depth limited books, see `BookDepth`
+    depth_limited_cptys: FxHashSet<CptyId>,
+    marketdata_config: MarketdataConfig,
+    health: Option<Arc<MarketdataMonitor>>,
     _subscription_driver: Option<JoinHandle<()>>,
     subscription_tx: mpsc::Sender<Pooled<Vec<(SubId, Event)>>>,
 }
 
 // CR alee: periodically garbage collect weaks that have been dropped
 pub struct BookHandles {
-    by_market: FxHashMap<MarketRef, Weak<Mutex<BookClient>>>,
+    /// one client per feed path, i.e. per market and book leaf, shared by
+    /// every consumer of it
+    by_path: FxHashMap<Path, (MarketRef, Weak<Mutex<BookClient>>)>,
     by_sub_id: FxHashMap<SubId, Weak<Mutex<BookClient>>>,
 }
 
+/// The state of a book subscription, see `ManagedMarketdata::subscriptions`
+#[derive(Debug, Clone)]
+pub struct BookSubscriptionState {
+    pub market: MarketRef,
+    pub path: Path,
+    pub synced: bool,
+    /// of the last message applied to the book
+    pub timestamp: DateTime<Utc>,
+}
+
+/// One consumer's handle on a shared book subscription
+pub struct BookSubscription {
+    /// the book at the subscribed leaf, shared with every other consumer
+    /// of it
+    pub book: Arc<Mutex<BookClient>>,
+    pub synced: Synced<u64>,
+    /// this consumer's lock-free view of the top `depth` levels
+    pub top: Published<BookTop>,
+    pub depth: BookDepth,
+}
+
 pub struct RfqHandles {
     by_rfq: FxHashMap<(Cpty, RfqRequest), Weak<Mutex<RfqResponseHandle>>>,
     by_sub_id: FxHashMap<SubId, Weak<Mutex<RfqResponseHandle>>>,
@@ -85,7 +125,7 @@ impl DvalHandle {
 impl ManagedMarketdata {
     pub fn start(common: Common, runtime: Option<&tokio::runtime::Handle>) -> Self {
         let book_handles = Arc::new(Mutex::new(BookHandles {
-            by_market: FxHashMap::default(),
+            by_path: FxHashMap::default(),
             by_sub_id: FxHashMap::default(),
         }));
         let rfq_handles = Arc::new(Mutex::new(RfqHandles {
@@ -103,6 +143,8 @@ impl ManagedMarketdata {
             let dval_handles = dval_handles.clone();
             let f = async move {
                 'outer: while let Some(mut batch) = rx.next().await {
+                    // before waiting on any lock, for the journal
+                    let stamp = RecvStamp::now();
                     let mut book_handles = book_handles.lock().await;
                     let mut rfq_handles = rfq_handles.lock().await;
                     let mut dval_handles = dval_handles.lock().await;
@@ -110,7 +152,9 @@ impl ManagedMarketdata {
                         if let Some(book) =
                             book_handles.by_sub_id.get_mut(&id).and_then(|w| w.upgrade())
                         {
-                            if let Err(e) = book.lock().await.process_event(event) {
+                            if let Err(e) =
+                                book.lock().await.process_event_at(event, stamp, None)
+                            {
                                 error!("error processing book event: {}", e);
                                 break 'outer;
                             }
@@ -144,7 +188,9 @@ impl ManagedMarketdata {
                                             rfq.tx_updates.send_replace(rfq.synced);
                                         }
                                         Err(e) => {
-                                            error!("failed to parse RFQ response: {e}",)
+                                            throttled_error!(
+                                                "failed to parse RFQ response: {e}"
+                                            )
                                         }
                                     }
                                 }
@@ -164,6 +210,9 @@ impl ManagedMarketdata {
             rfq_handles,
             dval_handles,
             common,
+            depth_limited_cptys: FxHashSet::default(),
+            marketdata_config: MarketdataConfig::default(),
+            health: None,
             _subscription_driver: Some(handle),
             subscription_tx: tx,
         }
@@ -173,7 +222,7 @@ impl ManagedMarketdata {
         let (tx, _rx) = mpsc::channel::<Pooled<Vec<(SubId, Event)>>>(1);
         Self {
             book_handles: Arc::new(Mutex::new(BookHandles {
-                by_market: FxHashMap::default(),
+                by_path: FxHashMap::default(),
                 by_sub_id: FxHashMap::default(),
             })),
             rfq_handles: Arc::new(Mutex::new(RfqHandles {
@@ -185,39 +234,154 @@ impl ManagedMarketdata {
                 by_sub_id: FxHashMap::default(),
             })),
             common,
+            depth_limited_cptys: FxHashSet::default(),
+            marketdata_config: MarketdataConfig::default(),
+            health: None,
             _subscription_driver: None,
             subscription_tx: tx,
         }
     }
 
+    /// Declare which cptys publish depth limited books.  Depth limited
+    /// subscriptions to those receive only the levels asked for; to other
+    /// cptys they fall back to the full book, since truncating a full book
+    /// at the source would lose levels that later move up.
+    pub fn set_depth_limited_cptys(&mut self, cptys: impl IntoIterator<Item = CptyId>) {
+        self.depth_limited_cptys = cptys.into_iter().collect();
+    }
+
+    /// The feed path a subscription to `market` at `depth` receives
+    fn book_path(&self, market: MarketRef, delayed: bool, depth: BookDepth) -> Path {
+        let leaf = if self.depth_limited_cptys.contains(&market.cpty().id()) {
+            depth.path_leaf()
+        } else {
+            BookDepth::Full.path_leaf()
+        };
+        self.common.paths.marketdata_by_name(market, false, delayed).append(leaf)
+    }
+
+    /// Record the latency and rate of updates to books subscribed from now
+    /// on, see `health`
+    pub fn set_health_monitor(&mut self, health: Option<Arc<MarketdataMonitor>>) {
+        self.health = health;
+    }
+
+    /// The live book subscriptions, one per feed path
+    pub async fn subscriptions(&self) -> Vec<BookSubscriptionState> {
+        let clients: Vec<_> = {
+            let handles = self.book_handles.lock().await;
+            handles
+                .by_path
+                .iter()
+                .filter_map(|(path, (market, w))| {
+                    w.upgrade().map(|b| (path.clone(), *market, b))
+                })
+                .collect()
+        };
+        let mut res = Vec::with_capacity(clients.len());
+        for (path, market, book) in clients {
+            let book = book.lock().await;
+            res.push(BookSubscriptionState {
+                market,
+                path,
+                synced: book.synced(),
+                timestamp: book.book().timestamp,
+            });
+        }
+        res
+    }
+
+    /// Per venue depths and subscription budgets for `subscribe_configured`
+    pub fn set_marketdata_config(&mut self, config: MarketdataConfig) {
+        self.marketdata_config = config;
+    }
+
+    /// Subscribe at the depth configured for the market's venue; fails if
+    /// that would take the venue past its subscription budget
+    pub async fn subscribe_configured(
+        &self,
+        market: MarketRef,
+        delayed: bool,
+    ) -> Result<BookSubscription> {
+        let venue = market.venue.name.as_str();
+        let settings = self.marketdata_config.resolve(venue);
+        let depth = match settings.depth {
+            Some(n) if n <= 10 => BookDepth::Top10,
+            Some(n) if n <= 50 => BookDepth::Top50,
+            _ => BookDepth::Full,
+        };
+        if let Some(max) = settings.max_subscriptions {
+            let book_path = self.book_path(market, delayed, depth);
+            let book_handles = self.book_handles.lock().await;
+            // live subscriptions to the venue, other than this one
+            let mut n = 0;
+            let mut subscribed = false;
+            for (path, (m, w)) in book_handles.by_path.iter() {
+                if m.venue.name != market.venue.name || w.strong_count() == 0 {
+                    continue;
+                }
+                if *path == book_path {
+                    subscribed = true;
+                } else {
+                    n += 1;
+                }
+            }
+            if !subscribed && n >= max {
+                bail!("{venue} is at its budget of {max} book subscriptions");
+            }
+        }
+        Ok(self.subscribe_with_depth(market, delayed, depth).await)
+    }
+
     pub async fn subscribe(
         &self,
         market: MarketRef,
         delayed: bool,
     ) -> (Arc<Mutex<BookClient>>, Synced<u64>) {
+        let BookSubscription { book, synced, .. } =
+            self.subscribe_with_depth(market, delayed, BookDepth::Full).await;
+        (book, synced)
+    }
+
+    /// Subscribe to the market's book, sharing the subscription with any
+    /// other consumer of the same feed path, and take a view of its top
+    /// `depth` levels.  For cptys that publish depth limited books each
+    /// depth is a separate subscription to its own leaf, see
+    /// `set_depth_limited_cptys`.
+    pub async fn subscribe_with_depth(
+        &self,
+        market: MarketRef,
+        delayed: bool,
+        depth: BookDepth,
+    ) -> BookSubscription {
+        let levels = depth.levels().unwrap_or(usize::MAX);
+        let book_path = self.book_path(market, delayed, depth);
         let mut book_handles = self.book_handles.lock().await;
         if let Some(existing) =
-            book_handles.by_market.get(&market).and_then(|w| w.upgrade())
+            book_handles.by_path.get(&book_path).and_then(|(_, w)| w.upgrade())
         {
-            let synced = existing.lock().await.subscribe_updates();
-            return (existing, synced);
+            let (synced, top) = {
+                let mut book = existing.lock().await;
+                (book.subscribe_updates(), book.published_at(levels))
+            };
+            return BookSubscription { book: existing, synced, top, depth };
         }
-        let book_path =
-            self.common.paths.marketdata_by_name(market, false, delayed).append("book");
         debug!("subscribing to book at {}", book_path);
-        let book_client = BookClient::new(
+        let mut book_client = BookClient::new(
             &self.common.subscriber,
             &book_path,
             false,
             market,
             self.subscription_tx.clone(),
         );
+        book_client.set_health_monitor(self.health.clone());
         let sub_id = book_client.id();
         let synced = book_client.subscribe_updates();
+        let top = book_client.published_at(levels);
         let book_client = Arc::new(Mutex::new(book_client));
-        book_handles.by_market.insert(market, Arc::downgrade(&book_client));
+        book_handles.by_path.insert(book_path, (market, Arc::downgrade(&book_client)));
         book_handles.by_sub_id.insert(sub_id, Arc::downgrade(&book_client));
-        (book_client, synced)
+        BookSubscription { book: book_client, synced, top, depth }
     }
 
     pub async fn subscribe_path(
@@ -295,18 +459,12 @@ impl ManagedMarketdata {
             rfq_handles.by_rfq.insert((cpty, rfq), Arc::downgrade(&handle));
             (handle, Synced(rx_updates))
         };
-        let path = self.common.paths.marketdata_api(cpty).append("subscribe-rfq");
-        let proc = Proc::new_with_timeout(
+        let res = RpcClient::new(
             &self.common.subscriber,
-            path,
-            Duration::from_secs(2),
-        )?;
-        let res = call_rpc!(
-            proc,
-            base: format!("\"{}\"", rfq.base),
-            quote: format!("\"{}\"", rfq.quote),
-            quantity: rfq.quantity
+            self.common.paths.marketdata_api(cpty),
         )
+        .with_timeout(Duration::from_secs(2))
+        .call(SubscribeRfq(rfq))
         .await?;
         let uuid = res.to_string_naked();
         let rfq_path = self.common.paths.marketdata_rfq(cpty).append(uuid.as_str());
@@ -322,6 +480,15 @@ impl ManagedMarketdata {
         Ok((handle, synced))
     }
 
+    /// Subscribe many markets most important first, see `WarmUp`
+    pub fn warm_up(
+        self: &Arc<Self>,
+        markets: impl IntoIterator<Item = (MarketRef, SubscriptionPriority)>,
+        config: WarmUpConfig,
+    ) -> WarmUp {
+        WarmUp::start(self.clone(), markets, config)
+    }
+
     /// Keep a book client alive for some time instead of dropping immediately
     pub fn retain(book_client: Arc<Mutex<BookClient>>, duration: Duration) {
         task::spawn(async move {
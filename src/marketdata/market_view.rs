@@ -0,0 +1,214 @@
+//! Snapshot-consistent market views for strategies.
+//!
+//! Feeds write into a `MarketViewWriter`, which publishes an immutable
+//! `MarketView` with `publish`.  Strategies call `MarketViewReader::load` once
+//! per decision cycle and get a consistent picture of BBO, depth, last trade,
+//! position, working orders, and marks for every market in the view.  Loading
+//! is a single atomic pointer read; no locks are held across strategy code.
+
+use crate::symbology::MarketRef;
+use api::{Dir, DirPair, OrderId};
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use immutable_chunkmap::map::MapM as Map;
+use rust_decimal::Decimal;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LastTrade {
+    pub price: Decimal,
+    pub size: Decimal,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WorkingOrder {
+    pub id: OrderId,
+    pub dir: Dir,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub filled: Decimal,
+}
+
+impl WorkingOrder {
+    pub fn remaining(&self) -> Decimal {
+        self.quantity - self.filled
+    }
+}
+
+/// Everything known about a single market as of the view's timestamp
+#[derive(Debug, Clone, Default)]
+pub struct MarketState {
+    /// book levels (price, size), best first
+    pub depth: DirPair<Vec<(Decimal, Decimal)>>,
+    pub last_trade: Option<LastTrade>,
+    pub mark: Option<Decimal>,
+    /// signed position, positive is long
+    pub position: Decimal,
+    pub working_orders: Vec<WorkingOrder>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl MarketState {
+    /// best (price, size) on the given side
+    pub fn best(&self, dir: Dir) -> Option<(Decimal, Decimal)> {
+        self.depth.get(dir).first().copied()
+    }
+
+    pub fn bbo(&self) -> (Option<(Decimal, Decimal)>, Option<(Decimal, Decimal)>) {
+        (self.best(Dir::Buy), self.best(Dir::Sell))
+    }
+
+    pub fn mid(&self) -> Option<Decimal> {
+        let (bid, _) = self.best(Dir::Buy)?;
+        let (ask, _) = self.best(Dir::Sell)?;
+        Some((bid + ask) / Decimal::TWO)
+    }
+
+    /// total remaining quantity of working orders on the given side
+    pub fn working_quantity(&self, dir: Dir) -> Decimal {
+        self.working_orders.iter().filter(|o| o.dir == dir).map(|o| o.remaining()).sum()
+    }
+}
+
+/// An immutable, consistent view of a set of markets
+#[derive(Debug, Clone, Default)]
+pub struct MarketView {
+    /// incremented on every publish
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    markets: Map<MarketRef, Arc<MarketState>>,
+}
+
+impl MarketView {
+    pub fn get(&self, market: &MarketRef) -> Option<&MarketState> {
+        self.markets.get(market).map(|s| &**s)
+    }
+
+    pub fn markets(&self) -> impl Iterator<Item = (&MarketRef, &MarketState)> {
+        self.markets.into_iter().map(|(m, s)| (m, &**s))
+    }
+
+    pub fn len(&self) -> usize {
+        self.markets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markets.len() == 0
+    }
+}
+
+/// A cheaply cloneable handle to the latest published view
+#[derive(Debug, Clone)]
+pub struct MarketViewReader(Arc<ArcSwap<MarketView>>);
+
+impl MarketViewReader {
+    /// Load the latest published view; hold on to the returned `Arc` for
+    /// the duration of the decision cycle.
+    pub fn load(&self) -> Arc<MarketView> {
+        self.0.load_full()
+    }
+}
+
+/// wasm32-unknown-unknown has no system clock, see `MarketViewWriter::set_time`
+#[cfg(not(target_arch = "wasm32"))]
+fn clock() -> DateTime<Utc> {
+    Utc::now()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn clock() -> DateTime<Utc> {
+    DateTime::<Utc>::default()
+}
+
+/// The single writer of a market view.  Mutations are staged until `publish`
+/// so readers never observe a partially applied update.
+pub struct MarketViewWriter {
+    staged: MarketView,
+    published: Arc<ArcSwap<MarketView>>,
+    time: Option<DateTime<Utc>>,
+}
+
+impl Default for MarketViewWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarketViewWriter {
+    pub fn new() -> Self {
+        Self {
+            staged: MarketView::default(),
+            published: Arc::new(ArcSwap::from_pointee(MarketView::default())),
+            time: None,
+        }
+    }
+
+    /// Stamp updates and publishes with `time` from now on instead of the
+    /// system clock, e.g. in a backtest.  On wasm32 there is no clock, and
+    /// without this everything is stamped with the unix epoch.
+    pub fn set_time(&mut self, time: DateTime<Utc>) {
+        self.time = Some(time);
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.time.unwrap_or_else(clock)
+    }
+
+    pub fn reader(&self) -> MarketViewReader {
+        MarketViewReader(self.published.clone())
+    }
+
+    fn update(&mut self, market: MarketRef, f: impl FnOnce(&mut MarketState)) {
+        let mut state =
+            self.staged.markets.get(&market).map(|s| (**s).clone()).unwrap_or_default();
+        f(&mut state);
+        state.timestamp = self.now();
+        self.staged.markets.insert_cow(market, Arc::new(state));
+    }
+
+    pub fn set_depth(
+        &mut self,
+        market: MarketRef,
+        depth: DirPair<Vec<(Decimal, Decimal)>>,
+    ) {
+        self.update(market, |s| s.depth = depth)
+    }
+
+    pub fn set_last_trade(&mut self, market: MarketRef, trade: LastTrade) {
+        self.update(market, |s| s.last_trade = Some(trade))
+    }
+
+    pub fn set_mark(&mut self, market: MarketRef, mark: Decimal) {
+        self.update(market, |s| s.mark = Some(mark))
+    }
+
+    pub fn set_position(&mut self, market: MarketRef, position: Decimal) {
+        self.update(market, |s| s.position = position)
+    }
+
+    /// Insert or replace a working order; orders with nothing remaining are removed
+    pub fn set_working_order(&mut self, market: MarketRef, order: WorkingOrder) {
+        self.update(market, |s| {
+            s.working_orders.retain(|o| o.id != order.id);
+            if order.remaining() > Decimal::ZERO {
+                s.working_orders.push(order);
+            }
+        })
+    }
+
+    pub fn remove_working_order(&mut self, market: MarketRef, id: OrderId) {
+        self.update(market, |s| s.working_orders.retain(|o| o.id != id))
+    }
+
+    pub fn remove_market(&mut self, market: &MarketRef) {
+        self.staged.markets.remove_cow(market);
+    }
+
+    /// Atomically publish all staged changes to readers
+    pub fn publish(&mut self) {
+        self.staged.sequence += 1;
+        self.staged.timestamp = self.now();
+        self.published.store(Arc::new(self.staged.clone()));
+    }
+}
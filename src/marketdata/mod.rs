@@ -1,16 +1,55 @@
+pub mod aggregates;
+pub mod alerts;
+#[cfg(feature = "netidx")]
+pub mod batched_recording;
 #[cfg(feature = "netidx")]
 pub mod book_client;
+#[cfg(feature = "grpc")]
+pub mod broker;
+pub mod candle_builder;
+pub mod candle_check;
+pub mod conflate;
+#[cfg(feature = "grpc")]
+pub mod consolidated_l1;
+#[cfg(all(feature = "grpc", feature = "netidx"))]
+pub mod consolidated_l2;
 #[cfg(feature = "netidx")]
 pub mod external_client;
+pub mod health;
 #[cfg(feature = "netidx")]
 pub mod historical_candles;
 #[cfg(feature = "netidx")]
+pub mod historical_trades;
+pub mod indicators;
+#[cfg(feature = "netidx")]
+pub mod ipc;
+#[cfg(feature = "netidx")]
+pub mod journal;
+pub mod level_book;
+#[cfg(feature = "grpc")]
+pub mod managed_l1_streams;
+#[cfg(feature = "netidx")]
 pub mod managed_marketdata;
+pub mod market_view;
 #[cfg(feature = "netidx")]
 pub mod netidx_feed_client;
+pub mod published;
+pub mod quote_activity;
+pub mod recorder;
+pub mod resample;
 #[cfg(feature = "netidx")]
 pub mod rfq_client;
 #[cfg(feature = "netidx")]
 pub mod snapshots;
+pub mod synthetic;
+pub mod ticker_cache;
+pub mod time_and_sales;
+pub mod trade_classifier;
+#[cfg(feature = "netidx")]
+pub mod universe_subscription;
 #[cfg(feature = "netidx")]
 pub mod utils;
+pub mod venue_config;
+#[cfg(feature = "netidx")]
+pub mod warm_up;
+pub mod watchlist;
@@ -1,12 +1,12 @@
 //! Subscribe to all netidx feed data
 
 use super::book_client::BookClient;
-use crate::symbology::MarketRef;
+use crate::{symbology::MarketRef, throttled_error};
 use anyhow::Result;
 use api::marketdata::TradeV1;
 use futures::channel::mpsc;
 use fxhash::{FxHashMap, FxHashSet};
-use log::{debug, error};
+use log::debug;
 use netidx::{
     path::Path,
     pool::{Poolable, Pooled},
@@ -164,7 +164,7 @@ impl NetidxFeedClient {
         } else if let Some(client) = self.extra_vals.get_mut(&sub_id) {
             client.process_event(ev)?;
         } else {
-            error!("unhandled event for sub_id: {:?}", sub_id);
+            throttled_error!("unhandled event for sub_id: {:?}", sub_id);
         }
         Ok(())
     }
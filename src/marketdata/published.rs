@@ -0,0 +1,140 @@
+//! Lock-free fan-out of immutable snapshots.
+//!
+//! A single writer stores a new snapshot after each update; any number of
+//! readers, on any thread, load the latest one with an atomic pointer read
+//! and never contend with the writer or each other.
+
+use super::level_book::LevelBook;
+use api::{Dir, DirPair};
+use arc_swap::{ArcSwap, Guard};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use std::sync::Arc;
+
+/// The writer and reader side are the same type; clone it to hand out
+/// readers.
+#[derive(Debug)]
+pub struct Published<T>(Arc<ArcSwap<T>>);
+
+impl<T> Clone for Published<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T: Default> Default for Published<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> Published<T> {
+    pub fn new(t: T) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(t)))
+    }
+
+    pub fn store(&self, t: T) {
+        self.0.store(Arc::new(t))
+    }
+
+    /// Cheap, short lived access to the latest snapshot; don't hold the
+    /// guard across awaits, use `load_full` for that
+    pub fn load(&self) -> Guard<Arc<T>> {
+        self.0.load()
+    }
+
+    pub fn load_full(&self) -> Arc<T> {
+        self.0.load_full()
+    }
+}
+
+/// An immutable view of the top N levels of a book
+#[derive(Debug, Clone, Default)]
+pub struct BookTop {
+    /// (price, size), best first
+    pub levels: DirPair<Vec<(Decimal, Decimal)>>,
+    pub timestamp: DateTime<Utc>,
+    /// incremented on every update to the underlying book
+    pub sequence: u64,
+}
+
+impl BookTop {
+    pub fn new(book: &LevelBook, depth: usize, sequence: u64) -> Self {
+        let side =
+            |dir| book.iter_levels(dir).take(depth).map(|(p, s)| (*p, *s)).collect();
+        Self {
+            levels: DirPair { buy: side(Dir::Buy), sell: side(Dir::Sell) },
+            timestamp: book.timestamp,
+            sequence,
+        }
+    }
+
+    pub fn best(&self, dir: Dir) -> Option<(Decimal, Decimal)> {
+        self.levels.get(dir).first().copied()
+    }
+
+    pub fn mid(&self) -> Option<Decimal> {
+        let (bid, _) = self.best(Dir::Buy)?;
+        let (ask, _) = self.best(Dir::Sell)?;
+        Some((bid + ask) / Decimal::TWO)
+    }
+}
+
+/// Tops of one book at several depths, one published snapshot per depth,
+/// so consumers sharing a subscription each see only the levels they asked
+/// for
+#[derive(Debug, Clone, Default)]
+pub struct DepthViews {
+    views: Vec<(usize, Published<BookTop>)>,
+}
+
+impl DepthViews {
+    /// The view at `depth`, shared with any other consumer of that depth
+    pub fn get(
+        &mut self,
+        book: &LevelBook,
+        depth: usize,
+        sequence: u64,
+    ) -> Published<BookTop> {
+        if let Some((_, view)) = self.views.iter().find(|(d, _)| *d == depth) {
+            return view.clone();
+        }
+        let view = Published::new(BookTop::new(book, depth, sequence));
+        self.views.push((depth, view.clone()));
+        view
+    }
+
+    pub fn publish(&self, book: &LevelBook, sequence: u64) {
+        for (depth, view) in &self.views {
+            view.store(BookTop::new(book, *depth, sequence));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_depth_views() {
+        let mut book = LevelBook::default();
+        let mut views = DepthViews::default();
+        let top1 = views.get(&book, 1, 0);
+        let top2 = views.get(&book, 2, 0);
+        let again = views.get(&book, 1, 0);
+        book.buy.insert(dec!(99), dec!(1));
+        book.buy.insert(dec!(98), dec!(2));
+        book.buy.insert(dec!(97), dec!(3));
+        book.sell.insert(dec!(101), dec!(1));
+        views.publish(&book, 1);
+        assert_eq!(top1.load().levels.buy, vec![(dec!(99), dec!(1))]);
+        assert_eq!(
+            top2.load().levels.buy,
+            vec![(dec!(99), dec!(1)), (dec!(98), dec!(2))]
+        );
+        assert_eq!(top2.load().levels.sell, vec![(dec!(101), dec!(1))]);
+        assert_eq!(again.load().sequence, 1);
+        assert_eq!(again.load().levels.buy.len(), 1);
+    }
+}
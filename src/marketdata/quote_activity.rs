@@ -0,0 +1,229 @@
+//! Streaming estimates of how fast a market is moving, from top of book
+//! updates, to drive adaptive behavior like widening quotes in fast
+//! markets.
+//!
+//! Over a sliding window, per market:
+//!
+//! - tick rate: top of book changes per second
+//! - realized volatility: the square root of the sum of squared mid
+//!   returns, i.e. not annualized, over the window
+//! - quote life: how long a best bid or ask price stood before it changed,
+//!   on average
+//!
+//! `QuoteActivity` keeps the estimates of each market up to date as
+//! updates are pushed, so reading them is a map lookup.  Estimates are as
+//! of each market's last update; call `expire` periodically for a quiet
+//! market's estimates to decay.
+
+use super::{indicators::sqrt, time_and_sales::TopOfBook};
+use crate::symbology::MarketRef;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ActivityStats {
+    pub timestamp: DateTime<Utc>,
+    /// top of book changes per second over the window
+    pub tick_rate: Decimal,
+    /// None unless the mid changed in the window
+    pub realized_vol: Option<Decimal>,
+    /// None until a best price has changed in the window
+    pub mean_quote_life: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+struct MarketActivity {
+    last: TopOfBook,
+    last_mid: Option<Decimal>,
+    /// when the current best bid and ask prices were first seen
+    bid_since: Option<DateTime<Utc>>,
+    ask_since: Option<DateTime<Utc>>,
+    changes: VecDeque<DateTime<Utc>>,
+    /// squared mid returns
+    returns: VecDeque<(DateTime<Utc>, Decimal)>,
+    returns_sum: Decimal,
+    lives: VecDeque<(DateTime<Utc>, Duration)>,
+    lives_sum: Duration,
+    stats: ActivityStats,
+}
+
+impl MarketActivity {
+    fn new() -> Self {
+        Self {
+            last: TopOfBook::default(),
+            last_mid: None,
+            bid_since: None,
+            ask_since: None,
+            changes: VecDeque::new(),
+            returns: VecDeque::new(),
+            returns_sum: Decimal::ZERO,
+            lives: VecDeque::new(),
+            lives_sum: Duration::zero(),
+            stats: ActivityStats::default(),
+        }
+    }
+
+    fn expire(&mut self, window: Duration, now: DateTime<Utc>) {
+        let cutoff = now - window;
+        while self.changes.front().is_some_and(|t| *t < cutoff) {
+            self.changes.pop_front();
+        }
+        while let Some((_, r)) = self.returns.front().filter(|(t, _)| *t < cutoff) {
+            self.returns_sum -= *r;
+            self.returns.pop_front();
+        }
+        while let Some((_, l)) = self.lives.front().filter(|(t, _)| *t < cutoff) {
+            self.lives_sum = self.lives_sum - *l;
+            self.lives.pop_front();
+        }
+        let secs = Decimal::from(window.num_milliseconds()) / Decimal::ONE_THOUSAND;
+        self.stats = ActivityStats {
+            timestamp: now,
+            tick_rate: Decimal::from(self.changes.len()) / secs,
+            realized_vol: (!self.returns.is_empty()).then(|| sqrt(self.returns_sum)),
+            mean_quote_life: (!self.lives.is_empty())
+                .then(|| self.lives_sum / self.lives.len() as i32),
+        };
+    }
+
+    /// Record the life of a best price first seen at `since` and replaced
+    /// at `now`
+    fn replaced(&mut self, since: Option<DateTime<Utc>>, now: DateTime<Utc>) {
+        if let Some(t) = since {
+            let life = now - t;
+            self.lives.push_back((now, life));
+            self.lives_sum = self.lives_sum + life;
+        }
+    }
+
+    fn update(&mut self, window: Duration, now: DateTime<Utc>, tob: TopOfBook) {
+        if tob != self.last {
+            self.changes.push_back(now);
+        }
+        let price = |side: Option<(Decimal, Decimal)>| side.map(|(p, _)| p);
+        if price(tob.bid) != price(self.last.bid) {
+            let since = self.bid_since.replace(now);
+            self.replaced(since, now);
+        }
+        if price(tob.ask) != price(self.last.ask) {
+            let since = self.ask_since.replace(now);
+            self.replaced(since, now);
+        }
+        let mid = match (tob.bid, tob.ask) {
+            (Some((bid, _)), Some((ask, _))) => Some((bid + ask) / Decimal::TWO),
+            _ => None,
+        };
+        if let (Some(mid), Some(last)) = (mid, self.last_mid) {
+            if mid != last && !last.is_zero() {
+                let r = mid / last - Decimal::ONE;
+                self.returns.push_back((now, r * r));
+                self.returns_sum += r * r;
+            }
+        }
+        self.last_mid = mid.or(self.last_mid);
+        self.last = tob;
+        self.expire(window, now);
+    }
+}
+
+/// Per market activity estimates over a sliding window
+#[derive(Debug, Clone)]
+pub struct QuoteActivity {
+    window: Duration,
+    markets: FxHashMap<MarketRef, MarketActivity>,
+}
+
+impl QuoteActivity {
+    pub fn new(window: Duration) -> Result<Self> {
+        if window <= Duration::zero() {
+            bail!("invalid activity window {window}");
+        }
+        Ok(Self { window, markets: FxHashMap::default() })
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Push a top of book update; updates must be time ordered per market
+    pub fn update(
+        &mut self,
+        market: MarketRef,
+        timestamp: DateTime<Utc>,
+        tob: TopOfBook,
+    ) {
+        let window = self.window;
+        self.markets
+            .entry(market)
+            .or_insert_with(MarketActivity::new)
+            .update(window, timestamp, tob)
+    }
+
+    /// Push an L1 snapshot
+    #[cfg(feature = "grpc")]
+    pub fn on_l1(
+        &mut self,
+        market: MarketRef,
+        snap: &api::external::marketdata::L1BookSnapshot,
+    ) {
+        let obs = super::alerts::Observation::from(snap);
+        self.update(
+            market,
+            obs.timestamp,
+            TopOfBook { bid: obs.best_bid, ask: obs.best_ask },
+        )
+    }
+
+    /// The estimates for `market` as of its last update or `expire`
+    pub fn stats(&self, market: &MarketRef) -> Option<ActivityStats> {
+        self.markets.get(market).map(|m| m.stats)
+    }
+
+    /// Drop observations that have left the window as of `now` from every
+    /// market's estimates
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        let window = self.window;
+        for m in self.markets.values_mut() {
+            m.expire(window, now);
+        }
+    }
+
+    pub fn remove(&mut self, market: &MarketRef) {
+        self.markets.remove(market);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_market_activity() {
+        let window = Duration::seconds(10);
+        let t = |s| Utc.with_ymd_and_hms(2024, 3, 5, 12, 0, s).unwrap();
+        let tob =
+            |bid, ask| TopOfBook { bid: Some((bid, dec!(1))), ask: Some((ask, dec!(1))) };
+        let mut m = MarketActivity::new();
+        m.update(window, t(0), tob(dec!(99), dec!(101)));
+        m.update(window, t(2), tob(dec!(100), dec!(102)));
+        // the same quote again isn't a change
+        m.update(window, t(3), tob(dec!(100), dec!(102)));
+        m.update(window, t(6), tob(dec!(99), dec!(101)));
+        assert_eq!(m.stats.tick_rate, dec!(0.3));
+        // mids 100, 101, 100
+        let r1 = dec!(0.01);
+        let r2 = dec!(100) / dec!(101) - Decimal::ONE;
+        assert_eq!(m.stats.realized_vol, Some(sqrt(r1 * r1 + r2 * r2)));
+        // both sides lived 2s, then 4s
+        assert_eq!(m.stats.mean_quote_life, Some(Duration::seconds(3)));
+        m.expire(window, t(13));
+        assert_eq!(m.stats.tick_rate, dec!(0.1));
+        assert_eq!(m.stats.realized_vol, Some(sqrt(r2 * r2)));
+        assert_eq!(m.stats.mean_quote_life, Some(Duration::seconds(4)));
+    }
+}
@@ -0,0 +1,533 @@
+//! Record marketdata streams to disk, and replay them for backtesting.
+//!
+//! `Recorder` subscribes to L1 snapshots, L2 book updates, trades, and
+//! candles and appends each message, stamped with the time it was received,
+//! to a `RollingRecordingWriter`: JSON lines segments in a `SegmentDir`,
+//! rolled per a `RollPolicy` and, with the netidx feature, compressed with
+//! zstd as they are closed, or written in the much smaller
+//! `batched_recording` format to begin with.  Stream outages are recorded
+//! as `Gap`s so a backtest sees them too.
+//!
+//! `RecordingReader` reads a recording back in the order it was written,
+//! and `replay` feeds it to a channel as if it were live, optionally paced
+//! by the recorded receive times.
+//!
+//! Unlike `journal`, which records raw netidx book messages for exact
+//! reconstruction, recordings hold the decoded messages of any source and
+//! don't need netidx to read.
+
+use super::{candle_builder::CandleEvent, resample::Bar, time_and_sales::Print};
+use crate::segments::{open_segment, RollPolicy, SegmentDir};
+use anyhow::{anyhow, Result};
+use api::{
+    external::marketdata::{L1BookSnapshot, L2BookUpdate},
+    symbology::MarketId,
+    Dir,
+};
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// The start of every segment in the `batched_recording` format
+pub(crate) const BATCHED_MAGIC: &[u8; 8] = b"ARCXREC1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedTrade {
+    pub timestamp: DateTime<Utc>,
+    pub price: Decimal,
+    pub size: Decimal,
+    pub dir: Option<Dir>,
+}
+
+impl RecordedTrade {
+    pub fn new(timestamp: DateTime<Utc>, print: &Print) -> Self {
+        Self { timestamp, price: print.price, size: print.size, dir: print.dir }
+    }
+
+    pub fn print(&self) -> Print {
+        Print { price: self.price, size: self.size, dir: self.dir }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedCandle {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    pub notional: Decimal,
+    pub trades: u64,
+}
+
+impl From<Bar> for RecordedCandle {
+    fn from(b: Bar) -> Self {
+        Self {
+            start: b.start,
+            end: b.end,
+            open: b.open,
+            high: b.high,
+            low: b.low,
+            close: b.close,
+            volume: b.volume,
+            notional: b.notional,
+            trades: b.trades,
+        }
+    }
+}
+
+impl From<RecordedCandle> for Bar {
+    fn from(c: RecordedCandle) -> Self {
+        Self {
+            start: c.start,
+            end: c.end,
+            open: c.open,
+            high: c.high,
+            low: c.low,
+            close: c.close,
+            volume: c.volume,
+            notional: c.notional,
+            trades: c.trades,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedData {
+    L1(L1BookSnapshot),
+    L2(L2BookUpdate),
+    Trade(RecordedTrade),
+    /// a finished candle; partials aren't recorded
+    Candle(RecordedCandle),
+    /// the stream was down for `downtime_ms` and was just restored;
+    /// messages may have been missed
+    Gap {
+        downtime_ms: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// when the recorder received the message
+    pub recv_time: DateTime<Utc>,
+    pub market: MarketId,
+    pub data: RecordedData,
+}
+
+/// An append only JSON lines file of records
+pub struct RecordingWriter {
+    out: BufWriter<File>,
+    len: u64,
+}
+
+impl RecordingWriter {
+    /// Open the recording at `path` for appending, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self { out: BufWriter::new(file), len })
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<()> {
+        let line = serde_json::to_vec(record)?;
+        self.out.write_all(&line)?;
+        self.out.write_all(b"\n")?;
+        self.len += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// The size of the recording, including any buffered records
+    pub fn bytes_written(&self) -> u64 {
+        self.len
+    }
+
+    /// Records are buffered; flush periodically and before dropping
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.out.flush()?)
+    }
+}
+
+impl Drop for RecordingWriter {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+/// The writer of the open segment
+enum SegmentWriter {
+    Lines(RecordingWriter),
+    #[cfg(feature = "netidx")]
+    Batched(super::batched_recording::BatchedRecordingWriter),
+}
+
+impl SegmentWriter {
+    fn write(&mut self, record: &Record) -> Result<()> {
+        match self {
+            Self::Lines(w) => w.write(record),
+            #[cfg(feature = "netidx")]
+            Self::Batched(w) => w.write(record),
+        }
+    }
+
+    fn bytes_written(&self) -> u64 {
+        match self {
+            Self::Lines(w) => w.bytes_written(),
+            #[cfg(feature = "netidx")]
+            Self::Batched(w) => w.bytes_written(),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::Lines(w) => w.flush(),
+            #[cfg(feature = "netidx")]
+            Self::Batched(w) => w.flush_due(Utc::now()),
+        }
+    }
+
+    fn roll(&mut self, path: PathBuf) -> Result<()> {
+        match self {
+            Self::Lines(w) => {
+                w.flush()?;
+                *w = RecordingWriter::open(path)?;
+            }
+            #[cfg(feature = "netidx")]
+            Self::Batched(w) => w.roll(path)?,
+        }
+        Ok(())
+    }
+}
+
+/// A recording that rolls to a new segment in a `SegmentDir` according to
+/// a `RollPolicy`
+pub struct RollingRecordingWriter {
+    dir: SegmentDir,
+    roll: RollPolicy,
+    current: SegmentWriter,
+    started: DateTime<Utc>,
+    compress: Option<i32>,
+}
+
+impl RollingRecordingWriter {
+    pub fn new(dir: SegmentDir, roll: RollPolicy) -> Result<Self> {
+        let started = Utc::now();
+        let current =
+            SegmentWriter::Lines(RecordingWriter::open(dir.segment_path(started))?);
+        Ok(Self { dir, roll, current, started, compress: None })
+    }
+
+    /// Compress closed JSON lines segments with zstd at `level` as they are
+    /// rolled; batched segments are already compressed
+    #[cfg(feature = "netidx")]
+    pub fn with_compression(mut self, level: i32) -> Self {
+        self.compress = Some(level);
+        self
+    }
+
+    /// Write segments in the `batched_recording` format
+    #[cfg(feature = "netidx")]
+    pub fn with_batching(
+        mut self,
+        config: super::batched_recording::BatchConfig,
+    ) -> Result<Self> {
+        use super::batched_recording::BatchedRecordingWriter;
+        if self.current.bytes_written() > 0 {
+            anyhow::bail!("batching must be set before anything is written");
+        }
+        let path = self.dir.segment_path(self.started);
+        self.current =
+            SegmentWriter::Batched(BatchedRecordingWriter::open(path, config)?);
+        Ok(self)
+    }
+
+    pub fn segments(&self) -> &SegmentDir {
+        &self.dir
+    }
+
+    /// Start a new segment if the current one is due, returning the path of
+    /// the segment that was closed.  Called by `write`.
+    pub fn roll_if_due(&mut self) -> Result<Option<PathBuf>> {
+        let now = Utc::now();
+        if !self.roll.due(self.current.bytes_written(), self.started, now) {
+            return Ok(None);
+        }
+        let closed = self.dir.segment_path(self.started);
+        self.current.roll(self.dir.segment_path(now))?;
+        self.started = now;
+        #[cfg(feature = "netidx")]
+        if let (Some(level), SegmentWriter::Lines(_)) = (self.compress, &self.current) {
+            if let Err(e) = self.dir.compact(level) {
+                error!("compressing recording segments: {e:?}");
+            }
+        }
+        Ok(Some(closed))
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<()> {
+        self.roll_if_due()?;
+        self.current.write(record)
+    }
+
+    /// Flush buffered records; batched segments write only the batches due
+    /// per their `FlushPolicy`, and the rest when dropped
+    pub fn flush(&mut self) -> Result<()> {
+        self.current.flush()
+    }
+}
+
+type Records = Box<dyn Iterator<Item = Result<Record>> + Send>;
+
+/// The records of one segment, JSON lines or batched
+fn open_records(path: &Path) -> Result<Records> {
+    let mut rd = BufReader::new(open_segment(path)?);
+    if rd.fill_buf()?.starts_with(BATCHED_MAGIC) {
+        #[cfg(feature = "netidx")]
+        return Ok(Box::new(super::batched_recording::BatchedRecordingReader::new(rd)?));
+        #[cfg(not(feature = "netidx"))]
+        anyhow::bail!(
+            "reading batched recording {} requires the netidx feature",
+            path.display()
+        );
+    }
+    Ok(Box::new(rd.lines().filter_map(|line| match line {
+        Err(e) => Some(Err(e.into())),
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => {
+            Some(serde_json::from_str(&line).map_err(|e| anyhow!("invalid record: {e}")))
+        }
+    })))
+}
+
+/// Records in the order written, from one segment or a whole `SegmentDir`
+pub struct RecordingReader {
+    pending: Vec<PathBuf>,
+    records: Option<Records>,
+}
+
+impl RecordingReader {
+    /// Read one recording or segment, compacted, batched, or not
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self { pending: vec![], records: Some(open_records(path.as_ref())?) })
+    }
+
+    /// Read every segment in `dir`, oldest first
+    pub fn open_dir(dir: &SegmentDir) -> Result<Self> {
+        let mut pending: Vec<PathBuf> =
+            dir.segments()?.into_iter().map(|s| s.path).collect();
+        pending.reverse();
+        Ok(Self { pending, records: None })
+    }
+}
+
+impl Iterator for RecordingReader {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let records = match &mut self.records {
+                Some(records) => records,
+                None => {
+                    let path = self.pending.pop()?;
+                    match open_records(&path) {
+                        Ok(records) => self.records.insert(records),
+                        Err(e) => {
+                            return Some(Err(
+                                e.context(format!("opening {}", path.display()))
+                            ))
+                        }
+                    }
+                }
+            };
+            match records.next() {
+                None => self.records = None,
+                Some(res) => return Some(res),
+            }
+        }
+    }
+}
+
+/// Feed a recording to a channel as if it were live.  With `speed`, each
+/// record is delayed by the time between its receive time and the
+/// previous record's, divided by `speed`, e.g. 1.0 for real time and 10.0
+/// for ten times faster; without it records are sent as fast as they are
+/// consumed.  Ends at the end of the recording, on the first unreadable
+/// record, or when the receiver is dropped.
+pub fn replay(reader: RecordingReader, speed: Option<f64>) -> mpsc::Receiver<Record> {
+    let (tx, rx) = mpsc::channel(1000);
+    tokio::task::spawn_blocking(move || {
+        let mut last: Option<DateTime<Utc>> = None;
+        for record in reader {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    error!("replaying recording: {e:?}");
+                    break;
+                }
+            };
+            if let (Some(speed), Some(last)) = (speed, last) {
+                let wait = (record.recv_time - last).to_std().unwrap_or_default();
+                std::thread::sleep(wait.div_f64(speed));
+            }
+            last = Some(record.recv_time);
+            if tx.blocking_send(record).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Records marketdata streams to a `RollingRecordingWriter`.  Each
+/// `record_*` call spawns a task that runs until its stream ends; the
+/// writer flushes every second and closes once the recorder and every
+/// recording task are dropped.
+#[derive(Clone)]
+pub struct Recorder {
+    tx: mpsc::Sender<Record>,
+}
+
+impl Recorder {
+    pub fn new(mut writer: RollingRecordingWriter) -> Self {
+        let (tx, mut rx) = mpsc::channel::<Record>(1000);
+        tokio::task::spawn(async move {
+            let mut flush = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                let res = tokio::select! {
+                    record = rx.recv() => match record {
+                        Some(record) => writer.write(&record),
+                        None => break,
+                    },
+                    _ = flush.tick() => writer.flush(),
+                };
+                if let Err(e) = res {
+                    error!("writing marketdata recording: {e:?}");
+                }
+            }
+            if let Err(e) = writer.flush() {
+                error!("flushing marketdata recording: {e:?}");
+            }
+            info!("marketdata recorder stopped");
+        });
+        Self { tx }
+    }
+
+    /// Record a stream of `market`'s data
+    pub fn record<S>(&self, market: MarketId, data: S) -> JoinHandle<()>
+    where
+        S: Stream<Item = RecordedData> + Send + 'static,
+    {
+        let tx = self.tx.clone();
+        tokio::task::spawn(async move {
+            let mut data = Box::pin(data);
+            while let Some(data) = data.next().await {
+                let record = Record { recv_time: Utc::now(), market, data };
+                if tx.send(record).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Record timestamped trades, e.g. the input of `build_candles`
+    pub fn record_trades<S>(&self, market: MarketId, trades: S) -> JoinHandle<()>
+    where
+        S: Stream<Item = (DateTime<Utc>, Print)> + Send + 'static,
+    {
+        self.record(
+            market,
+            trades.map(|(ts, print)| RecordedData::Trade(RecordedTrade::new(ts, &print))),
+        )
+    }
+
+    /// Record the finished candles from e.g. `build_candles`
+    pub fn record_candles(
+        &self,
+        market: MarketId,
+        mut candles: mpsc::Receiver<CandleEvent>,
+    ) -> JoinHandle<()> {
+        let candles = async_stream::stream! {
+            while let Some(ev) = candles.recv().await {
+                if let CandleEvent::Final(bar) = ev {
+                    yield RecordedData::Candle(bar.into());
+                }
+            }
+        };
+        self.record(market, candles)
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl Recorder {
+    /// Subscribe to and record L1 book snapshots of `market_ids`
+    pub fn record_l1(
+        &self,
+        client: &crate::ArchitectClient,
+        endpoint: impl AsRef<str>,
+        market_ids: Vec<MarketId>,
+    ) -> JoinHandle<()> {
+        use crate::client::StreamEvent;
+        let mut stream = client
+            .subscribe_l1_book_snapshots_resilient(endpoint, Some(market_ids.clone()));
+        let tx = self.tx.clone();
+        tokio::task::spawn(async move {
+            while let Some(ev) = stream.next().await {
+                let recv_time = Utc::now();
+                let records = match ev {
+                    StreamEvent::Data(snap) => vec![Record {
+                        recv_time,
+                        market: snap.market_id,
+                        data: RecordedData::L1(snap),
+                    }],
+                    StreamEvent::Gap { downtime } => market_ids
+                        .iter()
+                        .map(|market| Record {
+                            recv_time,
+                            market: *market,
+                            data: RecordedData::Gap {
+                                downtime_ms: downtime.as_millis() as u64,
+                            },
+                        })
+                        .collect(),
+                };
+                for record in records {
+                    if tx.send(record).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Subscribe to and record `market_id`'s L2 book updates; each
+    /// resubscription after a gap starts with a snapshot
+    pub fn record_l2(
+        &self,
+        client: &crate::ArchitectClient,
+        endpoint: impl AsRef<str>,
+        market_id: MarketId,
+    ) -> JoinHandle<()> {
+        use crate::client::StreamEvent;
+        let stream = client.subscribe_l2_book_updates_resilient(endpoint, market_id);
+        self.record(
+            market_id,
+            stream.map(|ev| match ev {
+                StreamEvent::Data(update) => RecordedData::L2(update),
+                StreamEvent::Gap { downtime } => {
+                    RecordedData::Gap { downtime_ms: downtime.as_millis() as u64 }
+                }
+            }),
+        )
+    }
+}
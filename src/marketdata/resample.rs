@@ -0,0 +1,274 @@
+//! Resample trades and bars into bars, for research pipelines that would
+//! otherwise round trip through a dataframe library just to change bar
+//! width.
+//!
+//! - time bars, aligned to the venue session open (or midnight UTC)
+//! - tick bars, every N trades
+//! - volume bars, closing once the traded size reaches a threshold
+//! - dollar bars, closing once the traded notional reaches a threshold
+//!
+//! Bars never span a session boundary; a bar still open at the session
+//! close is closed there.  Empty intervals produce no bar.
+//!
+//! Trades are `time_and_sales::Print`s.  Candles from other sources can be
+//! converted to `Bar` and resampled into wider bars; volume and dollar bars
+//! built from bars can only close on an input bar boundary.
+
+use super::time_and_sales::Print;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, FixedOffset, NaiveTime, TimeZone, Utc};
+use rust_decimal::Decimal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bar {
+    /// inclusive
+    pub start: DateTime<Utc>,
+    /// exclusive for time bars, the last trade's time otherwise
+    pub end: DateTime<Utc>,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    /// sum of price * size
+    pub notional: Decimal,
+    pub trades: u64,
+}
+
+impl Bar {
+    fn from_print(timestamp: DateTime<Utc>, print: &Print) -> Self {
+        Self {
+            start: timestamp,
+            end: timestamp,
+            open: print.price,
+            high: print.price,
+            low: print.price,
+            close: print.price,
+            volume: print.size,
+            notional: print.price * print.size,
+            trades: 1,
+        }
+    }
+
+    fn merge(&mut self, other: &Bar) {
+        self.end = self.end.max(other.end);
+        self.high = self.high.max(other.high);
+        self.low = self.low.min(other.low);
+        self.close = other.close;
+        self.volume += other.volume;
+        self.notional += other.notional;
+        self.trades += other.trades;
+    }
+
+    pub fn vwap(&self) -> Option<Decimal> {
+        (!self.volume.is_zero()).then(|| self.notional / self.volume)
+    }
+}
+
+/// A daily trading session.  The offset is fixed, so sessions in a
+/// timezone with daylight saving need a new `Session` at each change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    pub offset: FixedOffset,
+    /// local time of the session open
+    pub open: NaiveTime,
+    pub length: Duration,
+}
+
+impl Session {
+    /// Midnight to midnight UTC
+    pub fn utc_day() -> Self {
+        Self {
+            offset: FixedOffset::east_opt(0).unwrap(),
+            open: NaiveTime::MIN,
+            length: Duration::days(1),
+        }
+    }
+
+    /// The start of the session containing `t`, or None if `t` falls
+    /// between the close and the next open
+    pub fn start_of(&self, t: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local = t.with_timezone(&self.offset);
+        let today = self
+            .offset
+            .from_local_datetime(&local.date_naive().and_time(self.open))
+            .single()?
+            .with_timezone(&Utc);
+        let start = if today <= t { today } else { today - Duration::days(1) };
+        (t < start + self.length).then_some(start)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarSpec {
+    Time(Duration),
+    Ticks(u64),
+    Volume(Decimal),
+    Dollar(Decimal),
+}
+
+pub struct Resampler {
+    spec: BarSpec,
+    session: Session,
+    current: Option<Bar>,
+    /// the end of the current time bar, or the session close
+    closes_at: Option<DateTime<Utc>>,
+}
+
+impl Resampler {
+    pub fn new(spec: BarSpec, session: Session) -> Result<Self> {
+        let valid = match spec {
+            BarSpec::Time(d) => d > Duration::zero(),
+            BarSpec::Ticks(n) => n > 0,
+            BarSpec::Volume(v) | BarSpec::Dollar(v) => v > Decimal::ZERO,
+        };
+        if !valid {
+            bail!("invalid bar spec {spec:?}");
+        }
+        Ok(Self { spec, session, current: None, closes_at: None })
+    }
+
+    /// Add a trade, returning the bars it completed.  Trades outside the
+    /// session are dropped.
+    pub fn push_trade(&mut self, timestamp: DateTime<Utc>, print: &Print) -> Vec<Bar> {
+        self.push(Bar::from_print(timestamp, print))
+    }
+
+    /// Add a narrower bar, returning the bars it completed
+    pub fn push_bar(&mut self, bar: &Bar) -> Vec<Bar> {
+        self.push(*bar)
+    }
+
+    /// Close and return the bar in progress, e.g. at the end of the data
+    pub fn flush(&mut self) -> Option<Bar> {
+        self.closes_at = None;
+        self.current.take()
+    }
+
+    fn push(&mut self, bar: Bar) -> Vec<Bar> {
+        let mut res = vec![];
+        if let Some(closes_at) = self.closes_at {
+            if bar.start >= closes_at {
+                res.extend(self.flush());
+            }
+        }
+        let Some(session_start) = self.session.start_of(bar.start) else {
+            return res;
+        };
+        match &mut self.current {
+            Some(cur) => cur.merge(&bar),
+            None => {
+                let session_end = session_start + self.session.length;
+                let (start, closes_at) = match self.spec {
+                    BarSpec::Time(width) => {
+                        let n = (bar.start - session_start).num_milliseconds()
+                            / width.num_milliseconds();
+                        let start = session_start
+                            + Duration::milliseconds(width.num_milliseconds() * n);
+                        (start, (start + width).min(session_end))
+                    }
+                    _ => (bar.start, session_end),
+                };
+                self.current = Some(Bar { start, ..bar });
+                self.closes_at = Some(closes_at);
+            }
+        }
+        let full = match (self.spec, &self.current) {
+            (BarSpec::Ticks(n), Some(cur)) => cur.trades >= n,
+            (BarSpec::Volume(v), Some(cur)) => cur.volume >= v,
+            (BarSpec::Dollar(v), Some(cur)) => cur.notional >= v,
+            _ => false,
+        };
+        if full {
+            res.extend(self.flush());
+        }
+        if let (BarSpec::Time(_), Some(cur)) = (self.spec, &mut self.current) {
+            cur.end = self.closes_at.unwrap_or(cur.end);
+        }
+        res
+    }
+}
+
+/// Resample a time ordered series of trades
+pub fn resample_trades<'a>(
+    trades: impl IntoIterator<Item = &'a (DateTime<Utc>, Print)>,
+    spec: BarSpec,
+    session: Session,
+) -> Result<Vec<Bar>> {
+    let mut r = Resampler::new(spec, session)?;
+    let mut res = vec![];
+    for (ts, print) in trades {
+        res.extend(r.push_trade(*ts, print));
+    }
+    res.extend(r.flush());
+    Ok(res)
+}
+
+/// Resample a time ordered series of bars into wider ones
+pub fn resample_bars<'a>(
+    bars: impl IntoIterator<Item = &'a Bar>,
+    spec: BarSpec,
+    session: Session,
+) -> Result<Vec<Bar>> {
+    let mut r = Resampler::new(spec, session)?;
+    let mut res = vec![];
+    for bar in bars {
+        res.extend(r.push_bar(bar));
+    }
+    res.extend(r.flush());
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn print(price: Decimal, size: Decimal) -> Print {
+        Print { price, size, dir: None }
+    }
+
+    #[test]
+    fn test_time_bars_align_to_session() -> Result<()> {
+        // a session opening at 17:00 at UTC-5, i.e. 22:00 UTC
+        let session = Session {
+            offset: FixedOffset::west_opt(5 * 3600).unwrap(),
+            open: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+            length: Duration::hours(23),
+        };
+        let t = |h, m| Utc.with_ymd_and_hms(2024, 3, 5, h, m, 0).unwrap();
+        let trades = vec![
+            // in the maintenance break, dropped
+            (t(21, 30), print(dec!(99), dec!(5))),
+            (t(22, 10), print(dec!(100), dec!(1))),
+            (t(22, 50), print(dec!(102), dec!(2))),
+            (t(23, 5), print(dec!(101), dec!(1))),
+        ];
+        let bars = resample_trades(&trades, BarSpec::Time(Duration::hours(1)), session)?;
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].start, t(22, 0));
+        assert_eq!(bars[0].end, t(23, 0));
+        assert_eq!(bars[0].high, dec!(102));
+        assert_eq!(bars[0].close, dec!(102));
+        assert_eq!(bars[0].volume, dec!(3));
+        assert_eq!(bars[1].start, t(23, 0));
+        assert_eq!(bars[1].trades, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_volume_bars() -> Result<()> {
+        let t0 = Utc.with_ymd_and_hms(2024, 3, 5, 12, 0, 0).unwrap();
+        let trades: Vec<_> = (0..5)
+            .map(|i| (t0 + Duration::seconds(i), print(dec!(10), dec!(2))))
+            .collect();
+        let bars =
+            resample_trades(&trades, BarSpec::Volume(dec!(4)), Session::utc_day())?;
+        assert_eq!(bars.len(), 3);
+        assert_eq!(bars[0].volume, dec!(4));
+        assert_eq!(bars[0].end, t0 + Duration::seconds(1));
+        assert_eq!(bars[2].volume, dec!(2));
+        assert_eq!(bars[1].vwap(), Some(dec!(10)));
+        Ok(())
+    }
+}
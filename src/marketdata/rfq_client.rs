@@ -1,18 +1,38 @@
 use crate::{
+    rpc::{Rpc, RpcClient},
     symbology::{Cpty, RouteRef, VenueRef},
-    Common,
+    throttled_error, Common,
 };
 use anyhow::Result;
 use api::marketdata::*;
 use futures::channel::mpsc;
 use futures_util::StreamExt;
 use fxhash::FxHashMap;
-use log::error;
 use netidx::{
     pool::Pooled,
     subscriber::{Dval, Event, SubId, UpdatesFlags, Value},
 };
-use netidx_protocols::{call_rpc, rpc::client::Proc};
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The `subscribe-rfq` proc of a marketdata cpty; replies with the id of
+/// the RFQ response stream
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SubscribeRfq(pub RfqRequest);
+
+impl Rpc for SubscribeRfq {
+    const NAME: &'static str = "subscribe-rfq";
+    type Response = Value;
+
+    fn args(self) -> Result<Vec<(&'static str, Value)>> {
+        let SubscribeRfq(rfq) = self;
+        Ok(vec![
+            ("base", format!("\"{}\"", rfq.base).into()),
+            ("quote", format!("\"{}\"", rfq.quote).into()),
+            ("quantity", rfq.quantity.into()),
+        ])
+    }
+}
 
 pub struct RfqClient {
     common: Common,
@@ -52,7 +72,9 @@ impl RfqClient {
                             value.to_string_naked().as_str(),
                         ) {
                             Ok(r) => res.push(r),
-                            Err(e) => error!("failed to parse RFQ response: {}", e),
+                            Err(e) => {
+                                throttled_error!("failed to parse RFQ response: {}", e)
+                            }
                         }
                     }
                 }
@@ -80,18 +102,12 @@ impl RfqClient {
     ) -> Result<()> {
         if !self.index.contains_key(&(venue, route, rfq)) || !reuse_existing {
             let cpty = Cpty { venue, route };
-            let api_path = self.common.paths.marketdata_api(cpty).append("subscribe-rfq");
-            let proc = Proc::new_with_timeout(
+            let res = RpcClient::new(
                 &self.common.subscriber,
-                api_path,
-                std::time::Duration::from_secs(2),
-            )?;
-            let res = call_rpc!(
-                proc,
-                base: format!("\"{}\"", rfq.base),
-                quote: format!("\"{}\"", rfq.quote),
-                quantity: rfq.quantity
+                self.common.paths.marketdata_api(cpty),
             )
+            .with_timeout(Duration::from_secs(2))
+            .call(SubscribeRfq(rfq))
             .await?;
             let uuid = res.to_string_naked();
             let rfq_path = self.common.paths.marketdata_rfq(cpty).append(uuid.as_str());
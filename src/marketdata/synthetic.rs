@@ -0,0 +1,263 @@
+//! Synthetic cross books composed from two markets that share a product,
+//! e.g. BTC/EUR from BTC/USD and EUR/USD.
+//!
+//! Each leg is oriented so the shared product cancels (an inverted leg has
+//! its sides swapped and its prices reciprocated), then the two ladders are
+//! walked together: a synthetic level is as much size as can go through
+//! both legs at their current levels, at the product of their prices.  The
+//! result is real executable depth, not just a crossed top of book.
+//!
+//! Register synthetics in a `SyntheticBooks`, feed it the leg books from
+//! whatever marketdata source you are driving, and subscribe to synthetic
+//! names the same way as to native book clients.
+
+use super::{level_book::LevelBook, published::Published};
+use crate::{
+    prices::TickSize,
+    symbology::MarketRef,
+    synced::{SyncHandle, Synced},
+};
+use anyhow::{anyhow, bail, Result};
+use api::Dir;
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leg {
+    pub market: MarketRef,
+    /// use the reciprocal of the market, quote/base
+    pub invert: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntheticSpec {
+    pub name: String,
+    /// the first leg's (oriented) quote is the second leg's (oriented) base
+    pub legs: [Leg; 2],
+    /// round synthetic prices passively to this tick, bids down, asks up
+    pub tick: Option<TickSize>,
+}
+
+impl SyntheticSpec {
+    /// Compose `a` and `b`, which must share exactly one product; the
+    /// synthetic is the non shared product of `a` priced in the non shared
+    /// product of `b`.
+    pub fn cross(a: MarketRef, b: MarketRef) -> Result<Self> {
+        let products = |m: MarketRef| {
+            Ok::<_, anyhow::Error>((
+                m.base().ok_or_else(|| anyhow!("{m} has no base product"))?,
+                m.quote().ok_or_else(|| anyhow!("{m} has no quote product"))?,
+            ))
+        };
+        let (ab, aq) = products(a)?;
+        let (bb, bq) = products(b)?;
+        let (invert_a, shared) = if aq == bb || aq == bq {
+            (false, aq)
+        } else if ab == bb || ab == bq {
+            (true, ab)
+        } else {
+            bail!("{a} and {b} share no product");
+        };
+        let invert_b = bq == shared;
+        let base = if invert_a { aq } else { ab };
+        let quote = if invert_b { bb } else { bq };
+        if base == quote {
+            bail!("{a} and {b} compose to {base}/{quote}");
+        }
+        Ok(Self {
+            name: format!("{}/{} Synthetic", base.name, quote.name),
+            legs: [
+                Leg { market: a, invert: invert_a },
+                Leg { market: b, invert: invert_b },
+            ],
+            tick: None,
+        })
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn with_tick(mut self, tick: TickSize) -> Self {
+        self.tick = Some(tick);
+        self
+    }
+
+    /// Compose the synthetic book from the two leg books
+    pub fn compose(&self, a: &LevelBook, b: &LevelBook) -> LevelBook {
+        let a = orient(a, self.legs[0].invert);
+        let b = orient(b, self.legs[1].invert);
+        let mut res = LevelBook::default();
+        res.timestamp = a.timestamp.max(b.timestamp);
+        for dir in [Dir::Buy, Dir::Sell] {
+            let side = res.get_mut(dir);
+            for (price, size) in walk(a.levels(dir), b.levels(dir)) {
+                let price = match self.tick {
+                    Some(tick) => tick.round_passive(price, dir),
+                    None => price,
+                };
+                *side.entry(price).or_default() += size;
+            }
+        }
+        res
+    }
+}
+
+/// A book as (price, size) levels best first, size in units of the base
+struct Ladder {
+    buy: Vec<(Decimal, Decimal)>,
+    sell: Vec<(Decimal, Decimal)>,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl Ladder {
+    fn levels(&self, dir: Dir) -> &[(Decimal, Decimal)] {
+        match dir {
+            Dir::Buy => &self.buy,
+            Dir::Sell => &self.sell,
+        }
+    }
+}
+
+fn orient(book: &LevelBook, invert: bool) -> Ladder {
+    let side = |dir: Dir| -> Vec<(Decimal, Decimal)> {
+        book.iter_levels(dir).map(|(p, s)| (*p, *s)).collect()
+    };
+    if !invert {
+        return Ladder {
+            buy: side(Dir::Buy),
+            sell: side(Dir::Sell),
+            timestamp: book.timestamp,
+        };
+    }
+    // selling the inverse is buying the original: the inverse's bids are
+    // the original's asks, with the original notional as size
+    let inverse = |levels: Vec<(Decimal, Decimal)>| {
+        levels
+            .into_iter()
+            .filter(|(p, _)| !p.is_zero())
+            .map(|(p, s)| (Decimal::ONE / p, s * p))
+            .collect()
+    };
+    Ladder {
+        buy: inverse(side(Dir::Sell)),
+        sell: inverse(side(Dir::Buy)),
+        timestamp: book.timestamp,
+    }
+}
+
+/// Walk the first leg (sizes in synthetic base) and the second (sizes in
+/// the shared product) together, best first
+fn walk(a: &[(Decimal, Decimal)], b: &[(Decimal, Decimal)]) -> Vec<(Decimal, Decimal)> {
+    let mut res = vec![];
+    let (mut ia, mut ib) = (0, 0);
+    let mut left_a = a.first().map(|l| l.1).unwrap_or_default();
+    let mut left_b = b.first().map(|l| l.1).unwrap_or_default();
+    while ia < a.len() && ib < b.len() {
+        let (pa, pb) = (a[ia].0, b[ib].0);
+        // the size that fits through both levels, in synthetic base
+        let size = left_a.min(left_b / pa);
+        if size > Decimal::ZERO {
+            res.push((pa * pb, size));
+        }
+        left_a -= size;
+        left_b -= size * pa;
+        if left_a <= Decimal::ZERO {
+            ia += 1;
+            left_a = a.get(ia).map(|l| l.1).unwrap_or_default();
+        }
+        if left_b <= Decimal::ZERO {
+            ib += 1;
+            left_b = b.get(ib).map(|l| l.1).unwrap_or_default();
+        }
+    }
+    res
+}
+
+struct Registered {
+    spec: SyntheticSpec,
+    legs: [Option<LevelBook>; 2],
+    published: Published<LevelBook>,
+    sequence: u64,
+    updates: SyncHandle<u64>,
+}
+
+/// Synthetic books by name, recomputed as their legs update
+#[derive(Default)]
+pub struct SyntheticBooks {
+    by_name: FxHashMap<String, Registered>,
+    by_leg: FxHashMap<MarketRef, Vec<String>>,
+}
+
+impl SyntheticBooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, spec: SyntheticSpec) -> Result<()> {
+        if self.by_name.contains_key(&spec.name) {
+            bail!("synthetic {} is already registered", spec.name);
+        }
+        for leg in &spec.legs {
+            self.by_leg.entry(leg.market).or_default().push(spec.name.clone());
+        }
+        self.by_name.insert(
+            spec.name.clone(),
+            Registered {
+                spec,
+                legs: [None, None],
+                published: Published::default(),
+                sequence: 0,
+                updates: SyncHandle::new(0),
+            },
+        );
+        Ok(())
+    }
+
+    /// The markets whose books need to be fed to `on_book`
+    pub fn legs(&self) -> impl Iterator<Item = &MarketRef> {
+        self.by_leg.keys()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.by_name.keys().map(|s| s.as_str())
+    }
+
+    /// The latest synthetic book, and the number of updates so far, which
+    /// is zero until both legs have been seen
+    pub fn subscribe(&self, name: &str) -> Option<(Published<LevelBook>, Synced<u64>)> {
+        let r = self.by_name.get(name)?;
+        Some((r.published.clone(), r.updates.synced()))
+    }
+
+    /// Feed a leg book; returns the synthetics that were recomputed
+    pub fn on_book(&mut self, market: MarketRef, book: &LevelBook) -> Vec<String> {
+        let Some(names) = self.by_leg.get(&market) else { return vec![] };
+        let mut updated = vec![];
+        for name in names {
+            let Some(r) = self.by_name.get_mut(name) else { continue };
+            for (i, leg) in r.spec.legs.iter().enumerate() {
+                if leg.market == market {
+                    r.legs[i] = Some(book.clone());
+                }
+            }
+            if let [Some(a), Some(b)] = &r.legs {
+                r.published.store(r.spec.compose(a, b));
+                r.sequence += 1;
+                r.updates.set(r.sequence);
+                updated.push(name.clone());
+            }
+        }
+        updated
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        if self.by_name.remove(name).is_some() {
+            for names in self.by_leg.values_mut() {
+                names.retain(|n| n != name);
+            }
+            self.by_leg.retain(|_, names| !names.is_empty());
+        }
+    }
+}
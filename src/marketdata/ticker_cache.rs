@@ -0,0 +1,278 @@
+//! A cache of 24h ticker statistics for every market on a set of venues, for
+//! scanners and dashboards.
+//!
+//! `TickerCache` holds the latest ticker per market and indexes it by market
+//! name and exchange symbol; rankings (top movers, top volume) are computed
+//! on demand.  With the `netidx` feature, `watch_tickers` subscribes to the
+//! ticker fields of every market on the venues through the managed
+//! marketdata and keeps the cache current, picking up new listings every
+//! refresh.
+
+use crate::symbology::{MarketIndex, MarketRef};
+use chrono::{DateTime, Utc};
+use fxhash::{FxHashMap, FxHashSet};
+use rust_decimal::Decimal;
+#[cfg(feature = "netidx")]
+use {
+    super::managed_marketdata::{DvalHandle, ManagedMarketdata},
+    crate::throttled_warn,
+    parking_lot::RwLock,
+    std::{sync::Arc, time::Duration},
+    tokio::{
+        sync::{mpsc, Mutex},
+        task::{self, JoinHandle},
+    },
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ticker {
+    pub market: MarketRef,
+    pub last_price: Option<Decimal>,
+    pub open_24h: Option<Decimal>,
+    pub high_24h: Option<Decimal>,
+    pub low_24h: Option<Decimal>,
+    pub volume_24h: Option<Decimal>,
+    pub updated: DateTime<Utc>,
+}
+
+impl Ticker {
+    pub fn new(market: MarketRef) -> Self {
+        Self {
+            market,
+            last_price: None,
+            open_24h: None,
+            high_24h: None,
+            low_24h: None,
+            volume_24h: None,
+            updated: DateTime::<Utc>::MIN_UTC,
+        }
+    }
+
+    /// Percent change over 24h, from the open to the last price
+    pub fn change_pct(&self) -> Option<Decimal> {
+        let (last, open) = (self.last_price?, self.open_24h?);
+        (!open.is_zero()).then(|| (last - open) / open * Decimal::ONE_HUNDRED)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TickerField {
+    LastPrice,
+    Open24h,
+    High24h,
+    Low24h,
+    Volume24h,
+}
+
+impl TickerField {
+    pub const ALL: [TickerField; 5] = [
+        TickerField::LastPrice,
+        TickerField::Open24h,
+        TickerField::High24h,
+        TickerField::Low24h,
+        TickerField::Volume24h,
+    ];
+
+    /// The path leaf the field is published under
+    pub fn leaf(&self) -> &'static str {
+        match self {
+            TickerField::LastPrice => "last_price",
+            TickerField::Open24h => "open_24h",
+            TickerField::High24h => "high_24h",
+            TickerField::Low24h => "low_24h",
+            TickerField::Volume24h => "volume_24h",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TickerCache {
+    venues: Vec<String>,
+    tickers: FxHashMap<MarketRef, Ticker>,
+    /// market names and exchange symbols; an exchange symbol listed on
+    /// more than one venue maps to the first market seen
+    by_symbol: FxHashMap<String, MarketRef>,
+}
+
+impl TickerCache {
+    /// A cache of every market currently listed on the venues, by name
+    pub fn new<S: AsRef<str>>(venues: impl IntoIterator<Item = S>) -> Self {
+        let venues = venues.into_iter().map(|v| v.as_ref().to_string()).collect();
+        let mut t = Self { venues, ..Default::default() };
+        t.refresh_markets();
+        t
+    }
+
+    /// Re-read the markets on the venues from the global index; returns the
+    /// markets that were (added, removed)
+    pub fn refresh_markets(&mut self) -> (Vec<MarketRef>, Vec<MarketRef>) {
+        let all = MarketIndex::current().all();
+        let current: FxHashSet<MarketRef> = (&all)
+            .into_iter()
+            .filter(|m| self.venues.iter().any(|v| v == m.venue.name.as_str()))
+            .copied()
+            .collect();
+        let mut added = vec![];
+        for market in &current {
+            if !self.tickers.contains_key(market) {
+                self.tickers.insert(*market, Ticker::new(*market));
+                self.by_symbol.insert(market.name.to_string(), *market);
+                self.by_symbol
+                    .entry(market.exchange_symbol.to_string())
+                    .or_insert(*market);
+                added.push(*market);
+            }
+        }
+        let mut removed = vec![];
+        self.tickers.retain(|m, _| {
+            let keep = current.contains(m);
+            if !keep {
+                removed.push(*m);
+            }
+            keep
+        });
+        if !removed.is_empty() {
+            let tickers = &self.tickers;
+            self.by_symbol.retain(|_, m| tickers.contains_key(m));
+        }
+        (added, removed)
+    }
+
+    pub fn update(
+        &mut self,
+        market: MarketRef,
+        field: TickerField,
+        value: Option<Decimal>,
+        now: DateTime<Utc>,
+    ) {
+        let Some(t) = self.tickers.get_mut(&market) else { return };
+        let slot = match field {
+            TickerField::LastPrice => &mut t.last_price,
+            TickerField::Open24h => &mut t.open_24h,
+            TickerField::High24h => &mut t.high_24h,
+            TickerField::Low24h => &mut t.low_24h,
+            TickerField::Volume24h => &mut t.volume_24h,
+        };
+        *slot = value;
+        t.updated = now;
+    }
+
+    pub fn len(&self) -> usize {
+        self.tickers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tickers.is_empty()
+    }
+
+    pub fn get(&self, market: &MarketRef) -> Option<&Ticker> {
+        self.tickers.get(market)
+    }
+
+    /// Look up by market name or exchange symbol
+    pub fn by_symbol(&self, symbol: &str) -> Option<&Ticker> {
+        self.by_symbol.get(symbol).and_then(|m| self.tickers.get(m))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Ticker> {
+        self.tickers.values()
+    }
+
+    fn top_by(&self, n: usize, key: impl Fn(&Ticker) -> Option<Decimal>) -> Vec<Ticker> {
+        let mut res: Vec<(Decimal, &Ticker)> =
+            self.tickers.values().filter_map(|t| Some((key(t)?, t))).collect();
+        res.sort_by(|a, b| b.0.cmp(&a.0));
+        res.into_iter().take(n).map(|(_, t)| *t).collect()
+    }
+
+    /// The `n` markets with the largest 24h percent change
+    pub fn top_gainers(&self, n: usize) -> Vec<Ticker> {
+        self.top_by(n, |t| t.change_pct())
+    }
+
+    /// The `n` markets with the most negative 24h percent change
+    pub fn top_losers(&self, n: usize) -> Vec<Ticker> {
+        self.top_by(n, |t| t.change_pct().map(|c| -c))
+    }
+
+    /// The `n` markets with the most 24h volume, in the quote currency at
+    /// the last price
+    pub fn top_volume(&self, n: usize) -> Vec<Ticker> {
+        self.top_by(n, |t| Some(t.volume_24h? * t.last_price?))
+    }
+}
+
+/// Keep a ticker cache for the venues current through the managed
+/// marketdata, refreshing the market set every `refresh`
+#[cfg(feature = "netidx")]
+pub fn watch_tickers<S: AsRef<str>>(
+    marketdata: Arc<ManagedMarketdata>,
+    venues: impl IntoIterator<Item = S>,
+    refresh: Duration,
+    delayed: bool,
+) -> (Arc<RwLock<TickerCache>>, JoinHandle<()>) {
+    let cache = Arc::new(RwLock::new(TickerCache::new(venues)));
+    let task = {
+        let cache = cache.clone();
+        task::spawn(async move {
+            let (tx, mut rx) = mpsc::unbounded_channel::<(MarketRef, TickerField)>();
+            let mut handles: FxHashMap<
+                (MarketRef, TickerField),
+                (Arc<Mutex<DvalHandle>>, JoinHandle<()>),
+            > = FxHashMap::default();
+            let mut pending: Vec<MarketRef> =
+                cache.read().iter().map(|t| t.market).collect();
+            let mut refresh = tokio::time::interval(refresh);
+            loop {
+                for market in pending.drain(..) {
+                    for field in TickerField::ALL {
+                        let leaf = field.leaf().to_string();
+                        match marketdata.subscribe_path(market, leaf, delayed).await {
+                            Err(e) => throttled_warn!(
+                                "could not subscribe to {} for {market}: {e}",
+                                field.leaf()
+                            ),
+                            Ok((handle, mut synced)) => {
+                                let tx = tx.clone();
+                                let forward = task::spawn(async move {
+                                    while let Ok(()) = synced.changed().await {
+                                        if tx.send((market, field)).is_err() {
+                                            break;
+                                        }
+                                    }
+                                });
+                                handles.insert((market, field), (handle, forward));
+                            }
+                        }
+                    }
+                }
+                tokio::select! {
+                    _ = refresh.tick() => {
+                        let (added, removed) = cache.write().refresh_markets();
+                        handles.retain(|(m, _), (_, forward)| {
+                            let keep = !removed.contains(m);
+                            if !keep {
+                                forward.abort();
+                            }
+                            keep
+                        });
+                        pending.extend(added);
+                    }
+                    Some((market, field)) = rx.recv() => {
+                        let value = match handles.get(&(market, field)) {
+                            None => continue,
+                            Some((handle, _)) => handle
+                                .lock()
+                                .await
+                                .last_value
+                                .clone()
+                                .and_then(|v| v.cast_to::<Decimal>().ok()),
+                        };
+                        cache.write().update(market, field, value, Utc::now());
+                    }
+                }
+            }
+        })
+    };
+    (cache, task)
+}
@@ -0,0 +1,216 @@
+//! Rolling buffers of recent trades and top of book changes per market, for
+//! time and sales displays, heatmaps, and short horizon signals.
+//!
+//! Each buffer retains at most `max_age` of history and at most `max_len`
+//! entries, whichever is smaller, so memory use is bounded regardless of
+//! how busy a market gets.
+
+use crate::symbology::MarketRef;
+use api::Dir;
+use chrono::{DateTime, Duration, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use std::collections::{vec_deque, VecDeque};
+
+/// A time ordered ring buffer with age and length caps
+#[derive(Debug, Clone)]
+pub struct TimeBuffer<T> {
+    max_age: Duration,
+    max_len: usize,
+    items: VecDeque<(DateTime<Utc>, T)>,
+}
+
+impl<T> TimeBuffer<T> {
+    pub fn new(max_age: Duration, max_len: usize) -> Self {
+        Self { max_age, max_len, items: VecDeque::new() }
+    }
+
+    /// Push an item; timestamps earlier than the last pushed item are
+    /// clamped to it so the buffer stays ordered.
+    pub fn push(&mut self, timestamp: DateTime<Utc>, item: T) {
+        let timestamp = match self.items.back() {
+            Some((last, _)) if *last > timestamp => *last,
+            _ => timestamp,
+        };
+        self.items.push_back((timestamp, item));
+        while self.items.len() > self.max_len {
+            self.items.pop_front();
+        }
+        self.expire(timestamp);
+    }
+
+    /// Drop items older than `max_age` as of `now`
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - self.max_age;
+        while let Some((ts, _)) = self.items.front() {
+            if *ts < cutoff {
+                self.items.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Items with timestamps in [from, to)
+    pub fn range(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> vec_deque::Range<'_, (DateTime<Utc>, T)> {
+        let start = self.items.partition_point(|(ts, _)| *ts < from);
+        let end = self.items.partition_point(|(ts, _)| *ts < to).max(start);
+        self.items.range(start..end)
+    }
+
+    /// Items no older than `age` relative to the newest item
+    pub fn last(&self, age: Duration) -> vec_deque::Range<'_, (DateTime<Utc>, T)> {
+        match self.items.back() {
+            None => self.items.range(0..0),
+            Some((newest, _)) => {
+                let start = self.items.partition_point(|(ts, _)| *ts < *newest - age);
+                self.items.range(start..)
+            }
+        }
+    }
+
+    pub fn latest(&self) -> Option<&(DateTime<Utc>, T)> {
+        self.items.back()
+    }
+
+    pub fn iter(&self) -> vec_deque::Iter<'_, (DateTime<Utc>, T)> {
+        self.items.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Print {
+    pub price: Decimal,
+    pub size: Decimal,
+    /// aggressor side, if known
+    pub dir: Option<Dir>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TopOfBook {
+    /// best bid (price, size)
+    pub bid: Option<(Decimal, Decimal)>,
+    /// best ask (price, size)
+    pub ask: Option<(Decimal, Decimal)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BufferLimits {
+    pub max_age: Duration,
+    pub max_prints: usize,
+    pub max_quotes: usize,
+}
+
+impl Default for BufferLimits {
+    fn default() -> Self {
+        Self { max_age: Duration::minutes(5), max_prints: 100_000, max_quotes: 100_000 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MarketTape {
+    pub prints: TimeBuffer<Print>,
+    pub quotes: TimeBuffer<TopOfBook>,
+}
+
+impl MarketTape {
+    /// Volume weighted average price of prints in [from, to)
+    pub fn vwap(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Option<Decimal> {
+        let (notional, volume) = self
+            .prints
+            .range(from, to)
+            .fold((Decimal::ZERO, Decimal::ZERO), |(n, v), (_, p)| {
+                (n + p.price * p.size, v + p.size)
+            });
+        (!volume.is_zero()).then(|| notional / volume)
+    }
+
+    /// Total printed volume in [from, to), split by aggressor side; prints
+    /// without a known side are counted in neither
+    pub fn volume_by_side(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> (Decimal, Decimal) {
+        self.prints.range(from, to).fold(
+            (Decimal::ZERO, Decimal::ZERO),
+            |(buy, sell), (_, p)| match p.dir {
+                Some(Dir::Buy) => (buy + p.size, sell),
+                Some(Dir::Sell) => (buy, sell + p.size),
+                None => (buy, sell),
+            },
+        )
+    }
+}
+
+/// Time and sales buffers for a set of markets
+#[derive(Debug, Clone, Default)]
+pub struct TimeAndSales {
+    limits: BufferLimits,
+    tapes: FxHashMap<MarketRef, MarketTape>,
+}
+
+impl TimeAndSales {
+    pub fn new(limits: BufferLimits) -> Self {
+        Self { limits, tapes: FxHashMap::default() }
+    }
+
+    fn tape_mut(&mut self, market: MarketRef) -> &mut MarketTape {
+        let limits = &self.limits;
+        self.tapes.entry(market).or_insert_with(|| MarketTape {
+            prints: TimeBuffer::new(limits.max_age, limits.max_prints),
+            quotes: TimeBuffer::new(limits.max_age, limits.max_quotes),
+        })
+    }
+
+    pub fn record_print(
+        &mut self,
+        market: MarketRef,
+        timestamp: DateTime<Utc>,
+        print: Print,
+    ) {
+        self.tape_mut(market).prints.push(timestamp, print)
+    }
+
+    /// Record the top of book; unchanged tops are not stored
+    pub fn record_top(
+        &mut self,
+        market: MarketRef,
+        timestamp: DateTime<Utc>,
+        top: TopOfBook,
+    ) {
+        let tape = self.tape_mut(market);
+        if tape.quotes.latest().map(|(_, t)| *t != top).unwrap_or(true) {
+            tape.quotes.push(timestamp, top)
+        }
+    }
+
+    pub fn get(&self, market: &MarketRef) -> Option<&MarketTape> {
+        self.tapes.get(market)
+    }
+
+    /// Expire old entries in all markets, and drop markets that are empty
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        self.tapes.retain(|_, tape| {
+            tape.prints.expire(now);
+            tape.quotes.expire(now);
+            !tape.prints.is_empty() || !tape.quotes.is_empty()
+        })
+    }
+
+    pub fn remove(&mut self, market: &MarketRef) {
+        self.tapes.remove(market);
+    }
+}
@@ -0,0 +1,229 @@
+//! Classify the aggressor of each trade against the prevailing BBO, and
+//! detect sweeps: bursts of same side trades walking through several price
+//! levels.
+//!
+//! Aggressor side uses the quote rule: at or through the ask is a buy, at
+//! or through the bid is a sell, inside the spread the trade is compared
+//! to the mid, and trades exactly at the mid (or with no BBO) fall back to
+//! the tick test against the previous trade price.
+
+use super::time_and_sales::{Print, TopOfBook};
+use crate::symbology::MarketRef;
+use api::Dir;
+use chrono::{DateTime, Duration, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassifyMethod {
+    /// reported by the venue
+    Reported,
+    /// compared to the BBO or mid
+    Quote,
+    /// compared to the previous trade price
+    Tick,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EnrichedTrade {
+    pub market: MarketRef,
+    pub timestamp: DateTime<Utc>,
+    /// the print with its aggressor side filled in, if it could be
+    pub print: Print,
+    pub method: ClassifyMethod,
+    /// the BBO prevailing when the trade printed
+    pub top: TopOfBook,
+    /// true if the trade is part of a sweep in progress
+    pub sweeping: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sweep {
+    pub market: MarketRef,
+    pub dir: Dir,
+    pub levels: usize,
+    pub first_price: Decimal,
+    pub last_price: Decimal,
+    pub size: Decimal,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TradeEvent {
+    Trade(EnrichedTrade),
+    /// emitted once a sweep has ended
+    Sweep(Sweep),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SweepConfig {
+    /// trades further apart than this are not part of the same sweep
+    pub window: Duration,
+    /// minimum distinct price levels traded to count as a sweep
+    pub min_levels: usize,
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        Self { window: Duration::milliseconds(5), min_levels: 3 }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MarketState {
+    top: TopOfBook,
+    last_price: Option<Decimal>,
+    last_tick: Option<Dir>,
+    run: Option<Sweep>,
+}
+
+pub struct TradeClassifier {
+    config: SweepConfig,
+    markets: FxHashMap<MarketRef, MarketState>,
+    tx: broadcast::Sender<TradeEvent>,
+}
+
+impl TradeClassifier {
+    pub fn new(config: SweepConfig) -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self { config, markets: FxHashMap::default(), tx }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TradeEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Update the prevailing BBO for the market, e.g. from the L1 stream
+    pub fn on_top(&mut self, market: MarketRef, top: TopOfBook) {
+        self.markets.entry(market).or_default().top = top;
+    }
+
+    pub fn on_trade(
+        &mut self,
+        market: MarketRef,
+        timestamp: DateTime<Utc>,
+        print: Print,
+    ) -> EnrichedTrade {
+        let config = self.config;
+        let st = self.markets.entry(market).or_default();
+        let (dir, method) = match print.dir {
+            Some(dir) => (Some(dir), ClassifyMethod::Reported),
+            None => classify(&st.top, print.price, st.last_price, st.last_tick),
+        };
+        if let Some(last) = st.last_price {
+            if print.price > last {
+                st.last_tick = Some(Dir::Buy);
+            } else if print.price < last {
+                st.last_tick = Some(Dir::Sell);
+            }
+        }
+        st.last_price = Some(print.price);
+        let mut ended = None;
+        let continues = match (&st.run, dir) {
+            (Some(run), Some(dir)) => {
+                run.dir == dir && timestamp - run.end <= config.window && {
+                    // a sweep only walks away from the touch
+                    match dir {
+                        Dir::Buy => print.price >= run.last_price,
+                        Dir::Sell => print.price <= run.last_price,
+                    }
+                }
+            }
+            _ => false,
+        };
+        if continues {
+            let run = st.run.as_mut().unwrap();
+            if print.price != run.last_price {
+                run.levels += 1;
+            }
+            run.last_price = print.price;
+            run.size += print.size;
+            run.end = timestamp;
+        } else {
+            ended = st.run.take();
+            st.run = dir.map(|dir| Sweep {
+                market,
+                dir,
+                levels: 1,
+                first_price: print.price,
+                last_price: print.price,
+                size: print.size,
+                start: timestamp,
+                end: timestamp,
+            });
+        }
+        let sweeping = st.run.map(|r| r.levels >= config.min_levels).unwrap_or(false);
+        let trade = EnrichedTrade {
+            market,
+            timestamp,
+            print: Print { dir, ..print },
+            method,
+            top: st.top,
+            sweeping,
+        };
+        if let Some(run) = ended {
+            self.emit_sweep(run);
+        }
+        let _ = self.tx.send(TradeEvent::Trade(trade));
+        trade
+    }
+
+    /// End any sweeps that have been quiet for longer than the window
+    pub fn flush(&mut self, now: DateTime<Utc>) {
+        let window = self.config.window;
+        let mut ended = vec![];
+        for st in self.markets.values_mut() {
+            if st.run.map(|r| now - r.end > window).unwrap_or(false) {
+                ended.extend(st.run.take());
+            }
+        }
+        for run in ended {
+            self.emit_sweep(run);
+        }
+    }
+
+    fn emit_sweep(&self, run: Sweep) {
+        if run.levels >= self.config.min_levels {
+            let _ = self.tx.send(TradeEvent::Sweep(run));
+        }
+    }
+}
+
+fn classify(
+    top: &TopOfBook,
+    price: Decimal,
+    last_price: Option<Decimal>,
+    last_tick: Option<Dir>,
+) -> (Option<Dir>, ClassifyMethod) {
+    match (top.bid, top.ask) {
+        (_, Some((ask, _))) if price >= ask => {
+            return (Some(Dir::Buy), ClassifyMethod::Quote)
+        }
+        (Some((bid, _)), _) if price <= bid => {
+            return (Some(Dir::Sell), ClassifyMethod::Quote)
+        }
+        (Some((bid, _)), Some((ask, _))) => {
+            let mid = (bid + ask) / Decimal::TWO;
+            if price > mid {
+                return (Some(Dir::Buy), ClassifyMethod::Quote);
+            } else if price < mid {
+                return (Some(Dir::Sell), ClassifyMethod::Quote);
+            }
+        }
+        _ => (),
+    }
+    // tick test, zero ticks take the direction of the last nonzero tick
+    let tick = match last_price {
+        Some(last) if price > last => Some(Dir::Buy),
+        Some(last) if price < last => Some(Dir::Sell),
+        Some(_) => last_tick,
+        None => None,
+    };
+    match tick {
+        Some(dir) => (Some(dir), ClassifyMethod::Tick),
+        None => (None, ClassifyMethod::Unknown),
+    }
+}
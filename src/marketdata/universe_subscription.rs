@@ -0,0 +1,114 @@
+//! Keep managed marketdata book subscriptions in sync with a symbol universe
+
+use super::{book_client::BookClient, managed_marketdata::ManagedMarketdata};
+use crate::{
+    symbology::{
+        index::Set,
+        listings::ListingEvent,
+        universe::{Universe, UniverseDiff},
+        MarketRef,
+    },
+    synced::Synced,
+};
+use anyhow::Result;
+use fxhash::FxHashMap;
+use log::debug;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Book subscriptions for every market in a universe.  Subscriptions for
+/// markets that leave the universe are dropped, which unsubscribes them
+/// unless something else is holding the same book.
+pub struct UniverseSubscription {
+    marketdata: Arc<ManagedMarketdata>,
+    universe: Universe,
+    delayed: bool,
+    markets: Set<MarketRef>,
+    books: FxHashMap<MarketRef, (Arc<Mutex<BookClient>>, Synced<u64>)>,
+}
+
+impl UniverseSubscription {
+    pub async fn new(
+        marketdata: Arc<ManagedMarketdata>,
+        universe: Universe,
+        delayed: bool,
+    ) -> Result<Self> {
+        let mut t = Self {
+            marketdata,
+            universe,
+            delayed,
+            markets: Set::new(),
+            books: FxHashMap::default(),
+        };
+        t.refresh().await?;
+        Ok(t)
+    }
+
+    pub fn universe(&self) -> &Universe {
+        &self.universe
+    }
+
+    /// Replace the universe definition, applying the difference
+    pub async fn set_universe(&mut self, universe: Universe) -> Result<UniverseDiff> {
+        self.universe = universe;
+        self.refresh().await
+    }
+
+    /// Re-resolve the universe, e.g. after a symbology update or an edit to a
+    /// universe file, and subscribe/unsubscribe the difference.
+    pub async fn refresh(&mut self) -> Result<UniverseDiff> {
+        let markets = self.universe.resolve()?;
+        let diff = UniverseDiff::new(&self.markets, &markets);
+        for market in &diff.removed {
+            debug!("universe unsubscribing {market}");
+            self.books.remove(market);
+        }
+        for market in &diff.added {
+            debug!("universe subscribing {market}");
+            let sub = self.marketdata.subscribe(*market, self.delayed).await;
+            self.books.insert(*market, sub);
+        }
+        self.markets = markets;
+        Ok(diff)
+    }
+
+    /// Follow a listing change: a new listing in the universe or a delisting
+    /// of a subscribed market re-resolves the universe
+    pub async fn on_listing(
+        &mut self,
+        ev: &ListingEvent,
+    ) -> Result<Option<UniverseDiff>> {
+        let relevant = match ev {
+            ListingEvent::NewListing { in_universe, .. } => *in_universe,
+            ListingEvent::Delisting { market, .. } => self.books.contains_key(market),
+        };
+        if relevant {
+            Ok(Some(self.refresh().await?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn markets(&self) -> &Set<MarketRef> {
+        &self.markets
+    }
+
+    pub fn get(&self, market: &MarketRef) -> Option<&Arc<Mutex<BookClient>>> {
+        self.books.get(market).map(|(book, _)| book)
+    }
+
+    pub fn books(&self) -> impl Iterator<Item = (&MarketRef, &Arc<Mutex<BookClient>>)> {
+        self.books.iter().map(|(market, (book, _))| (market, book))
+    }
+
+    /// Wait until every book in the universe has received data
+    pub async fn wait_synced(
+        &mut self,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<()> {
+        for (_, synced) in self.books.values_mut() {
+            synced.wait_synced(timeout).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+//! Per venue marketdata settings, declared in config instead of wired up in
+//! code by each deployment.
+//!
+//! Settings are resolved most specific first: the venue's entry, then the
+//! default one.  `ArchitectClient::apply_marketdata_config` routes venues to
+//! their endpoints; `ManagedMarketdata::subscribe_configured` subscribes at
+//! the venue's depth, within its subscription budget.  The conflation
+//! interval is for consumers that conflate updates before passing them on,
+//! e.g. publishers to a UI.
+//!
+//! In the core config file they live under `marketdata`:
+//!
+//! ```yaml
+//! marketdata:
+//!   default:
+//!     endpoint: https://marketdata.example.com
+//!     max_subscriptions: 500
+//!   venues:
+//!     BINANCE:
+//!       endpoint: https://binance.marketdata.example.com
+//!       depth: 10
+//!       conflation_ms: 100
+//!       max_subscriptions: 200
+//! ```
+
+use fxhash::FxHashMap;
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// One layer of settings, unset fields fall through to the default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VenueMarketdataEntry {
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// book levels to subscribe to; unset for the full book
+    #[serde(default)]
+    pub depth: Option<usize>,
+    #[serde(default)]
+    pub conflation_ms: Option<u64>,
+    /// the most book subscriptions to the venue at once
+    #[serde(default)]
+    pub max_subscriptions: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketdataConfig {
+    #[serde(default)]
+    pub default: VenueMarketdataEntry,
+    /// keyed by venue name
+    #[serde(default)]
+    pub venues: FxHashMap<String, VenueMarketdataEntry>,
+}
+
+/// The settings that apply to one venue
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvedMarketdata {
+    pub endpoint: Option<String>,
+    pub depth: Option<usize>,
+    pub conflation: Option<Duration>,
+    pub max_subscriptions: Option<usize>,
+}
+
+impl MarketdataConfig {
+    pub fn resolve(&self, venue: &str) -> ResolvedMarketdata {
+        let entry = self.venues.get(venue);
+        let default = &self.default;
+        ResolvedMarketdata {
+            endpoint: entry
+                .and_then(|e| e.endpoint.clone())
+                .or_else(|| default.endpoint.clone()),
+            depth: entry.and_then(|e| e.depth).or(default.depth),
+            conflation: entry
+                .and_then(|e| e.conflation_ms)
+                .or(default.conflation_ms)
+                .map(Duration::from_millis),
+            max_subscriptions: entry
+                .and_then(|e| e.max_subscriptions)
+                .or(default.max_subscriptions),
+        }
+    }
+}
+
+#[cfg(feature = "netidx")]
+impl MarketdataConfig {
+    /// Read the `marketdata` section of a core config file; a file without
+    /// one gets empty settings
+    pub fn from_config_file(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        use anyhow::Context;
+        let path = path.as_ref();
+        let f: serde_yaml::Value = serde_yaml::from_slice(
+            &std::fs::read(path)
+                .with_context(|| format!("reading config {}", path.display()))?,
+        )?;
+        match f.get("marketdata") {
+            None => Ok(Self::default()),
+            Some(v) => Ok(serde_yaml::from_value(v.clone())?),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Prioritized startup subscription of many markets.
+//!
+//! Subscribing to hundreds of books at once leaves the markets a strategy
+//! actually trades syncing at the same pace as the long tail.  A `WarmUp`
+//! subscribes markets a priority tier at a time, most important first,
+//! moving on to the next tier once the current one has synced or
+//! `tier_timeout` passes.  `progress` reports how far along each tier is, so
+//! a strategy can `wait_for` its critical markets and start trading while
+//! the rest are still syncing.
+
+use super::{book_client::BookClient, managed_marketdata::ManagedMarketdata};
+use crate::symbology::MarketRef;
+use anyhow::{bail, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use fxhash::FxHashMap;
+use log::{debug, info, warn};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::{
+    sync::{watch, Mutex},
+    task::{self, JoinHandle},
+    time::Instant,
+};
+
+/// Ordered most important first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SubscriptionPriority {
+    /// markets the strategy trades
+    Critical,
+    Normal,
+    /// reference markets that can arrive last
+    Background,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WarmUpConfig {
+    /// how long to wait for a tier to sync before subscribing the next
+    pub tier_timeout: Duration,
+    pub delayed: bool,
+}
+
+impl Default for WarmUpConfig {
+    fn default() -> Self {
+        Self { tier_timeout: Duration::from_secs(10), delayed: false }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TierProgress {
+    pub total: usize,
+    pub subscribed: usize,
+    pub synced: usize,
+}
+
+impl TierProgress {
+    pub fn is_synced(&self) -> bool {
+        self.synced >= self.total
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WarmUpProgress {
+    pub tiers: BTreeMap<SubscriptionPriority, TierProgress>,
+}
+
+impl WarmUpProgress {
+    /// True once every market at `priority` or more important has synced
+    pub fn is_synced_through(&self, priority: SubscriptionPriority) -> bool {
+        self.tiers.range(..=priority).all(|(_, t)| t.is_synced())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.tiers.values().all(|t| t.is_synced())
+    }
+
+    pub fn synced(&self) -> usize {
+        self.tiers.values().map(|t| t.synced).sum()
+    }
+
+    pub fn total(&self) -> usize {
+        self.tiers.values().map(|t| t.total).sum()
+    }
+}
+
+type Books = Arc<parking_lot::Mutex<FxHashMap<MarketRef, Arc<Mutex<BookClient>>>>>;
+
+/// Dropping the `WarmUp` stops subscribing further tiers; books already
+/// handed out stay subscribed while held
+pub struct WarmUp {
+    books: Books,
+    progress: watch::Receiver<WarmUpProgress>,
+    driver: JoinHandle<()>,
+}
+
+impl Drop for WarmUp {
+    fn drop(&mut self) {
+        self.driver.abort();
+    }
+}
+
+impl WarmUp {
+    /// Start subscribing `markets`; a market listed more than once gets its
+    /// most important priority
+    pub fn start(
+        marketdata: Arc<ManagedMarketdata>,
+        markets: impl IntoIterator<Item = (MarketRef, SubscriptionPriority)>,
+        config: WarmUpConfig,
+    ) -> Self {
+        let mut by_market: FxHashMap<MarketRef, SubscriptionPriority> =
+            FxHashMap::default();
+        for (market, priority) in markets {
+            let p = by_market.entry(market).or_insert(priority);
+            *p = (*p).min(priority);
+        }
+        let mut tiers: BTreeMap<SubscriptionPriority, Vec<MarketRef>> = BTreeMap::new();
+        for (market, priority) in by_market {
+            tiers.entry(priority).or_default().push(market);
+        }
+        let progress = WarmUpProgress {
+            tiers: tiers
+                .iter()
+                .map(|(p, m)| (*p, TierProgress { total: m.len(), ..Default::default() }))
+                .collect(),
+        };
+        let (tx, rx) = watch::channel(progress);
+        let books: Books = Arc::default();
+        let driver = task::spawn(Self::run(marketdata, tiers, config, books.clone(), tx));
+        Self { books, progress: rx, driver }
+    }
+
+    async fn run(
+        marketdata: Arc<ManagedMarketdata>,
+        tiers: BTreeMap<SubscriptionPriority, Vec<MarketRef>>,
+        config: WarmUpConfig,
+        books: Books,
+        progress: watch::Sender<WarmUpProgress>,
+    ) {
+        // syncs from earlier tiers that timed out keep being counted
+        let mut pending = FuturesUnordered::new();
+        for (priority, markets) in tiers {
+            debug!("warm up subscribing {} {priority:?} markets", markets.len());
+            for market in markets {
+                let (book, mut synced) =
+                    marketdata.subscribe(market, config.delayed).await;
+                books.lock().insert(market, book);
+                progress.send_modify(|p| {
+                    p.tiers.entry(priority).or_default().subscribed += 1;
+                });
+                pending.push(async move {
+                    synced.wait_synced(None).await.ok().map(|()| priority)
+                });
+            }
+            let deadline = Instant::now() + config.tier_timeout;
+            while !progress.borrow().tiers.get(&priority).map_or(true, |t| t.is_synced())
+            {
+                match tokio::time::timeout_at(deadline, pending.next()).await {
+                    Ok(Some(Some(p))) => progress.send_modify(|s| {
+                        s.tiers.entry(p).or_default().synced += 1;
+                    }),
+                    Ok(Some(None)) => (),
+                    Ok(None) => break,
+                    Err(_) => {
+                        warn!(
+                            "warm up timed out waiting for {priority:?} markets to sync"
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+        while let Some(res) = pending.next().await {
+            if let Some(p) = res {
+                progress.send_modify(|s| {
+                    s.tiers.entry(p).or_default().synced += 1;
+                });
+            }
+        }
+        info!("warm up complete, {} markets", progress.borrow().total());
+    }
+
+    pub fn progress(&self) -> watch::Receiver<WarmUpProgress> {
+        self.progress.clone()
+    }
+
+    /// Wait until every market at `priority` or more important has synced
+    pub async fn wait_for(
+        &self,
+        priority: SubscriptionPriority,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let mut rx = self.progress.clone();
+        let wait = rx.wait_for(|p| p.is_synced_through(priority));
+        match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, wait).await {
+                Ok(res) => {
+                    res?;
+                }
+                Err(_) => bail!("timed out waiting for {priority:?} markets to sync"),
+            },
+            None => {
+                wait.await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The book for `market`, once it's been subscribed
+    pub fn get(&self, market: &MarketRef) -> Option<Arc<Mutex<BookClient>>> {
+        self.books.lock().get(market).cloned()
+    }
+}
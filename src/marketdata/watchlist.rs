@@ -0,0 +1,368 @@
+//! Watchlists: cheap top of book subscriptions for a large universe, with
+//! full depth only where something is happening.
+//!
+//! `WatchlistPolicy` decides, per market, whether it should be watched at
+//! L1 or upgraded to L2.  A market is upgraded when its top of book size
+//! imbalance or its update rate crosses a threshold, and downgraded again
+//! once it has been quiet for the quiet period.  The number of markets at
+//! L2 is capped so the process stays under its subscription budget; an
+//! upgrade past the cap is refused until a slot frees up.
+//!
+//! With the `grpc` and `netidx` features, `Watchlist::start` drives the
+//! policy over L1 snapshot streams from `ManagedL1Streams` and books from
+//! the managed marketdata.
+
+use crate::symbology::MarketRef;
+use chrono::{DateTime, Duration, Utc};
+use fxhash::FxHashMap;
+use log::debug;
+use rust_decimal::Decimal;
+#[cfg(all(feature = "grpc", feature = "netidx"))]
+use {
+    super::{
+        book_client::{BookClient, BookDepth},
+        managed_l1_streams::ManagedL1Streams,
+        managed_marketdata::{BookSubscription, ManagedMarketdata},
+    },
+    anyhow::Result,
+    api::{external::marketdata::L1BookSnapshot, symbology::MarketId, Dir},
+    log::error,
+    std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    tokio::{
+        sync::{
+            broadcast::{self, error::RecvError},
+            Mutex,
+        },
+        task::{self, JoinHandle},
+    },
+};
+
+/// Best bid and ask, (price, size)
+type Top = (Option<(Decimal, Decimal)>, Option<(Decimal, Decimal)>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchLevel {
+    L1,
+    L2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchReason {
+    /// signed top of book size imbalance, positive is bid heavy
+    Imbalance(Decimal),
+    /// updates seen in the activity window
+    Activity(u64),
+    Quiet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchChange {
+    pub market: MarketRef,
+    pub level: WatchLevel,
+    pub reason: WatchReason,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchlistConfig {
+    /// upgrade when |bid size - ask size| / (bid size + ask size) at the
+    /// top of book reaches this
+    pub imbalance: Decimal,
+    /// upgrade when a market sees this many book updates within `window`
+    pub activity: u64,
+    pub window: Duration,
+    /// downgrade after this long without crossing either threshold
+    pub quiet_period: Duration,
+    /// the most markets at L2 at once
+    pub max_l2: usize,
+}
+
+impl Default for WatchlistConfig {
+    fn default() -> Self {
+        Self {
+            imbalance: Decimal::new(8, 1),
+            activity: 100,
+            window: Duration::seconds(10),
+            quiet_period: Duration::minutes(5),
+            max_l2: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Watched {
+    level: WatchLevel,
+    window_start: Option<DateTime<Utc>>,
+    updates: u64,
+    last_hot: Option<DateTime<Utc>>,
+}
+
+pub struct WatchlistPolicy {
+    config: WatchlistConfig,
+    markets: FxHashMap<MarketRef, Watched>,
+    num_l2: usize,
+}
+
+impl WatchlistPolicy {
+    pub fn new(config: WatchlistConfig) -> Self {
+        Self { config, markets: FxHashMap::default(), num_l2: 0 }
+    }
+
+    pub fn config(&self) -> &WatchlistConfig {
+        &self.config
+    }
+
+    /// Start watching `market` at L1
+    pub fn watch(&mut self, market: MarketRef) {
+        self.markets.entry(market).or_insert(Watched {
+            level: WatchLevel::L1,
+            window_start: None,
+            updates: 0,
+            last_hot: None,
+        });
+    }
+
+    pub fn unwatch(&mut self, market: &MarketRef) {
+        if let Some(w) = self.markets.remove(market) {
+            if w.level == WatchLevel::L2 {
+                self.num_l2 -= 1;
+            }
+        }
+    }
+
+    pub fn level(&self, market: &MarketRef) -> Option<WatchLevel> {
+        self.markets.get(market).map(|w| w.level)
+    }
+
+    pub fn markets(&self) -> impl Iterator<Item = (&MarketRef, WatchLevel)> {
+        self.markets.iter().map(|(m, w)| (m, w.level))
+    }
+
+    pub fn num_l2(&self) -> usize {
+        self.num_l2
+    }
+
+    /// Feed the top of book and the number of book updates since the last
+    /// call for `market`; returns an upgrade if one is due
+    pub fn on_book(
+        &mut self,
+        market: MarketRef,
+        top: Top,
+        updates: u64,
+        now: DateTime<Utc>,
+    ) -> Option<WatchChange> {
+        let config = self.config;
+        let w = self.markets.get_mut(&market)?;
+        match w.window_start {
+            Some(start) if now - start < config.window => w.updates += updates,
+            _ => {
+                w.window_start = Some(now);
+                w.updates = updates;
+            }
+        }
+        let imbalance = match top {
+            (Some((_, bid)), Some((_, ask))) if !(bid + ask).is_zero() => {
+                Some((bid - ask) / (bid + ask))
+            }
+            _ => None,
+        };
+        let reason = match imbalance {
+            Some(i) if i.abs() >= config.imbalance => WatchReason::Imbalance(i),
+            _ if w.updates >= config.activity => WatchReason::Activity(w.updates),
+            _ => return None,
+        };
+        w.last_hot = Some(now);
+        if w.level == WatchLevel::L2 {
+            return None;
+        }
+        if self.num_l2 >= config.max_l2 {
+            debug!("not upgrading {market} ({reason:?}), {} markets at L2", self.num_l2);
+            return None;
+        }
+        w.level = WatchLevel::L2;
+        self.num_l2 += 1;
+        Some(WatchChange { market, level: WatchLevel::L2, reason, timestamp: now })
+    }
+
+    /// Downgrade markets that have been quiet for the quiet period
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<WatchChange> {
+        let mut res = vec![];
+        for (market, w) in self.markets.iter_mut() {
+            if w.level == WatchLevel::L2
+                && w.last_hot.map_or(true, |t| now - t >= self.config.quiet_period)
+            {
+                w.level = WatchLevel::L1;
+                self.num_l2 -= 1;
+                res.push(WatchChange {
+                    market: *market,
+                    level: WatchLevel::L1,
+                    reason: WatchReason::Quiet,
+                    timestamp: now,
+                });
+            }
+        }
+        res
+    }
+}
+
+#[cfg(all(feature = "grpc", feature = "netidx"))]
+type Book = Arc<Mutex<BookClient>>;
+
+/// A running watchlist: L1 snapshot streams for markets at L1, a full
+/// book for markets at L2, and never both for the same market
+#[cfg(all(feature = "grpc", feature = "netidx"))]
+pub struct Watchlist {
+    l1: Arc<ManagedL1Streams>,
+    group: String,
+    books: Arc<parking_lot::Mutex<FxHashMap<MarketRef, Book>>>,
+    changes: broadcast::Sender<WatchChange>,
+    task: JoinHandle<()>,
+}
+
+#[cfg(all(feature = "grpc", feature = "netidx"))]
+impl Drop for Watchlist {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.l1.unsubscribe_group(&self.group);
+    }
+}
+
+#[cfg(all(feature = "grpc", feature = "netidx"))]
+impl Watchlist {
+    /// Watch `markets` on their L1 streams, as a group of their own in
+    /// `l1`, upgrading to a book of `l2_depth` from `marketdata` per the policy.
+    /// A market's L1 stream is dropped while its book is held, and the
+    /// policy watches the book instead.  Activity is sampled every `poll`.
+    /// Fails if a market has no L1 endpoint.
+    pub fn start(
+        marketdata: Arc<ManagedMarketdata>,
+        l1: Arc<ManagedL1Streams>,
+        markets: impl IntoIterator<Item = MarketRef>,
+        config: WatchlistConfig,
+        l2_depth: BookDepth,
+        poll: std::time::Duration,
+        delayed: bool,
+    ) -> Result<Self> {
+        static NEXT_GROUP: AtomicU64 = AtomicU64::new(0);
+        let group = format!("watchlist#{}", NEXT_GROUP.fetch_add(1, Ordering::Relaxed));
+        let markets: Vec<MarketRef> = markets.into_iter().collect();
+        // subscribe before the group so no snapshot is missed
+        let mut snaps = l1.subscribe();
+        l1.set_group(group.clone(), markets.iter().copied())?;
+        let books: Arc<parking_lot::Mutex<FxHashMap<MarketRef, Book>>> =
+            Arc::new(parking_lot::Mutex::new(FxHashMap::default()));
+        let (changes, _) = broadcast::channel(1000);
+        let task = {
+            let l1 = l1.clone();
+            let group = group.clone();
+            let books = books.clone();
+            let changes = changes.clone();
+            task::spawn(async move {
+                let mut policy = WatchlistPolicy::new(config);
+                let by_id: FxHashMap<MarketId, MarketRef> =
+                    markets.iter().map(|m| (m.id, *m)).collect();
+                // per market at L1, the top and the snapshots since the
+                // last poll
+                let mut tops: FxHashMap<MarketRef, (Top, u64)> = FxHashMap::default();
+                for market in &markets {
+                    policy.watch(*market);
+                }
+                // per market at L2, the subscription and its update count at
+                // the last poll
+                let mut l2: FxHashMap<MarketRef, (BookSubscription, u64)> =
+                    FxHashMap::default();
+                let mut poll = tokio::time::interval(poll);
+                loop {
+                    tokio::select! {
+                        snap = snaps.recv() => match snap {
+                            Ok(snap) => {
+                                if let Some(market) = by_id.get(&snap.market_id) {
+                                    let (top, n) = tops.entry(*market).or_default();
+                                    *top = (snap.best_bid, snap.best_ask);
+                                    *n += 1;
+                                }
+                            }
+                            Err(RecvError::Lagged(n)) => {
+                                debug!("watchlist lagged {n} l1 snapshots")
+                            }
+                            Err(RecvError::Closed) => break,
+                        },
+                        _ = poll.tick() => {
+                            let now = Utc::now();
+                            let mut due = vec![];
+                            for (market, (top, n)) in tops.iter_mut() {
+                                if policy.level(market) == Some(WatchLevel::L1) {
+                                    due.extend(policy.on_book(*market, *top, *n, now));
+                                }
+                                *n = 0;
+                            }
+                            for (market, (sub, seen)) in l2.iter_mut() {
+                                let count = *sub.synced.0.borrow();
+                                let updates = count.saturating_sub(*seen);
+                                *seen = count;
+                                let top = sub.top.load();
+                                let top = (top.best(Dir::Buy), top.best(Dir::Sell));
+                                due.extend(policy.on_book(*market, top, updates, now));
+                            }
+                            due.extend(policy.expire(now));
+                            if due.is_empty() {
+                                continue;
+                            }
+                            for change in &due {
+                                let market = change.market;
+                                match change.level {
+                                    WatchLevel::L2 => {
+                                        let sub = marketdata
+                                            .subscribe_with_depth(market, delayed, l2_depth)
+                                            .await;
+                                        let seen = *sub.synced.0.borrow();
+                                        books.lock().insert(market, sub.book.clone());
+                                        l2.insert(market, (sub, seen));
+                                        tops.remove(&market);
+                                    }
+                                    WatchLevel::L1 => {
+                                        l2.remove(&market);
+                                        books.lock().remove(&market);
+                                    }
+                                }
+                            }
+                            // L1 streams for exactly the markets at L1
+                            let at_l1 = policy
+                                .markets()
+                                .filter(|(_, level)| *level == WatchLevel::L1)
+                                .map(|(m, _)| *m);
+                            if let Err(e) = l1.set_group(group.clone(), at_l1) {
+                                error!("watchlist {group}: updating l1 streams: {e:?}");
+                            }
+                            for change in due {
+                                debug!(
+                                    "watchlist {} -> {:?} ({:?})",
+                                    change.market, change.level, change.reason
+                                );
+                                let _ = changes.send(change);
+                            }
+                        }
+                    }
+                }
+            })
+        };
+        Ok(Self { l1, group, books, changes, task })
+    }
+
+    /// The book for `market`, while it's at L2
+    pub fn book(&self, market: &MarketRef) -> Option<Book> {
+        self.books.lock().get(market).cloned()
+    }
+
+    /// The latest L1 snapshot for `market`, while it's at L1
+    pub fn l1(&self, market: &MarketRef) -> Option<L1BookSnapshot> {
+        self.l1.latest(market.id)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<WatchChange> {
+        self.changes.subscribe()
+    }
+}
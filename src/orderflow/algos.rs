@@ -0,0 +1,275 @@
+//! TWAP and VWAP execution of a parent order.
+//!
+//! An `AlgoExecutor` works a parent order over [start, end) in child
+//! orders.  The schedule splits the window into buckets: equal quantity per
+//! bucket for TWAP, quantity in proportion to a volume profile for VWAP.
+//! At each bucket the parent should have filled its share of every bucket
+//! so far; `plan` compares that target with what has filled and produces
+//! the child order (and cancels) to catch up, priced like a `TradeIntent`
+//! off the current `MarketState`.  After the end the remainder is worked
+//! aggressively.
+//!
+//! Feed the executor the tracker's events for its children with
+//! `on_event`; `handle` gives a watch of the parent's aggregate fill state.
+
+use super::{
+    intent::{IntentAction, IntentExecutor, Target, TradeIntent, Urgency},
+    tracker::{OrderEvent, OrderRequest},
+};
+use crate::{marketdata::market_view::MarketState, symbology::MarketRef};
+use anyhow::{bail, Result};
+use api::{Dir, OrderId};
+use chrono::{DateTime, Utc};
+use fxhash::FxHashSet;
+use rust_decimal::Decimal;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParentOrder {
+    pub market: MarketRef,
+    pub dir: Dir,
+    pub quantity: Decimal,
+    /// never buy above / sell below this price
+    pub limit: Option<Decimal>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParentState {
+    Working,
+    Filled,
+    /// canceled by `AlgoExecutor::cancel`; children may still be working
+    /// until the next `plan`
+    Canceled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParentStatus {
+    pub state: ParentState,
+    pub filled: Decimal,
+    pub avg_fill_price: Option<Decimal>,
+    /// the quantity the schedule wants filled by now
+    pub target: Decimal,
+    pub children: usize,
+}
+
+pub struct AlgoExecutor {
+    parent: ParentOrder,
+    /// cumulative fraction of the parent due by the end of each bucket
+    schedule: Vec<Decimal>,
+    urgency: Urgency,
+    /// children smaller than this are not sent, except to finish
+    min_quantity: Decimal,
+    children: FxHashSet<OrderId>,
+    notional: Decimal,
+    status: watch::Sender<ParentStatus>,
+}
+
+impl AlgoExecutor {
+    /// Equal quantity in each of `slices` buckets
+    pub fn twap(parent: ParentOrder, slices: usize, urgency: Urgency) -> Result<Self> {
+        Self::new(parent, &vec![Decimal::ONE; slices], urgency)
+    }
+
+    /// Quantity in proportion to `profile`, the expected volume in each of
+    /// equal length buckets over the window, e.g. from historical candles
+    pub fn vwap(
+        parent: ParentOrder,
+        profile: &[Decimal],
+        urgency: Urgency,
+    ) -> Result<Self> {
+        Self::new(parent, profile, urgency)
+    }
+
+    fn new(parent: ParentOrder, weights: &[Decimal], urgency: Urgency) -> Result<Self> {
+        if parent.end <= parent.start {
+            bail!("algo window must end after it starts");
+        }
+        if parent.quantity <= Decimal::ZERO {
+            bail!("algo quantity must be positive");
+        }
+        if weights.iter().any(|w| *w < Decimal::ZERO) {
+            bail!("algo schedule weights must not be negative");
+        }
+        let total: Decimal = weights.iter().sum();
+        if total.is_zero() {
+            bail!("algo schedule has no weight");
+        }
+        let mut cum = Decimal::ZERO;
+        let schedule = weights
+            .iter()
+            .map(|w| {
+                cum += w;
+                cum / total
+            })
+            .collect();
+        let (status, _) = watch::channel(ParentStatus {
+            state: ParentState::Working,
+            filled: Decimal::ZERO,
+            avg_fill_price: None,
+            target: Decimal::ZERO,
+            children: 0,
+        });
+        Ok(Self {
+            parent,
+            schedule,
+            urgency,
+            min_quantity: Decimal::ZERO,
+            children: FxHashSet::default(),
+            notional: Decimal::ZERO,
+            status,
+        })
+    }
+
+    pub fn with_min_quantity(mut self, min_quantity: Decimal) -> Self {
+        self.min_quantity = min_quantity;
+        self
+    }
+
+    pub fn parent(&self) -> &ParentOrder {
+        &self.parent
+    }
+
+    /// The parent's aggregate state, updated as children fill
+    pub fn handle(&self) -> watch::Receiver<ParentStatus> {
+        self.status.subscribe()
+    }
+
+    pub fn status(&self) -> ParentStatus {
+        self.status.borrow().clone()
+    }
+
+    pub fn is_child(&self, id: &OrderId) -> bool {
+        self.children.contains(id)
+    }
+
+    /// Stop working the parent; the next `plan` cancels its children
+    pub fn cancel(&mut self) {
+        self.status.send_if_modified(|s| {
+            let working = s.state == ParentState::Working;
+            if working {
+                s.state = ParentState::Canceled;
+            }
+            working
+        });
+    }
+
+    /// The quantity the schedule wants filled by `now`
+    pub fn target_at(&self, now: DateTime<Utc>) -> Decimal {
+        let ParentOrder { start, end, quantity, .. } = self.parent;
+        if now < start {
+            return Decimal::ZERO;
+        }
+        if now >= end {
+            return quantity;
+        }
+        let n = self.schedule.len() as i64;
+        let elapsed = (now - start).num_milliseconds();
+        let window = (end - start).num_milliseconds().max(1);
+        let bucket = ((elapsed * n / window) as usize).min(self.schedule.len() - 1);
+        quantity * self.schedule[bucket]
+    }
+
+    /// Plan the child order and cancels that bring the parent to its
+    /// scheduled quantity.  A working child at the right price and size is
+    /// left alone; anything else is canceled and replaced.
+    pub fn plan(
+        &mut self,
+        now: DateTime<Utc>,
+        state: &MarketState,
+        next_order_id: impl FnMut() -> OrderId,
+    ) -> Vec<IntentAction> {
+        let status = self.status();
+        let target = self.target_at(now);
+        self.status.send_if_modified(|s| {
+            let changed = s.target != target;
+            s.target = target;
+            changed
+        });
+        let working: Vec<_> = state
+            .working_orders
+            .iter()
+            .filter(|o| self.children.contains(&o.id))
+            .copied()
+            .collect();
+        let mut needed = target.min(self.parent.quantity) - status.filled;
+        let step = self.parent.market.extra_info.step_size();
+        if step > Decimal::ZERO {
+            needed = (needed / step).floor() * step;
+        }
+        let remaining = self.parent.quantity - status.filled;
+        let too_small = needed < self.min_quantity && needed < remaining;
+        if status.state != ParentState::Working || needed <= Decimal::ZERO || too_small {
+            return working.iter().map(|o| IntentAction::Cancel(o.id)).collect();
+        }
+        // plan as an intent to trade `needed` from a flat position with
+        // only this parent's children working; if the book lacks the side
+        // to price off, nothing is placed
+        let signed = match self.parent.dir {
+            Dir::Buy => needed,
+            Dir::Sell => -needed,
+        };
+        let intent = TradeIntent {
+            market: self.parent.market,
+            target: Target::Position(signed),
+            urgency: if now >= self.parent.end {
+                Urgency::Aggressive
+            } else {
+                self.urgency
+            },
+            limit: self.parent.limit,
+            min_quantity: Decimal::ZERO,
+        };
+        let view = MarketState {
+            position: Decimal::ZERO,
+            working_orders: working,
+            ..state.clone()
+        };
+        let actions = IntentExecutor::plan(&intent, &view, next_order_id);
+        for a in &actions {
+            if let IntentAction::Place(OrderRequest { id, .. }) = a {
+                self.children.insert(*id);
+            }
+        }
+        let children = self.children.len();
+        self.status.send_if_modified(|s| {
+            let changed = s.children != children;
+            s.children = children;
+            changed
+        });
+        actions
+    }
+
+    /// Apply a tracker event; events for other orders are ignored
+    pub fn on_event(&mut self, ev: &OrderEvent) {
+        if !self.children.contains(&ev.id()) {
+            return;
+        }
+        let (quantity, price) = match ev {
+            OrderEvent::Fill { quantity, price, .. } => (*quantity, *price),
+            OrderEvent::Bust { quantity, price, .. } => (-*quantity, *price),
+            OrderEvent::Correction {
+                old_quantity, old_price, quantity, price, ..
+            } => {
+                self.apply_fill(-*old_quantity, *old_price);
+                (*quantity, *price)
+            }
+            _ => return,
+        };
+        self.apply_fill(quantity, price);
+    }
+
+    fn apply_fill(&mut self, quantity: Decimal, price: Decimal) {
+        let parent_quantity = self.parent.quantity;
+        self.notional += quantity * price;
+        let notional = self.notional;
+        self.status.send_modify(|s| {
+            s.filled += quantity;
+            s.avg_fill_price = (!s.filled.is_zero()).then(|| notional / s.filled);
+            if s.state == ParentState::Working && s.filled >= parent_quantity {
+                s.state = ParentState::Filled;
+            }
+        });
+    }
+}
@@ -0,0 +1,264 @@
+//! Post trade allocation of block orders across sub-accounts.
+//!
+//! Register a block order placed against one account with an
+//! `AllocationRule`; as its fills arrive `Allocator::on_fill` splits each
+//! one across the sub-accounts, emits an `AllocationRecord` per
+//! sub-account and applies the allocated quantity to that sub-account's
+//! `LocalLedger`.
+//!
+//! Ratio allocation is cumulative: each fill is split so that the running
+//! allocation per account tracks its share of the total filled so far, so
+//! lot rounding doesn't drift in favor of any one account over many small
+//! fills.
+
+use super::drift::LocalLedger;
+use crate::symbology::MarketRef;
+use anyhow::{bail, Result};
+use api::{AccountId, Dir, OrderId};
+use chrono::{DateTime, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use std::{fmt, sync::Arc};
+use tokio::sync::broadcast;
+
+/// Split a fill of `quantity`, given what each account has been allocated
+/// so far; must return quantities summing to `quantity`
+pub type CustomRule = Arc<
+    dyn Fn(Decimal, &FxHashMap<AccountId, Decimal>) -> Vec<(AccountId, Decimal)>
+        + Send
+        + Sync,
+>;
+
+#[derive(Clone)]
+pub enum AllocationRule {
+    /// split by relative weight, e.g. 2:1:1
+    Ratio(Vec<(AccountId, Decimal)>),
+    Custom(CustomRule),
+}
+
+impl fmt::Debug for AllocationRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocationRule::Ratio(r) => f.debug_tuple("Ratio").field(r).finish(),
+            AllocationRule::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllocationRecord {
+    pub block: OrderId,
+    pub block_account: AccountId,
+    pub account: AccountId,
+    pub market: MarketRef,
+    pub dir: Dir,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    /// the sub-account's pro rata share of the fill fee
+    pub fee: Decimal,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+struct Block {
+    account: AccountId,
+    market: MarketRef,
+    dir: Dir,
+    rule: AllocationRule,
+    /// allocations are multiples of this, the remainder of a fill that isn't
+    /// goes to the account furthest below its share
+    step: Option<Decimal>,
+    filled: Decimal,
+    allocated: FxHashMap<AccountId, Decimal>,
+}
+
+pub struct Allocator {
+    blocks: FxHashMap<OrderId, Block>,
+    ledgers: FxHashMap<AccountId, LocalLedger>,
+    records: Vec<AllocationRecord>,
+    tx: broadcast::Sender<AllocationRecord>,
+}
+
+impl Default for Allocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Allocator {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self {
+            blocks: FxHashMap::default(),
+            ledgers: FxHashMap::default(),
+            records: vec![],
+            tx,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AllocationRecord> {
+        self.tx.subscribe()
+    }
+
+    /// Register a block order before (or as) it is sent
+    pub fn register_block(
+        &mut self,
+        id: OrderId,
+        account: AccountId,
+        market: MarketRef,
+        dir: Dir,
+        rule: AllocationRule,
+        step: Option<Decimal>,
+    ) -> Result<()> {
+        if let AllocationRule::Ratio(weights) = &rule {
+            if weights.is_empty() {
+                bail!("ratio allocation needs at least one account");
+            }
+            if weights.iter().any(|(_, w)| *w <= Decimal::ZERO) {
+                bail!("ratio allocation weights must be positive");
+            }
+        }
+        if step.map(|s| s <= Decimal::ZERO).unwrap_or(false) {
+            bail!("allocation step must be positive");
+        }
+        self.blocks.insert(
+            id,
+            Block {
+                account,
+                market,
+                dir,
+                rule,
+                step,
+                filled: Decimal::ZERO,
+                allocated: FxHashMap::default(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Allocate a fill of a registered block order
+    pub fn on_fill(
+        &mut self,
+        id: OrderId,
+        quantity: Decimal,
+        price: Decimal,
+        fee: Decimal,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<AllocationRecord>> {
+        let Some(block) = self.blocks.get_mut(&id) else {
+            bail!("fill for unregistered block order {id:?}")
+        };
+        let split = match &block.rule {
+            AllocationRule::Ratio(weights) => {
+                split_ratio(weights, block.step, block.filled, &block.allocated, quantity)
+            }
+            AllocationRule::Custom(f) => {
+                let split = f(quantity, &block.allocated);
+                let total: Decimal = split.iter().map(|(_, q)| *q).sum();
+                if total != quantity {
+                    bail!("custom allocation of {id:?} sums to {total}, not {quantity}");
+                }
+                split
+            }
+        };
+        block.filled += quantity;
+        let mut records = vec![];
+        for (account, q) in split {
+            if q.is_zero() {
+                continue;
+            }
+            *block.allocated.entry(account).or_default() += q;
+            let fee = if quantity.is_zero() { fee } else { fee * q / quantity };
+            records.push(AllocationRecord {
+                block: id,
+                block_account: block.account,
+                account,
+                market: block.market,
+                dir: block.dir,
+                quantity: q,
+                price,
+                fee,
+                timestamp: now,
+            });
+        }
+        for r in &records {
+            self.ledgers
+                .entry(r.account)
+                .or_default()
+                .apply_fill(r.market, r.dir, r.quantity, r.price, r.fee);
+            let _ = self.tx.send(*r);
+        }
+        self.records.extend_from_slice(&records);
+        Ok(records)
+    }
+
+    /// Forget a block once it is done; its records and ledgers are kept
+    pub fn remove_block(&mut self, id: &OrderId) {
+        self.blocks.remove(id);
+    }
+
+    /// Total allocated to each sub-account of the block so far
+    pub fn allocated(&self, id: &OrderId) -> Option<&FxHashMap<AccountId, Decimal>> {
+        self.blocks.get(id).map(|b| &b.allocated)
+    }
+
+    pub fn ledger(&self, account: &AccountId) -> Option<&LocalLedger> {
+        self.ledgers.get(account)
+    }
+
+    pub fn ledger_mut(&mut self, account: AccountId) -> &mut LocalLedger {
+        self.ledgers.entry(account).or_default()
+    }
+
+    pub fn records(&self) -> &[AllocationRecord] {
+        &self.records
+    }
+}
+
+fn split_ratio(
+    weights: &[(AccountId, Decimal)],
+    step: Option<Decimal>,
+    filled_before: Decimal,
+    allocated: &FxHashMap<AccountId, Decimal>,
+    quantity: Decimal,
+) -> Vec<(AccountId, Decimal)> {
+    let total_weight: Decimal = weights.iter().map(|(_, w)| *w).sum();
+    let filled = filled_before + quantity;
+    let mut out: Vec<(AccountId, Decimal)> =
+        weights.iter().map(|(a, _)| (*a, Decimal::ZERO)).collect();
+    // how far each account is below its share after what's been given
+    let deficit = |i: usize, out: &[(AccountId, Decimal)]| {
+        let (account, w) = weights[i];
+        filled * w / total_weight
+            - allocated.get(&account).copied().unwrap_or_default()
+            - out[i].1
+    };
+    let neediest = |out: &[(AccountId, Decimal)]| {
+        (0..out.len())
+            .max_by(|a, b| deficit(*a, out).cmp(&deficit(*b, out)).then(b.cmp(a)))
+            .unwrap()
+    };
+    let unit = step.unwrap_or(Decimal::ZERO);
+    let mut left = quantity;
+    for i in 0..out.len() {
+        let mut give = deficit(i, &out).max(Decimal::ZERO).min(left);
+        if !unit.is_zero() {
+            give = (give / unit).floor() * unit;
+        }
+        out[i].1 += give;
+        left -= give;
+    }
+    if !unit.is_zero() {
+        while left >= unit {
+            let i = neediest(&out);
+            out[i].1 += unit;
+            left -= unit;
+        }
+    }
+    // whatever is left is less than a step, or decimal division residue
+    if !left.is_zero() {
+        let i = neediest(&out);
+        out[i].1 += left;
+    }
+    out
+}
@@ -0,0 +1,424 @@
+//! Regulatory audit trail for orders.
+//!
+//! Trade reporting regimes (CAT, MiFID II) want the time each order was
+//! created, transmitted to the venue, accepted by it, and executed, to the
+//! microsecond and in UTC, together with the venue's own timestamps where it
+//! reports them.  `AuditJournal` appends one `AuditEvent` per lifecycle step
+//! to a JSON lines file as it happens, `OrderTimestamps` summarizes the
+//! events of each order, and `write_csv` exports them one row per event in
+//! a fixed schema that compliance tooling can map directly.
+//!
+//! Events usually come from the `OrderTracker` broadcast via
+//! `AuditEvent::from_order_event`, given the tracked order so an out can be
+//! told apart as filled or canceled; order creation happens before the
+//! tracker sees the order, so record it with `AuditEvent::created`.
+//!
+//! Retention rules typically require years of audit trail;
+//! `RollingAuditJournal` writes it as a directory of segments that can be
+//! compacted and pruned, see `crate::segments`.
+
+use super::{
+    state::TrackedOrder,
+    tracker::{OrderEvent, OrderRequest},
+};
+use crate::{
+    csv,
+    segments::{open_segment, RollPolicy, SegmentDir},
+};
+use anyhow::{anyhow, Result};
+use api::{Dir, OrderId};
+use chrono::{DateTime, SecondsFormat, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventKind {
+    /// the order was decided on, before any checks or routing
+    Created,
+    /// the order left this process for the venue
+    Transmitted,
+    /// the venue accepted the order
+    Accepted,
+    Executed,
+    /// a previous execution was busted by the venue
+    Busted,
+    /// a previous execution was corrected by the venue
+    Corrected,
+    CancelRequested,
+    CancelRejected,
+    Canceled,
+    /// the order is done, fully executed
+    Filled,
+    Rejected,
+    ModifyRequested,
+    /// the venue accepted a modify
+    Modified,
+    ModifyRejected,
+}
+
+impl AuditEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditEventKind::Created => "created",
+            AuditEventKind::Transmitted => "transmitted",
+            AuditEventKind::Accepted => "accepted",
+            AuditEventKind::Executed => "executed",
+            AuditEventKind::Busted => "busted",
+            AuditEventKind::Corrected => "corrected",
+            AuditEventKind::CancelRequested => "cancel_requested",
+            AuditEventKind::CancelRejected => "cancel_rejected",
+            AuditEventKind::Canceled => "canceled",
+            AuditEventKind::Filled => "filled",
+            AuditEventKind::Rejected => "rejected",
+            AuditEventKind::ModifyRequested => "modify_requested",
+            AuditEventKind::Modified => "modified",
+            AuditEventKind::ModifyRejected => "modify_rejected",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub order_id: OrderId,
+    pub kind: AuditEventKind,
+    /// the local clock when the event happened, or was received
+    pub timestamp: DateTime<Utc>,
+    /// the venue's timestamp for the event, if it reports one
+    #[serde(default)]
+    pub venue_timestamp: Option<DateTime<Utc>>,
+    /// set on creation
+    #[serde(default)]
+    pub market: Option<String>,
+    #[serde(default)]
+    pub dir: Option<Dir>,
+    /// the order's limit price on creation, the execution price on
+    /// executions
+    #[serde(default)]
+    pub price: Option<Decimal>,
+    /// the order's quantity on creation, the executed quantity on
+    /// executions
+    #[serde(default)]
+    pub quantity: Option<Decimal>,
+    /// the venue's execution id, or the reject reason
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(
+        order_id: OrderId,
+        kind: AuditEventKind,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            order_id,
+            kind,
+            timestamp,
+            venue_timestamp: None,
+            market: None,
+            dir: None,
+            price: None,
+            quantity: None,
+            detail: None,
+        }
+    }
+
+    pub fn created(request: &OrderRequest, timestamp: DateTime<Utc>) -> Self {
+        Self {
+            market: Some(request.market.name.to_string()),
+            dir: Some(request.dir),
+            price: Some(request.price),
+            quantity: Some(request.quantity),
+            ..Self::new(request.id, AuditEventKind::Created, timestamp)
+        }
+    }
+
+    /// The audit event for a tracker event received at `timestamp`.
+    /// `order` is the tracked order after the event, e.g. from
+    /// `OrderTracker::get`; an order going out is recorded as filled if
+    /// nothing remains of it, otherwise as canceled, and as canceled if the
+    /// order isn't known.
+    pub fn from_order_event(
+        ev: &OrderEvent,
+        order: Option<&TrackedOrder>,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        let new = |kind| Self::new(ev.id(), kind, timestamp);
+        match ev {
+            OrderEvent::Sent(_) => new(AuditEventKind::Transmitted),
+            OrderEvent::Ack(_) => new(AuditEventKind::Accepted),
+            OrderEvent::Fill { quantity, price, .. } => Self {
+                price: Some(*price),
+                quantity: Some(*quantity),
+                ..new(AuditEventKind::Executed)
+            },
+            OrderEvent::Bust { quantity, price, .. } => Self {
+                price: Some(*price),
+                quantity: Some(*quantity),
+                ..new(AuditEventKind::Busted)
+            },
+            OrderEvent::Correction { quantity, price, .. } => Self {
+                price: Some(*price),
+                quantity: Some(*quantity),
+                ..new(AuditEventKind::Corrected)
+            },
+            OrderEvent::Reject { reason, .. } => {
+                Self { detail: Some(reason.to_string()), ..new(AuditEventKind::Rejected) }
+            }
+            OrderEvent::CancelSent(_) => new(AuditEventKind::CancelRequested),
+            OrderEvent::Out(_) => match order {
+                Some(o) if o.remaining() <= Decimal::ZERO => new(AuditEventKind::Filled),
+                _ => new(AuditEventKind::Canceled),
+            },
+            OrderEvent::ModifySent(req) => Self {
+                price: req.price,
+                quantity: req.quantity,
+                ..new(AuditEventKind::ModifyRequested)
+            },
+            OrderEvent::Modified { price, quantity, .. } => Self {
+                price: Some(*price),
+                quantity: Some(*quantity),
+                ..new(AuditEventKind::Modified)
+            },
+            OrderEvent::CancelReject { reason, .. } => Self {
+                detail: Some(reason.to_string()),
+                ..new(AuditEventKind::CancelRejected)
+            },
+            OrderEvent::ModifyReject { reason, .. } => Self {
+                detail: Some(reason.to_string()),
+                ..new(AuditEventKind::ModifyRejected)
+            },
+        }
+    }
+
+    pub fn with_venue_timestamp(mut self, ts: DateTime<Utc>) -> Self {
+        self.venue_timestamp = Some(ts);
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// An append only JSON lines file of audit events
+pub struct AuditJournal {
+    out: BufWriter<File>,
+    len: u64,
+}
+
+impl AuditJournal {
+    /// Open the journal at `path` for appending, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self { out: BufWriter::new(file), len })
+    }
+
+    pub fn record(&mut self, ev: &AuditEvent) -> Result<()> {
+        let line = serde_json::to_vec(ev)?;
+        self.out.write_all(&line)?;
+        self.out.write_all(b"\n")?;
+        self.len += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// The size of the journal, including any buffered events
+    pub fn bytes_written(&self) -> u64 {
+        self.len
+    }
+
+    /// Events are buffered; flush periodically and before dropping
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.out.flush()?)
+    }
+
+    /// Read every event in the journal or segment at `path`, compacted or
+    /// not, in the order written
+    pub fn read(path: impl AsRef<Path>) -> Result<Vec<AuditEvent>> {
+        let mut res = vec![];
+        for line in BufReader::new(open_segment(path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                res.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Check that every line of a journal parses and that it ends with a
+    /// complete line; returns the number of events.  Suitable for
+    /// `SegmentDir::verify`.
+    pub fn verify(rd: Box<dyn Read + Send>) -> Result<u64> {
+        let mut rd = BufReader::new(rd);
+        let mut line = String::new();
+        let mut n = 0;
+        let mut lineno = 0;
+        loop {
+            line.clear();
+            if rd.read_line(&mut line)? == 0 {
+                break Ok(n);
+            }
+            lineno += 1;
+            if !line.ends_with('\n') {
+                break Err(anyhow!("truncated event at line {lineno}"));
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            serde_json::from_str::<AuditEvent>(&line)
+                .map_err(|e| anyhow!("invalid event at line {lineno}: {e}"))?;
+            n += 1;
+        }
+    }
+}
+
+impl Drop for AuditJournal {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+/// An audit journal that rolls to a new segment in a `SegmentDir`
+/// according to a `RollPolicy`
+pub struct RollingAuditJournal {
+    dir: SegmentDir,
+    roll: RollPolicy,
+    current: AuditJournal,
+    started: DateTime<Utc>,
+}
+
+impl RollingAuditJournal {
+    pub fn new(dir: SegmentDir, roll: RollPolicy) -> Result<Self> {
+        let started = Utc::now();
+        let current = AuditJournal::open(dir.segment_path(started))?;
+        Ok(Self { dir, roll, current, started })
+    }
+
+    pub fn segments(&self) -> &SegmentDir {
+        &self.dir
+    }
+
+    /// Start a new segment if the current one is due, returning the path of
+    /// the segment that was closed.  Called by `record`.
+    pub fn roll_if_due(&mut self) -> Result<Option<PathBuf>> {
+        let now = Utc::now();
+        if !self.roll.due(self.current.bytes_written(), self.started, now) {
+            return Ok(None);
+        }
+        let closed = self.dir.segment_path(self.started);
+        self.current.flush()?;
+        self.current = AuditJournal::open(self.dir.segment_path(now))?;
+        self.started = now;
+        Ok(Some(closed))
+    }
+
+    pub fn record(&mut self, ev: &AuditEvent) -> Result<()> {
+        self.roll_if_due()?;
+        self.current.record(ev)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.current.flush()
+    }
+}
+
+/// The regulatory timestamps of one order, local and venue
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderTimestamps {
+    pub created: Option<DateTime<Utc>>,
+    pub transmitted: Option<DateTime<Utc>>,
+    pub accepted: Option<DateTime<Utc>>,
+    pub venue_accepted: Option<DateTime<Utc>>,
+    /// (local, venue) for each execution
+    pub executions: Vec<(DateTime<Utc>, Option<DateTime<Utc>>)>,
+    pub cancel_requested: Option<DateTime<Utc>>,
+    /// canceled or rejected
+    pub done: Option<DateTime<Utc>>,
+}
+
+impl OrderTimestamps {
+    /// Summarize events per order; the first of each step wins
+    pub fn collect<'a>(
+        events: impl IntoIterator<Item = &'a AuditEvent>,
+    ) -> FxHashMap<OrderId, OrderTimestamps> {
+        let mut res: FxHashMap<OrderId, OrderTimestamps> = FxHashMap::default();
+        for ev in events {
+            let ts = res.entry(ev.order_id).or_default();
+            let t = Some(ev.timestamp);
+            match ev.kind {
+                AuditEventKind::Created => ts.created = ts.created.or(t),
+                AuditEventKind::Transmitted => ts.transmitted = ts.transmitted.or(t),
+                AuditEventKind::Accepted => {
+                    ts.accepted = ts.accepted.or(t);
+                    ts.venue_accepted = ts.venue_accepted.or(ev.venue_timestamp);
+                }
+                AuditEventKind::Executed => {
+                    ts.executions.push((ev.timestamp, ev.venue_timestamp))
+                }
+                AuditEventKind::Busted
+                | AuditEventKind::Corrected
+                | AuditEventKind::ModifyRequested
+                | AuditEventKind::Modified
+                | AuditEventKind::ModifyRejected
+                | AuditEventKind::CancelRejected => (),
+                AuditEventKind::CancelRequested => {
+                    ts.cancel_requested = ts.cancel_requested.or(t)
+                }
+                AuditEventKind::Canceled
+                | AuditEventKind::Filled
+                | AuditEventKind::Rejected => ts.done = ts.done.or(t),
+            }
+        }
+        res
+    }
+}
+
+/// UTC, microsecond precision, as CAT and MiFID II RTS 25 expect
+fn timestamp(t: &DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Write events as CSV with a header row.  The columns are fixed:
+/// order_id, event, timestamp, venue_timestamp, market, side, price,
+/// quantity, detail; empty where not applicable.
+pub fn write_csv<'a>(
+    mut w: impl Write,
+    events: impl IntoIterator<Item = &'a AuditEvent>,
+) -> Result<()> {
+    fn opt<T: ToString>(t: &Option<T>) -> String {
+        t.as_ref().map(|t| t.to_string()).unwrap_or_default()
+    }
+    writeln!(
+        w,
+        "order_id,event,timestamp,venue_timestamp,market,side,price,quantity,detail"
+    )?;
+    for ev in events {
+        let side = match ev.dir {
+            Some(Dir::Buy) => "buy",
+            Some(Dir::Sell) => "sell",
+            None => "",
+        };
+        writeln!(
+            w,
+            "{},{},{},{},{},{side},{},{},{}",
+            ev.order_id,
+            ev.kind.name(),
+            timestamp(&ev.timestamp),
+            ev.venue_timestamp.as_ref().map(timestamp).unwrap_or_default(),
+            csv::field(&opt(&ev.market)),
+            opt(&ev.price),
+            opt(&ev.quantity),
+            csv::field(&opt(&ev.detail)),
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! Batch orderflow messages into fewer channel writes for bulk order entry.
+//!
+//! A message that arrives when the sender has been idle for at least the
+//! linger is written immediately, together with anything else already
+//! queued, so single orders pay no extra latency.  During a burst, messages
+//! are held for up to the linger and written together.
+
+use crate::ChannelDriver;
+use anyhow::{anyhow, Result};
+use api::{ComponentId, TypedMessage};
+use log::{error, info};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{self, Instant},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// how long to hold messages during a burst
+    pub linger: Duration,
+    /// write as soon as this many messages are queued
+    pub max_batch: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self { linger: Duration::from_millis(1), max_batch: 100 }
+    }
+}
+
+pub struct BatchedSender {
+    tx: mpsc::UnboundedSender<TypedMessage>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for BatchedSender {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
+
+impl BatchedSender {
+    pub(super) fn new(
+        driver: Arc<ChannelDriver>,
+        target: ComponentId,
+        shadow: Arc<AtomicBool>,
+        config: BatchConfig,
+    ) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run(driver, target, shadow, config, rx));
+        Self { tx, task }
+    }
+
+    /// Queue a message to the target
+    pub fn send<M>(&self, msg: M) -> Result<()>
+    where
+        M: Into<TypedMessage>,
+    {
+        self.tx.send(msg.into()).map_err(|_| anyhow!("batched sender closed"))
+    }
+}
+
+async fn run(
+    driver: Arc<ChannelDriver>,
+    target: ComponentId,
+    shadow: Arc<AtomicBool>,
+    config: BatchConfig,
+    mut rx: mpsc::UnboundedReceiver<TypedMessage>,
+) {
+    let mut batch: Vec<TypedMessage> = Vec::with_capacity(config.max_batch);
+    let mut last_write: Option<Instant> = None;
+    while let Some(msg) = rx.recv().await {
+        batch.push(msg);
+        let bursting = last_write.map(|t| t.elapsed() < config.linger).unwrap_or(false);
+        if bursting {
+            let deadline = Instant::now() + config.linger;
+            while batch.len() < config.max_batch {
+                match time::timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(msg)) => batch.push(msg),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+        }
+        while batch.len() < config.max_batch {
+            match rx.try_recv() {
+                Ok(msg) => batch.push(msg),
+                Err(_) => break,
+            }
+        }
+        if shadow.load(Ordering::Relaxed) {
+            for msg in batch.drain(..) {
+                info!("shadow: not routing {msg:?}");
+            }
+        } else if let Err(e) = driver.send_batch_to(target, batch.drain(..)) {
+            error!("error sending orderflow batch: {e:?}");
+        }
+        last_write = Some(Instant::now());
+    }
+}
@@ -0,0 +1,277 @@
+//! Bracket orders: an entry with a take profit and a stop loss, managed
+//! client side.
+//!
+//! Once the entry is done (fully filled, or out after a partial fill) the
+//! take profit is placed as a resting limit for the filled quantity.  The
+//! stop loss is held locally and fed marks with `on_mark`; when the mark
+//! trades through it, the take profit is canceled and the stop is sent as a
+//! limit `stop_slippage` beyond the stop price.  Whichever exit fills first
+//! cancels the other: one cancels the other (OCO).
+//!
+//! Feed `on_event` the tracker's events and send the `IntentAction`s it
+//! returns.  To survive a restart, persist `snapshot` with the spec and
+//! rebuild with `Bracket::resume`, which reconciles the snapshot with the
+//! current state of its orders in an `OrderLog`, e.g. one restored from a
+//! journal or the Oms.
+
+use super::{
+    intent::IntentAction,
+    state::OrderLog,
+    tracker::{OrderEvent, OrderRequest},
+};
+use anyhow::{bail, Result};
+use api::{Dir, OrderId};
+use log::info;
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BracketSpec {
+    pub entry: OrderRequest,
+    pub take_profit: Decimal,
+    pub stop_loss: Decimal,
+    /// how far through the stop price the stop's limit is set
+    pub stop_slippage: Decimal,
+}
+
+impl BracketSpec {
+    pub fn validate(&self) -> Result<()> {
+        let OrderRequest { dir, price, .. } = self.entry;
+        let ok = match dir {
+            Dir::Buy => self.take_profit > price && self.stop_loss < price,
+            Dir::Sell => self.take_profit < price && self.stop_loss > price,
+        };
+        if !ok {
+            bail!("take profit and stop loss must be either side of the entry");
+        }
+        if self.stop_slippage < Decimal::ZERO {
+            bail!("stop slippage must not be negative");
+        }
+        Ok(())
+    }
+
+    fn exit_dir(&self) -> Dir {
+        match self.entry.dir {
+            Dir::Buy => Dir::Sell,
+            Dir::Sell => Dir::Buy,
+        }
+    }
+
+    fn stop_triggered(&self, mark: Decimal) -> bool {
+        match self.entry.dir {
+            Dir::Buy => mark <= self.stop_loss,
+            Dir::Sell => mark >= self.stop_loss,
+        }
+    }
+
+    fn stop_price(&self) -> Decimal {
+        match self.entry.dir {
+            Dir::Buy => self.stop_loss - self.stop_slippage,
+            Dir::Sell => self.stop_loss + self.stop_slippage,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BracketPhase {
+    /// the entry is working
+    Entry,
+    /// the position is on and the exits are armed
+    Exits,
+    Done,
+}
+
+/// The persistent state of a bracket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BracketSnapshot {
+    pub phase: BracketPhase,
+    pub entry_filled: Decimal,
+    pub exited: Decimal,
+    pub take_profit_id: Option<OrderId>,
+    /// set once the stop triggered
+    pub stop_id: Option<OrderId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Bracket {
+    spec: BracketSpec,
+    st: BracketSnapshot,
+}
+
+impl Bracket {
+    /// A bracket whose entry is about to be sent; send `spec.entry`
+    pub fn new(spec: BracketSpec) -> Result<Self> {
+        spec.validate()?;
+        let st = BracketSnapshot {
+            phase: BracketPhase::Entry,
+            entry_filled: Decimal::ZERO,
+            exited: Decimal::ZERO,
+            take_profit_id: None,
+            stop_id: None,
+        };
+        Ok(Self { spec, st })
+    }
+
+    pub fn spec(&self) -> &BracketSpec {
+        &self.spec
+    }
+
+    pub fn snapshot(&self) -> BracketSnapshot {
+        self.st
+    }
+
+    pub fn phase(&self) -> BracketPhase {
+        self.st.phase
+    }
+
+    /// The quantity still to exit
+    pub fn open_quantity(&self) -> Decimal {
+        self.st.entry_filled - self.st.exited
+    }
+
+    fn is_leg(&self, id: OrderId) -> bool {
+        id == self.spec.entry.id
+            || Some(id) == self.st.take_profit_id
+            || Some(id) == self.st.stop_id
+    }
+
+    fn exit(&self, id: OrderId, price: Decimal) -> IntentAction {
+        IntentAction::Place(OrderRequest {
+            id,
+            market: self.spec.entry.market,
+            dir: self.spec.exit_dir(),
+            price,
+            quantity: self.open_quantity(),
+        })
+    }
+
+    fn arm(&mut self, next_order_id: &mut impl FnMut() -> OrderId) -> Vec<IntentAction> {
+        if self.st.entry_filled.is_zero() {
+            self.st.phase = BracketPhase::Done;
+            return vec![];
+        }
+        self.st.phase = BracketPhase::Exits;
+        let id = next_order_id();
+        self.st.take_profit_id = Some(id);
+        vec![self.exit(id, self.spec.take_profit)]
+    }
+
+    /// Apply a tracker event; returns the orders to send and cancel
+    pub fn on_event(
+        &mut self,
+        ev: &OrderEvent,
+        mut next_order_id: impl FnMut() -> OrderId,
+    ) -> Vec<IntentAction> {
+        let id = ev.id();
+        if !self.is_leg(id) || self.st.phase == BracketPhase::Done {
+            return vec![];
+        }
+        let entry = id == self.spec.entry.id;
+        match ev {
+            OrderEvent::Fill { quantity, .. } if entry => {
+                self.st.entry_filled += quantity;
+                if self.st.entry_filled >= self.spec.entry.quantity {
+                    return self.arm(&mut next_order_id);
+                }
+            }
+            OrderEvent::Out(_) | OrderEvent::Reject { .. }
+                if entry && self.st.phase == BracketPhase::Entry =>
+            {
+                return self.arm(&mut next_order_id);
+            }
+            OrderEvent::Fill { quantity, .. } => {
+                self.st.exited += quantity;
+                if self.open_quantity() <= Decimal::ZERO {
+                    self.st.phase = BracketPhase::Done;
+                    // the other exit, if it's live
+                    let other = if Some(id) == self.st.take_profit_id {
+                        self.st.stop_id
+                    } else {
+                        self.st.take_profit_id
+                    };
+                    return other.map(IntentAction::Cancel).into_iter().collect();
+                }
+            }
+            _ => (),
+        }
+        vec![]
+    }
+
+    /// Check the stop against a mark; when it triggers, returns the cancel
+    /// of the take profit and the stop order
+    pub fn on_mark(
+        &mut self,
+        mark: Decimal,
+        mut next_order_id: impl FnMut() -> OrderId,
+    ) -> Vec<IntentAction> {
+        if self.st.phase != BracketPhase::Exits
+            || self.st.stop_id.is_some()
+            || !self.spec.stop_triggered(mark)
+        {
+            return vec![];
+        }
+        info!("bracket {:?} stop triggered at {mark}", self.spec.entry.id);
+        let mut actions: Vec<IntentAction> =
+            self.st.take_profit_id.map(IntentAction::Cancel).into_iter().collect();
+        let id = next_order_id();
+        self.st.stop_id = Some(id);
+        actions.push(self.exit(id, self.spec.stop_price()));
+        actions
+    }
+
+    /// Rebuild a bracket after a restart from its last snapshot, catching
+    /// up on what happened to its orders since from `log`.  Returns the
+    /// bracket and any actions needed to restore its invariants.
+    pub fn resume(
+        spec: BracketSpec,
+        snapshot: BracketSnapshot,
+        log: &OrderLog,
+        mut next_order_id: impl FnMut() -> OrderId,
+    ) -> Result<(Self, Vec<IntentAction>)> {
+        spec.validate()?;
+        let mut b = Self { spec, st: snapshot };
+        let filled = |id: Option<OrderId>| {
+            id.and_then(|id| log.get(&id)).map(|o| o.filled).unwrap_or_default()
+        };
+        let live = |id: Option<OrderId>| {
+            id.and_then(|id| log.get(&id)).is_some_and(|o| !o.state.is_done())
+        };
+        if let Some(entry) = log.get(&spec.entry.id) {
+            b.st.entry_filled = b.st.entry_filled.max(entry.filled);
+        }
+        b.st.exited = b.st.exited.max(filled(b.st.take_profit_id) + filled(b.st.stop_id));
+        let entry_done = log.get(&spec.entry.id).map_or(true, |o| o.state.is_done());
+        let mut actions = vec![];
+        match b.st.phase {
+            BracketPhase::Entry if entry_done => actions = b.arm(&mut next_order_id),
+            BracketPhase::Entry => (),
+            BracketPhase::Exits if b.open_quantity() <= Decimal::ZERO => {
+                b.st.phase = BracketPhase::Done;
+                for id in [b.st.take_profit_id, b.st.stop_id] {
+                    if live(id) {
+                        actions.extend(id.map(IntentAction::Cancel));
+                    }
+                }
+            }
+            BracketPhase::Exits => {
+                let exiting = live(b.st.take_profit_id) || live(b.st.stop_id);
+                if !exiting {
+                    // the resting exit was lost while we were down, put
+                    // back whichever leg was working
+                    let id = next_order_id();
+                    let price = if b.st.stop_id.is_some() {
+                        b.st.stop_id = Some(id);
+                        b.spec.stop_price()
+                    } else {
+                        b.st.take_profit_id = Some(id);
+                        b.spec.take_profit
+                    };
+                    actions.push(b.exit(id, price));
+                }
+            }
+            BracketPhase::Done => (),
+        }
+        Ok((b, actions))
+    }
+}
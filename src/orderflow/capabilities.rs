@@ -0,0 +1,195 @@
+//! What each execution venue supports: native order types and time in
+//! force instructions, post only, amends, and rate limits.
+//!
+//! `CapabilityRegistry` answers per venue from, in order, capabilities set
+//! explicitly or read from config, then a loader (e.g. a query of the
+//! venue's execution info) whose answers are cached, then a conservative
+//! default: limit orders only, GTC only, no post only or amends.  The TIF
+//! emulator, the rate monitor and order validation consult it, and
+//! strategies can use it to adapt per venue.
+//!
+//! In the core config file they live under `venue_capabilities`:
+//!
+//! ```yaml
+//! venue_capabilities:
+//!   BINANCE:
+//!     order_types: [limit, market]
+//!     tif: { ioc: true, fok: true }
+//!     post_only: true
+//!     amend: false
+//!     max_orders_per_sec: 50
+//! ```
+
+use super::{
+    message_rate::RateLimits,
+    tif::{TifCapabilities, TimeInForce},
+};
+use crate::symbology::VenueRef;
+use anyhow::{bail, Result};
+use fxhash::FxHashMap;
+use parking_lot::RwLock;
+use serde_derive::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderKind {
+    Limit,
+    Market,
+    Stop,
+    StopLimit,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VenueCapabilities {
+    #[serde(default = "VenueCapabilities::default_order_types")]
+    pub order_types: Vec<OrderKind>,
+    #[serde(default)]
+    pub tif: TifCapabilities,
+    #[serde(default)]
+    pub post_only: bool,
+    /// amends in place; otherwise a modify is a cancel and replace
+    #[serde(default)]
+    pub amend: bool,
+    #[serde(default)]
+    pub max_messages_per_sec: Option<u32>,
+    #[serde(default)]
+    pub max_orders_per_sec: Option<u32>,
+    #[serde(default)]
+    pub max_cancels_per_sec: Option<u32>,
+}
+
+impl Default for VenueCapabilities {
+    fn default() -> Self {
+        Self {
+            order_types: Self::default_order_types(),
+            tif: TifCapabilities::default(),
+            post_only: false,
+            amend: false,
+            max_messages_per_sec: None,
+            max_orders_per_sec: None,
+            max_cancels_per_sec: None,
+        }
+    }
+}
+
+impl VenueCapabilities {
+    fn default_order_types() -> Vec<OrderKind> {
+        vec![OrderKind::Limit]
+    }
+
+    pub fn supports(&self, kind: OrderKind) -> bool {
+        self.order_types.contains(&kind)
+    }
+
+    /// Check an order can be sent to the venue.  Time in force
+    /// instructions the venue lacks are emulated, see `tif`, so only order
+    /// types and post only are checked.
+    pub fn validate(
+        &self,
+        kind: OrderKind,
+        tif: &TimeInForce,
+        post_only: bool,
+    ) -> Result<()> {
+        if !self.supports(kind) {
+            bail!("{kind:?} orders are not supported");
+        }
+        if post_only && !self.post_only {
+            bail!("post only orders are not supported");
+        }
+        if post_only
+            && !matches!(tif, TimeInForce::GoodTilCancel | TimeInForce::GoodTilDate(_))
+        {
+            bail!("post only orders must rest, not {tif:?}");
+        }
+        Ok(())
+    }
+
+    /// The venue's rate limits for `MessageRateMonitor`, if it has any
+    pub fn rate_limits(&self) -> Option<RateLimits> {
+        if self.max_messages_per_sec.is_none()
+            && self.max_orders_per_sec.is_none()
+            && self.max_cancels_per_sec.is_none()
+        {
+            return None;
+        }
+        Some(RateLimits {
+            window: Duration::from_secs(1),
+            max_messages: self.max_messages_per_sec,
+            max_orders: self.max_orders_per_sec,
+            max_cancels: self.max_cancels_per_sec,
+            ..Default::default()
+        })
+    }
+}
+
+type Loader = Box<dyn Fn(&VenueRef) -> Option<VenueCapabilities> + Send + Sync>;
+
+#[derive(Default)]
+pub struct CapabilityRegistry {
+    default: Arc<VenueCapabilities>,
+    /// by venue name, explicit or loaded
+    venues: RwLock<FxHashMap<String, Arc<VenueCapabilities>>>,
+    loader: Option<Loader>,
+}
+
+impl CapabilityRegistry {
+    pub fn new(default: VenueCapabilities) -> Self {
+        Self { default: Arc::new(default), ..Default::default() }
+    }
+
+    /// Ask `loader` about venues with nothing set; its answers are cached
+    pub fn with_loader(
+        mut self,
+        loader: impl Fn(&VenueRef) -> Option<VenueCapabilities> + Send + Sync + 'static,
+    ) -> Self {
+        self.loader = Some(Box::new(loader));
+        self
+    }
+
+    pub fn set(&self, venue: &str, capabilities: VenueCapabilities) {
+        self.venues.write().insert(venue.to_string(), Arc::new(capabilities));
+    }
+
+    /// Forget a venue, so it's loaded again on next use
+    pub fn invalidate(&self, venue: &str) {
+        self.venues.write().remove(venue);
+    }
+
+    pub fn get(&self, venue: &VenueRef) -> Arc<VenueCapabilities> {
+        if let Some(c) = self.venues.read().get(venue.name.as_str()) {
+            return c.clone();
+        }
+        match self.loader.as_ref().and_then(|l| l(venue)) {
+            Some(c) => {
+                let c = Arc::new(c);
+                self.venues.write().insert(venue.name.to_string(), c.clone());
+                c
+            }
+            None => self.default.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "netidx")]
+impl CapabilityRegistry {
+    /// Read the `venue_capabilities` section of a core config file; a file
+    /// without one gets an empty registry
+    pub fn from_config_file(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        use anyhow::Context;
+        let path = path.as_ref();
+        let f: serde_yaml::Value = serde_yaml::from_slice(
+            &std::fs::read(path)
+                .with_context(|| format!("reading config {}", path.display()))?,
+        )?;
+        let t = Self::default();
+        if let Some(v) = f.get("venue_capabilities") {
+            let venues: FxHashMap<String, VenueCapabilities> =
+                serde_yaml::from_value(v.clone())?;
+            for (venue, c) in venues {
+                t.set(&venue, c);
+            }
+        }
+        Ok(t)
+    }
+}
@@ -0,0 +1,349 @@
+//! Simple orderflow client suitable for connecting to an Oms or directly
+//! to a Cpty.  It handles tracking order ids and passing orderflow messages.
+
+use super::{
+    batch::{BatchConfig, BatchedSender},
+    clip::{ClipLimits, ClipMode, ClippedOrder},
+    kill_switch::KillSwitch,
+    mass_cancel::CancelAllFilter,
+    risk::{RiskChecker, RiskSnapshot},
+    shadow::{ShadowExchange, ShadowOrder},
+    state::TrackedOrder,
+    tracker::{OrderRequest, OrderTracker},
+};
+use crate::{AtomicOrderIdAllocator, ChannelDriver, Common};
+use anyhow::{anyhow, bail, Result};
+use api::{orderflow::*, ComponentId, TypedMessage};
+use chrono::Utc;
+use log::{error, info, warn};
+use parking_lot::RwLock;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::Mutex, task::JoinHandle};
+
+pub struct OrderflowClient {
+    driver: Arc<ChannelDriver>,
+    target: ComponentId,
+    order_ids: Arc<AtomicOrderIdAllocator>,
+    shadow: Arc<AtomicBool>,
+    risk: RwLock<Option<Arc<RiskChecker>>>,
+    shadow_exchange: RwLock<Option<Arc<parking_lot::Mutex<ShadowExchange>>>>,
+    kill_switch: KillSwitch,
+}
+
+impl OrderflowClient {
+    /// Connect to a component that implements an orderflow interface.  If no target is specified,
+    /// search for an "Oms" component in the config.  If no order authority is specified, search
+    /// for a "OrderAuthority" component in the config.
+    ///
+    /// If no order id range is specified, defaults to 100.
+    pub fn new(
+        common: &Common,
+        driver: Arc<ChannelDriver>,
+        target: Option<ComponentId>,
+        // if specified, resume order ids from the given seqid/seqno
+        order_ids: Option<AtomicOrderIdAllocator>,
+    ) -> Result<Self> {
+        let target = target
+            .or_else(|| {
+                info!("no target specified; searching for an Oms in config...");
+                common.get_component_of_kind("Oms")
+            })
+            .ok_or_else(|| anyhow!("no target found"))?;
+        let order_ids = order_ids.unwrap_or_else(AtomicOrderIdAllocator::new);
+        Ok(Self {
+            driver,
+            target,
+            order_ids: Arc::new(order_ids),
+            shadow: Arc::new(AtomicBool::new(false)),
+            risk: RwLock::new(None),
+            shadow_exchange: RwLock::new(None),
+            kill_switch: KillSwitch::new(),
+        })
+    }
+
+    /// Share a kill switch, e.g. the one P&L breakers trip, instead of the
+    /// client's own
+    pub fn with_kill_switch(mut self, kill_switch: KillSwitch) -> Self {
+        self.kill_switch = kill_switch;
+        self
+    }
+
+    /// While tripped, `place` refuses new orders
+    pub fn kill_switch(&self) -> &KillSwitch {
+        &self.kill_switch
+    }
+
+    /// Get the next order id.
+    pub fn next_order_id(&self) -> OrderId {
+        self.order_ids.next_order_id()
+    }
+
+    /// Send a message to the configured target.  In shadow mode the message
+    /// is logged and dropped instead; only orders placed and canceled
+    /// through `place` and friends reach the shadow exchange.
+    pub fn send<M>(&self, msg: M) -> Result<()>
+    where
+        M: Into<TypedMessage>,
+    {
+        if self.is_shadow() {
+            let msg: TypedMessage = msg.into();
+            info!("shadow: not routing {msg:?}");
+            return Ok(());
+        }
+        self.driver.send_to(self.target, msg)
+    }
+
+    /// Send several messages to the target in one channel write; for a
+    /// batch of new orders use `place_all`, which also checks and tracks
+    /// them.  In shadow mode the messages are logged and dropped instead.
+    pub fn send_all<M>(&self, msgs: impl IntoIterator<Item = M>) -> Result<()>
+    where
+        M: Into<TypedMessage>,
+    {
+        if self.is_shadow() {
+            for msg in msgs {
+                let msg: TypedMessage = msg.into();
+                info!("shadow: not routing {msg:?}");
+            }
+            return Ok(());
+        }
+        self.driver.send_batch_to(self.target, msgs)
+    }
+
+    /// Check new orders sent with `place` against `risk` first, or stop
+    /// checking with `None`
+    pub fn set_risk_checker(&self, risk: Option<Arc<RiskChecker>>) {
+        *self.risk.write() = risk;
+    }
+
+    /// Send a new order and record it with the tracker.  `msg` is the wire
+    /// message for `req`.  Nothing is sent while the kill switch is tripped.
+    /// If a risk checker is set the order is checked first, and a failed
+    /// check is returned as a `RiskRejection` error without sending
+    /// anything.
+    pub fn place<M>(
+        &self,
+        tracker: &mut OrderTracker,
+        req: OrderRequest,
+        msg: M,
+    ) -> Result<()>
+    where
+        M: Into<TypedMessage>,
+    {
+        if let Some(reason) = self.kill_switch.reason() {
+            bail!("kill switch tripped, not placing {:?}: {reason}", req.id);
+        }
+        if let Some(risk) = &*self.risk.read() {
+            let snapshot = RiskSnapshot::capture(tracker);
+            if let Err(rejection) = risk.check_snapshot(&req, &snapshot) {
+                warn!("risk rejected order {:?}: {rejection}", req.id);
+                return Err(rejection.into());
+            }
+        }
+        self.send(msg)?;
+        self.mirror_place(&req);
+        tracker.on_sent(req, Utc::now());
+        Ok(())
+    }
+
+    /// Send a batch of new orders in one channel write and record them with
+    /// the tracker, returning their ids for `order_batch::await_batch`.
+    /// Each pair is an order and its wire message.  Like `place`, nothing is
+    /// sent while the kill switch is tripped; with a risk checker set, each
+    /// order is checked counting the orders ahead of it in the batch as
+    /// open, and if any fails nothing is sent.
+    pub fn place_all<M>(
+        &self,
+        tracker: &mut OrderTracker,
+        orders: Vec<(OrderRequest, M)>,
+    ) -> Result<Vec<OrderId>>
+    where
+        M: Into<TypedMessage>,
+    {
+        if let Some(reason) = self.kill_switch.reason() {
+            bail!("kill switch tripped, not placing {} orders: {reason}", orders.len());
+        }
+        if let Some(risk) = &*self.risk.read() {
+            let mut snapshot = RiskSnapshot::capture(tracker);
+            let now = Utc::now();
+            for (req, _) in &orders {
+                if let Err(rejection) = risk.check_snapshot(req, &snapshot) {
+                    warn!("risk rejected order {:?} of a batch: {rejection}", req.id);
+                    return Err(rejection.into());
+                }
+                snapshot.open_orders.push(TrackedOrder::sent(*req, now));
+            }
+        }
+        let (reqs, msgs): (Vec<_>, Vec<_>) = orders.into_iter().unzip();
+        self.send_all(msgs)?;
+        let now = Utc::now();
+        for req in &reqs {
+            self.mirror_place(req);
+            tracker.on_sent(*req, now);
+        }
+        Ok(reqs.into_iter().map(|r| r.id).collect())
+    }
+
+    /// Place an order sliced to the venue's max clip, see `clip`.  `to_msg`
+    /// builds the wire message for each clip.  With `ClipMode::Sequential`,
+    /// place the clips `ClippedOrder::on_event` returns as clips complete.
+    pub fn place_clipped<M>(
+        &self,
+        tracker: &mut OrderTracker,
+        limits: &ClipLimits,
+        parent: OrderRequest,
+        mode: ClipMode,
+        to_msg: impl Fn(&OrderRequest) -> M,
+    ) -> Result<ClippedOrder>
+    where
+        M: Into<TypedMessage>,
+    {
+        let (clipped, clips) = limits.slice(parent, mode, || self.next_order_id());
+        for clip in clips {
+            self.place(tracker, clip, to_msg(&clip))?;
+        }
+        Ok(clipped)
+    }
+
+    /// Cancel every open order in the tracker that matches `filter`, in one
+    /// channel write, and record the cancels with the tracker.  `cancel`
+    /// builds the cancel message for an order.  Returns the ids of the
+    /// orders canceled.
+    pub fn cancel_all<M>(
+        &self,
+        tracker: &mut OrderTracker,
+        filter: &CancelAllFilter,
+        cancel: impl Fn(OrderId) -> M,
+    ) -> Result<Vec<OrderId>>
+    where
+        M: Into<TypedMessage>,
+    {
+        let ids = filter.select(tracker);
+        if ids.is_empty() {
+            return Ok(ids);
+        }
+        warn!("canceling {} orders matching {filter:?}", ids.len());
+        self.send_all(ids.iter().map(|id| cancel(*id)))?;
+        let now = Utc::now();
+        for id in &ids {
+            self.mirror_cancel(*id);
+            tracker.on_cancel_sent(*id, now);
+        }
+        Ok(ids)
+    }
+
+    /// Trip the kill switch if the connection to the target stays down
+    /// longer than `timeout`
+    pub fn enable_cancel_on_disconnect(&self, timeout: Duration) -> JoinHandle<()> {
+        let mut connected = self.driver.subscribe_connected();
+        let kill_switch = self.kill_switch.clone();
+        tokio::spawn(async move {
+            loop {
+                if connected.wait_for(|c| *c).await.is_err()
+                    || connected.wait_for(|c| !*c).await.is_err()
+                {
+                    break;
+                }
+                let reconnect = connected.wait_for(|c| *c);
+                if tokio::time::timeout(timeout, reconnect).await.is_err() {
+                    kill_switch.trip(format!("disconnected for more than {timeout:?}"));
+                }
+            }
+        })
+    }
+
+    /// Cancel every open order in the tracker each time the kill switch
+    /// trips, once connected; `cancel` builds the cancel message for an
+    /// order
+    pub fn cancel_all_on_kill<M>(
+        self: &Arc<Self>,
+        tracker: Arc<Mutex<OrderTracker>>,
+        cancel: impl Fn(OrderId) -> M + Send + Sync + 'static,
+    ) -> JoinHandle<()>
+    where
+        M: Into<TypedMessage>,
+    {
+        let client = self.clone();
+        let mut tripped = self.kill_switch.subscribe();
+        tokio::spawn(async move {
+            loop {
+                if tripped.wait_for(|r| r.is_some()).await.is_err() {
+                    break;
+                }
+                if let Err(e) = client.driver.wait_connected().await {
+                    error!("kill switch cancel all: {e:?}");
+                    break;
+                }
+                let mut tracker = tracker.lock().await;
+                let filter = CancelAllFilter::default();
+                if let Err(e) = client.cancel_all(&mut tracker, &filter, &cancel) {
+                    error!("kill switch cancel all: {e:?}");
+                }
+                drop(tracker);
+                if tripped.wait_for(|r| r.is_none()).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// A sender that coalesces queued messages to the same target into
+    /// fewer channel writes, see `batch` for the latency tradeoff.  Shadow
+    /// mode applies to the batched sender too.
+    pub fn batched(&self, config: BatchConfig) -> BatchedSender {
+        BatchedSender::new(self.driver.clone(), self.target, self.shadow.clone(), config)
+    }
+
+    /// Switch shadow mode on or off at runtime; see `set_shadow_exchange`
+    /// for simulating the lifecycle of orders that aren't routed.
+    pub fn set_shadow(&self, shadow: bool) {
+        self.shadow.store(shadow, Ordering::Relaxed)
+    }
+
+    /// In shadow mode, mirror the orders placed and canceled through this
+    /// client into `exchange`, or stop with `None`
+    pub fn set_shadow_exchange(
+        &self,
+        exchange: Option<Arc<parking_lot::Mutex<ShadowExchange>>>,
+    ) {
+        *self.shadow_exchange.write() = exchange;
+    }
+
+    fn mirror_place(&self, req: &OrderRequest) {
+        if !self.is_shadow() {
+            return;
+        }
+        if let Some(exchange) = &*self.shadow_exchange.read() {
+            exchange.lock().place(ShadowOrder {
+                id: req.id,
+                market: req.market,
+                dir: req.dir,
+                price: req.price,
+                quantity: req.quantity,
+            });
+        }
+    }
+
+    fn mirror_cancel(&self, id: OrderId) {
+        if !self.is_shadow() {
+            return;
+        }
+        if let Some(exchange) = &*self.shadow_exchange.read() {
+            exchange.lock().cancel(id);
+        }
+    }
+
+    pub fn is_shadow(&self) -> bool {
+        self.shadow.load(Ordering::Relaxed)
+    }
+
+    pub fn driver(&self) -> &ChannelDriver {
+        &self.driver
+    }
+}
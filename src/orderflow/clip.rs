@@ -0,0 +1,208 @@
+//! Slice orders larger than a venue's maximum clip size.
+//!
+//! `ClipLimits` holds the largest order each venue accepts.  `slice` turns
+//! a parent order into child clips no larger than that, rounded down to the
+//! market's step size: all at once with `ClipMode::Concurrent`, or one at a
+//! time with `ClipMode::Sequential`, where `on_event` sends the next clip
+//! once the working one is done.  `status` aggregates the children's
+//! states in the tracker's `OrderLog` into a `TrackedOrder` for the parent,
+//! so callers can work with natural sizes and see one order.
+
+use super::{
+    state::{OrderLog, TrackedOrder, TrackedOrderState},
+    tracker::{OrderEvent, OrderRequest},
+};
+use crate::symbology::MarketRef;
+use api::OrderId;
+use chrono::{DateTime, Utc};
+use fxhash::FxHashMap;
+use log::warn;
+use rust_decimal::Decimal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipMode {
+    /// send every clip at once
+    Concurrent,
+    /// send the next clip when the working one is done
+    Sequential,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClipLimits {
+    /// for venues not listed
+    pub default: Option<Decimal>,
+    /// by execution venue name
+    pub per_venue: FxHashMap<String, Decimal>,
+}
+
+impl ClipLimits {
+    pub fn max_clip(&self, market: &MarketRef) -> Option<Decimal> {
+        self.per_venue.get(market.venue.name.as_str()).copied().or(self.default)
+    }
+
+    /// Slice `parent` into clips; returns the clipped order and the clips to
+    /// send now.  An order within the limit is a single clip with the
+    /// parent's id.
+    pub fn slice(
+        &self,
+        parent: OrderRequest,
+        mode: ClipMode,
+        mut next_order_id: impl FnMut() -> OrderId,
+    ) -> (ClippedOrder, Vec<OrderRequest>) {
+        let step = parent.market.extra_info.step_size();
+        let clip = match self.max_clip(&parent.market) {
+            Some(max) if step > Decimal::ZERO => (max / step).floor() * step,
+            Some(max) => max,
+            None => parent.quantity,
+        };
+        let clip = if clip <= Decimal::ZERO {
+            warn!("max clip for {} is below the step size, not slicing", parent.market);
+            parent.quantity
+        } else {
+            clip
+        };
+        let mut t =
+            ClippedOrder { parent, clip, mode, children: vec![], canceled: false };
+        let clips = if parent.quantity <= clip {
+            t.children.push(parent.id);
+            vec![parent]
+        } else {
+            t.next_clips(None, &mut next_order_id)
+        };
+        (t, clips)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClippedOrder {
+    parent: OrderRequest,
+    clip: Decimal,
+    mode: ClipMode,
+    children: Vec<OrderId>,
+    canceled: bool,
+}
+
+impl ClippedOrder {
+    pub fn parent(&self) -> &OrderRequest {
+        &self.parent
+    }
+
+    pub fn children(&self) -> &[OrderId] {
+        &self.children
+    }
+
+    pub fn is_child(&self, id: &OrderId) -> bool {
+        self.children.contains(id)
+    }
+
+    /// The quantity children have taken up: what filled of the done ones
+    /// and all of the live ones
+    fn committed(&self, log: Option<&OrderLog>) -> Decimal {
+        self.children
+            .iter()
+            .map(|id| match log.and_then(|l| l.get(id)) {
+                Some(o) if o.state.is_done() => o.filled,
+                Some(o) => o.request.quantity,
+                None => Decimal::ZERO,
+            })
+            .sum()
+    }
+
+    fn next_clips(
+        &mut self,
+        log: Option<&OrderLog>,
+        next_order_id: &mut impl FnMut() -> OrderId,
+    ) -> Vec<OrderRequest> {
+        let mut remaining = self.parent.quantity - self.committed(log);
+        let mut clips = vec![];
+        while remaining > Decimal::ZERO {
+            let quantity = remaining.min(self.clip);
+            let req = OrderRequest { id: next_order_id(), quantity, ..self.parent };
+            self.children.push(req.id);
+            clips.push(req);
+            remaining -= quantity;
+            if self.mode == ClipMode::Sequential {
+                break;
+            }
+        }
+        clips
+    }
+
+    /// Call after the tracker has applied `ev`; returns the next clip to
+    /// send, if one is due.  A rejected clip stops the parent.
+    pub fn on_event(
+        &mut self,
+        ev: &OrderEvent,
+        log: &OrderLog,
+        mut next_order_id: impl FnMut() -> OrderId,
+    ) -> Vec<OrderRequest> {
+        if self.mode != ClipMode::Sequential || self.canceled || !self.is_child(&ev.id())
+        {
+            return vec![];
+        }
+        let children: Vec<&TrackedOrder> =
+            self.children.iter().filter_map(|id| log.get(id)).collect();
+        if children.iter().any(|o| o.state == TrackedOrderState::Rejected) {
+            self.canceled = true;
+            return vec![];
+        }
+        if children.iter().any(|o| !o.state.is_done()) {
+            return vec![];
+        }
+        self.next_clips(Some(log), &mut next_order_id)
+    }
+
+    /// Stop sending clips; returns the live children to cancel
+    pub fn cancel(&mut self, log: &OrderLog) -> Vec<OrderId> {
+        self.canceled = true;
+        self.children
+            .iter()
+            .filter(|id| {
+                log.get(id).is_some_and(|o| {
+                    !o.state.is_done() && o.state != TrackedOrderState::Canceling
+                })
+            })
+            .copied()
+            .collect()
+    }
+
+    /// The parent as one order, aggregated from its children in `log`
+    pub fn status(&self, log: &OrderLog, now: DateTime<Utc>) -> TrackedOrder {
+        let children: Vec<&TrackedOrder> =
+            self.children.iter().filter_map(|id| log.get(id)).collect();
+        let filled: Decimal = children.iter().map(|o| o.filled).sum();
+        let notional: Decimal = children
+            .iter()
+            .map(|o| o.avg_fill_price.unwrap_or_default() * o.filled)
+            .sum();
+        let live = |s| children.iter().any(|o| o.state == s);
+        let state = if filled >= self.parent.quantity {
+            TrackedOrderState::Filled
+        } else if live(TrackedOrderState::Open) {
+            TrackedOrderState::Open
+        } else if live(TrackedOrderState::Pending) {
+            TrackedOrderState::Pending
+        } else if live(TrackedOrderState::Canceling) {
+            TrackedOrderState::Canceling
+        } else if filled.is_zero() && live(TrackedOrderState::Rejected) {
+            TrackedOrderState::Rejected
+        } else if self.canceled || !children.is_empty() {
+            TrackedOrderState::Canceled
+        } else {
+            TrackedOrderState::Pending
+        };
+        let first = children.first();
+        TrackedOrder {
+            request: self.parent,
+            state,
+            filled,
+            avg_fill_price: (!filled.is_zero()).then(|| notional / filled),
+            reject_reason: children.iter().find_map(|o| o.reject_reason.clone()),
+            pending_modify: None,
+            owner: first.map(|o| o.owner).unwrap_or_default(),
+            sent_at: first.map_or(now, |o| o.sent_at),
+            cancel_sent_at: children.iter().filter_map(|o| o.cancel_sent_at).min(),
+            updated_at: children.iter().map(|o| o.updated_at).max().unwrap_or(now),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! Order defaults per venue and account, so order construction doesn't
+//! have to spell out the account, time in force, and quantity handling on
+//! every order.
+//!
+//! Defaults are resolved most specific first: an account's entry, then
+//! the venue's, then the global one.  The account itself is taken from the
+//! caller if given, else from the venue's entry, else from the global one.
+//!
+//! In the core config file they live under `order_defaults`:
+//!
+//! ```yaml
+//! order_defaults:
+//!   default:
+//!     tif: good_til_cancel
+//!     quantity: reject
+//!   venues:
+//!     BINANCE:
+//!       account: 5f1c0a3e-...
+//!       quantity: round_down
+//!   accounts:
+//!     5f1c0a3e-...:
+//!       tif: { good_for_secs: 30 }
+//! ```
+
+use super::{tif::TimeInForce, tracker::OrderRequest};
+use crate::symbology::MarketRef;
+use anyhow::{anyhow, bail, Result};
+use api::{AccountId, Dir, OrderId};
+use chrono::{DateTime, Duration, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+
+/// The config file form of a time in force; a fixed expiry date makes no
+/// sense as a default, so GTD is given as a lifetime instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DefaultTif {
+    GoodTilCancel,
+    ImmediateOrCancel,
+    FillOrKill,
+    GoodForSecs(u32),
+}
+
+impl DefaultTif {
+    pub fn resolve(&self, now: DateTime<Utc>) -> TimeInForce {
+        match self {
+            DefaultTif::GoodTilCancel => TimeInForce::GoodTilCancel,
+            DefaultTif::ImmediateOrCancel => TimeInForce::ImmediateOrCancel,
+            DefaultTif::FillOrKill => TimeInForce::FillOrKill,
+            DefaultTif::GoodForSecs(secs) => {
+                TimeInForce::GoodTilDate(now + Duration::seconds(*secs as i64))
+            }
+        }
+    }
+}
+
+/// What to do with a quantity that isn't a multiple of the market's step
+/// size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuantityIncrement {
+    #[default]
+    Reject,
+    RoundDown,
+    RoundNearest,
+}
+
+impl QuantityIncrement {
+    pub fn apply(&self, quantity: Decimal, step: Decimal) -> Result<Decimal> {
+        if step <= Decimal::ZERO || (quantity % step).is_zero() {
+            return Ok(quantity);
+        }
+        let rounded = match self {
+            QuantityIncrement::Reject => {
+                bail!("quantity {quantity} is not a multiple of the step size {step}")
+            }
+            QuantityIncrement::RoundDown => (quantity / step).floor() * step,
+            QuantityIncrement::RoundNearest => (quantity / step).round() * step,
+        };
+        if rounded.is_zero() {
+            bail!("quantity {quantity} rounds to zero at step size {step}");
+        }
+        Ok(rounded)
+    }
+}
+
+/// One layer of defaults, unset fields fall through to the next layer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DefaultsEntry {
+    #[serde(default)]
+    pub account: Option<AccountId>,
+    #[serde(default)]
+    pub tif: Option<DefaultTif>,
+    #[serde(default)]
+    pub quantity: Option<QuantityIncrement>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrderDefaultsConfig {
+    #[serde(default)]
+    pub default: DefaultsEntry,
+    /// keyed by venue name
+    #[serde(default)]
+    pub venues: FxHashMap<String, DefaultsEntry>,
+    #[serde(default)]
+    pub accounts: FxHashMap<AccountId, DefaultsEntry>,
+}
+
+#[cfg(feature = "netidx")]
+impl OrderDefaultsConfig {
+    /// Read the `order_defaults` section of a core config file; a file
+    /// without one gets empty defaults
+    pub fn from_config_file(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        use anyhow::Context;
+        let path = path.as_ref();
+        let f: serde_yaml::Value = serde_yaml::from_slice(
+            &std::fs::read(path)
+                .with_context(|| format!("reading config {}", path.display()))?,
+        )?;
+        match f.get("order_defaults") {
+            None => Ok(Self::default()),
+            Some(v) => Ok(serde_yaml::from_value(v.clone())?),
+        }
+    }
+}
+
+/// The defaults that apply to one order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedDefaults {
+    pub account: Option<AccountId>,
+    pub tif: DefaultTif,
+    pub quantity: QuantityIncrement,
+}
+
+/// An order built from defaults, with the account and time in force that
+/// go with it on the wire
+#[derive(Debug, Clone, Copy)]
+pub struct DefaultedOrder {
+    pub request: OrderRequest,
+    pub account: Option<AccountId>,
+    pub tif: TimeInForce,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OrderDefaults {
+    config: OrderDefaultsConfig,
+}
+
+impl OrderDefaults {
+    pub fn new(config: OrderDefaultsConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &OrderDefaultsConfig {
+        &self.config
+    }
+
+    /// Resolve the defaults for an order on `market`, sent from `account`
+    /// if given
+    pub fn resolve(
+        &self,
+        market: &MarketRef,
+        account: Option<AccountId>,
+    ) -> ResolvedDefaults {
+        let venue = self.config.venues.get(market.venue.name.as_str());
+        let account = account
+            .or_else(|| venue.and_then(|v| v.account))
+            .or(self.config.default.account);
+        let by_account = account.and_then(|a| self.config.accounts.get(&a));
+        let layers = [by_account, venue, Some(&self.config.default)];
+        let layers = || layers.into_iter().flatten();
+        ResolvedDefaults {
+            account,
+            tif: layers().find_map(|e| e.tif).unwrap_or(DefaultTif::GoodTilCancel),
+            quantity: layers().find_map(|e| e.quantity).unwrap_or_default(),
+        }
+    }
+
+    /// Build an order with the defaults for its market, applying the
+    /// quantity increment behavior to the market's step size
+    pub fn order(
+        &self,
+        id: OrderId,
+        market: MarketRef,
+        dir: Dir,
+        price: Decimal,
+        quantity: Decimal,
+        now: DateTime<Utc>,
+    ) -> Result<DefaultedOrder> {
+        self.apply(None, OrderRequest { id, market, dir, price, quantity }, now)
+    }
+
+    /// Apply the defaults to an existing request, sent from `account` if
+    /// given
+    pub fn apply(
+        &self,
+        account: Option<AccountId>,
+        mut request: OrderRequest,
+        now: DateTime<Utc>,
+    ) -> Result<DefaultedOrder> {
+        let market = request.market;
+        let defaults = self.resolve(&market, account);
+        request.quantity = defaults
+            .quantity
+            .apply(request.quantity, market.extra_info.step_size())
+            .map_err(|e| anyhow!("{market}: {e}"))?;
+        Ok(DefaultedOrder {
+            request,
+            account: defaults.account,
+            tif: defaults.tif.resolve(now),
+        })
+    }
+}
@@ -0,0 +1,249 @@
+//! Detect drift between locally computed positions and balances and the
+//! account summary reported by the venue or Oms.
+//!
+//! Apply fills to a `LocalLedger` as they arrive; periodically fetch the
+//! remote summary and `DriftDetector::check` the two.  Differences above
+//! tolerance are broadcast as `DriftEvent`s, which usually mean a missed
+//! or double counted fill.
+
+use crate::symbology::{MarketRef, ProductKind, ProductRef};
+use anyhow::Result;
+use api::Dir;
+use chrono::{DateTime, Utc};
+use fxhash::FxHashMap;
+use log::{error, warn};
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use std::{future::Future, hash::Hash, sync::Arc, time::Duration};
+use tokio::{sync::broadcast, task::JoinHandle};
+
+/// Positions by market and balances by product, signed
+#[derive(Debug, Clone, Default)]
+pub struct AccountSummary {
+    pub positions: FxHashMap<MarketRef, Decimal>,
+    pub balances: FxHashMap<ProductRef, Decimal>,
+}
+
+/// Positions and balances computed from fills seen locally, starting from
+/// a known summary
+#[derive(Debug, Clone, Default)]
+pub struct LocalLedger {
+    summary: AccountSummary,
+}
+
+impl LocalLedger {
+    pub fn new(initial: AccountSummary) -> Self {
+        Self { summary: initial }
+    }
+
+    /// Apply a fill.  For spot markets the base balance moves by the
+    /// quantity and the quote balance by the notional, both scaled by the
+    /// contract multiplier; for derivatives, which are margined, only the
+    /// position moves.  Either way the quote balance is charged `fee`.
+    pub fn apply_fill(
+        &mut self,
+        market: MarketRef,
+        dir: Dir,
+        quantity: Decimal,
+        price: Decimal,
+        fee: Decimal,
+    ) {
+        let signed = match dir {
+            Dir::Buy => quantity,
+            Dir::Sell => -quantity,
+        };
+        let base = market.base();
+        let multiplier = base.map(|p| p.kind.multiplier()).unwrap_or(Decimal::ONE);
+        let spot = base.is_some_and(|p| !is_derivative(&p.kind));
+        if spot {
+            if let Some(base) = base {
+                *self.summary.balances.entry(base).or_default() += signed * multiplier;
+            }
+        } else {
+            *self.summary.positions.entry(market).or_default() += signed;
+        }
+        if let Some(quote) = market.quote() {
+            let notional = if spot { signed * price * multiplier } else { Decimal::ZERO };
+            *self.summary.balances.entry(quote).or_default() -= notional + fee;
+        }
+    }
+
+    /// Undo a fill the venue busted
+    pub fn reverse_fill(
+        &mut self,
+        market: MarketRef,
+        dir: Dir,
+        quantity: Decimal,
+        price: Decimal,
+        fee: Decimal,
+    ) {
+        self.apply_fill(market, dir, -quantity, price, -fee)
+    }
+
+    /// Reset to a summary known to be correct, e.g. after a drift was
+    /// resolved
+    pub fn reset(&mut self, summary: AccountSummary) {
+        self.summary = summary;
+    }
+
+    pub fn summary(&self) -> &AccountSummary {
+        &self.summary
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DriftKind {
+    Position(MarketRef),
+    Balance(ProductRef),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DriftEvent {
+    pub kind: DriftKind,
+    pub local: Decimal,
+    pub remote: Decimal,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl DriftEvent {
+    /// remote minus local
+    pub fn drift(&self) -> Decimal {
+        self.remote - self.local
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DriftTolerance {
+    pub position: Decimal,
+    pub balance: Decimal,
+}
+
+pub struct DriftDetector {
+    tolerance: DriftTolerance,
+    tx: broadcast::Sender<DriftEvent>,
+}
+
+impl DriftDetector {
+    pub fn new(tolerance: DriftTolerance) -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self { tolerance, tx }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DriftEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Compare local against remote, treating missing entries as zero;
+    /// broadcast and return every difference above tolerance
+    pub fn check(
+        &self,
+        local: &AccountSummary,
+        remote: &AccountSummary,
+        now: DateTime<Utc>,
+    ) -> Vec<DriftEvent> {
+        let mut events = vec![];
+        for (market, local, remote) in pairs(&local.positions, &remote.positions) {
+            if (remote - local).abs() > self.tolerance.position {
+                events.push(DriftEvent {
+                    kind: DriftKind::Position(market),
+                    local,
+                    remote,
+                    timestamp: now,
+                });
+            }
+        }
+        for (product, local, remote) in pairs(&local.balances, &remote.balances) {
+            if (remote - local).abs() > self.tolerance.balance {
+                events.push(DriftEvent {
+                    kind: DriftKind::Balance(product),
+                    local,
+                    remote,
+                    timestamp: now,
+                });
+            }
+        }
+        for ev in &events {
+            warn!("account drift {:?}: local {} remote {}", ev.kind, ev.local, ev.remote);
+            let _ = self.tx.send(*ev);
+        }
+        events
+    }
+
+    /// Check `ledger` against `fetch` every `interval` in the background.
+    /// Fills in flight while the summary is fetched show up as transient
+    /// drift; consumers may want to act only on drift that persists.
+    pub fn spawn_reconciler<F, Fut>(
+        self: Arc<Self>,
+        ledger: Arc<Mutex<LocalLedger>>,
+        interval: Duration,
+        mut fetch: F,
+    ) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<AccountSummary>> + Send,
+    {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                match fetch().await {
+                    Ok(remote) => {
+                        let local = ledger.lock().summary.clone();
+                        self.check(&local, &remote, Utc::now());
+                    }
+                    Err(e) => error!("fetching account summary: {e:?}"),
+                }
+            }
+        })
+    }
+}
+
+fn is_derivative(kind: &ProductKind) -> bool {
+    matches!(
+        kind,
+        ProductKind::Future { .. }
+            | ProductKind::FutureSpread { .. }
+            | ProductKind::Perpetual { .. }
+            | ProductKind::Option { .. }
+    )
+}
+
+fn pairs<K: Copy + Eq + Hash>(
+    local: &FxHashMap<K, Decimal>,
+    remote: &FxHashMap<K, Decimal>,
+) -> impl Iterator<Item = (K, Decimal, Decimal)> {
+    let mut all: FxHashMap<K, (Decimal, Decimal)> = FxHashMap::default();
+    for (k, v) in local {
+        all.entry(*k).or_default().0 = *v;
+    }
+    for (k, v) in remote {
+        all.entry(*k).or_default().1 = *v;
+    }
+    all.into_iter().map(|(k, (l, r))| (k, l, r))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbology::test_market;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_round_trip_leaves_no_drift() -> Result<()> {
+        let market = test_market("EURUSD", "EUR", "USD")?;
+        let (eur, usd) = (market.base().unwrap(), market.quote().unwrap());
+        let mut initial = AccountSummary::default();
+        initial.balances.insert(usd, dec!(1000));
+        let mut ledger = LocalLedger::new(initial.clone());
+        ledger.apply_fill(market, Dir::Buy, dec!(10), dec!(1.1), Decimal::ZERO);
+        assert_eq!(ledger.summary().balances[&eur], dec!(10));
+        assert_eq!(ledger.summary().balances[&usd], dec!(989));
+        ledger.apply_fill(market, Dir::Sell, dec!(10), dec!(1.1), Decimal::ZERO);
+        let detector = DriftDetector::new(DriftTolerance {
+            position: Decimal::ZERO,
+            balance: Decimal::ZERO,
+        });
+        assert!(detector.check(ledger.summary(), &initial, Utc::now()).is_empty());
+        Ok(())
+    }
+}
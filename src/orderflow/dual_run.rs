@@ -0,0 +1,206 @@
+//! Compare orderflow across two transports during a migration.
+//!
+//! When moving a strategy from one orderflow transport to another (e.g. from
+//! the netidx `OrderflowClient` to a gRPC client), route real orders over
+//! one of them and mirror the same orders, in dry run or against a test
+//! account, over the other.  Feed both transports' updates, as tracker
+//! `OrderEvent`s under the same order ids, into a `DualRunComparator`: it
+//! pairs each event with its counterpart from the other transport and
+//! reports a `Divergence` when the counterpart never arrives or doesn't
+//! agree, e.g. a fill at a different price.
+
+use super::tracker::OrderEvent;
+use api::OrderId;
+use chrono::{DateTime, Duration, Utc};
+use fxhash::FxHashMap;
+use log::warn;
+use rust_decimal::Decimal;
+use std::mem::discriminant;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// the transport real orders are routed over
+    Primary,
+    /// the transport being shadowed
+    Secondary,
+}
+
+impl Transport {
+    fn other(&self) -> Self {
+        match self {
+            Transport::Primary => Transport::Secondary,
+            Transport::Secondary => Transport::Primary,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DualRunConfig {
+    /// how long to wait for the other transport's counterpart of an event
+    pub tolerance: Duration,
+    /// fill prices closer than this agree
+    pub price_tolerance: Decimal,
+}
+
+impl Default for DualRunConfig {
+    fn default() -> Self {
+        Self { tolerance: Duration::seconds(5), price_tolerance: Decimal::ZERO }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Divergence {
+    /// `seen_on` reported the event, the other transport didn't within the
+    /// tolerance
+    Missing {
+        seen_on: Transport,
+        event: OrderEvent,
+    },
+    Mismatch {
+        primary: OrderEvent,
+        secondary: OrderEvent,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DualRunStats {
+    pub matched: u64,
+    pub missing_primary: u64,
+    pub missing_secondary: u64,
+    pub mismatched: u64,
+}
+
+pub struct DualRunComparator {
+    config: DualRunConfig,
+    /// events waiting for their counterpart, in arrival order
+    unmatched: FxHashMap<OrderId, Vec<(Transport, DateTime<Utc>, OrderEvent)>>,
+    stats: DualRunStats,
+    tx: broadcast::Sender<Divergence>,
+}
+
+impl DualRunComparator {
+    pub fn new(config: DualRunConfig) -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self {
+            config,
+            unmatched: FxHashMap::default(),
+            stats: DualRunStats::default(),
+            tx,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Divergence> {
+        self.tx.subscribe()
+    }
+
+    pub fn stats(&self) -> DualRunStats {
+        self.stats
+    }
+
+    /// The number of events still waiting for a counterpart
+    pub fn unmatched_count(&self) -> usize {
+        self.unmatched.values().map(|u| u.len()).sum()
+    }
+
+    fn diverge(&mut self, d: Divergence) {
+        match &d {
+            Divergence::Missing { seen_on: Transport::Primary, .. } => {
+                self.stats.missing_secondary += 1
+            }
+            Divergence::Missing { seen_on: Transport::Secondary, .. } => {
+                self.stats.missing_primary += 1
+            }
+            Divergence::Mismatch { .. } => self.stats.mismatched += 1,
+        }
+        warn!("orderflow transports diverged: {d:?}");
+        let _ = self.tx.send(d);
+    }
+
+    fn agree(&self, a: &OrderEvent, b: &OrderEvent) -> bool {
+        let px = |a: &Decimal, b: &Decimal| (a - b).abs() <= self.config.price_tolerance;
+        match (a, b) {
+            (
+                OrderEvent::Fill { quantity: qa, price: pa, .. },
+                OrderEvent::Fill { quantity: qb, price: pb, .. },
+            )
+            | (
+                OrderEvent::Bust { quantity: qa, price: pa, .. },
+                OrderEvent::Bust { quantity: qb, price: pb, .. },
+            ) => qa == qb && px(pa, pb),
+            (
+                OrderEvent::Correction { quantity: qa, price: pa, .. },
+                OrderEvent::Correction { quantity: qb, price: pb, .. },
+            ) => qa == qb && px(pa, pb),
+            (
+                OrderEvent::Modified { quantity: qa, price: pa, .. },
+                OrderEvent::Modified { quantity: qb, price: pb, .. },
+            ) => qa == qb && px(pa, pb),
+            (
+                OrderEvent::Reject { reason: ra, .. },
+                OrderEvent::Reject { reason: rb, .. },
+            )
+            | (
+                OrderEvent::ModifyReject { reason: ra, .. },
+                OrderEvent::ModifyReject { reason: rb, .. },
+            )
+            | (
+                OrderEvent::CancelReject { reason: ra, .. },
+                OrderEvent::CancelReject { reason: rb, .. },
+            ) => ra == rb,
+            _ => true,
+        }
+    }
+
+    /// Record an event reported by `transport`
+    pub fn on_event(&mut self, transport: Transport, ev: OrderEvent, now: DateTime<Utc>) {
+        let id = ev.id();
+        let unmatched = self.unmatched.entry(id).or_default();
+        // the oldest unmatched event of the same kind from the other side
+        let counterpart = unmatched.iter().position(|(t, _, e)| {
+            *t == transport.other() && discriminant(e) == discriminant(&ev)
+        });
+        match counterpart {
+            None => unmatched.push((transport, now, ev)),
+            Some(i) => {
+                let (_, _, other) = unmatched.remove(i);
+                if unmatched.is_empty() {
+                    self.unmatched.remove(&id);
+                }
+                let (primary, secondary) = match transport {
+                    Transport::Primary => (ev, other),
+                    Transport::Secondary => (other, ev),
+                };
+                if self.agree(&primary, &secondary) {
+                    self.stats.matched += 1;
+                } else {
+                    self.diverge(Divergence::Mismatch { primary, secondary });
+                }
+            }
+        }
+        self.poll(now);
+    }
+
+    /// Report events whose counterpart is overdue.  Call this periodically;
+    /// it is also done on every event.
+    pub fn poll(&mut self, now: DateTime<Utc>) {
+        let tolerance = self.config.tolerance;
+        let mut overdue = vec![];
+        self.unmatched.retain(|_, unmatched| {
+            unmatched.retain(|(transport, at, ev)| {
+                let late = now - *at > tolerance;
+                if late {
+                    overdue.push(Divergence::Missing {
+                        seen_on: *transport,
+                        event: ev.clone(),
+                    });
+                }
+                !late
+            });
+            !unmatched.is_empty()
+        });
+        for d in overdue {
+            self.diverge(d);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! Expiry lifecycle automation for futures and options positions.
+//!
+//! `ExpiryWatcher` looks up each held contract's expiration in the market
+//! index and, per the policy for its product, warns a number of days ahead
+//! and then optionally flattens the position or rolls it into the next
+//! contract on the same venue and route.  A roll is done as a single
+//! calendar spread order when the venue lists the spread, otherwise as a
+//! pair of intents, close the old contract and open the new.  Options are
+//! never rolled: the symbology has no strike or put/call to find the same
+//! series in a later expiry, so a roll policy on an option only warns.
+//!
+//! Policies are keyed by the contract's underlying product name (e.g. all
+//! ES futures), falling back to the contract's own product name, then the
+//! default.  Each step fires once per contract; call `check` periodically
+//! with the current positions.
+
+use super::intent::{Target, TradeIntent, Urgency};
+use crate::symbology::{MarketIndex, MarketRef, ProductKind, ProductRef};
+use api::Dir;
+use chrono::{DateTime, Duration, Utc};
+use fxhash::{FxHashMap, FxHashSet};
+use log::warn;
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryAction {
+    /// only warn
+    #[default]
+    Warn,
+    Flatten,
+    Roll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpiryPolicy {
+    /// warn this many days before expiration
+    pub warn_days: u32,
+    #[serde(default)]
+    pub action: ExpiryAction,
+    /// flatten or roll this many days before expiration
+    #[serde(default)]
+    pub action_days: u32,
+    /// cross the spread to flatten or roll instead of joining the touch
+    #[serde(default)]
+    pub aggressive: bool,
+}
+
+impl Default for ExpiryPolicy {
+    fn default() -> Self {
+        Self {
+            warn_days: 5,
+            action: ExpiryAction::Warn,
+            action_days: 1,
+            aggressive: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExpiryConfig {
+    #[serde(default)]
+    pub default: ExpiryPolicy,
+    /// keyed by underlying or product name
+    #[serde(default)]
+    pub products: FxHashMap<String, ExpiryPolicy>,
+}
+
+/// An order on a calendar spread market
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpreadOrder {
+    pub market: MarketRef,
+    pub dir: Dir,
+    pub quantity: Decimal,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RollPlan {
+    Spread(SpreadOrder),
+    Legs { close: TradeIntent, open: TradeIntent },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExpiryStep {
+    Warn,
+    Flatten(TradeIntent),
+    Roll {
+        to: MarketRef,
+        plan: RollPlan,
+    },
+    /// the policy says roll but there is no later contract to roll into,
+    /// or the contract is an option
+    NoRollTarget,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExpiryEvent {
+    pub market: MarketRef,
+    pub expiration: DateTime<Utc>,
+    /// signed
+    pub position: Decimal,
+    pub step: ExpiryStep,
+}
+
+fn future_expiration(
+    product: &ProductRef,
+) -> Option<(Option<ProductRef>, DateTime<Utc>)> {
+    match &product.kind {
+        ProductKind::Future { underlying, expiration: Some(e), .. }
+        | ProductKind::Option { underlying, expiration: Some(e), .. } => {
+            Some((*underlying, *e))
+        }
+        _ => None,
+    }
+}
+
+pub struct ExpiryWatcher {
+    config: ExpiryConfig,
+    warned: FxHashSet<MarketRef>,
+    acted: FxHashSet<MarketRef>,
+}
+
+impl ExpiryWatcher {
+    pub fn new(config: ExpiryConfig) -> Self {
+        Self { config, warned: FxHashSet::default(), acted: FxHashSet::default() }
+    }
+
+    pub fn policy(&self, product: &ProductRef) -> ExpiryPolicy {
+        let underlying = future_expiration(product).and_then(|(u, _)| u);
+        underlying
+            .and_then(|u| self.config.products.get(u.name.as_str()))
+            .or_else(|| self.config.products.get(product.name.as_str()))
+            .copied()
+            .unwrap_or(self.config.default)
+    }
+
+    /// Check held positions against their expirations, returning the steps
+    /// that are now due.  Flat positions and contracts without an
+    /// expiration are ignored.
+    pub fn check<'a>(
+        &mut self,
+        index: &MarketIndex,
+        positions: impl IntoIterator<Item = (&'a MarketRef, &'a Decimal)>,
+        now: DateTime<Utc>,
+    ) -> Vec<ExpiryEvent> {
+        let mut res = vec![];
+        for (market, position) in positions {
+            if position.is_zero() {
+                continue;
+            }
+            let Some(product) = market.base() else { continue };
+            let Some((underlying, expiration)) = future_expiration(&product) else {
+                continue;
+            };
+            let policy = self.policy(&product);
+            let left = expiration - now;
+            let event = |step| ExpiryEvent {
+                market: *market,
+                expiration,
+                position: *position,
+                step,
+            };
+            if left <= Duration::days(policy.warn_days as i64)
+                && self.warned.insert(*market)
+            {
+                warn!(
+                    "{market} expires at {expiration}, holding {position}, policy {:?}",
+                    policy.action
+                );
+                res.push(event(ExpiryStep::Warn));
+            }
+            if policy.action == ExpiryAction::Warn
+                || left > Duration::days(policy.action_days as i64)
+                || !self.acted.insert(*market)
+            {
+                continue;
+            }
+            let urgency =
+                if policy.aggressive { Urgency::Aggressive } else { Urgency::Passive };
+            let close = TradeIntent {
+                market: *market,
+                target: Target::Position(Decimal::ZERO),
+                urgency,
+                limit: None,
+                min_quantity: Decimal::ZERO,
+            };
+            let step = match policy.action {
+                ExpiryAction::Warn => continue,
+                ExpiryAction::Flatten => ExpiryStep::Flatten(close),
+                ExpiryAction::Roll if product.kind.is_option() => {
+                    warn!("{market} is an option, not rolling it");
+                    ExpiryStep::NoRollTarget
+                }
+                ExpiryAction::Roll => {
+                    match next_contract(index, market, underlying, expiration) {
+                        None => {
+                            warn!("{market} has no later contract to roll into");
+                            ExpiryStep::NoRollTarget
+                        }
+                        Some(to) => ExpiryStep::Roll {
+                            to,
+                            plan: roll_plan(index, &product, &to, *position, close),
+                        },
+                    }
+                }
+            };
+            res.push(event(step));
+        }
+        res
+    }
+
+    /// Forget contracts that are no longer held, so a new position in them
+    /// is warned about again
+    pub fn retain_held(&mut self, held: &FxHashSet<MarketRef>) {
+        self.warned.retain(|m| held.contains(m));
+        self.acted.retain(|m| held.contains(m));
+    }
+}
+
+/// The earliest contract on the same underlying, venue and route that
+/// expires after `expiration`; never an option, which would need a matching
+/// strike and put/call
+fn next_contract(
+    index: &MarketIndex,
+    market: &MarketRef,
+    underlying: Option<ProductRef>,
+    expiration: DateTime<Utc>,
+) -> Option<MarketRef> {
+    let underlying = underlying?;
+    let base = market.base()?;
+    if base.kind.is_option() {
+        return None;
+    }
+    let kind = std::mem::discriminant(&base.kind);
+    index
+        .by_underlying(&underlying)
+        .into_iter()
+        .filter(|m| m.venue == market.venue && m.route == market.route)
+        .filter_map(|m| {
+            let base = m.base()?;
+            if std::mem::discriminant(&base.kind) != kind {
+                return None;
+            }
+            let (_, e) = future_expiration(&base)?;
+            (e > expiration).then_some((e, *m))
+        })
+        .min_by_key(|(e, _)| *e)
+        .map(|(_, m)| m)
+}
+
+/// Roll `position` in the contract `from` (the product of `close`'s
+/// market) into `to`
+fn roll_plan(
+    index: &MarketIndex,
+    from: &ProductRef,
+    to: &MarketRef,
+    position: Decimal,
+    close: TradeIntent,
+) -> RollPlan {
+    // rolling a long sells the old contract and buys the new one
+    let (close_dir, open_dir) = if position.is_sign_positive() {
+        (Dir::Sell, Dir::Buy)
+    } else {
+        (Dir::Buy, Dir::Sell)
+    };
+    let to_p = to.base();
+    let spread = index
+        .by_underlying(from)
+        .into_iter()
+        .filter(|m| m.venue == to.venue && m.route == to.route)
+        .find_map(|m| match &m.base()?.kind {
+            ProductKind::FutureSpread { same_side_leg, opp_side_leg } => {
+                if *same_side_leg == Some(*from) && *opp_side_leg == to_p {
+                    Some((*m, close_dir))
+                } else if *same_side_leg == to_p && *opp_side_leg == Some(*from) {
+                    Some((*m, open_dir))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        });
+    match spread {
+        Some((market, dir)) => {
+            RollPlan::Spread(SpreadOrder { market, dir, quantity: position.abs() })
+        }
+        None => RollPlan::Legs {
+            close,
+            open: TradeIntent {
+                market: *to,
+                target: Target::Position(position),
+                ..close
+            },
+        },
+    }
+}
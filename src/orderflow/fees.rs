@@ -0,0 +1,200 @@
+//! Venue fee schedules.
+//!
+//! A `FeeModel` holds maker and taker rates per venue, optionally tiered by
+//! trailing traded notional, with per product overrides (e.g. a venue that
+//! charges less on its own token's markets) and an optional fixed charge
+//! per contract.  It is plain serde config, loaded with `FeeModel::load`.
+//!
+//! `Fees` pairs a model with the notional traded per venue so far, which
+//! picks the tier, and computes the fee on each fill.  The P&L tracker uses
+//! it to charge fills that arrive without a fee, and the venue ranker to
+//! score venues by their effective taker rate.
+
+use crate::symbology::MarketRef;
+use anyhow::{bail, Result};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+
+const BPS: Decimal = Decimal::from_parts(10_000, 0, 0, false, 0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+/// Fee rates in bps of notional; negative is a rebate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    pub maker_bps: Decimal,
+    pub taker_bps: Decimal,
+}
+
+impl FeeSchedule {
+    pub fn bps(&self, liquidity: Liquidity) -> Decimal {
+        match liquidity {
+            Liquidity::Maker => self.maker_bps,
+            Liquidity::Taker => self.taker_bps,
+        }
+    }
+}
+
+/// The rates that apply once trailing notional reaches `min_notional`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub min_notional: Decimal,
+    #[serde(flatten)]
+    pub schedule: FeeSchedule,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VenueFees {
+    /// the rates below the first tier
+    #[serde(flatten)]
+    pub base: FeeSchedule,
+    #[serde(default)]
+    pub tiers: Vec<FeeTier>,
+    /// overrides by base product name, ignoring tiers
+    #[serde(default)]
+    pub products: FxHashMap<String, FeeSchedule>,
+    /// a fixed charge per unit of quantity, on top of the rate
+    #[serde(default)]
+    pub per_contract: Decimal,
+}
+
+impl VenueFees {
+    fn schedule(&self, market: &MarketRef, notional: Decimal) -> FeeSchedule {
+        if let Some(s) = market.base().and_then(|p| self.products.get(p.name.as_str())) {
+            return *s;
+        }
+        self.tiers
+            .iter()
+            .filter(|t| notional >= t.min_notional)
+            .max_by_key(|t| t.min_notional)
+            .map(|t| t.schedule)
+            .unwrap_or(self.base)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FeeModel {
+    /// fees by venue name
+    #[serde(default)]
+    pub venues: FxHashMap<String, VenueFees>,
+    /// fees for venues not listed in `venues`
+    #[serde(default)]
+    pub default: VenueFees,
+}
+
+impl FeeModel {
+    /// Load a fee model from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let model: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+        model.validate()?;
+        Ok(model)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (venue, fees) in self.venues.iter().map(|(v, f)| (v.as_str(), f)) {
+            Self::validate_venue(venue, fees)?;
+        }
+        Self::validate_venue("default", &self.default)
+    }
+
+    fn validate_venue(venue: &str, fees: &VenueFees) -> Result<()> {
+        if fees.per_contract < Decimal::ZERO {
+            bail!("{venue} per contract fee must not be negative");
+        }
+        if fees.tiers.iter().any(|t| t.min_notional < Decimal::ZERO) {
+            bail!("{venue} fee tiers must start at non negative notional");
+        }
+        Ok(())
+    }
+
+    pub fn venue(&self, market: &MarketRef) -> &VenueFees {
+        self.venues.get(market.venue.name.as_str()).unwrap_or(&self.default)
+    }
+
+    /// The rate in bps for `market` given the trailing notional traded on
+    /// its venue
+    pub fn bps(
+        &self,
+        market: &MarketRef,
+        liquidity: Liquidity,
+        notional: Decimal,
+    ) -> Decimal {
+        self.venue(market).schedule(market, notional).bps(liquidity)
+    }
+
+    /// The fee on a fill, given the trailing notional traded on the venue
+    /// before it
+    pub fn fee(
+        &self,
+        market: &MarketRef,
+        liquidity: Liquidity,
+        quantity: Decimal,
+        price: Decimal,
+        notional: Decimal,
+    ) -> Decimal {
+        let venue = self.venue(market);
+        let bps = venue.schedule(market, notional).bps(liquidity);
+        (quantity * price).abs() * bps / BPS + quantity.abs() * venue.per_contract
+    }
+}
+
+/// A fee model and the notional traded per venue in the current tier
+/// period
+#[derive(Debug, Clone, Default)]
+pub struct Fees {
+    model: FeeModel,
+    notional: FxHashMap<String, Decimal>,
+}
+
+impl Fees {
+    pub fn new(model: FeeModel) -> Self {
+        Self { model, notional: FxHashMap::default() }
+    }
+
+    pub fn model(&self) -> &FeeModel {
+        &self.model
+    }
+
+    /// Seed the trailing notional for a venue, e.g. from the venue's
+    /// reported 30 day volume
+    pub fn set_notional(&mut self, venue: &str, notional: Decimal) {
+        self.notional.insert(venue.to_string(), notional);
+    }
+
+    pub fn notional(&self, venue: &str) -> Decimal {
+        self.notional.get(venue).copied().unwrap_or_default()
+    }
+
+    /// Start a new tier period
+    pub fn reset_notional(&mut self) {
+        self.notional.clear();
+    }
+
+    /// The current rate in bps for `market`
+    pub fn bps(&self, market: &MarketRef, liquidity: Liquidity) -> Decimal {
+        self.model.bps(market, liquidity, self.notional(market.venue.name.as_str()))
+    }
+
+    /// Charge a fill: returns its fee and adds its notional to the venue's
+    /// total
+    pub fn on_fill(
+        &mut self,
+        market: &MarketRef,
+        liquidity: Liquidity,
+        quantity: Decimal,
+        price: Decimal,
+    ) -> Decimal {
+        let venue = market.venue.name.as_str();
+        let notional = self.notional(venue);
+        let fee = self.model.fee(market, liquidity, quantity, price, notional);
+        *self.notional.entry(venue.to_string()).or_default() += (quantity * price).abs();
+        fee
+    }
+}
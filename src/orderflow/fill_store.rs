@@ -0,0 +1,200 @@
+//! An in-process store of fills, one source for strategies and reports.
+//!
+//! `FillStore` holds fills from live updates and historical backfill,
+//! deduplicated by the venue's fill id so the two may overlap, and indexes
+//! them by order, market, account and time.  `query` selects fills with a
+//! `FillQuery`; `volume_by_market` and `fees_by_day` aggregate over one.
+//! Busted fills are removed with `remove`.
+
+use super::fees::Liquidity;
+use crate::symbology::MarketRef;
+use api::{AccountId, Dir, OrderId};
+use chrono::{DateTime, NaiveDate, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredFill {
+    /// the venue's fill id, unique per venue
+    pub fill_id: String,
+    pub order_id: Option<OrderId>,
+    pub account: Option<AccountId>,
+    pub market: MarketRef,
+    pub dir: Dir,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub fee: Decimal,
+    pub liquidity: Option<Liquidity>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Which fills to select; unset fields match anything.  The time range is
+/// [from, to).
+#[derive(Debug, Clone, Default)]
+pub struct FillQuery {
+    pub order_id: Option<OrderId>,
+    pub market: Option<MarketRef>,
+    pub account: Option<AccountId>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl FillQuery {
+    fn matches(&self, f: &StoredFill) -> bool {
+        self.order_id.map_or(true, |o| f.order_id == Some(o))
+            && self.market.map_or(true, |m| f.market == m)
+            && self.account.map_or(true, |a| f.account == Some(a))
+            && self.from.map_or(true, |t| f.timestamp >= t)
+            && self.to.map_or(true, |t| f.timestamp < t)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Volume {
+    pub quantity: Decimal,
+    pub notional: Decimal,
+    pub fills: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct FillStore {
+    /// removed fills leave a hole so indexes stay valid
+    fills: Vec<Option<StoredFill>>,
+    by_id: FxHashMap<(String, String), usize>,
+    by_order: FxHashMap<OrderId, Vec<usize>>,
+    by_market: FxHashMap<MarketRef, Vec<usize>>,
+    by_account: FxHashMap<AccountId, Vec<usize>>,
+    by_time: BTreeSet<(DateTime<Utc>, usize)>,
+    len: usize,
+}
+
+impl FillStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn key(market: &MarketRef, fill_id: &str) -> (String, String) {
+        (market.venue.name.to_string(), fill_id.to_string())
+    }
+
+    /// Add a fill; returns false if the store already has a fill with the
+    /// same venue and fill id
+    pub fn insert(&mut self, fill: StoredFill) -> bool {
+        let key = Self::key(&fill.market, &fill.fill_id);
+        if self.by_id.contains_key(&key) {
+            return false;
+        }
+        let i = self.fills.len();
+        self.by_id.insert(key, i);
+        if let Some(order_id) = fill.order_id {
+            self.by_order.entry(order_id).or_default().push(i);
+        }
+        self.by_market.entry(fill.market).or_default().push(i);
+        if let Some(account) = fill.account {
+            self.by_account.entry(account).or_default().push(i);
+        }
+        self.by_time.insert((fill.timestamp, i));
+        self.fills.push(Some(fill));
+        self.len += 1;
+        true
+    }
+
+    /// Add backfilled fills, skipping ones already stored; returns the
+    /// number added
+    pub fn extend(&mut self, fills: impl IntoIterator<Item = StoredFill>) -> usize {
+        fills.into_iter().map(|f| self.insert(f)).filter(|added| *added).count()
+    }
+
+    /// Remove a fill, e.g. one the venue busted
+    pub fn remove(&mut self, market: &MarketRef, fill_id: &str) -> Option<StoredFill> {
+        let i = self.by_id.remove(&Self::key(market, fill_id))?;
+        let fill = self.fills[i].take()?;
+        let unindex = |ix: Option<&mut Vec<usize>>| {
+            if let Some(ix) = ix {
+                ix.retain(|j| *j != i);
+            }
+        };
+        if let Some(order_id) = &fill.order_id {
+            unindex(self.by_order.get_mut(order_id));
+        }
+        unindex(self.by_market.get_mut(&fill.market));
+        if let Some(account) = &fill.account {
+            unindex(self.by_account.get_mut(account));
+        }
+        self.by_time.remove(&(fill.timestamp, i));
+        self.len -= 1;
+        Some(fill)
+    }
+
+    pub fn get(&self, market: &MarketRef, fill_id: &str) -> Option<&StoredFill> {
+        let i = self.by_id.get(&Self::key(market, fill_id))?;
+        self.fills[*i].as_ref()
+    }
+
+    /// The fills matching `q`, oldest first
+    pub fn query(&self, q: &FillQuery) -> Vec<&StoredFill> {
+        // narrow by the most selective index given, else scan the time range
+        let candidates = [
+            q.order_id.map(|o| self.by_order.get(&o)),
+            q.market.map(|m| self.by_market.get(&m)),
+            q.account.map(|a| self.by_account.get(&a)),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|ix| ix.map_or(0, |ix| ix.len()));
+        let mut res: Vec<&StoredFill> = match candidates {
+            Some(None) => return vec![],
+            Some(Some(ix)) => ix.iter().filter_map(|i| self.fills[*i].as_ref()).collect(),
+            None => {
+                let range = match (q.from, q.to) {
+                    (Some(from), Some(to)) if from >= to => return vec![],
+                    (from, to) => (
+                        from.map_or(std::ops::Bound::Unbounded, |t| {
+                            std::ops::Bound::Included((t, 0))
+                        }),
+                        to.map_or(std::ops::Bound::Unbounded, |t| {
+                            std::ops::Bound::Excluded((t, 0))
+                        }),
+                    ),
+                };
+                self.by_time
+                    .range(range)
+                    .filter_map(|(_, i)| self.fills[*i].as_ref())
+                    .collect()
+            }
+        };
+        res.retain(|f| q.matches(f));
+        res.sort_by_key(|f| f.timestamp);
+        res
+    }
+
+    /// Traded quantity and notional per market over the fills matching `q`
+    pub fn volume_by_market(&self, q: &FillQuery) -> FxHashMap<MarketRef, Volume> {
+        let mut res: FxHashMap<MarketRef, Volume> = FxHashMap::default();
+        for f in self.query(q) {
+            let v = res.entry(f.market).or_default();
+            v.quantity += f.quantity;
+            v.notional += f.quantity * f.price;
+            v.fills += 1;
+        }
+        res
+    }
+
+    /// Fees per UTC day over the fills matching `q`
+    pub fn fees_by_day(&self, q: &FillQuery) -> BTreeMap<NaiveDate, Decimal> {
+        let mut res: BTreeMap<NaiveDate, Decimal> = BTreeMap::new();
+        for f in self.query(q) {
+            *res.entry(f.timestamp.date_naive()).or_default() += f.fee;
+        }
+        res
+    }
+}
@@ -0,0 +1,139 @@
+//! Gate orders on market status.  Orders for markets that are closed or
+//! halted are either rejected up front, or, with explicit opt-in, held and
+//! released when the market opens.
+
+use super::{reject::RejectReason, tracker::OrderRequest};
+use crate::symbology::MarketRef;
+use fxhash::FxHashMap;
+use log::info;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MarketStatus {
+    Open,
+    PreOpen,
+    Closed,
+    Halted,
+    #[default]
+    Unknown,
+}
+
+impl MarketStatus {
+    pub fn is_open(&self) -> bool {
+        matches!(self, MarketStatus::Open)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatePolicy {
+    /// don't gate, send regardless of status
+    Off,
+    /// reject orders for markets that aren't open
+    Reject,
+    /// reject, unless the order opted in to being queued for the open
+    HoldIfRequested,
+}
+
+#[derive(Debug, Clone)]
+pub enum GateDecision {
+    Send,
+    Held,
+    Rejected(RejectReason),
+}
+
+#[derive(Debug, Clone)]
+pub enum GateEvent {
+    Held(OrderRequest),
+    Released(OrderRequest),
+    /// held orders dropped, e.g. by `cancel_held`
+    Dropped(OrderRequest),
+}
+
+pub struct OrderGate {
+    policy: GatePolicy,
+    /// whether markets with unknown status are treated as open
+    unknown_is_open: bool,
+    status: FxHashMap<MarketRef, MarketStatus>,
+    held: FxHashMap<MarketRef, Vec<OrderRequest>>,
+    tx: broadcast::Sender<GateEvent>,
+}
+
+impl OrderGate {
+    pub fn new(policy: GatePolicy, unknown_is_open: bool) -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self {
+            policy,
+            unknown_is_open,
+            status: FxHashMap::default(),
+            held: FxHashMap::default(),
+            tx,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<GateEvent> {
+        self.tx.subscribe()
+    }
+
+    pub fn status(&self, market: &MarketRef) -> MarketStatus {
+        self.status.get(market).copied().unwrap_or_default()
+    }
+
+    fn is_open(&self, market: &MarketRef) -> bool {
+        match self.status(market) {
+            MarketStatus::Unknown => self.unknown_is_open,
+            s => s.is_open(),
+        }
+    }
+
+    /// Decide what to do with an order; `queue_for_open` opts the order in
+    /// to being held until the market opens.
+    pub fn submit(&mut self, req: OrderRequest, queue_for_open: bool) -> GateDecision {
+        if self.policy == GatePolicy::Off || self.is_open(&req.market) {
+            return GateDecision::Send;
+        }
+        if self.policy == GatePolicy::HoldIfRequested && queue_for_open {
+            info!("holding order {:?} until {} opens", req.id, req.market);
+            self.held.entry(req.market).or_default().push(req);
+            let _ = self.tx.send(GateEvent::Held(req));
+            GateDecision::Held
+        } else {
+            GateDecision::Rejected(RejectReason::MarketClosed)
+        }
+    }
+
+    /// Update the status of a market from the exchange calendar or a status
+    /// feed; returns held orders that should now be sent, in the order they
+    /// were submitted.
+    pub fn on_status(
+        &mut self,
+        market: MarketRef,
+        status: MarketStatus,
+    ) -> Vec<OrderRequest> {
+        self.status.insert(market, status);
+        if !self.is_open(&market) {
+            return vec![];
+        }
+        let released = self.held.remove(&market).unwrap_or_default();
+        for req in &released {
+            info!("releasing held order {:?} for {market}", req.id);
+            let _ = self.tx.send(GateEvent::Released(*req));
+        }
+        released
+    }
+
+    pub fn held(&self, market: &MarketRef) -> &[OrderRequest] {
+        self.held.get(market).map(|v| &v[..]).unwrap_or(&[])
+    }
+
+    /// Drop a held order before it's released
+    pub fn cancel_held(&mut self, id: &api::OrderId) -> bool {
+        for reqs in self.held.values_mut() {
+            if let Some(i) = reqs.iter().position(|r| r.id == *id) {
+                let req = reqs.remove(i);
+                let _ = self.tx.send(GateEvent::Dropped(req));
+                return true;
+            }
+        }
+        false
+    }
+}
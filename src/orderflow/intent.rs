@@ -0,0 +1,199 @@
+//! Trade intents: say "be long 10" instead of managing order arithmetic.
+//! The executor compares an intent against the current position and working
+//! orders for the market and produces the orders and cancels needed to get
+//! there.
+
+use super::tracker::OrderRequest;
+use crate::{marketdata::market_view::MarketState, symbology::MarketRef};
+use api::{Dir, OrderId};
+use log::warn;
+use rust_decimal::Decimal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// be at exactly this signed position
+    Position(Decimal),
+    /// change the position by this signed amount, relative to the position
+    /// when the intent is accepted, see `IntentExecutor::accept`
+    Delta(Decimal),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    /// join the near touch
+    Passive,
+    /// cross to the far touch
+    Aggressive,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TradeIntent {
+    pub market: MarketRef,
+    pub target: Target,
+    pub urgency: Urgency,
+    /// never buy above / sell below this price
+    pub limit: Option<Decimal>,
+    /// differences smaller than this are ignored
+    pub min_quantity: Decimal,
+}
+
+impl TradeIntent {
+    /// Fix a delta target into an absolute position given the position now
+    pub fn resolve(mut self, position: Decimal) -> Self {
+        if let Target::Delta(d) = self.target {
+            self.target = Target::Position(position + d);
+        }
+        self
+    }
+
+    /// The absolute target, None for a delta that hasn't been resolved
+    pub fn target_position(&self) -> Option<Decimal> {
+        match self.target {
+            Target::Position(p) => Some(p),
+            Target::Delta(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum IntentAction {
+    Place(OrderRequest),
+    Cancel(OrderId),
+}
+
+pub struct IntentExecutor;
+
+impl IntentExecutor {
+    /// Take on an intent, fixing a delta target against the position now.
+    /// Do this once; resolving again at every replan would move the target
+    /// by each fill in the meantime.
+    pub fn accept(intent: TradeIntent, state: &MarketState) -> TradeIntent {
+        intent.resolve(state.position)
+    }
+
+    /// The price to work an order at for the intent, or None if the book
+    /// doesn't have the needed side
+    pub fn price(intent: &TradeIntent, dir: Dir, state: &MarketState) -> Option<Decimal> {
+        let touch = match (intent.urgency, dir) {
+            (Urgency::Passive, Dir::Buy) | (Urgency::Aggressive, Dir::Sell) => {
+                state.best(Dir::Buy)
+            }
+            (Urgency::Passive, Dir::Sell) | (Urgency::Aggressive, Dir::Buy) => {
+                state.best(Dir::Sell)
+            }
+        };
+        let (price, _) = touch?;
+        Some(match (dir, intent.limit) {
+            (_, None) => price,
+            (Dir::Buy, Some(limit)) => price.min(limit),
+            (Dir::Sell, Some(limit)) => price.max(limit),
+        })
+    }
+
+    /// Plan the actions that move the market's position and working orders
+    /// towards the intent.  A single working order at the right side, price,
+    /// and size is left alone; anything else is canceled and replaced.  The
+    /// intent must have been `accept`ed; an unresolved delta plans nothing.
+    pub fn plan(
+        intent: &TradeIntent,
+        state: &MarketState,
+        mut next_order_id: impl FnMut() -> OrderId,
+    ) -> Vec<IntentAction> {
+        let mut actions = vec![];
+        let Some(target) = intent.target_position() else {
+            warn!("{}: planning an intent that wasn't accepted", intent.market.name);
+            return actions;
+        };
+        let needed = target - state.position;
+        let desired = if needed.abs() < intent.min_quantity || needed.is_zero() {
+            None
+        } else {
+            let dir = if needed > Decimal::ZERO { Dir::Buy } else { Dir::Sell };
+            Self::price(intent, dir, state).map(|price| (dir, price, needed.abs()))
+        };
+        let mut keep = None;
+        if let Some((dir, price, quantity)) = desired {
+            if let [o] = &state.working_orders[..] {
+                if o.dir == dir && o.price == price && o.remaining() == quantity {
+                    keep = Some(o.id);
+                }
+            }
+        }
+        for o in &state.working_orders {
+            if Some(o.id) != keep {
+                actions.push(IntentAction::Cancel(o.id));
+            }
+        }
+        if let (None, Some((dir, price, quantity))) = (keep, desired) {
+            actions.push(IntentAction::Place(OrderRequest {
+                id: next_order_id(),
+                market: intent.market,
+                dir,
+                price,
+                quantity,
+            }));
+        }
+        actions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{marketdata::market_view::WorkingOrder, symbology::test_market};
+    use anyhow::Result;
+    use api::DirPair;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_replan_after_partial_fill() -> Result<()> {
+        let market = test_market("GBPUSD", "GBP", "USD")?;
+        let seqno = std::cell::Cell::new(0);
+        let next_order_id = || OrderId {
+            seqid: Default::default(),
+            seqno: seqno.replace(seqno.get() + 1),
+        };
+        let mut state = MarketState {
+            depth: DirPair {
+                buy: vec![(dec!(1.25), dec!(100))],
+                sell: vec![(dec!(1.26), dec!(100))],
+            },
+            position: dec!(5),
+            ..Default::default()
+        };
+        let intent = IntentExecutor::accept(
+            TradeIntent {
+                market,
+                target: Target::Delta(dec!(10)),
+                urgency: Urgency::Passive,
+                limit: None,
+                min_quantity: Decimal::ZERO,
+            },
+            &state,
+        );
+        assert_eq!(intent.target, Target::Position(dec!(15)));
+        let actions = IntentExecutor::plan(&intent, &state, next_order_id);
+        let [IntentAction::Place(req)] = actions[..] else {
+            panic!("expected a single place, got {actions:?}")
+        };
+        assert_eq!((req.dir, req.price, req.quantity), (Dir::Buy, dec!(1.25), dec!(10)));
+        // 4 filled: the remaining 6 still gets to the target, so nothing to do
+        state.position = dec!(9);
+        state.working_orders = vec![WorkingOrder {
+            id: req.id,
+            dir: Dir::Buy,
+            price: req.price,
+            quantity: dec!(10),
+            filled: dec!(4),
+        }];
+        assert!(IntentExecutor::plan(&intent, &state, next_order_id).is_empty());
+        // the rest of the order was canceled, replace only what's missing
+        state.working_orders.clear();
+        let actions = IntentExecutor::plan(&intent, &state, next_order_id);
+        let [IntentAction::Place(req)] = actions[..] else {
+            panic!("expected a single place, got {actions:?}")
+        };
+        assert_eq!(req.quantity, dec!(6));
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! A process wide stop for order entry.  Anything may trip it (a risk
+//! breaker, an operator); order entry paths should check it, or watch it
+//! with `subscribe`, and stop sending while it is tripped.
+//!
+//! A `Watchdog` trips the switch if the process stops beating it, e.g. a
+//! strategy loop that hangs.  With the `netidx` feature,
+//! `OrderflowClient::enable_cancel_on_disconnect` trips it when the
+//! connection to the Oms is down too long, and
+//! `OrderflowClient::cancel_all_on_kill` cancels every open order when it
+//! trips.
+
+use log::error;
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::watch, task::JoinHandle};
+
+#[derive(Debug, Clone)]
+pub struct KillSwitch(Arc<watch::Sender<Option<String>>>);
+
+impl Default for KillSwitch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KillSwitch {
+    pub fn new() -> Self {
+        let (tx, _) = watch::channel(None);
+        Self(Arc::new(tx))
+    }
+
+    /// Trip the switch; returns false if it was already tripped, in which
+    /// case the original reason is kept
+    pub fn trip(&self, reason: impl Into<String>) -> bool {
+        let reason = reason.into();
+        let tripped = self.0.send_if_modified(|cur| {
+            if cur.is_some() {
+                return false;
+            }
+            *cur = Some(reason.clone());
+            true
+        });
+        if tripped {
+            error!("kill switch tripped: {reason}");
+        }
+        tripped
+    }
+
+    pub fn reset(&self) {
+        self.0.send_replace(None);
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.0.borrow().is_some()
+    }
+
+    pub fn reason(&self) -> Option<String> {
+        self.0.borrow().clone()
+    }
+
+    /// Holds the trip reason while tripped
+    pub fn subscribe(&self) -> watch::Receiver<Option<String>> {
+        self.0.subscribe()
+    }
+
+    /// A watchdog that trips the switch if it isn't beaten every `timeout`
+    pub fn watchdog(&self, timeout: Duration) -> Watchdog {
+        let (tx, mut rx) = watch::channel(());
+        let kill_switch = self.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                match tokio::time::timeout(timeout, rx.changed()).await {
+                    Ok(Ok(())) => (),
+                    // the watchdog was dropped
+                    Ok(Err(_)) => break,
+                    Err(_) => {
+                        kill_switch
+                            .trip(format!("watchdog heartbeat missed for {timeout:?}"));
+                        break;
+                    }
+                }
+            }
+        });
+        Watchdog { tx, task }
+    }
+}
+
+/// Dropping the watchdog disarms it
+pub struct Watchdog {
+    tx: watch::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Watchdog {
+    pub fn beat(&self) {
+        self.tx.send_replace(());
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
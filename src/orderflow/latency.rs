@@ -0,0 +1,62 @@
+//! Rolling latency samples with percentile summaries.
+
+use std::{collections::VecDeque, time::Duration};
+
+const DEFAULT_WINDOW: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySummary {
+    /// samples in the window
+    pub count: usize,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// The most recent `window` latency samples
+#[derive(Debug, Clone)]
+pub struct LatencyStats {
+    window: usize,
+    samples: VecDeque<Duration>,
+}
+
+impl Default for LatencyStats {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW)
+    }
+}
+
+impl LatencyStats {
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self { window, samples: VecDeque::with_capacity(window) }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        if self.samples.len() >= self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+    }
+
+    pub fn summary(&self) -> Option<LatencySummary> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        // nearest rank
+        let pct = |p: usize| sorted[((n * p + 99) / 100).clamp(1, n) - 1];
+        Some(LatencySummary {
+            count: n,
+            mean: sorted.iter().sum::<Duration>() / n as u32,
+            p50: pct(50),
+            p90: pct(90),
+            p99: pct(99),
+            max: sorted[n - 1],
+        })
+    }
+}
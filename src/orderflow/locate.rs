@@ -0,0 +1,205 @@
+//! Pre-trade shortability checks for venues that require a borrow locate
+//! before a short sale.
+//!
+//! A sell that would take the position in the market's base product below
+//! zero is a short sale.  On venues listed in the `LocateConfig` the short
+//! quantity must be covered by a locate, either one already held or one
+//! obtained from the `LocateProvider`; short sales that can't be covered are
+//! rejected with `RejectReason::NoLocate`.  The locate id covering an order
+//! is returned so it can be attached to the order sent to the venue.
+
+use super::{reject::RejectReason, tracker::OrderRequest};
+use crate::symbology::ProductRef;
+use anyhow::Result;
+use api::{Dir, OrderId};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use fxhash::{FxHashMap, FxHashSet};
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LocateConfig {
+    /// venue names that require a locate for short sales
+    pub venues: Vec<String>,
+    /// product names on the easy to borrow list, which don't need one
+    #[serde(default)]
+    pub easy_to_borrow: Vec<String>,
+    /// request a locate from the provider when none is held, instead of
+    /// rejecting
+    #[serde(default)]
+    pub auto_request: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locate {
+    pub id: String,
+    pub product: ProductRef,
+    /// quantity still available to short against this locate
+    pub quantity: Decimal,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+/// A source of borrow locates, e.g. the prime broker's locate API
+pub trait LocateProvider: Send + Sync {
+    /// Request a locate for at least `quantity`; a smaller grant should be
+    /// returned as a locate with less quantity rather than an error
+    fn request(
+        &self,
+        product: ProductRef,
+        quantity: Decimal,
+    ) -> BoxFuture<'static, Result<Locate>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShortDecision {
+    /// not a short sale, or a short sale that doesn't need a locate
+    Send,
+    /// a short sale covered by the locate with this id
+    Covered(String),
+    Rejected(RejectReason),
+}
+
+pub struct ShortabilityCheck {
+    venues: FxHashSet<String>,
+    easy_to_borrow: FxHashSet<String>,
+    auto_request: bool,
+    provider: Option<Arc<dyn LocateProvider>>,
+    locates: FxHashMap<ProductRef, Vec<Locate>>,
+    attached: FxHashMap<OrderId, String>,
+}
+
+impl ShortabilityCheck {
+    pub fn new(config: LocateConfig, provider: Option<Arc<dyn LocateProvider>>) -> Self {
+        Self {
+            venues: config.venues.into_iter().collect(),
+            easy_to_borrow: config.easy_to_borrow.into_iter().collect(),
+            auto_request: config.auto_request,
+            provider,
+            locates: FxHashMap::default(),
+            attached: FxHashMap::default(),
+        }
+    }
+
+    /// Add a locate obtained out of band
+    pub fn add_locate(&mut self, locate: Locate) {
+        info!("locate {} for {} {}", locate.id, locate.quantity, locate.product);
+        self.locates.entry(locate.product).or_default().push(locate);
+    }
+
+    pub fn set_easy_to_borrow(&mut self, products: impl IntoIterator<Item = String>) {
+        self.easy_to_borrow = products.into_iter().collect();
+    }
+
+    /// Total unexpired locate quantity held for the product
+    pub fn available(&self, product: &ProductRef, now: DateTime<Utc>) -> Decimal {
+        self.locates
+            .get(product)
+            .map(|ls| ls.iter().filter(|l| live(l, now)).map(|l| l.quantity).sum())
+            .unwrap_or_default()
+    }
+
+    /// The locate id attached to an order by a previous check
+    pub fn locate_for(&self, id: &OrderId) -> Option<&str> {
+        self.attached.get(id).map(|s| s.as_str())
+    }
+
+    /// The short quantity of the order, if it needs a locate, given the
+    /// current signed position in the market's base product
+    fn short_quantity(
+        &self,
+        req: &OrderRequest,
+        position: Decimal,
+    ) -> Option<(ProductRef, Decimal)> {
+        if req.dir != Dir::Sell || !self.venues.contains(req.market.venue.name.as_str()) {
+            return None;
+        }
+        let short = req.quantity - position.max(Decimal::ZERO);
+        if short <= Decimal::ZERO {
+            return None;
+        }
+        let product = req.market.base()?;
+        if self.easy_to_borrow.contains(product.name.as_str()) {
+            return None;
+        }
+        Some((product, short))
+    }
+
+    /// Check an order against the locates held, consuming locate quantity
+    /// if it is a short sale.  Doesn't call the provider, see `check_or_request`.
+    pub fn check(
+        &mut self,
+        req: &OrderRequest,
+        position: Decimal,
+        now: DateTime<Utc>,
+    ) -> ShortDecision {
+        let Some((product, short)) = self.short_quantity(req, position) else {
+            return ShortDecision::Send;
+        };
+        let locates = self.locates.entry(product).or_default();
+        // used up locates are kept so `release` can return quantity to them
+        locates.retain(|l| live(l, now));
+        // a short sale must be covered by a single locate so the venue can
+        // be told which one
+        match locates.iter_mut().find(|l| l.quantity >= short) {
+            Some(l) => {
+                l.quantity -= short;
+                self.attached.insert(req.id, l.id.clone());
+                ShortDecision::Covered(l.id.clone())
+            }
+            None => ShortDecision::Rejected(RejectReason::NoLocate),
+        }
+    }
+
+    /// Like `check`, but if no locate is held and `auto_request` is
+    /// configured, request one from the provider first
+    pub async fn check_or_request(
+        &mut self,
+        req: &OrderRequest,
+        position: Decimal,
+        now: DateTime<Utc>,
+    ) -> ShortDecision {
+        let decision = self.check(req, position, now);
+        if decision != ShortDecision::Rejected(RejectReason::NoLocate)
+            || !self.auto_request
+        {
+            return decision;
+        }
+        let (Some(provider), Some((product, short))) =
+            (self.provider.clone(), self.short_quantity(req, position))
+        else {
+            return decision;
+        };
+        match provider.request(product, short).await {
+            Ok(locate) => {
+                self.add_locate(locate);
+                self.check(req, position, now)
+            }
+            Err(e) => {
+                warn!("locate request for {short} {product} failed: {e:?}");
+                decision
+            }
+        }
+    }
+
+    /// Return the unused locate quantity of an order that didn't fill
+    /// completely, e.g. when it goes out
+    pub fn release(&mut self, id: &OrderId, unused: Decimal) {
+        let Some(locate_id) = self.attached.remove(id) else { return };
+        if unused <= Decimal::ZERO {
+            return;
+        }
+        for l in self.locates.values_mut().flatten() {
+            if l.id == locate_id {
+                l.quantity += unused;
+                return;
+            }
+        }
+    }
+}
+
+fn live(l: &Locate, now: DateTime<Utc>) -> bool {
+    l.expires.map(|t| t > now).unwrap_or(true)
+}
@@ -0,0 +1,57 @@
+//! Cancel every open order in a scope in one call: the panic button.
+//!
+//! A `CancelAllFilter` selects open orders from the `OrderTracker` by
+//! account, trader, market and venue; unset fields match anything, so the
+//! default filter selects every open order.  Orders are matched by account
+//! or trader only if their owner was recorded with
+//! `OrderTracker::set_owner`.  `OrderflowClient::cancel_all` sends the
+//! cancels for the selected orders in one write and records them with the
+//! tracker.
+
+use super::tracker::{OrderTracker, TrackedOrder, TrackedOrderState};
+use crate::symbology::MarketRef;
+use api::{AccountId, OrderId, UserId};
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelAllFilter {
+    pub account: Option<AccountId>,
+    pub trader: Option<UserId>,
+    pub market: Option<MarketRef>,
+    /// the execution venue name
+    pub venue: Option<String>,
+    /// also select orders with a cancel already pending, to resend it
+    pub include_canceling: bool,
+}
+
+impl CancelAllFilter {
+    pub fn account(account: AccountId) -> Self {
+        Self { account: Some(account), ..Default::default() }
+    }
+
+    pub fn trader(trader: UserId) -> Self {
+        Self { trader: Some(trader), ..Default::default() }
+    }
+
+    pub fn market(market: MarketRef) -> Self {
+        Self { market: Some(market), ..Default::default() }
+    }
+
+    pub fn venue(venue: impl Into<String>) -> Self {
+        Self { venue: Some(venue.into()), ..Default::default() }
+    }
+
+    pub fn matches(&self, order: &TrackedOrder) -> bool {
+        let market = &order.request.market;
+        !order.state.is_done()
+            && (self.include_canceling || order.state != TrackedOrderState::Canceling)
+            && self.account.map_or(true, |a| order.owner.account == Some(a))
+            && self.trader.map_or(true, |t| order.owner.trader == Some(t))
+            && self.market.map_or(true, |m| *market == m)
+            && self.venue.as_ref().map_or(true, |v| market.venue.name.as_str() == v)
+    }
+
+    /// The ids of the tracker's open orders that match
+    pub fn select(&self, tracker: &OrderTracker) -> Vec<OrderId> {
+        tracker.open_orders().filter(|o| self.matches(o)).map(|o| o.request.id).collect()
+    }
+}
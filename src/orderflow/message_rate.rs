@@ -0,0 +1,283 @@
+//! Outbound message-rate and order-to-trade ratio monitoring.  Venues impose
+//! compliance limits on how fast you may send orders and cancels and on how
+//! many orders you may send per fill; check each outbound message against
+//! the monitor before sending it to warn, or throttle, ahead of a breach.
+
+use super::capabilities::CapabilityRegistry;
+use crate::symbology::VenueRef;
+use fxhash::FxHashMap;
+use log::warn;
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Order,
+    Cancel,
+    Modify,
+}
+
+/// Compliance limits for a venue; None disables the corresponding check
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    /// length of the sliding window that per-window limits are counted over
+    pub window: Duration,
+    /// max total outbound messages per window
+    pub max_messages: Option<u32>,
+    pub max_orders: Option<u32>,
+    pub max_cancels: Option<u32>,
+    /// max ratio of orders (including modifies) to fills
+    pub max_order_to_trade: Option<f64>,
+    /// the order-to-trade ratio is not enforced until at least this many
+    /// orders have been sent
+    pub order_to_trade_min_orders: u64,
+    /// warn once usage reaches this fraction of any limit
+    pub warn_at: f64,
+    /// refuse to send once usage reaches this fraction of any limit
+    pub throttle_at: f64,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(1),
+            max_messages: None,
+            max_orders: None,
+            max_cancels: None,
+            max_order_to_trade: None,
+            order_to_trade_min_orders: 1000,
+            warn_at: 0.8,
+            throttle_at: 0.95,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Messages,
+    Orders,
+    Cancels,
+    OrderToTrade,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Messages => write!(f, "messages per window"),
+            Limit::Orders => write!(f, "orders per window"),
+            Limit::Cancels => write!(f, "cancels per window"),
+            Limit::OrderToTrade => write!(f, "order to trade ratio"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Ok,
+    /// send, but usage of the limit is at the given fraction
+    Warn(Limit, f64),
+    /// don't send; usage of the limit is at the given fraction
+    Throttle(Limit, f64),
+}
+
+impl Verdict {
+    pub fn allowed(&self) -> bool {
+        !matches!(self, Verdict::Throttle(..))
+    }
+
+    fn worst(self, other: Verdict) -> Verdict {
+        match (self, other) {
+            (Verdict::Throttle(..), _) => self,
+            (_, Verdict::Throttle(..)) => other,
+            (Verdict::Warn(..), _) => self,
+            (_, Verdict::Warn(..)) => other,
+            _ => Verdict::Ok,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct VenueState {
+    sent: VecDeque<(Instant, MessageKind)>,
+    orders: u64,
+    trades: u64,
+}
+
+impl VenueState {
+    fn expire(&mut self, window: Duration, now: Instant) {
+        while let Some((ts, _)) = self.sent.front() {
+            if now.saturating_duration_since(*ts) >= window {
+                self.sent.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn count(&self, kind: Option<MessageKind>) -> u32 {
+        match kind {
+            None => self.sent.len() as u32,
+            Some(kind) => self.sent.iter().filter(|(_, k)| *k == kind).count() as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VenueUsage {
+    pub messages: u32,
+    pub orders: u32,
+    pub cancels: u32,
+    pub total_orders: u64,
+    pub total_trades: u64,
+}
+
+impl VenueUsage {
+    pub fn order_to_trade(&self) -> f64 {
+        self.total_orders as f64 / self.total_trades.max(1) as f64
+    }
+}
+
+pub struct MessageRateMonitor {
+    default_limits: RateLimits,
+    limits: FxHashMap<VenueRef, RateLimits>,
+    venues: FxHashMap<VenueRef, VenueState>,
+}
+
+impl MessageRateMonitor {
+    pub fn new(default_limits: RateLimits) -> Self {
+        Self {
+            default_limits,
+            limits: FxHashMap::default(),
+            venues: FxHashMap::default(),
+        }
+    }
+
+    /// Set venue specific limits, overriding the defaults
+    pub fn set_limits(&mut self, venue: VenueRef, limits: RateLimits) {
+        self.limits.insert(venue, limits);
+    }
+
+    /// Set the limits of each venue the registry knows them for
+    pub fn load_limits(
+        &mut self,
+        registry: &CapabilityRegistry,
+        venues: impl IntoIterator<Item = VenueRef>,
+    ) {
+        for venue in venues {
+            if let Some(limits) = registry.get(&venue).rate_limits() {
+                self.set_limits(venue, limits);
+            }
+        }
+    }
+
+    pub fn limits(&self, venue: &VenueRef) -> &RateLimits {
+        self.limits.get(venue).unwrap_or(&self.default_limits)
+    }
+
+    /// Check whether sending a message of the given kind now would approach
+    /// or breach a limit.  This doesn't record anything; call `record` once the
+    /// message is actually sent.
+    pub fn check(&mut self, venue: VenueRef, kind: MessageKind, now: Instant) -> Verdict {
+        let limits = *self.limits(&venue);
+        let st = self.venues.entry(venue).or_default();
+        st.expire(limits.window, now);
+        let usage = |n: u32, max: Option<u32>| -> Option<f64> {
+            max.filter(|m| *m > 0).map(|m| (n + 1) as f64 / m as f64)
+        };
+        let classify = |limit: Limit, used: Option<f64>| match used {
+            Some(u) if u >= limits.throttle_at => Verdict::Throttle(limit, u),
+            Some(u) if u >= limits.warn_at => Verdict::Warn(limit, u),
+            _ => Verdict::Ok,
+        };
+        let mut verdict =
+            classify(Limit::Messages, usage(st.count(None), limits.max_messages));
+        match kind {
+            MessageKind::Order | MessageKind::Modify => {
+                let n = st.count(Some(MessageKind::Order))
+                    + st.count(Some(MessageKind::Modify));
+                verdict =
+                    verdict.worst(classify(Limit::Orders, usage(n, limits.max_orders)));
+                if let Some(max) = limits.max_order_to_trade {
+                    if st.orders + 1 >= limits.order_to_trade_min_orders && max > 0. {
+                        let ratio = (st.orders + 1) as f64 / st.trades.max(1) as f64;
+                        verdict = verdict
+                            .worst(classify(Limit::OrderToTrade, Some(ratio / max)));
+                    }
+                }
+            }
+            MessageKind::Cancel => {
+                let n = st.count(Some(MessageKind::Cancel));
+                verdict =
+                    verdict.worst(classify(Limit::Cancels, usage(n, limits.max_cancels)));
+            }
+        }
+        match verdict {
+            Verdict::Ok => (),
+            Verdict::Warn(limit, u) => {
+                warn!("{venue} {limit} at {:.0}% of compliance limit", u * 100.)
+            }
+            Verdict::Throttle(limit, u) => {
+                warn!(
+                    "throttling {venue}, {limit} at {:.0}% of compliance limit",
+                    u * 100.
+                )
+            }
+        }
+        verdict
+    }
+
+    /// Record a message that was sent
+    pub fn record(&mut self, venue: VenueRef, kind: MessageKind, now: Instant) {
+        let window = self.limits(&venue).window;
+        let st = self.venues.entry(venue).or_default();
+        st.expire(window, now);
+        st.sent.push_back((now, kind));
+        if kind != MessageKind::Cancel {
+            st.orders += 1;
+        }
+    }
+
+    /// Check and, if allowed, record in one step
+    pub fn try_send(
+        &mut self,
+        venue: VenueRef,
+        kind: MessageKind,
+        now: Instant,
+    ) -> Verdict {
+        let verdict = self.check(venue, kind, now);
+        if verdict.allowed() {
+            self.record(venue, kind, now);
+        }
+        verdict
+    }
+
+    /// Record a fill, for the order-to-trade ratio
+    pub fn record_trade(&mut self, venue: VenueRef) {
+        self.venues.entry(venue).or_default().trades += 1;
+    }
+
+    /// Reset the order-to-trade counters, e.g. at the start of the trading day
+    pub fn reset_order_to_trade(&mut self) {
+        for st in self.venues.values_mut() {
+            st.orders = 0;
+            st.trades = 0;
+        }
+    }
+
+    pub fn usage(&mut self, venue: VenueRef, now: Instant) -> VenueUsage {
+        let window = self.limits(&venue).window;
+        let st = self.venues.entry(venue).or_default();
+        st.expire(window, now);
+        VenueUsage {
+            messages: st.count(None),
+            orders: st.count(Some(MessageKind::Order))
+                + st.count(Some(MessageKind::Modify)),
+            cancels: st.count(Some(MessageKind::Cancel)),
+            total_orders: st.orders,
+            total_trades: st.trades,
+        }
+    }
+}
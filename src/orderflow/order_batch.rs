@@ -0,0 +1,104 @@
+//! Per-order outcomes for a batch of orders sent together.
+//!
+//! Send the batch in one write (`OrderflowClient::place_all`), then
+//! collect what happened to each order from the `OrderTracker` broadcast
+//! with `await_batch`: acked, rejected with a reason, or no answer in time.
+//! Subscribe before sending so no event is missed.
+//!
+//! The Oms has no atomic batch entry, so a batch is not all-or-nothing.
+//! When any order of a batch that should be fails, `BatchOutcome::rollback`
+//! lists the orders that may have gone live, for the caller to cancel.
+//! Those orders are exposed to the market from their ack until the cancel
+//! lands, at least `await_batch`'s wait plus a round trip, and can fill in
+//! the meantime.
+
+use super::{reject::RejectReason, tracker::OrderEvent};
+use api::OrderId;
+use fxhash::FxHashMap;
+use log::warn;
+use std::time::Duration;
+use tokio::{sync::broadcast, time::Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchError {
+    Rejected(RejectReason),
+    /// neither acked nor rejected before the deadline
+    TimedOut,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchOutcome {
+    /// in the order the ids were given
+    pub results: Vec<(OrderId, Result<(), BatchError>)>,
+}
+
+impl BatchOutcome {
+    pub fn all_ok(&self) -> bool {
+        self.results.iter().all(|(_, r)| r.is_ok())
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = (&OrderId, &BatchError)> {
+        self.results.iter().filter_map(|(id, r)| r.as_ref().err().map(|e| (id, e)))
+    }
+
+    /// The orders to cancel to undo a batch that should have gone in
+    /// whole, i.e. every order not known to have failed if any order
+    /// failed, otherwise none.  Orders that timed out may still be live, so
+    /// they are included.  Any of them may have filled before the cancel.
+    pub fn rollback(&self) -> Vec<OrderId> {
+        if self.all_ok() {
+            return vec![];
+        }
+        self.results
+            .iter()
+            .filter(|(_, r)| !matches!(r, Err(BatchError::Rejected(_))))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+/// Wait for each of `ids` to be acked or rejected, for at most `timeout`.
+/// A fill counts as an ack.
+pub async fn await_batch(
+    mut rx: broadcast::Receiver<OrderEvent>,
+    ids: &[OrderId],
+    timeout: Duration,
+) -> BatchOutcome {
+    let mut outcomes: FxHashMap<OrderId, Option<Result<(), BatchError>>> =
+        ids.iter().map(|id| (*id, None)).collect();
+    let mut waiting = outcomes.len();
+    let deadline = Instant::now() + timeout;
+    while waiting > 0 {
+        let ev = match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Err(_) | Ok(Err(broadcast::error::RecvError::Closed)) => break,
+            Ok(Err(broadcast::error::RecvError::Lagged(n))) => {
+                warn!("batch outcome lagged {n} order events");
+                continue;
+            }
+            Ok(Ok(ev)) => ev,
+        };
+        let outcome = match &ev {
+            OrderEvent::Ack(_) | OrderEvent::Fill { .. } => Ok(()),
+            OrderEvent::Reject { reason, .. } => {
+                Err(BatchError::Rejected(reason.clone()))
+            }
+            _ => continue,
+        };
+        match outcomes.get_mut(&ev.id()) {
+            Some(slot) if slot.is_none() => {
+                *slot = Some(outcome);
+                waiting -= 1;
+            }
+            _ => (),
+        }
+    }
+    let results = ids
+        .iter()
+        .map(|id| {
+            let r =
+                outcomes.get(id).cloned().flatten().unwrap_or(Err(BatchError::TimedOut));
+            (*id, r)
+        })
+        .collect();
+    BatchOutcome { results }
+}
@@ -0,0 +1,393 @@
+//! Session P&L and drawdown, with circuit breakers.
+//!
+//! `PnlTracker` keeps average cost positions per key (a strategy name, an
+//! account, whatever the caller groups by) and market.  Feed it fills and
+//! marks; it computes realized and unrealized P&L and the drawdown from
+//! the session's peak.  When a key breaches its `BreakerConfig` the kill
+//! switch is tripped and a `BreakerEvent` is delivered on the broadcast
+//! channel and to any registered callbacks.
+//!
+//! Fills are kept for the session so a venue bust or correction can be
+//! applied exactly: the affected market is replayed from the start of the
+//! session without the busted fill, rather than approximated by trading
+//! back out of it.  The session peak is moved by the change in P&L, since
+//! marks aren't kept to replay it exactly.
+//!
+//! With a fee model set, `on_venue_fill` charges fills that arrive without
+//! a fee by the venue's schedule, so the total is net of fees either way.
+
+use super::{
+    fees::{Fees, Liquidity},
+    kill_switch::KillSwitch,
+};
+use crate::symbology::MarketRef;
+use api::Dir;
+use chrono::{DateTime, Utc};
+use fxhash::FxHashMap;
+use log::warn;
+use rust_decimal::Decimal;
+use std::{fmt::Debug, hash::Hash};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BreakerConfig {
+    /// trip when session P&L falls to minus this
+    pub max_loss: Option<Decimal>,
+    /// trip when P&L falls this far below its session peak
+    pub max_drawdown: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerKind {
+    MaxLoss,
+    MaxDrawdown,
+}
+
+#[derive(Debug, Clone)]
+pub struct BreakerEvent<K> {
+    pub key: K,
+    pub kind: BreakerKind,
+    pub pnl: PnlSummary,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PnlFill {
+    pub market: MarketRef,
+    pub dir: Dir,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub fee: Decimal,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PnlSummary {
+    pub realized: Decimal,
+    pub unrealized: Decimal,
+    pub fees: Decimal,
+    /// realized + unrealized - fees
+    pub total: Decimal,
+    /// highest total seen this session
+    pub peak: Decimal,
+    pub drawdown: Decimal,
+    pub max_drawdown: Decimal,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Position {
+    /// signed
+    quantity: Decimal,
+    avg_cost: Decimal,
+    realized: Decimal,
+}
+
+impl Position {
+    fn apply(&mut self, signed: Decimal, price: Decimal) {
+        let same_side = self.quantity.is_zero()
+            || self.quantity.is_sign_positive() == signed.is_sign_positive();
+        if same_side {
+            let qty = self.quantity + signed;
+            self.avg_cost =
+                (self.avg_cost * self.quantity.abs() + price * signed.abs()) / qty.abs();
+            self.quantity = qty;
+        } else {
+            let closed = signed.abs().min(self.quantity.abs());
+            let sign = if self.quantity.is_sign_positive() {
+                Decimal::ONE
+            } else {
+                Decimal::NEGATIVE_ONE
+            };
+            self.realized += closed * (price - self.avg_cost) * sign;
+            self.quantity += signed;
+            if self.quantity.is_zero() {
+                self.avg_cost = Decimal::ZERO;
+            } else if self.quantity.is_sign_positive() != sign.is_sign_positive() {
+                // flipped through flat, the remainder opens at this price
+                self.avg_cost = price;
+            }
+        }
+    }
+
+    fn unrealized(&self, mark: Option<Decimal>) -> Decimal {
+        match mark {
+            Some(mark) => self.quantity * (mark - self.avg_cost),
+            None => Decimal::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Book {
+    positions: FxHashMap<MarketRef, Position>,
+    /// positions at the start of the session
+    opening: FxHashMap<MarketRef, Position>,
+    /// fills this session, in order
+    fills: FxHashMap<MarketRef, Vec<PnlFill>>,
+    fees: Decimal,
+    peak: Decimal,
+    max_drawdown: Decimal,
+    breaker: Option<BreakerConfig>,
+    tripped: bool,
+}
+
+pub struct PnlTracker<K> {
+    books: FxHashMap<K, Book>,
+    marks: FxHashMap<MarketRef, Decimal>,
+    default_breaker: BreakerConfig,
+    kill_switch: KillSwitch,
+    callbacks: Vec<Box<dyn Fn(&BreakerEvent<K>) + Send + Sync>>,
+    fees: Option<Fees>,
+    tx: broadcast::Sender<BreakerEvent<K>>,
+}
+
+impl<K: Clone + Debug + Eq + Hash> PnlTracker<K> {
+    pub fn new(default_breaker: BreakerConfig, kill_switch: KillSwitch) -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self {
+            books: FxHashMap::default(),
+            marks: FxHashMap::default(),
+            default_breaker,
+            kill_switch,
+            callbacks: vec![],
+            fees: None,
+            tx,
+        }
+    }
+
+    /// Override the breaker thresholds for one key
+    pub fn set_breaker(&mut self, key: K, config: BreakerConfig) {
+        self.books.entry(key).or_default().breaker = Some(config);
+    }
+
+    pub fn on_breaker(&mut self, f: impl Fn(&BreakerEvent<K>) + Send + Sync + 'static) {
+        self.callbacks.push(Box::new(f));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BreakerEvent<K>> {
+        self.tx.subscribe()
+    }
+
+    pub fn set_fees(&mut self, fees: Option<Fees>) {
+        self.fees = fees;
+    }
+
+    pub fn fees(&self) -> Option<&Fees> {
+        self.fees.as_ref()
+    }
+
+    /// Like `on_fill`, but a fill with a zero fee is charged by the fee
+    /// model, if one is set.  Every fill counts toward its venue's tier.
+    pub fn on_venue_fill(
+        &mut self,
+        key: K,
+        mut fill: PnlFill,
+        liquidity: Liquidity,
+        now: DateTime<Utc>,
+    ) {
+        if let Some(fees) = &mut self.fees {
+            let fee = fees.on_fill(&fill.market, liquidity, fill.quantity, fill.price);
+            if fill.fee.is_zero() {
+                fill.fee = fee;
+            }
+        }
+        self.on_fill(key, fill, now)
+    }
+
+    pub fn on_fill(&mut self, key: K, fill: PnlFill, now: DateTime<Utc>) {
+        let signed = match fill.dir {
+            Dir::Buy => fill.quantity,
+            Dir::Sell => -fill.quantity,
+        };
+        let book = self.books.entry(key.clone()).or_default();
+        book.positions.entry(fill.market).or_default().apply(signed, fill.price);
+        book.fills.entry(fill.market).or_default().push(fill);
+        book.fees += fill.fee;
+        self.marks.entry(fill.market).or_insert(fill.price);
+        self.check(&key, now);
+    }
+
+    /// Remove a fill the venue busted, returns false if no such fill was
+    /// seen this session
+    pub fn on_bust(&mut self, key: &K, fill: PnlFill, now: DateTime<Utc>) -> bool {
+        self.on_correction(key, fill, None, now)
+    }
+
+    /// Replace a fill the venue corrected with `new`, or remove it if
+    /// `new` is None; returns false if no such fill was seen this session
+    pub fn on_correction(
+        &mut self,
+        key: &K,
+        old: PnlFill,
+        new: Option<PnlFill>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let Some(before) = self.books.get(key).map(|b| self.summarize(b).total) else {
+            return false;
+        };
+        let book = self.books.get_mut(key).unwrap();
+        let fills = book.fills.entry(old.market).or_default();
+        let Some(i) = fills.iter().position(|f| *f == old) else {
+            warn!("{key:?} bust or correction for unknown fill {old:?}");
+            return false;
+        };
+        fills.remove(i);
+        book.fees -= old.fee;
+        if let Some(new) = new {
+            book.fees += new.fee;
+            // keep the corrected fill in the busted one's place in the order
+            let fills = book.fills.entry(new.market).or_default();
+            if new.market == old.market {
+                fills.insert(i, new);
+            } else {
+                fills.push(new);
+            }
+        }
+        for market in [Some(old.market), new.map(|n| n.market)].into_iter().flatten() {
+            let mut p = book.opening.get(&market).copied().unwrap_or_default();
+            for f in book.fills.get(&market).into_iter().flatten() {
+                let signed = match f.dir {
+                    Dir::Buy => f.quantity,
+                    Dir::Sell => -f.quantity,
+                };
+                p.apply(signed, f.price);
+            }
+            book.positions.insert(market, p);
+        }
+        // the peak was reached with the old fill counted; move it by what
+        // the correction changed, so a busted winner isn't a drawdown
+        let total = self.summarize(&self.books[key]).total;
+        let book = self.books.get_mut(key).unwrap();
+        book.peak = (book.peak + total - before).max(total);
+        self.check(key, now);
+        true
+    }
+
+    /// Update the mark price of a market, e.g. from its mid or last trade
+    pub fn on_mark(&mut self, market: MarketRef, price: Decimal, now: DateTime<Utc>) {
+        self.marks.insert(market, price);
+        let keys: Vec<K> = self
+            .books
+            .iter()
+            .filter(|(_, b)| b.positions.contains_key(&market))
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in keys {
+            self.check(&key, now);
+        }
+    }
+
+    pub fn summary(&self, key: &K) -> Option<PnlSummary> {
+        self.books.get(key).map(|b| self.summarize(b))
+    }
+
+    pub fn position(&self, key: &K, market: &MarketRef) -> Decimal {
+        self.books
+            .get(key)
+            .and_then(|b| b.positions.get(market))
+            .map(|p| p.quantity)
+            .unwrap_or_default()
+    }
+
+    /// Start a new session: realized P&L, fees and the peak reset, open
+    /// positions carry over at their current mark
+    pub fn new_session(&mut self) {
+        for book in self.books.values_mut() {
+            for (market, p) in book.positions.iter_mut() {
+                if let Some(mark) = self.marks.get(market) {
+                    p.avg_cost = *mark;
+                }
+                p.realized = Decimal::ZERO;
+            }
+            book.positions.retain(|_, p| !p.quantity.is_zero());
+            book.opening = book.positions.clone();
+            book.fills.clear();
+            book.fees = Decimal::ZERO;
+            book.peak = Decimal::ZERO;
+            book.max_drawdown = Decimal::ZERO;
+            book.tripped = false;
+        }
+    }
+
+    fn summarize(&self, book: &Book) -> PnlSummary {
+        let (realized, unrealized) = book.positions.iter().fold(
+            (Decimal::ZERO, Decimal::ZERO),
+            |(r, u), (market, p)| {
+                (r + p.realized, u + p.unrealized(self.marks.get(market).copied()))
+            },
+        );
+        let total = realized + unrealized - book.fees;
+        let peak = book.peak.max(total);
+        let drawdown = peak - total;
+        PnlSummary {
+            realized,
+            unrealized,
+            fees: book.fees,
+            total,
+            peak,
+            drawdown,
+            max_drawdown: book.max_drawdown.max(drawdown),
+        }
+    }
+
+    fn check(&mut self, key: &K, now: DateTime<Utc>) {
+        let Some(book) = self.books.get(key) else { return };
+        let pnl = self.summarize(book);
+        let config = book.breaker.unwrap_or(self.default_breaker);
+        let book = self.books.get_mut(key).unwrap();
+        book.peak = pnl.peak;
+        book.max_drawdown = pnl.max_drawdown;
+        if book.tripped {
+            return;
+        }
+        let kind = if config.max_loss.map(|l| pnl.total <= -l).unwrap_or(false) {
+            BreakerKind::MaxLoss
+        } else if config.max_drawdown.map(|d| pnl.drawdown >= d).unwrap_or(false) {
+            BreakerKind::MaxDrawdown
+        } else {
+            return;
+        };
+        book.tripped = true;
+        warn!("{key:?} breached {kind:?}: {pnl:?}");
+        self.kill_switch.trip(format!("{key:?} breached {kind:?}, P&L {}", pnl.total));
+        let ev = BreakerEvent { key: key.clone(), kind, pnl, timestamp: now };
+        for cb in &self.callbacks {
+            cb(&ev);
+        }
+        let _ = self.tx.send(ev);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbology::test_market;
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_bust_winning_fill() -> Result<()> {
+        let market = test_market("CHFUSD", "CHF", "USD")?;
+        let kill_switch = KillSwitch::new();
+        let breaker = BreakerConfig { max_loss: None, max_drawdown: Some(dec!(20)) };
+        let mut pnl = PnlTracker::new(breaker, kill_switch.clone());
+        let now = Utc::now();
+        let fill = |dir, price| PnlFill {
+            market,
+            dir,
+            quantity: dec!(10),
+            price,
+            fee: Decimal::ZERO,
+        };
+        pnl.on_fill("s", fill(Dir::Buy, dec!(100)), now);
+        let winner = fill(Dir::Sell, dec!(105));
+        pnl.on_fill("s", winner, now);
+        assert_eq!(pnl.summary(&"s").unwrap().peak, dec!(50));
+        assert!(pnl.on_bust(&"s", winner, now));
+        let summary = pnl.summary(&"s").unwrap();
+        assert_eq!(summary.total, Decimal::ZERO);
+        assert_eq!(summary.peak, Decimal::ZERO);
+        assert_eq!(summary.drawdown, Decimal::ZERO);
+        assert!(kill_switch.reason().is_none());
+        Ok(())
+    }
+}
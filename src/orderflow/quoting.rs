@@ -0,0 +1,241 @@
+//! Two sided quoting, the skeleton every market maker rebuilds.
+//!
+//! `QuotingEngine` keeps a bid and an ask around a fair value (the mid,
+//! skewed against the current inventory) at configured offsets and sizes.
+//! Like `IntentExecutor::plan`, each call to `update` compares the desired
+//! quotes against the market's working orders and returns the cancels and
+//! places needed; a working quote within the hysteresis band of where it
+//! should be is left alone so it keeps its queue position.  The built in
+//! skew is linear in the position; `set_skew` swaps in a `skew::SkewConfig`
+//! for a target position, a curve, and size reduction.
+//!
+//! Quotes are pulled, i.e. every working order is canceled and nothing new
+//! is placed, while the engine is pulled by a risk trigger, while the kill
+//! switch is tripped, and while the book is missing a side.
+
+use super::{
+    intent::IntentAction,
+    kill_switch::KillSwitch,
+    skew::{self, SkewConfig},
+    tracker::OrderRequest,
+};
+use crate::{
+    marketdata::market_view::MarketState,
+    prices::{offset_bps, passive_offset_bps, TickSize},
+    symbology::MarketRef,
+};
+use anyhow::{bail, Result};
+use api::{Dir, DirPair, OrderId};
+use log::warn;
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuoteConfig {
+    /// distance of each quote from fair value
+    pub offset_bps: Decimal,
+    pub size: Decimal,
+    /// fair value moves this many bps against the position per unit held,
+    /// so a long quotes lower and sells down its inventory
+    #[serde(default)]
+    pub skew_bps: Decimal,
+    /// absolute position limit; the side that would take the position past
+    /// it quotes only the room that is left
+    pub max_position: Decimal,
+    /// leave a working quote alone unless its price is at least this many
+    /// ticks from where it should be
+    #[serde(default)]
+    pub requote_ticks: u32,
+    /// replace a partially filled quote once its remaining size falls below
+    /// this fraction of the quote size
+    #[serde(default)]
+    pub refill_below: Decimal,
+}
+
+impl QuoteConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.offset_bps < Decimal::ZERO {
+            bail!("quote offset must not be negative, got {}", self.offset_bps);
+        }
+        if self.size <= Decimal::ZERO {
+            bail!("quote size must be positive, got {}", self.size);
+        }
+        if self.max_position < Decimal::ZERO {
+            bail!("max position must not be negative, got {}", self.max_position);
+        }
+        if self.refill_below < Decimal::ZERO || self.refill_below > Decimal::ONE {
+            bail!("refill fraction must be between 0 and 1, got {}", self.refill_below);
+        }
+        Ok(())
+    }
+}
+
+/// A quote the engine wants to have working
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quote {
+    pub price: Decimal,
+    pub quantity: Decimal,
+}
+
+pub struct QuotingEngine {
+    market: MarketRef,
+    tick: TickSize,
+    config: QuoteConfig,
+    kill_switch: KillSwitch,
+    skew: Option<SkewConfig>,
+    pulled: Option<String>,
+}
+
+impl QuotingEngine {
+    pub fn new(
+        market: MarketRef,
+        config: QuoteConfig,
+        kill_switch: KillSwitch,
+    ) -> Result<Self> {
+        config.validate()?;
+        let tick = TickSize::for_market(&market)?;
+        Ok(Self { market, tick, config, kill_switch, skew: None, pulled: None })
+    }
+
+    pub fn market(&self) -> MarketRef {
+        self.market
+    }
+
+    pub fn config(&self) -> &QuoteConfig {
+        &self.config
+    }
+
+    /// Change the offsets and sizes; working quotes move on the next update
+    pub fn set_config(&mut self, config: QuoteConfig) -> Result<()> {
+        config.validate()?;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Skew with an inventory model instead of the config's linear
+    /// `skew_bps`, or go back to it with None
+    pub fn set_skew(&mut self, skew: Option<SkewConfig>) -> Result<()> {
+        if let Some(skew) = &skew {
+            skew.validate()?;
+        }
+        self.skew = skew;
+        Ok(())
+    }
+
+    /// Pull quotes for a risk trigger local to this engine, until `resume`.
+    /// Returns false if already pulled, in which case the original reason is
+    /// kept.
+    pub fn pull(&mut self, reason: impl Into<String>) -> bool {
+        if self.pulled.is_some() {
+            return false;
+        }
+        let reason = reason.into();
+        warn!("pulling quotes on {}: {reason}", self.market);
+        self.pulled = Some(reason);
+        true
+    }
+
+    /// Resume quoting after `pull`; a tripped kill switch still applies
+    pub fn resume(&mut self) {
+        self.pulled = None;
+    }
+
+    /// Why quotes are pulled, if they are
+    pub fn pulled(&self) -> Option<String> {
+        self.pulled.clone().or_else(|| self.kill_switch.reason())
+    }
+
+    /// The skewed fair value, or None if the book is missing a side
+    pub fn fair_value(&self, state: &MarketState) -> Option<Decimal> {
+        let mid = state.mid()?;
+        Some(match &self.skew {
+            Some(skew) => skew::skewed_fair(skew, mid, state.position),
+            None => offset_bps(mid, -state.position * self.config.skew_bps),
+        })
+    }
+
+    /// The quotes the engine wants given the state, ignoring working orders
+    pub fn desired(&self, state: &MarketState) -> DirPair<Option<Quote>> {
+        let fair = match self.pulled() {
+            Some(_) => None,
+            None => self.fair_value(state),
+        };
+        DirPair {
+            buy: fair.and_then(|fair| self.quote(state, fair, Dir::Buy)),
+            sell: fair.and_then(|fair| self.quote(state, fair, Dir::Sell)),
+        }
+    }
+
+    fn quote(&self, state: &MarketState, fair: Decimal, dir: Dir) -> Option<Quote> {
+        let room = match dir {
+            Dir::Buy => self.config.max_position - state.position,
+            Dir::Sell => self.config.max_position + state.position,
+        };
+        let size = match &self.skew {
+            Some(skew) => skew::skewed_size(skew, self.config.size, dir, state.position),
+            None => self.config.size,
+        };
+        let quantity = size.min(room);
+        if quantity <= Decimal::ZERO {
+            return None;
+        }
+        let mut price = passive_offset_bps(fair, self.config.offset_bps, dir, self.tick);
+        // a skewed fair value can land through the far touch; stay a tick
+        // behind it so the quote never takes
+        let behind = |far| self.tick.improve(far, dir, -1);
+        price = match dir {
+            Dir::Buy => match state.best(Dir::Sell) {
+                Some((ask, _)) => price.min(behind(ask)),
+                None => price,
+            },
+            Dir::Sell => match state.best(Dir::Buy) {
+                Some((bid, _)) => price.max(behind(bid)),
+                None => price,
+            },
+        };
+        (price > Decimal::ZERO).then_some(Quote { price, quantity })
+    }
+
+    /// Plan the cancels and places that bring the working orders in line
+    /// with the desired quotes.  At most one order per side is kept, the
+    /// first that is within the hysteresis band and not depleted.
+    pub fn update(
+        &self,
+        state: &MarketState,
+        mut next_order_id: impl FnMut() -> OrderId,
+    ) -> Vec<IntentAction> {
+        let desired = self.desired(state);
+        let mut actions = vec![];
+        let (mut kept_buy, mut kept_sell) = (false, false);
+        for o in &state.working_orders {
+            let kept = match o.dir {
+                Dir::Buy => &mut kept_buy,
+                Dir::Sell => &mut kept_sell,
+            };
+            match desired.get(o.dir) {
+                Some(q) if !*kept && self.keep(o.price, o.remaining(), q) => *kept = true,
+                _ => actions.push(IntentAction::Cancel(o.id)),
+            }
+        }
+        for (dir, kept) in [(Dir::Buy, kept_buy), (Dir::Sell, kept_sell)] {
+            if let (false, Some(q)) = (kept, desired.get(dir)) {
+                actions.push(IntentAction::Place(OrderRequest {
+                    id: next_order_id(),
+                    market: self.market,
+                    dir,
+                    price: q.price,
+                    quantity: q.quantity,
+                }));
+            }
+        }
+        actions
+    }
+
+    fn keep(&self, price: Decimal, remaining: Decimal, quote: &Quote) -> bool {
+        let moved = self.tick.ticks_between(price, quote.price).abs();
+        moved < Decimal::from(self.config.requote_ticks.max(1))
+            && remaining <= quote.quantity
+            && remaining >= quote.quantity * self.config.refill_below
+            && !remaining.is_zero()
+    }
+}
@@ -0,0 +1,128 @@
+//! Typed reject reasons.  Venues and the Oms report rejects as free form
+//! strings; classify them so callers can decide whether to retry, abort, or
+//! alert without string matching of their own.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    InsufficientMargin,
+    PriceOutOfBand,
+    DuplicateOrderId,
+    MarketClosed,
+    RiskBlock,
+    /// short sale without a borrow locate
+    NoLocate,
+    /// the venue is rate limiting us
+    RateLimited,
+    /// unclassified; the original message is kept
+    Unknown(String),
+}
+
+/// What an automated caller should do about a reject
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectAction {
+    /// transient, the same order may be resent after a delay
+    Retry,
+    /// the order is bad as constructed, don't resend it unchanged
+    Abort,
+    /// needs a human to look at it
+    Alert,
+}
+
+// best effort across the venues seen so far; add venue specific codes as
+// they come up
+const PATTERNS: &[(&[&str], fn() -> RejectReason)] = &[
+    (
+        &["insufficient margin", "insufficient funds", "insufficient balance", "margin"],
+        || RejectReason::InsufficientMargin,
+    ),
+    (
+        &["price band", "out of band", "price limit", "price collar", "outside limit"],
+        || RejectReason::PriceOutOfBand,
+    ),
+    (&["duplicate", "already exists", "clordid in use"], || {
+        RejectReason::DuplicateOrderId
+    }),
+    (
+        &["market closed", "market is closed", "not open", "halted", "trading halt"],
+        || RejectReason::MarketClosed,
+    ),
+    (&["locate", "not shortable", "hard to borrow", "short sale restrict"], || {
+        RejectReason::NoLocate
+    }),
+    (&["rate limit", "too many requests", "throttle"], || RejectReason::RateLimited),
+    (&["risk", "limit exceeded", "blocked"], || RejectReason::RiskBlock),
+];
+
+/// Whether `needle` occurs in `haystack` as whole words, so "locate"
+/// doesn't match inside "allocate"
+fn contains_words(haystack: &str, needle: &str) -> bool {
+    let is_word = |c: char| c.is_alphanumeric();
+    haystack.match_indices(needle).any(|(i, _)| {
+        let before = haystack[..i].chars().next_back();
+        let after = haystack[i + needle.len()..].chars().next();
+        !before.is_some_and(is_word) && !after.is_some_and(is_word)
+    })
+}
+
+impl RejectReason {
+    /// Classify a venue or Oms reject message
+    pub fn classify(msg: &str) -> Self {
+        let lower = msg.to_lowercase();
+        for (needles, reason) in PATTERNS {
+            if needles.iter().any(|n| contains_words(&lower, n)) {
+                return reason();
+            }
+        }
+        RejectReason::Unknown(msg.to_string())
+    }
+
+    pub fn action(&self) -> RejectAction {
+        match self {
+            RejectReason::RateLimited | RejectReason::MarketClosed => RejectAction::Retry,
+            RejectReason::PriceOutOfBand
+            | RejectReason::DuplicateOrderId
+            | RejectReason::NoLocate => RejectAction::Abort,
+            RejectReason::InsufficientMargin
+            | RejectReason::RiskBlock
+            | RejectReason::Unknown(_) => RejectAction::Alert,
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::InsufficientMargin => write!(f, "insufficient margin"),
+            RejectReason::PriceOutOfBand => write!(f, "price out of band"),
+            RejectReason::DuplicateOrderId => write!(f, "duplicate order id"),
+            RejectReason::MarketClosed => write!(f, "market closed"),
+            RejectReason::RiskBlock => write!(f, "blocked by risk"),
+            RejectReason::NoLocate => write!(f, "no locate for short sale"),
+            RejectReason::RateLimited => write!(f, "rate limited"),
+            RejectReason::Unknown(msg) => write!(f, "rejected: {msg}"),
+        }
+    }
+}
+
+/// Rejects can be returned through `anyhow` and recovered with `downcast_ref`
+impl std::error::Error for RejectReason {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_matches_whole_words() {
+        assert_eq!(RejectReason::classify("No locate available"), RejectReason::NoLocate);
+        assert_eq!(
+            RejectReason::classify("Order rejected: LOCATE REQUIRED"),
+            RejectReason::NoLocate
+        );
+        // "locate" inside "allocate" isn't a locate reject
+        let msg = "internal error: failed to allocate order";
+        assert_eq!(RejectReason::classify(msg), RejectReason::Unknown(msg.to_string()));
+        assert_eq!(RejectReason::classify(msg).action(), RejectAction::Alert);
+    }
+}
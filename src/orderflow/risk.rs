@@ -0,0 +1,258 @@
+//! Client side pre-trade risk checks.
+//!
+//! A `RiskChecker` checks an order against `RiskLimits` before it's sent:
+//! maximum quantity and notional, a price collar around the last trade or
+//! the top of book, a maximum number of open orders and a list of
+//! restricted markets.  Limits may be overridden per market.  A failed
+//! check returns a `RiskRejection` saying which limit and by how much;
+//! through `OrderflowClient::place` it's an error that can be downcast to
+//! one.
+//!
+//! Reference prices for the collar are fed with `update_reference`; an
+//! order for a market without a reference price passes the collar unless
+//! `require_reference` is set.
+//!
+//! Position limits are checked against a `RiskSnapshot`, a copy of the
+//! tracker's open orders and positions taken at one epoch, so a check
+//! never sees a fill reflected in an order but not yet in the position,
+//! or the other way around.
+
+use super::{
+    reject::RejectReason,
+    tracker::{OrderRequest, OrderTracker, TrackedOrder},
+};
+use crate::{marketdata::market_view::MarketState, symbology::MarketRef};
+use api::Dir;
+use fxhash::{FxHashMap, FxHashSet};
+use parking_lot::RwLock;
+use rust_decimal::Decimal;
+use std::fmt;
+
+#[derive(Debug, Clone, Default)]
+pub struct RiskLimits {
+    pub max_quantity: Option<Decimal>,
+    /// price times quantity
+    pub max_notional: Option<Decimal>,
+    /// the furthest a price may be from the reference, as a fraction of the
+    /// reference, e.g. 0.05 for 5%
+    pub collar: Option<Decimal>,
+    /// reject orders when there is no reference price to collar against
+    pub require_reference: bool,
+    pub max_open_orders: Option<usize>,
+    /// the largest absolute position the order could leave, counting open
+    /// orders on the same side as filled; checked by `check_snapshot`
+    pub max_position: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RiskRejection {
+    Restricted(MarketRef),
+    MaxQuantity { quantity: Decimal, limit: Decimal },
+    MaxNotional { notional: Decimal, limit: Decimal },
+    PriceCollar { price: Decimal, reference: Decimal, collar: Decimal },
+    NoReference(MarketRef),
+    MaxOpenOrders { open: usize, limit: usize },
+    MaxPosition { position: Decimal, limit: Decimal },
+}
+
+impl RiskRejection {
+    /// The reject reason to report, as if the venue had rejected the order
+    pub fn reason(&self) -> RejectReason {
+        match self {
+            RiskRejection::PriceCollar { .. } => RejectReason::PriceOutOfBand,
+            _ => RejectReason::RiskBlock,
+        }
+    }
+}
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskRejection::Restricted(m) => write!(f, "{m} is restricted"),
+            RiskRejection::MaxQuantity { quantity, limit } => {
+                write!(f, "quantity {quantity} exceeds the limit of {limit}")
+            }
+            RiskRejection::MaxNotional { notional, limit } => {
+                write!(f, "notional {notional} exceeds the limit of {limit}")
+            }
+            RiskRejection::PriceCollar { price, reference, collar } => write!(
+                f,
+                "price {price} is more than {collar} from the reference {reference}"
+            ),
+            RiskRejection::NoReference(m) => {
+                write!(f, "no reference price for {m} to collar against")
+            }
+            RiskRejection::MaxOpenOrders { open, limit } => {
+                write!(f, "{open} open orders, the limit is {limit}")
+            }
+            RiskRejection::MaxPosition { position, limit } => {
+                write!(f, "potential position {position} exceeds the limit of {limit}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RiskRejection {}
+
+/// Open orders and positions as of one tracker epoch
+#[derive(Debug, Clone)]
+pub struct RiskSnapshot {
+    pub epoch: u64,
+    pub open_orders: Vec<TrackedOrder>,
+    pub positions: FxHashMap<MarketRef, Decimal>,
+}
+
+impl RiskSnapshot {
+    /// Copy the tracker's open orders and positions; they are consistent
+    /// since both change only under the tracker's `&mut`
+    pub fn capture(tracker: &OrderTracker) -> Self {
+        Self {
+            epoch: tracker.epoch(),
+            open_orders: tracker.open_orders().cloned().collect(),
+            positions: tracker.positions().clone(),
+        }
+    }
+
+    pub fn position(&self, market: &MarketRef) -> Decimal {
+        self.positions.get(market).copied().unwrap_or_default()
+    }
+
+    /// The unfilled quantity of open orders in `market` on `dir`
+    pub fn open_quantity(&self, market: &MarketRef, dir: Dir) -> Decimal {
+        self.open_orders
+            .iter()
+            .filter(|o| o.request.market == *market && o.request.dir == dir)
+            .map(|o| o.remaining().max(Decimal::ZERO))
+            .sum()
+    }
+}
+
+/// The reference for a market's price collar: the last trade if there is
+/// one, else the near touch of the book
+#[derive(Debug, Clone, Copy, Default)]
+struct Reference {
+    last_trade: Option<Decimal>,
+    bid: Option<Decimal>,
+    ask: Option<Decimal>,
+}
+
+impl Reference {
+    fn price(&self, dir: Dir) -> Option<Decimal> {
+        self.last_trade.or(match dir {
+            Dir::Buy => self.ask.or(self.bid),
+            Dir::Sell => self.bid.or(self.ask),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RiskChecker {
+    limits: RiskLimits,
+    per_market: FxHashMap<MarketRef, RiskLimits>,
+    restricted: FxHashSet<MarketRef>,
+    references: RwLock<FxHashMap<MarketRef, Reference>>,
+}
+
+impl RiskChecker {
+    pub fn new(limits: RiskLimits) -> Self {
+        Self { limits, ..Default::default() }
+    }
+
+    /// Use `limits` instead of the defaults for `market`
+    pub fn with_market_limits(mut self, market: MarketRef, limits: RiskLimits) -> Self {
+        self.per_market.insert(market, limits);
+        self
+    }
+
+    pub fn with_restricted(
+        mut self,
+        markets: impl IntoIterator<Item = MarketRef>,
+    ) -> Self {
+        self.restricted.extend(markets);
+        self
+    }
+
+    pub fn limits(&self, market: &MarketRef) -> &RiskLimits {
+        self.per_market.get(market).unwrap_or(&self.limits)
+    }
+
+    /// Update the collar reference for a market from its current state
+    pub fn update_reference(&self, market: MarketRef, state: &MarketState) {
+        let reference = Reference {
+            last_trade: state.last_trade.map(|t| t.price),
+            bid: state.best(Dir::Buy).map(|(p, _)| p),
+            ask: state.best(Dir::Sell).map(|(p, _)| p),
+        };
+        self.references.write().insert(market, reference);
+    }
+
+    /// Check an order given the number of orders already open
+    pub fn check(
+        &self,
+        req: &OrderRequest,
+        open_orders: usize,
+    ) -> Result<(), RiskRejection> {
+        if self.restricted.contains(&req.market) {
+            return Err(RiskRejection::Restricted(req.market));
+        }
+        let limits = self.limits(&req.market);
+        if let Some(limit) = limits.max_quantity {
+            if req.quantity > limit {
+                return Err(RiskRejection::MaxQuantity { quantity: req.quantity, limit });
+            }
+        }
+        if let Some(limit) = limits.max_notional {
+            let notional = (req.price * req.quantity).abs();
+            if notional > limit {
+                return Err(RiskRejection::MaxNotional { notional, limit });
+            }
+        }
+        if let Some(collar) = limits.collar {
+            let reference =
+                self.references.read().get(&req.market).and_then(|r| r.price(req.dir));
+            match reference {
+                Some(reference) => {
+                    if (req.price - reference).abs() > reference.abs() * collar {
+                        return Err(RiskRejection::PriceCollar {
+                            price: req.price,
+                            reference,
+                            collar,
+                        });
+                    }
+                }
+                None if limits.require_reference => {
+                    return Err(RiskRejection::NoReference(req.market))
+                }
+                None => (),
+            }
+        }
+        if let Some(limit) = limits.max_open_orders {
+            if open_orders >= limit {
+                return Err(RiskRejection::MaxOpenOrders { open: open_orders, limit });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check an order against a snapshot of the open orders and positions,
+    /// including the position limit
+    pub fn check_snapshot(
+        &self,
+        req: &OrderRequest,
+        snapshot: &RiskSnapshot,
+    ) -> Result<(), RiskRejection> {
+        self.check(req, snapshot.open_orders.len())?;
+        if let Some(limit) = self.limits(&req.market).max_position {
+            let open = snapshot.open_quantity(&req.market, req.dir) + req.quantity;
+            let position = snapshot.position(&req.market)
+                + match req.dir {
+                    Dir::Buy => open,
+                    Dir::Sell => -open,
+                };
+            if position.abs() > limit {
+                return Err(RiskRejection::MaxPosition { position, limit });
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Scripted, deterministic order simulation for tests.
+//!
+//! A `Scenario` is a timeline of book updates, either recorded or written
+//! by hand, interleaved with order actions ("place at t=10s, market gaps at
+//! t=12s").  `run` replays it through a `ShadowExchange` in simulated time
+//! and returns every resulting order event, so a test can assert on the
+//! exact order and fill sequence without a network or a wall clock.
+
+use super::shadow::{ShadowEvent, ShadowExchange, ShadowFill, ShadowOrder};
+use crate::{marketdata::level_book::LevelBook, symbology::MarketRef};
+use anyhow::{bail, Result};
+use api::OrderId;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use tokio::sync::broadcast::error::TryRecvError;
+
+#[derive(Debug, Clone)]
+enum Step {
+    Book(MarketRef, LevelBook),
+    /// shift every level of the market's last book by the offset
+    Gap(MarketRef, Decimal),
+    Place(ShadowOrder),
+    Cancel(OrderId),
+}
+
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    start: DateTime<Utc>,
+    /// steps at the same time run in the order they were added
+    steps: Vec<(DateTime<Utc>, Step)>,
+}
+
+impl Scenario {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self { start, steps: vec![] }
+    }
+
+    fn at(&mut self, offset: Duration, step: Step) -> &mut Self {
+        self.steps.push((self.start + offset, step));
+        self
+    }
+
+    /// Replay recorded books, each at its own timestamp
+    pub fn replay(
+        &mut self,
+        market: MarketRef,
+        books: impl IntoIterator<Item = LevelBook>,
+    ) -> &mut Self {
+        for book in books {
+            self.steps.push((book.timestamp, Step::Book(market, book)));
+        }
+        self
+    }
+
+    /// Set the market's book at `offset` from the start
+    pub fn book_at(
+        &mut self,
+        offset: Duration,
+        market: MarketRef,
+        mut book: LevelBook,
+    ) -> &mut Self {
+        book.timestamp = self.start + offset;
+        self.at(offset, Step::Book(market, book))
+    }
+
+    /// Move the whole book of the market by `by` in price at `offset`
+    pub fn gap_at(
+        &mut self,
+        offset: Duration,
+        market: MarketRef,
+        by: Decimal,
+    ) -> &mut Self {
+        self.at(offset, Step::Gap(market, by))
+    }
+
+    pub fn place_at(&mut self, offset: Duration, order: ShadowOrder) -> &mut Self {
+        self.at(offset, Step::Place(order))
+    }
+
+    pub fn cancel_at(&mut self, offset: Duration, id: OrderId) -> &mut Self {
+        self.at(offset, Step::Cancel(id))
+    }
+
+    /// Fails if a single step produced more events than the exchange's
+    /// channel holds
+    pub fn run(&self) -> Result<ScenarioResult> {
+        let mut steps = self.steps.clone();
+        // stable, so same time steps keep insertion order
+        steps.sort_by_key(|(t, _)| *t);
+        let mut exchange = ShadowExchange::new();
+        let mut rx = exchange.subscribe();
+        let mut events = vec![];
+        for (t, step) in steps {
+            match step {
+                Step::Book(market, book) => exchange.on_book(market, &book),
+                Step::Gap(market, by) => {
+                    if let Some(book) = exchange.book(&market) {
+                        let book = shifted(book, by, t);
+                        exchange.on_book(market, &book);
+                    }
+                }
+                Step::Place(order) => exchange.place(order),
+                Step::Cancel(id) => {
+                    exchange.cancel(id);
+                }
+            }
+            loop {
+                match rx.try_recv() {
+                    Ok(ev) => events.push((t, ev)),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Lagged(n)) => {
+                        bail!("scenario dropped {n} events at {t}, too many in one step")
+                    }
+                    Err(TryRecvError::Closed) => break,
+                }
+            }
+        }
+        Ok(ScenarioResult { events })
+    }
+}
+
+fn shifted(book: &LevelBook, by: Decimal, t: DateTime<Utc>) -> LevelBook {
+    let shift = |side: &BTreeMap<Decimal, Decimal>| {
+        side.iter().map(|(p, s)| (*p + by, *s)).collect::<BTreeMap<_, _>>()
+    };
+    let mut res = book.clone();
+    res.book.buy = shift(&book.buy);
+    res.book.sell = shift(&book.sell);
+    res.timestamp = t;
+    res
+}
+
+#[derive(Debug, Clone)]
+pub struct ScenarioResult {
+    /// every event with the simulated time of the step that caused it
+    pub events: Vec<(DateTime<Utc>, ShadowEvent)>,
+}
+
+impl ScenarioResult {
+    pub fn fills(&self) -> Vec<ShadowFill> {
+        self.events
+            .iter()
+            .filter_map(|(_, ev)| match ev {
+                ShadowEvent::Fill(f) => Some(*f),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn fills_for(&self, id: OrderId) -> Vec<ShadowFill> {
+        self.fills().into_iter().filter(|f| f.order_id == id).collect()
+    }
+
+    pub fn filled_quantity(&self, id: OrderId) -> Decimal {
+        self.fills_for(id).iter().map(|f| f.quantity).sum()
+    }
+
+    /// whether the order was done (filled or canceled) by the end
+    pub fn is_out(&self, id: OrderId) -> bool {
+        self.events.iter().any(|(_, ev)| matches!(ev, ShadowEvent::Out(o) if *o == id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbology::test_market;
+    use api::Dir;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_fills_deplete_liquidity() -> Result<()> {
+        let market = test_market("USDJPY", "USD", "JPY")?;
+        let buy = |seqno, quantity| ShadowOrder {
+            id: OrderId { seqid: Default::default(), seqno },
+            market,
+            dir: Dir::Buy,
+            price: dec!(150),
+            quantity,
+        };
+        let (first, second) = (buy(0, dec!(4)), buy(1, dec!(4)));
+        let mut book = LevelBook::default();
+        book.buy.insert(dec!(149), dec!(10));
+        book.sell.insert(dec!(150), dec!(5));
+        let s = Duration::seconds;
+        let result = Scenario::new(Utc.with_ymd_and_hms(2024, 6, 3, 14, 0, 0).unwrap())
+            .book_at(s(0), market, book.clone())
+            .place_at(s(1), first)
+            .place_at(s(1), second)
+            // the same book again: the displayed size is there to take anew
+            .book_at(s(2), market, book)
+            .run()?;
+        assert_eq!(result.filled_quantity(first.id), dec!(4));
+        assert!(result.is_out(first.id));
+        // only 1 of the 5 was left for the second order until the next book
+        let fills = result.fills_for(second.id);
+        assert_eq!(
+            fills.iter().map(|f| f.quantity).collect::<Vec<_>>(),
+            [dec!(1), dec!(3)]
+        );
+        assert!(result.is_out(second.id));
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Shadow trading: track the orders a strategy intends to send and simulate
+//! their lifecycle against live books without routing anything, so a new
+//! strategy can run dark on production infrastructure before being enabled.
+//!
+//! Put the `OrderflowClient` in shadow mode with `set_shadow(true)` so nothing
+//! leaves the process; with a `ShadowExchange` set on the client
+//! (`set_shadow_exchange`), the orders it places and cancels in shadow mode
+//! are mirrored into the exchange.  Feed the exchange every book change
+//! with `on_book`.
+
+use crate::{marketdata::level_book::LevelBook, symbology::MarketRef};
+use api::{Dir, OrderId};
+use chrono::{DateTime, Utc};
+use fxhash::FxHashMap;
+use log::info;
+use rust_decimal::Decimal;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowOrder {
+    pub id: OrderId,
+    pub market: MarketRef,
+    pub dir: Dir,
+    pub price: Decimal,
+    pub quantity: Decimal,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowFill {
+    pub order_id: OrderId,
+    pub market: MarketRef,
+    pub dir: Dir,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ShadowEvent {
+    Ack(OrderId),
+    Fill(ShadowFill),
+    /// the order is done, either filled or canceled
+    Out(OrderId),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowOrderState {
+    pub order: ShadowOrder,
+    pub filled: Decimal,
+    /// average fill price
+    pub avg_price: Option<Decimal>,
+}
+
+impl ShadowOrderState {
+    pub fn remaining(&self) -> Decimal {
+        self.order.quantity - self.filled
+    }
+}
+
+/// Simulated matching of intended orders against live books.  Orders take
+/// whatever displayed liquidity they cross on arrival, then rest; resting
+/// orders fill when the opposite side of the book trades through them.
+/// Liquidity taken at a level is gone for every order until the market's
+/// next book, so two orders can't both fill against the same size.  Queue
+/// position is not modeled, so fills are optimistic at the touch.
+pub struct ShadowExchange {
+    orders: FxHashMap<OrderId, ShadowOrderState>,
+    /// live orders in arrival order, so matching is deterministic
+    arrival: Vec<OrderId>,
+    /// the last book of each market
+    books: FxHashMap<MarketRef, LevelBook>,
+    /// size taken per market, side and price since the market's last book
+    taken: FxHashMap<(MarketRef, Dir, Decimal), Decimal>,
+    tx: broadcast::Sender<ShadowEvent>,
+}
+
+impl Default for ShadowExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShadowExchange {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self {
+            orders: FxHashMap::default(),
+            arrival: vec![],
+            books: FxHashMap::default(),
+            taken: FxHashMap::default(),
+            tx,
+        }
+    }
+
+    /// The last book seen for `market`
+    pub fn book(&self, market: &MarketRef) -> Option<&LevelBook> {
+        self.books.get(market)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ShadowEvent> {
+        self.tx.subscribe()
+    }
+
+    fn emit(&self, ev: ShadowEvent) {
+        info!("shadow: {ev:?}");
+        let _ = self.tx.send(ev);
+    }
+
+    /// Place an order, matching it against the market's last book
+    pub fn place(&mut self, order: ShadowOrder) {
+        self.orders.insert(
+            order.id,
+            ShadowOrderState { order, filled: Decimal::ZERO, avg_price: None },
+        );
+        self.arrival.push(order.id);
+        self.emit(ShadowEvent::Ack(order.id));
+        self.match_order(order.id);
+    }
+
+    pub fn cancel(&mut self, id: OrderId) -> bool {
+        match self.orders.remove(&id) {
+            None => false,
+            Some(_) => {
+                self.arrival.retain(|o| *o != id);
+                self.emit(ShadowEvent::Out(id));
+                true
+            }
+        }
+    }
+
+    /// Match resting orders in the market against a new book
+    pub fn on_book(&mut self, market: MarketRef, book: &LevelBook) {
+        match self.books.get_mut(&market) {
+            Some(last) => last.clone_from(book),
+            None => {
+                self.books.insert(market, book.clone());
+            }
+        }
+        self.taken.retain(|(m, _, _), _| *m != market);
+        let ids: Vec<OrderId> = self
+            .arrival
+            .iter()
+            .filter(|id| self.orders.get(id).map(|o| o.order.market) == Some(market))
+            .copied()
+            .collect();
+        for id in ids {
+            self.match_order(id);
+        }
+    }
+
+    fn match_order(&mut self, id: OrderId) {
+        let (fills, done) = {
+            let Some(st) = self.orders.get_mut(&id) else { return };
+            let Some(book) = self.books.get(&st.order.market) else { return };
+            let mut fills = vec![];
+            let opposite = match st.order.dir {
+                Dir::Buy => Dir::Sell,
+                Dir::Sell => Dir::Buy,
+            };
+            for (price, size) in book.iter_levels(opposite) {
+                let crosses = match st.order.dir {
+                    Dir::Buy => *price <= st.order.price,
+                    Dir::Sell => *price >= st.order.price,
+                };
+                if !crosses || st.remaining() <= Decimal::ZERO {
+                    break;
+                }
+                let taken =
+                    self.taken.entry((st.order.market, opposite, *price)).or_default();
+                let quantity = st.remaining().min(*size - *taken);
+                if quantity <= Decimal::ZERO {
+                    continue;
+                }
+                *taken += quantity;
+                let notional = st.avg_price.unwrap_or_default() * st.filled;
+                st.filled += quantity;
+                st.avg_price = Some((notional + *price * quantity) / st.filled);
+                fills.push(ShadowFill {
+                    order_id: id,
+                    market: st.order.market,
+                    dir: st.order.dir,
+                    price: *price,
+                    quantity,
+                    timestamp: book.timestamp,
+                });
+            }
+            (fills, st.remaining() <= Decimal::ZERO)
+        };
+        for fill in fills {
+            self.emit(ShadowEvent::Fill(fill));
+        }
+        if done {
+            self.orders.remove(&id);
+            self.arrival.retain(|o| *o != id);
+            self.emit(ShadowEvent::Out(id));
+        }
+    }
+
+    pub fn get(&self, id: &OrderId) -> Option<&ShadowOrderState> {
+        self.orders.get(id)
+    }
+
+    pub fn working_orders(&self) -> impl Iterator<Item = &ShadowOrderState> {
+        self.orders.values()
+    }
+}
@@ -0,0 +1,206 @@
+//! Inventory skew for quoting, as plain functions of the position.
+//!
+//! The position (e.g. from `PnlTracker::position`) is measured against a
+//! target and normalized by the position limit into an inventory ratio in
+//! [-1, 1].  A `SkewCurve` maps the ratio to a skew fraction, also in
+//! [-1, 1], which scales the maximum price skew and the size reduction on
+//! the side that would add to the inventory.  Long of target skews both
+//! quotes down and shrinks the bid; short of target the opposite.
+//!
+//! Nothing here holds state, so a strategy can evaluate a skew for any
+//! hypothetical position, and `QuotingEngine::set_skew` uses it in place of
+//! the engine's linear skew.
+
+use crate::prices::offset_bps;
+use anyhow::{bail, Result};
+use api::{Dir, DirPair};
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkewCurve {
+    #[default]
+    Linear,
+    /// gentle near the target, steep near the limit
+    Quadratic,
+    Cubic,
+    /// piecewise linear through (ratio, skew) points with ratio in (0, 1],
+    /// sorted by ratio; (0, 0) is implied, beyond the last point the skew
+    /// stays flat, and negative ratios mirror positive ones
+    Points(Vec<(Decimal, Decimal)>),
+}
+
+impl SkewCurve {
+    pub fn validate(&self) -> Result<()> {
+        if let SkewCurve::Points(points) = self {
+            let mut last = Decimal::ZERO;
+            for (x, y) in points {
+                if *x <= last || *x > Decimal::ONE {
+                    bail!("skew curve ratios must increase within (0, 1], got {x}");
+                }
+                if y.abs() > Decimal::ONE {
+                    bail!("skew curve values must be within [-1, 1], got {y}");
+                }
+                last = *x;
+            }
+        }
+        Ok(())
+    }
+
+    /// The skew fraction for an inventory ratio; odd, so a short skews by
+    /// the same amount as an equal long in the other direction
+    pub fn eval(&self, ratio: Decimal) -> Decimal {
+        let x = ratio.abs().min(Decimal::ONE);
+        let y = match self {
+            SkewCurve::Linear => x,
+            SkewCurve::Quadratic => x * x,
+            SkewCurve::Cubic => x * x * x,
+            SkewCurve::Points(points) => interpolate(points, x),
+        };
+        if ratio.is_sign_negative() {
+            -y
+        } else {
+            y
+        }
+    }
+}
+
+fn interpolate(points: &[(Decimal, Decimal)], x: Decimal) -> Decimal {
+    let mut prev = (Decimal::ZERO, Decimal::ZERO);
+    for &(px, py) in points {
+        if x <= px {
+            return prev.1 + (py - prev.1) * (x - prev.0) / (px - prev.0);
+        }
+        prev = (px, py);
+    }
+    prev.1
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkewConfig {
+    /// the signed position to skew towards
+    #[serde(default)]
+    pub target: Decimal,
+    /// the distance from target at which the skew is at its maximum
+    pub max_position: Decimal,
+    /// price skew at the maximum, applied to fair value
+    pub max_skew_bps: Decimal,
+    /// fraction of the quote size removed from the side that adds to the
+    /// inventory at the maximum; 1 stops quoting that side entirely
+    #[serde(default)]
+    pub max_size_reduction: Decimal,
+    #[serde(default)]
+    pub curve: SkewCurve,
+}
+
+impl SkewConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_position <= Decimal::ZERO {
+            bail!("skew max position must be positive, got {}", self.max_position);
+        }
+        if self.max_skew_bps < Decimal::ZERO {
+            bail!("max skew must not be negative, got {}", self.max_skew_bps);
+        }
+        if self.max_size_reduction < Decimal::ZERO
+            || self.max_size_reduction > Decimal::ONE
+        {
+            bail!(
+                "max size reduction must be between 0 and 1, got {}",
+                self.max_size_reduction
+            );
+        }
+        self.curve.validate()
+    }
+}
+
+/// The distance of `position` from `target` as a fraction of
+/// `max_position`, clamped to [-1, 1]
+pub fn inventory_ratio(
+    position: Decimal,
+    target: Decimal,
+    max_position: Decimal,
+) -> Decimal {
+    if max_position <= Decimal::ZERO {
+        return Decimal::ZERO;
+    }
+    ((position - target) / max_position).clamp(-Decimal::ONE, Decimal::ONE)
+}
+
+/// The price skew in bps for `position`, negative when long of target
+pub fn skew_bps(config: &SkewConfig, position: Decimal) -> Decimal {
+    let ratio = inventory_ratio(position, config.target, config.max_position);
+    -config.curve.eval(ratio) * config.max_skew_bps
+}
+
+/// Fair value shifted by the skew for `position`
+pub fn skewed_fair(config: &SkewConfig, fair: Decimal, position: Decimal) -> Decimal {
+    offset_bps(fair, skew_bps(config, position))
+}
+
+/// The multiplier on the quote size for each side: the side that would
+/// take the position further from target shrinks, the other is unchanged
+pub fn size_multipliers(config: &SkewConfig, position: Decimal) -> DirPair<Decimal> {
+    let ratio = inventory_ratio(position, config.target, config.max_position);
+    let cut = (config.curve.eval(ratio) * config.max_size_reduction).abs();
+    let (buy, sell) = if ratio > Decimal::ZERO {
+        (Decimal::ONE - cut, Decimal::ONE)
+    } else {
+        (Decimal::ONE, Decimal::ONE - cut)
+    };
+    DirPair { buy, sell }
+}
+
+/// The skewed quote size for `dir`
+pub fn skewed_size(
+    config: &SkewConfig,
+    size: Decimal,
+    dir: Dir,
+    position: Decimal,
+) -> Decimal {
+    size * *size_multipliers(config, position).get(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn config(curve: SkewCurve) -> SkewConfig {
+        SkewConfig {
+            target: dec!(0),
+            max_position: dec!(10),
+            max_skew_bps: dec!(20),
+            max_size_reduction: dec!(1),
+            curve,
+        }
+    }
+
+    #[test]
+    fn test_curves() -> Result<()> {
+        assert_eq!(skew_bps(&config(SkewCurve::Linear), dec!(5)), dec!(-10));
+        assert_eq!(skew_bps(&config(SkewCurve::Linear), dec!(-50)), dec!(20));
+        assert_eq!(skew_bps(&config(SkewCurve::Quadratic), dec!(5)), dec!(-5));
+        assert_eq!(skew_bps(&config(SkewCurve::Cubic), dec!(-5)), dec!(2.5));
+        let points = SkewCurve::Points(vec![(dec!(0.5), dec!(0.2)), (dec!(1), dec!(1))]);
+        points.validate()?;
+        assert_eq!(skew_bps(&config(points.clone()), dec!(2.5)), dec!(-2));
+        assert_eq!(skew_bps(&config(points), dec!(-7.5)), dec!(12));
+        assert!(SkewCurve::Points(vec![(dec!(0.5), dec!(0)), (dec!(0.5), dec!(1))])
+            .validate()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_and_sizes() {
+        let mut c = config(SkewCurve::Linear);
+        c.target = dec!(4);
+        assert_eq!(skew_bps(&c, dec!(4)), dec!(0));
+        assert_eq!(skewed_fair(&c, dec!(100), dec!(9)), dec!(99.9));
+        let m = size_multipliers(&c, dec!(9));
+        assert_eq!((m.buy, m.sell), (dec!(0.5), dec!(1)));
+        assert_eq!(skewed_size(&c, dec!(2), Dir::Sell, dec!(-1)), dec!(1));
+        assert_eq!(skewed_size(&c, dec!(2), Dir::Buy, dec!(14)), dec!(0));
+    }
+}
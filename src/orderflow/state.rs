@@ -0,0 +1,352 @@
+//! The order state machine, shared by everything that mirrors order state.
+//!
+//! An `OrderLog` holds the current state of each order placed through it
+//! and applies `OrderEvent`s to it: acks, fills (accumulating the filled
+//! quantity and average price), busts and corrections, rejects, cancels,
+//! outs and modifies.  Fills reported with a venue fill id can be
+//! deduplicated with `record_fill`, e.g. when a fill is reported both live
+//! and on a reconnect's backfill.
+//!
+//! The `OrderTracker` is built on it, and any other client that mirrors
+//! order state (a gRPC client, an Oms mirror) should be too, so they agree
+//! on what each update means.  `snapshot` and `restore` checkpoint a log;
+//! `replay` applies a sequence of `LogEntry`s recorded since, e.g. to
+//! rebuild state after a restart.
+
+use super::{reject::RejectReason, tracker::OrderEvent};
+use crate::symbology::MarketRef;
+use api::{AccountId, Dir, OrderId, UserId};
+use chrono::{DateTime, Utc};
+use fxhash::{FxHashMap, FxHashSet};
+use log::warn;
+use rust_decimal::Decimal;
+
+/// An order as the client intends to place it, as tracked here; the
+/// message sent to the venue is built from it
+#[derive(Debug, Clone, Copy)]
+pub struct OrderRequest {
+    pub id: OrderId,
+    pub market: MarketRef,
+    pub dir: Dir,
+    pub price: Decimal,
+    pub quantity: Decimal,
+}
+
+/// An amendment to a live order's price and/or quantity.  The quantity is
+/// the new total order quantity, including anything already filled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModifyOrderRequest {
+    pub id: OrderId,
+    pub price: Option<Decimal>,
+    pub quantity: Option<Decimal>,
+}
+
+/// Who an order was placed for, where known; see `OrderTracker::set_owner`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OrderOwner {
+    pub account: Option<AccountId>,
+    pub trader: Option<UserId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackedOrderState {
+    /// sent, not yet acked
+    Pending,
+    Open,
+    /// cancel sent, not yet out
+    Canceling,
+    Filled,
+    Canceled,
+    Rejected,
+}
+
+impl TrackedOrderState {
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self,
+            TrackedOrderState::Filled
+                | TrackedOrderState::Canceled
+                | TrackedOrderState::Rejected
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackedOrder {
+    pub request: OrderRequest,
+    pub state: TrackedOrderState,
+    pub filled: Decimal,
+    pub avg_fill_price: Option<Decimal>,
+    pub reject_reason: Option<RejectReason>,
+    /// a modify sent and not yet acked or rejected
+    pub pending_modify: Option<ModifyOrderRequest>,
+    pub owner: OrderOwner,
+    pub sent_at: DateTime<Utc>,
+    pub cancel_sent_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TrackedOrder {
+    /// A newly sent order, pending until acked
+    pub fn sent(request: OrderRequest, now: DateTime<Utc>) -> Self {
+        Self {
+            request,
+            state: TrackedOrderState::Pending,
+            filled: Decimal::ZERO,
+            avg_fill_price: None,
+            reject_reason: None,
+            pending_modify: None,
+            owner: OrderOwner::default(),
+            sent_at: now,
+            cancel_sent_at: None,
+            updated_at: now,
+        }
+    }
+
+    pub fn remaining(&self) -> Decimal {
+        self.request.quantity - self.filled
+    }
+
+    /// Add a fill to the filled quantity and average price; a negative
+    /// quantity takes one out
+    fn add_fill(&mut self, quantity: Decimal, price: Decimal) {
+        let notional = self.avg_fill_price.unwrap_or_default() * self.filled;
+        self.filled += quantity;
+        self.avg_fill_price = if self.filled.is_zero() {
+            None
+        } else {
+            Some((notional + price * quantity) / self.filled)
+        };
+    }
+}
+
+/// A state change made by an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from: TrackedOrderState,
+    pub to: TrackedOrderState,
+}
+
+/// One step of an order log's history, for `replay`
+#[derive(Debug, Clone)]
+pub enum LogEntry {
+    Placed(OrderRequest),
+    Event(OrderEvent),
+    /// a fill with its venue fill id, deduplicated on replay
+    Fill {
+        fill_id: String,
+        event: OrderEvent,
+    },
+}
+
+/// How many orders an `OrderLog` holds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OrderCounts {
+    /// not done
+    pub live: usize,
+    /// done, kept until they are collected or evicted
+    pub retained: usize,
+}
+
+/// A point in time copy of an `OrderLog`
+#[derive(Debug, Clone, Default)]
+pub struct OrderLogSnapshot {
+    pub orders: Vec<TrackedOrder>,
+    pub fill_ids: Vec<(OrderId, String)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OrderLog {
+    orders: FxHashMap<OrderId, TrackedOrder>,
+    fill_ids: FxHashMap<OrderId, FxHashSet<String>>,
+}
+
+impl OrderLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn restore(snapshot: OrderLogSnapshot) -> Self {
+        let mut log = Self::new();
+        for o in snapshot.orders {
+            log.orders.insert(o.request.id, o);
+        }
+        for (id, fill_id) in snapshot.fill_ids {
+            log.fill_ids.entry(id).or_default().insert(fill_id);
+        }
+        log
+    }
+
+    pub fn snapshot(&self) -> OrderLogSnapshot {
+        OrderLogSnapshot {
+            orders: self.orders.values().cloned().collect(),
+            fill_ids: self
+                .fill_ids
+                .iter()
+                .flat_map(|(id, f)| f.iter().map(|f| (*id, f.clone())))
+                .collect(),
+        }
+    }
+
+    /// Apply entries in order, as if they were happening at their
+    /// timestamps
+    pub fn replay(
+        &mut self,
+        entries: impl IntoIterator<Item = (DateTime<Utc>, LogEntry)>,
+    ) {
+        for (at, entry) in entries {
+            match entry {
+                LogEntry::Placed(request) => self.place(request, at),
+                LogEntry::Event(ev) => {
+                    self.apply(&ev, at);
+                }
+                LogEntry::Fill { fill_id, event } => {
+                    if self.record_fill(event.id(), &fill_id) {
+                        self.apply(&event, at);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: &OrderId) -> Option<&TrackedOrder> {
+        self.orders.get(id)
+    }
+
+    pub fn contains(&self, id: &OrderId) -> bool {
+        self.orders.contains_key(id)
+    }
+
+    pub fn orders(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders.values()
+    }
+
+    pub fn open_orders(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders.values().filter(|o| !o.state.is_done())
+    }
+
+    pub fn set_owner(&mut self, id: OrderId, owner: OrderOwner) {
+        if let Some(o) = self.orders.get_mut(&id) {
+            o.owner = owner;
+        }
+    }
+
+    pub fn counts(&self) -> OrderCounts {
+        let live = self.open_orders().count();
+        OrderCounts { live, retained: self.orders.len() - live }
+    }
+
+    /// Forget orders that are done
+    pub fn gc(&mut self) {
+        self.orders.retain(|_, o| !o.state.is_done());
+        let orders = &self.orders;
+        self.fill_ids.retain(|id, _| orders.contains_key(id));
+    }
+
+    /// Forget orders that are done and were last updated before `cutoff`,
+    /// returning them
+    pub fn evict_done(&mut self, cutoff: DateTime<Utc>) -> Vec<TrackedOrder> {
+        let ids: Vec<OrderId> = self
+            .orders
+            .values()
+            .filter(|o| o.state.is_done() && o.updated_at < cutoff)
+            .map(|o| o.request.id)
+            .collect();
+        let mut evicted = Vec::with_capacity(ids.len());
+        for id in ids {
+            self.fill_ids.remove(&id);
+            evicted.extend(self.orders.remove(&id));
+        }
+        evicted
+    }
+
+    /// Start tracking an order
+    pub fn place(&mut self, request: OrderRequest, now: DateTime<Utc>) {
+        self.orders.insert(request.id, TrackedOrder::sent(request, now));
+    }
+
+    /// Note a fill's venue fill id; returns false if the order already had
+    /// a fill with that id, in which case the fill should be dropped
+    pub fn record_fill(&mut self, id: OrderId, fill_id: &str) -> bool {
+        let seen = self.fill_ids.entry(id).or_default();
+        if seen.contains(fill_id) {
+            return false;
+        }
+        seen.insert(fill_id.to_string());
+        true
+    }
+
+    /// Apply an event to its order; returns None if the order isn't
+    /// tracked
+    pub fn apply(&mut self, ev: &OrderEvent, now: DateTime<Utc>) -> Option<Transition> {
+        let id = ev.id();
+        let Some(o) = self.orders.get_mut(&id) else {
+            warn!("update for untracked order {id:?}");
+            return None;
+        };
+        let from = o.state;
+        match ev {
+            OrderEvent::Sent(_) => (),
+            OrderEvent::Ack(_) => {
+                if o.state == TrackedOrderState::Pending {
+                    o.state = TrackedOrderState::Open;
+                }
+            }
+            OrderEvent::Fill { quantity, price, .. } => {
+                o.add_fill(*quantity, *price);
+                if o.state == TrackedOrderState::Pending {
+                    o.state = TrackedOrderState::Open;
+                }
+                if o.remaining() <= Decimal::ZERO {
+                    o.state = TrackedOrderState::Filled;
+                }
+            }
+            // the venue doesn't reopen a done order because one of its
+            // fills was busted
+            OrderEvent::Bust { quantity, price, .. } => o.add_fill(-*quantity, *price),
+            OrderEvent::Correction {
+                old_quantity, old_price, quantity, price, ..
+            } => {
+                o.add_fill(-*old_quantity, *old_price);
+                o.add_fill(*quantity, *price);
+            }
+            OrderEvent::Reject { reason, .. } => {
+                o.state = TrackedOrderState::Rejected;
+                o.reject_reason = Some(reason.clone());
+            }
+            OrderEvent::CancelSent(_) => {
+                if !o.state.is_done() {
+                    o.state = TrackedOrderState::Canceling;
+                    o.cancel_sent_at.get_or_insert(now);
+                }
+            }
+            OrderEvent::CancelReject { .. } => {
+                if o.state == TrackedOrderState::Canceling {
+                    o.state = TrackedOrderState::Open;
+                }
+            }
+            OrderEvent::Out(_) => {
+                if !o.state.is_done() {
+                    o.state = TrackedOrderState::Canceled;
+                }
+            }
+            OrderEvent::ModifySent(req) => {
+                if !o.state.is_done() {
+                    o.pending_modify = Some(*req);
+                }
+            }
+            OrderEvent::Modified { price, quantity, .. } => {
+                o.pending_modify = None;
+                o.request.price = *price;
+                o.request.quantity = *quantity;
+                // reducing the quantity to what has filled completes the order
+                if !o.state.is_done() && o.remaining() <= Decimal::ZERO {
+                    o.state = TrackedOrderState::Filled;
+                }
+            }
+            OrderEvent::ModifyReject { .. } => o.pending_modify = None,
+        }
+        o.updated_at = now;
+        Some(Transition { from, to: o.state })
+    }
+}
@@ -0,0 +1,193 @@
+//! Panic isolation for strategy tasks.
+//!
+//! `Supervisor::spawn` runs a strategy in its own task.  If it panics or
+//! returns an error, the supervisor makes its orders safe according to the
+//! `PanicAction`, either tripping the kill switch or cancelling the
+//! strategy's orders through the cancel hook, records a `CrashReport`,
+//! and, if configured, restarts the strategy with backoff.  A strategy
+//! that returns `Ok` is done and isn't restarted.
+//!
+//! The cancel hook is given the strategy's name; typically it runs a mass
+//! cancel filtered by the strategy's account, see `mass_cancel`.
+
+use super::kill_switch::KillSwitch;
+use crate::reconnect::{Backoff, BackoffConfig};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use log::{error, info, warn};
+use parking_lot::Mutex;
+use std::{any::Any, future::Future, sync::Arc, time::Instant};
+use tokio::{sync::broadcast, task::JoinHandle};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicAction {
+    /// stop all order entry in the process
+    TripKillSwitch,
+    /// cancel the strategy's orders; trips the kill switch if there is no
+    /// cancel hook or it fails
+    CancelOrders,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SupervisorConfig {
+    pub action: PanicAction,
+    /// restart after a crash, waiting per the backoff; None to leave the
+    /// strategy stopped
+    pub restart: Option<BackoffConfig>,
+    /// the most restarts before giving up; None for no limit
+    pub max_restarts: Option<u32>,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self { action: PanicAction::TripKillSwitch, restart: None, max_restarts: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    pub strategy: String,
+    pub at: DateTime<Utc>,
+    /// the panic message or error
+    pub message: String,
+    pub panicked: bool,
+    /// restarts before this crash
+    pub restarts: u32,
+    pub action: PanicAction,
+    /// whether the strategy's orders were made safe as configured
+    pub contained: bool,
+}
+
+type CancelHook = Arc<dyn Fn(&str) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Supervisor {
+    kill_switch: KillSwitch,
+    cancel: Option<CancelHook>,
+    reports: Arc<Mutex<Vec<CrashReport>>>,
+    tx: broadcast::Sender<CrashReport>,
+}
+
+/// Aborts the strategy task if the supervisor task is aborted
+struct AbortOnDrop(JoinHandle<Result<()>>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "non string panic payload".to_string()
+    }
+}
+
+impl Supervisor {
+    pub fn new(kill_switch: KillSwitch) -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self { kill_switch, cancel: None, reports: Arc::new(Mutex::new(vec![])), tx }
+    }
+
+    /// Cancel a crashed strategy's orders with `cancel`, given its name
+    pub fn with_cancel(
+        mut self,
+        cancel: impl Fn(&str) -> BoxFuture<'static, Result<()>> + Send + Sync + 'static,
+    ) -> Self {
+        self.cancel = Some(Arc::new(cancel));
+        self
+    }
+
+    /// Every crash so far, oldest first
+    pub fn crash_reports(&self) -> Vec<CrashReport> {
+        self.reports.lock().clone()
+    }
+
+    /// Crash reports as they happen
+    pub fn subscribe(&self) -> broadcast::Receiver<CrashReport> {
+        self.tx.subscribe()
+    }
+
+    /// Make the strategy's orders safe; returns false if that failed and
+    /// the kill switch was tripped instead
+    async fn contain(&self, name: &str, action: PanicAction, reason: &str) -> bool {
+        if action == PanicAction::CancelOrders {
+            match &self.cancel {
+                Some(cancel) => match cancel(name).await {
+                    Ok(()) => return true,
+                    Err(e) => error!("cancelling orders of strategy {name}: {e:?}"),
+                },
+                None => warn!("no cancel hook for strategy {name}"),
+            }
+        }
+        self.kill_switch.trip(format!("strategy {name} crashed: {reason}"));
+        action == PanicAction::TripKillSwitch
+    }
+
+    /// Run `strategy` under supervision; it's called again for each
+    /// restart
+    pub fn spawn<F, Fut>(
+        &self,
+        name: impl Into<String>,
+        config: SupervisorConfig,
+        mut strategy: F,
+    ) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let name = name.into();
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut backoff =
+                config.restart.map(|c| Backoff::new("strategy_supervisor", c));
+            let mut restarts = 0;
+            loop {
+                let started = Instant::now();
+                let mut task = AbortOnDrop(tokio::spawn(strategy()));
+                let (message, panicked) = match (&mut task.0).await {
+                    Ok(Ok(())) => {
+                        info!("strategy {name} finished");
+                        break;
+                    }
+                    Ok(Err(e)) => (format!("{e:?}"), false),
+                    Err(e) if e.is_panic() => (panic_message(&*e.into_panic()), true),
+                    Err(_) => {
+                        warn!("strategy {name} was cancelled");
+                        break;
+                    }
+                };
+                error!("strategy {name} crashed: {message}");
+                let contained = this.contain(&name, config.action, &message).await;
+                let report = CrashReport {
+                    strategy: name.clone(),
+                    at: Utc::now(),
+                    message,
+                    panicked,
+                    restarts,
+                    action: config.action,
+                    contained,
+                };
+                this.reports.lock().push(report.clone());
+                let _ = this.tx.send(report);
+                let Some(backoff) = backoff.as_mut() else { break };
+                if config.max_restarts.is_some_and(|max| restarts >= max) {
+                    error!(
+                        "strategy {name} crashed {} times, not restarting",
+                        restarts + 1
+                    );
+                    break;
+                }
+                backoff.on_disconnect(started.elapsed());
+                backoff.wait().await;
+                restarts += 1;
+                info!("restarting strategy {name}, restart {restarts}");
+            }
+        })
+    }
+}
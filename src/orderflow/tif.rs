@@ -0,0 +1,157 @@
+//! Client side time in force emulation for venues that don't support
+//! GTD, IOC, or FOK natively.
+//!
+//! - IOC is emulated by canceling whatever is left once the ack arrives,
+//!   after a short ack window.
+//! - GTD is emulated by scheduling a cancel at the expiry time.
+//! - FOK is emulated by checking displayed size before sending, then
+//!   treating the order as IOC.
+//!
+//! Call `poll` periodically to collect the cancels that are due.
+
+use super::tracker::OrderRequest;
+use crate::marketdata::market_view::MarketState;
+use anyhow::{bail, Result};
+use api::{Dir, OrderId};
+use chrono::{DateTime, Duration, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeInForce {
+    GoodTilCancel,
+    GoodTilDate(DateTime<Utc>),
+    ImmediateOrCancel,
+    FillOrKill,
+}
+
+/// Which time in force instructions a venue supports natively
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TifCapabilities {
+    #[serde(default)]
+    pub gtd: bool,
+    #[serde(default)]
+    pub ioc: bool,
+    #[serde(default)]
+    pub fok: bool,
+}
+
+impl TifCapabilities {
+    pub fn supports(&self, tif: &TimeInForce) -> bool {
+        match tif {
+            TimeInForce::GoodTilCancel => true,
+            TimeInForce::GoodTilDate(_) => self.gtd,
+            TimeInForce::ImmediateOrCancel => self.ioc,
+            TimeInForce::FillOrKill => self.fok,
+        }
+    }
+}
+
+/// How to send an order: the instruction to put on the wire, and whether
+/// the emulator is responsible for the rest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TifPlan {
+    pub send_as: TimeInForce,
+    pub emulated: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Pending {
+    /// cancel once acked, after the ack window
+    CancelOnAck,
+    CancelAt(DateTime<Utc>),
+}
+
+pub struct TifEmulator {
+    ack_window: Duration,
+    pending: FxHashMap<OrderId, Pending>,
+}
+
+impl TifEmulator {
+    pub fn new(ack_window: Duration) -> Self {
+        Self { ack_window, pending: FxHashMap::default() }
+    }
+
+    /// Decide how to send the order; unsupported instructions are emulated
+    /// and the order is sent GTC.  FOK orders that can't be filled from
+    /// displayed size are refused.
+    pub fn prepare(
+        &mut self,
+        req: &OrderRequest,
+        tif: TimeInForce,
+        capabilities: &TifCapabilities,
+        state: &MarketState,
+        now: DateTime<Utc>,
+    ) -> Result<TifPlan> {
+        if capabilities.supports(&tif) {
+            return Ok(TifPlan { send_as: tif, emulated: false });
+        }
+        let (send_as, pending) = match tif {
+            TimeInForce::GoodTilCancel => unreachable!(),
+            TimeInForce::GoodTilDate(expiry) => {
+                if expiry <= now {
+                    bail!("good til date order already expired");
+                }
+                (TimeInForce::GoodTilCancel, Pending::CancelAt(expiry))
+            }
+            TimeInForce::ImmediateOrCancel => {
+                (TimeInForce::GoodTilCancel, Pending::CancelOnAck)
+            }
+            TimeInForce::FillOrKill => {
+                let available = displayed_through(state, req.dir, req.price);
+                if available < req.quantity {
+                    bail!(
+                        "fill or kill: only {available} displayed at or better than {}",
+                        req.price
+                    );
+                }
+                if capabilities.ioc {
+                    return Ok(TifPlan {
+                        send_as: TimeInForce::ImmediateOrCancel,
+                        emulated: true,
+                    });
+                }
+                (TimeInForce::GoodTilCancel, Pending::CancelOnAck)
+            }
+        };
+        self.pending.insert(req.id, pending);
+        Ok(TifPlan { send_as, emulated: true })
+    }
+
+    pub fn on_ack(&mut self, id: OrderId, now: DateTime<Utc>) {
+        if let Some(p) = self.pending.get_mut(&id) {
+            if let Pending::CancelOnAck = p {
+                *p = Pending::CancelAt(now + self.ack_window);
+            }
+        }
+    }
+
+    /// The order is done; stop tracking it
+    pub fn on_out(&mut self, id: OrderId) {
+        self.pending.remove(&id);
+    }
+
+    /// Return the orders that should be canceled now
+    pub fn poll(&mut self, now: DateTime<Utc>) -> Vec<OrderId> {
+        let mut due = vec![];
+        self.pending.retain(|id, p| match p {
+            Pending::CancelAt(t) if *t <= now => {
+                due.push(*id);
+                false
+            }
+            _ => true,
+        });
+        due
+    }
+}
+
+/// Total displayed size on the opposite side at prices at least as good
+/// as `price`
+fn displayed_through(state: &MarketState, dir: Dir, price: Decimal) -> Decimal {
+    let (side, crosses): (_, fn(Decimal, Decimal) -> bool) = match dir {
+        Dir::Buy => (state.depth.get(Dir::Sell), |px, limit| px <= limit),
+        Dir::Sell => (state.depth.get(Dir::Buy), |px, limit| px >= limit),
+    };
+    side.iter().take_while(|(px, _)| crosses(*px, price)).map(|(_, sz)| *sz).sum()
+}
@@ -0,0 +1,966 @@
+//! Track the lifecycle of orders sent through an orderflow client.  Feed
+//! the tracker the orders you send and the updates you receive; it keeps the
+//! current state of every open order and of recently closed ones, and
+//! broadcasts each state transition.  The state of each order is kept by
+//! a `state::OrderLog`, which defines what each update does to it.
+//!
+//! It also measures ack latency (order sent to ack) and cancel latency
+//! (cancel sent to out) per cpty, see `latency_summaries`.
+//!
+//! Updates from the venue can arrive out of order, e.g. a fill before its
+//! ack.  With `set_strict_sequencing` the tracker guarantees each order's
+//! events are applied and broadcast in lifecycle order (sent, ack, fills,
+//! out or reject): an event that arrives early is held until the events it
+//! depends on arrive, or until the hold time runs out, when it is applied
+//! anyway behind a synthesized ack.  Events that arrive after the order has
+//! moved past them are dropped, except fills, which are always applied.
+//! Either case is recorded as a `SequenceViolation`.
+//!
+//! Cancels are tracked in their own right, see `CancelState`: a cancel is
+//! pending until the order goes out (acked), the venue rejects the cancel,
+//! or the order is done some other way first (too late).  Pending cancels
+//! can be listed for resending after a reconnect, and
+//! `await_cancel_outcome` resolves once a cancel's outcome is known.
+//!
+//! Done orders are kept until `gc`, so a long session grows without bound
+//! unless it calls `gc` or sets a retention with `set_retention`, after
+//! which done orders are evicted once they have been done that long,
+//! optionally passing each to a spill hook first, e.g. to journal it.
+//!
+//! The tracker also keeps the net position per market from the fills it
+//! applies, seeded with `set_position`, and counts every change to its
+//! state in `epoch`, so a `risk::RiskSnapshot` of orders and positions is
+//! always consistent.
+
+pub use super::state::{
+    ModifyOrderRequest, OrderCounts, OrderOwner, OrderRequest, TrackedOrder,
+    TrackedOrderState,
+};
+use super::{
+    latency::{LatencyStats, LatencySummary},
+    reject::RejectReason,
+    state::OrderLog,
+};
+use crate::symbology::{Cpty, MarketRef};
+use api::{Dir, OrderId};
+use chrono::{DateTime, Duration, Utc};
+use fxhash::FxHashMap;
+use log::{debug, warn};
+use rust_decimal::Decimal;
+use tokio::sync::{broadcast, watch};
+
+#[derive(Debug, Clone)]
+pub enum OrderEvent {
+    Sent(OrderId),
+    Ack(OrderId),
+    Fill {
+        id: OrderId,
+        quantity: Decimal,
+        price: Decimal,
+    },
+    /// a previously reported fill was busted by the venue
+    Bust {
+        id: OrderId,
+        quantity: Decimal,
+        price: Decimal,
+    },
+    /// a previously reported fill was corrected by the venue
+    Correction {
+        id: OrderId,
+        old_quantity: Decimal,
+        old_price: Decimal,
+        quantity: Decimal,
+        price: Decimal,
+    },
+    Reject {
+        id: OrderId,
+        reason: RejectReason,
+    },
+    CancelSent(OrderId),
+    Out(OrderId),
+    ModifySent(ModifyOrderRequest),
+    /// the venue accepted a modify; the order's price and quantity are now
+    /// these
+    Modified {
+        id: OrderId,
+        price: Decimal,
+        quantity: Decimal,
+    },
+    /// the venue rejected a modify; the order stands as it was
+    ModifyReject {
+        id: OrderId,
+        reason: RejectReason,
+    },
+    /// the venue rejected a cancel; the order stays live unless it was
+    /// already done
+    CancelReject {
+        id: OrderId,
+        reason: RejectReason,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CancelState {
+    /// sent, outcome not yet known
+    Pending,
+    /// the order is out
+    Acked,
+    Rejected(RejectReason),
+    /// the order filled or was rejected before the cancel took effect
+    TooLate,
+}
+
+impl CancelState {
+    pub fn is_final(&self) -> bool {
+        !matches!(self, CancelState::Pending)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CancelRequest {
+    pub id: OrderId,
+    pub state: CancelState,
+    pub first_sent_at: DateTime<Utc>,
+    pub last_sent_at: DateTime<Utc>,
+    /// the number of times the cancel was sent
+    pub attempts: u32,
+}
+
+impl OrderEvent {
+    pub fn id(&self) -> OrderId {
+        match self {
+            OrderEvent::Sent(id)
+            | OrderEvent::Ack(id)
+            | OrderEvent::CancelSent(id)
+            | OrderEvent::Out(id) => *id,
+            OrderEvent::Fill { id, .. }
+            | OrderEvent::Bust { id, .. }
+            | OrderEvent::Correction { id, .. }
+            | OrderEvent::Reject { id, .. }
+            | OrderEvent::Modified { id, .. }
+            | OrderEvent::ModifyReject { id, .. }
+            | OrderEvent::CancelReject { id, .. } => *id,
+            OrderEvent::ModifySent(req) => req.id,
+        }
+    }
+}
+
+/// How far along its lifecycle an order is, for strict sequencing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OrderStage {
+    Sent,
+    Acked,
+    Filling,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub enum SequenceViolation {
+    /// the event arrived after the order had reached `stage`
+    Late { event: OrderEvent, stage: OrderStage },
+    /// the event was held for the whole hold time without the events it
+    /// depends on arriving, and was applied anyway
+    Timeout { event: OrderEvent, stage: Option<OrderStage> },
+}
+
+enum Verdict {
+    Apply,
+    Hold,
+    /// out of order; apply it anyway or drop it
+    Late(bool),
+}
+
+impl Verdict {
+    fn of(ev: &OrderEvent, stage: Option<OrderStage>) -> Self {
+        use OrderStage::*;
+        let (requires, late_after) = match ev {
+            OrderEvent::Sent(_)
+            | OrderEvent::CancelSent(_)
+            | OrderEvent::ModifySent(_) => return Verdict::Apply,
+            OrderEvent::Ack(_) => (Sent, Acked),
+            OrderEvent::Fill { .. } => (Acked, Filling),
+            // busts and corrections may come long after the order is done
+            OrderEvent::Bust { .. } | OrderEvent::Correction { .. } => (Filling, Done),
+            OrderEvent::Reject { .. } => (Sent, Filling),
+            OrderEvent::Out(_) => (Acked, Filling),
+            OrderEvent::Modified { .. } | OrderEvent::ModifyReject { .. } => {
+                (Acked, Filling)
+            }
+            // a cancel may be rejected because the order is already done
+            OrderEvent::CancelReject { .. } => (Sent, Done),
+        };
+        match stage {
+            None => Verdict::Hold,
+            Some(stage) if stage > late_after => {
+                Verdict::Late(matches!(ev, OrderEvent::Fill { .. }))
+            }
+            Some(stage) if stage < requires => Verdict::Hold,
+            Some(_) => Verdict::Apply,
+        }
+    }
+}
+
+struct Sequencer {
+    hold: Duration,
+    stages: FxHashMap<OrderId, OrderStage>,
+    /// early events with the time they arrived, in arrival order
+    held: FxHashMap<OrderId, Vec<(DateTime<Utc>, OrderEvent)>>,
+    violations: Vec<SequenceViolation>,
+}
+
+impl Sequencer {
+    fn advance(&mut self, ev: &OrderEvent) {
+        let to = match ev {
+            OrderEvent::Sent(_) => OrderStage::Sent,
+            OrderEvent::Ack(_) => OrderStage::Acked,
+            OrderEvent::Fill { .. } => OrderStage::Filling,
+            OrderEvent::Reject { .. } | OrderEvent::Out(_) => OrderStage::Done,
+            OrderEvent::Bust { .. }
+            | OrderEvent::Correction { .. }
+            | OrderEvent::CancelSent(_)
+            | OrderEvent::ModifySent(_)
+            | OrderEvent::Modified { .. }
+            | OrderEvent::ModifyReject { .. }
+            | OrderEvent::CancelReject { .. } => return,
+        };
+        let stage = self.stages.entry(ev.id()).or_insert(to);
+        *stage = (*stage).max(to);
+    }
+
+    /// Where the event comes in an order's lifecycle, to release early
+    /// events that are ready at the same time in lifecycle order
+    fn rank(ev: &OrderEvent) -> u8 {
+        match ev {
+            OrderEvent::Sent(_)
+            | OrderEvent::CancelSent(_)
+            | OrderEvent::ModifySent(_) => 0,
+            OrderEvent::Ack(_) => 1,
+            OrderEvent::Fill { .. }
+            | OrderEvent::Bust { .. }
+            | OrderEvent::Correction { .. }
+            | OrderEvent::Modified { .. }
+            | OrderEvent::ModifyReject { .. }
+            | OrderEvent::CancelReject { .. } => 2,
+            OrderEvent::Reject { .. } | OrderEvent::Out(_) => 3,
+        }
+    }
+
+    fn flag(&mut self, v: SequenceViolation) {
+        warn!("order sequence violation: {v:?}");
+        self.violations.push(v);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LatencyKind {
+    /// order sent to ack
+    Ack,
+    /// cancel sent to out
+    Cancel,
+}
+
+impl LatencyKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LatencyKind::Ack => "ack",
+            LatencyKind::Cancel => "cancel",
+        }
+    }
+}
+
+pub struct OrderTracker {
+    log: OrderLog,
+    latency: FxHashMap<(Cpty, LatencyKind), LatencyStats>,
+    sequencer: Option<Sequencer>,
+    cancels: FxHashMap<OrderId, (CancelRequest, watch::Sender<CancelState>)>,
+    tx: broadcast::Sender<OrderEvent>,
+    retention: Option<Duration>,
+    spill: Option<Box<dyn FnMut(&TrackedOrder) + Send + Sync>>,
+    last_evict: Option<DateTime<Utc>>,
+    positions: FxHashMap<MarketRef, Decimal>,
+    epoch: u64,
+}
+
+/// How often orders past their retention are evicted
+const EVICT_INTERVAL_SECS: i64 = 10;
+
+impl Default for OrderTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderTracker {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self {
+            log: OrderLog::new(),
+            latency: FxHashMap::default(),
+            sequencer: None,
+            cancels: FxHashMap::default(),
+            tx,
+            retention: None,
+            spill: None,
+            last_evict: None,
+            positions: FxHashMap::default(),
+            epoch: 0,
+        }
+    }
+
+    /// Apply each order's events in lifecycle order, holding early events
+    /// for up to `hold`, or turn strict sequencing off with None.  Turning
+    /// it off applies any held events immediately.  Orders sent before it
+    /// was turned on aren't sequenced.
+    pub fn set_strict_sequencing(&mut self, hold: Option<Duration>, now: DateTime<Utc>) {
+        match hold {
+            Some(hold) => {
+                let seq = self.sequencer.get_or_insert_with(|| Sequencer {
+                    hold,
+                    stages: FxHashMap::default(),
+                    held: FxHashMap::default(),
+                    violations: vec![],
+                });
+                seq.hold = hold;
+            }
+            None => {
+                let Some(seq) = self.sequencer.take() else { return };
+                for (_, held) in seq.held {
+                    for (_, ev) in held {
+                        self.dispatch(ev, now);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Take the sequence violations flagged since the last call
+    pub fn take_violations(&mut self) -> Vec<SequenceViolation> {
+        self.sequencer
+            .as_mut()
+            .map(|s| std::mem::take(&mut s.violations))
+            .unwrap_or_default()
+    }
+
+    /// The number of events being held for events they depend on
+    pub fn held_count(&self) -> usize {
+        self.sequencer.as_ref().map_or(0, |s| s.held.values().map(|h| h.len()).sum())
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<OrderEvent> {
+        self.tx.subscribe()
+    }
+
+    pub fn get(&self, id: &OrderId) -> Option<&TrackedOrder> {
+        self.log.get(id)
+    }
+
+    pub fn open_orders(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.log.open_orders()
+    }
+
+    /// The order state the tracker maintains, e.g. to snapshot it
+    pub fn log(&self) -> &OrderLog {
+        &self.log
+    }
+
+    /// Record the account and trader of an order, after `on_sent`, so it
+    /// can be selected by them, e.g. by a `CancelAllFilter`
+    pub fn set_owner(&mut self, id: OrderId, owner: OrderOwner) {
+        self.log.set_owner(id, owner)
+    }
+
+    /// Incremented on every change to order state or positions
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// The net position in `market`, positive long, from the starting
+    /// position and the fills applied since
+    pub fn position(&self, market: &MarketRef) -> Decimal {
+        self.positions.get(market).copied().unwrap_or_default()
+    }
+
+    pub fn positions(&self) -> &FxHashMap<MarketRef, Decimal> {
+        &self.positions
+    }
+
+    /// Set the position in `market`, e.g. from the venue at startup or
+    /// after a reconciliation
+    pub fn set_position(&mut self, market: MarketRef, position: Decimal) {
+        self.positions.insert(market, position);
+        self.epoch += 1;
+    }
+
+    /// The number of live orders and of done orders still held
+    pub fn order_counts(&self) -> OrderCounts {
+        self.log.counts()
+    }
+
+    /// Forget orders that are done, e.g. periodically or at end of day
+    pub fn gc(&mut self) {
+        self.log.gc();
+        self.epoch += 1;
+        self.forget_untracked();
+    }
+
+    /// Evict done orders once they have been done for `retention`, or keep
+    /// them until `gc` with None.  Eviction runs as events are handled, at
+    /// most every 10 seconds, and on `evict`.
+    pub fn set_retention(&mut self, retention: Option<Duration>) {
+        self.retention = retention;
+    }
+
+    /// Pass each order to `spill` before it is evicted, e.g. to write it
+    /// to a journal
+    pub fn set_spill(
+        &mut self,
+        spill: impl FnMut(&TrackedOrder) + Send + Sync + 'static,
+    ) {
+        self.spill = Some(Box::new(spill));
+    }
+
+    /// Evict done orders past the retention, returning how many were
+    /// evicted; does nothing without a retention
+    pub fn evict(&mut self, now: DateTime<Utc>) -> usize {
+        let Some(retention) = self.retention else { return 0 };
+        self.last_evict = Some(now);
+        let evicted = self.log.evict_done(now - retention);
+        if evicted.is_empty() {
+            return 0;
+        }
+        self.epoch += 1;
+        if let Some(spill) = &mut self.spill {
+            for o in &evicted {
+                spill(o);
+            }
+        }
+        self.forget_untracked();
+        debug!("evicted {} done orders", evicted.len());
+        evicted.len()
+    }
+
+    /// Drop sequencing and cancel state of orders the log no longer has
+    fn forget_untracked(&mut self) {
+        let log = &self.log;
+        if let Some(seq) = &mut self.sequencer {
+            seq.stages.retain(|id, _| log.contains(id));
+        }
+        self.cancels.retain(|id, _| log.contains(id));
+    }
+
+    pub fn cancel(&self, id: &OrderId) -> Option<&CancelRequest> {
+        self.cancels.get(id).map(|(c, _)| c)
+    }
+
+    /// Cancels still pending whose last attempt was at least `older_than`
+    /// ago, e.g. all of them after a reconnect, when cancels in flight may
+    /// have been lost.  Resend them and record each with `on_cancel_sent`.
+    pub fn cancels_to_retry(
+        &self,
+        older_than: Duration,
+        now: DateTime<Utc>,
+    ) -> Vec<OrderId> {
+        self.cancels
+            .values()
+            .filter(|(c, _)| {
+                c.state == CancelState::Pending && now - c.last_sent_at >= older_than
+            })
+            .map(|(c, _)| c.id)
+            .collect()
+    }
+
+    /// A future that resolves to the cancel's outcome once it is known, or
+    /// None if no cancel was sent for the order.  It doesn't borrow the
+    /// tracker, so it can be awaited while events are still being fed in.
+    pub fn await_cancel_outcome(
+        &self,
+        id: &OrderId,
+    ) -> Option<impl std::future::Future<Output = CancelState> + Send + 'static> {
+        let mut rx = self.cancels.get(id)?.1.subscribe();
+        Some(async move {
+            loop {
+                let state = rx.borrow_and_update().clone();
+                if state.is_final() || rx.changed().await.is_err() {
+                    return state;
+                }
+            }
+        })
+    }
+
+    fn set_cancel_state(&mut self, id: OrderId, state: CancelState) {
+        if let Some((c, tx)) = self.cancels.get_mut(&id) {
+            if c.state == CancelState::Pending {
+                debug!("cancel for {id:?}: {state:?}");
+                c.state = state.clone();
+                tx.send_replace(state);
+            }
+        }
+    }
+
+    /// Resolve a pending cancel from the order's state
+    fn settle_cancel(&mut self, id: OrderId) {
+        let state = match self.log.get(&id).map(|o| o.state) {
+            Some(TrackedOrderState::Canceled) => CancelState::Acked,
+            Some(TrackedOrderState::Filled | TrackedOrderState::Rejected) => {
+                CancelState::TooLate
+            }
+            _ => return,
+        };
+        self.set_cancel_state(id, state)
+    }
+
+    fn record_latency(
+        &mut self,
+        market: MarketRef,
+        kind: LatencyKind,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) {
+        // clock skew between the sender and the venue can make this negative
+        if let Ok(d) = (to - from).to_std() {
+            self.latency.entry((market.cpty(), kind)).or_default().record(d);
+        }
+    }
+
+    pub fn latency_summary(
+        &self,
+        cpty: &Cpty,
+        kind: LatencyKind,
+    ) -> Option<LatencySummary> {
+        self.latency.get(&(*cpty, kind)).and_then(|s| s.summary())
+    }
+
+    /// Latency percentiles over the recent window for every cpty and kind
+    /// with samples
+    pub fn latency_summaries(&self) -> Vec<(Cpty, LatencyKind, LatencySummary)> {
+        self.latency
+            .iter()
+            .filter_map(|((cpty, kind), s)| Some((*cpty, *kind, s.summary()?)))
+            .collect()
+    }
+
+    /// Publish the latency summaries through the admin stats API, in
+    /// milliseconds, under orderflow/latency/$venue/$route/$kind
+    #[cfg(feature = "netidx")]
+    pub fn publish_latency_stats(&self, common: &crate::Common) {
+        use netidx::path::Path;
+        for (cpty, kind, s) in self.latency_summaries() {
+            let base = Path::from("orderflow/latency")
+                .append(cpty.venue.name.as_str())
+                .append(cpty.route.name.as_str())
+                .append(kind.name());
+            let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.;
+            common.stat_set(base.append("count"), s.count as u64);
+            common.stat_set(base.append("mean"), ms(s.mean));
+            common.stat_set(base.append("p50"), ms(s.p50));
+            common.stat_set(base.append("p90"), ms(s.p90));
+            common.stat_set(base.append("p99"), ms(s.p99));
+            common.stat_set(base.append("max"), ms(s.max));
+        }
+    }
+
+    fn emit(&self, ev: OrderEvent) {
+        let _ = self.tx.send(ev);
+    }
+
+    pub fn on_sent(&mut self, request: OrderRequest, now: DateTime<Utc>) {
+        self.log.place(request, now);
+        self.handle(OrderEvent::Sent(request.id), now);
+    }
+
+    pub fn on_ack(&mut self, id: OrderId, now: DateTime<Utc>) {
+        self.handle(OrderEvent::Ack(id), now)
+    }
+
+    pub fn on_fill(
+        &mut self,
+        id: OrderId,
+        quantity: Decimal,
+        price: Decimal,
+        now: DateTime<Utc>,
+    ) {
+        self.handle(OrderEvent::Fill { id, quantity, price }, now)
+    }
+
+    /// Take a busted fill back out of the order's filled quantity and
+    /// average price.  The order's state is left alone: the venue doesn't
+    /// reopen a done order because one of its fills was busted.
+    pub fn on_bust(
+        &mut self,
+        id: OrderId,
+        quantity: Decimal,
+        price: Decimal,
+        now: DateTime<Utc>,
+    ) {
+        self.handle(OrderEvent::Bust { id, quantity, price }, now)
+    }
+
+    /// Replace a previously reported fill with its corrected quantity and
+    /// price
+    pub fn on_correction(
+        &mut self,
+        id: OrderId,
+        old_quantity: Decimal,
+        old_price: Decimal,
+        quantity: Decimal,
+        price: Decimal,
+        now: DateTime<Utc>,
+    ) {
+        let ev = OrderEvent::Correction { id, old_quantity, old_price, quantity, price };
+        self.handle(ev, now)
+    }
+
+    /// Record a reject; the venue or Oms message is classified into a
+    /// `RejectReason`, which is returned.
+    pub fn on_reject(
+        &mut self,
+        id: OrderId,
+        message: &str,
+        now: DateTime<Utc>,
+    ) -> RejectReason {
+        let reason = RejectReason::classify(message);
+        self.handle(OrderEvent::Reject { id, reason: reason.clone() }, now);
+        reason
+    }
+
+    pub fn on_cancel_sent(&mut self, id: OrderId, now: DateTime<Utc>) {
+        self.handle(OrderEvent::CancelSent(id), now)
+    }
+
+    /// The venue rejected a cancel.  If the order is already done, or the
+    /// venue says so, the cancel was too late; otherwise the order is live
+    /// again.  Returns the cancel's outcome.
+    pub fn on_cancel_reject(
+        &mut self,
+        id: OrderId,
+        message: &str,
+        now: DateTime<Utc>,
+    ) -> CancelState {
+        let reason = RejectReason::classify(message);
+        self.handle(OrderEvent::CancelReject { id, reason: reason.clone() }, now);
+        match self.cancels.get(&id) {
+            Some((c, _)) => c.state.clone(),
+            None => CancelState::Rejected(reason),
+        }
+    }
+
+    pub fn on_out(&mut self, id: OrderId, now: DateTime<Utc>) {
+        self.handle(OrderEvent::Out(id), now)
+    }
+
+    /// Record a modify sent for a live order.  A venue processes one
+    /// modify at a time per order, so one sent while another is pending
+    /// replaces it.
+    pub fn on_modify_sent(&mut self, req: ModifyOrderRequest, now: DateTime<Utc>) {
+        self.handle(OrderEvent::ModifySent(req), now)
+    }
+
+    /// The venue accepted the pending modify
+    pub fn on_modified(&mut self, id: OrderId, now: DateTime<Utc>) {
+        let Some(o) = self.log.get(&id) else {
+            warn!("modify ack for untracked order {id:?}");
+            return;
+        };
+        let Some(m) = o.pending_modify else {
+            warn!("modify ack for {id:?} with no modify pending");
+            return;
+        };
+        let price = m.price.unwrap_or(o.request.price);
+        let quantity = m.quantity.unwrap_or(o.request.quantity);
+        self.handle(OrderEvent::Modified { id, price, quantity }, now)
+    }
+
+    /// The venue rejected the pending modify; the reason is returned, see
+    /// `on_reject`
+    pub fn on_modify_reject(
+        &mut self,
+        id: OrderId,
+        message: &str,
+        now: DateTime<Utc>,
+    ) -> RejectReason {
+        let reason = RejectReason::classify(message);
+        self.handle(OrderEvent::ModifyReject { id, reason: reason.clone() }, now);
+        reason
+    }
+
+    /// Apply held events whose hold time has run out.  Call this
+    /// periodically when strict sequencing is on; it is also done on
+    /// every event.
+    pub fn poll(&mut self, now: DateTime<Utc>) {
+        let Some(seq) = &mut self.sequencer else { return };
+        let hold = seq.hold;
+        let expired: Vec<OrderId> = seq
+            .held
+            .iter()
+            .filter(|(_, h)| h.first().is_some_and(|(t, _)| now - *t >= hold))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            let Some(seq) = &mut self.sequencer else { return };
+            let mut held = seq.held.remove(&id).unwrap_or_default();
+            held.sort_by_key(|(_, ev)| Sequencer::rank(ev));
+            for (_, ev) in held {
+                self.force(ev, now);
+            }
+        }
+    }
+
+    fn handle(&mut self, ev: OrderEvent, now: DateTime<Utc>) {
+        if self.retention.is_some()
+            && self
+                .last_evict
+                .map_or(true, |t| now - t >= Duration::seconds(EVICT_INTERVAL_SECS))
+        {
+            self.evict(now);
+        }
+        let Some(seq) = &mut self.sequencer else {
+            return self.dispatch(ev, now);
+        };
+        let id = ev.id();
+        let stage = seq.stages.get(&id).copied();
+        // sent before strict sequencing was turned on
+        if stage.is_none() && !matches!(ev, OrderEvent::Sent(_)) && self.log.contains(&id)
+        {
+            return self.dispatch(ev, now);
+        }
+        match Verdict::of(&ev, stage) {
+            Verdict::Hold => {
+                debug!("holding out of order event {ev:?}");
+                seq.held.entry(id).or_default().push((now, ev));
+            }
+            Verdict::Late(apply) => {
+                let stage = stage.unwrap_or(OrderStage::Sent);
+                seq.flag(SequenceViolation::Late { event: ev.clone(), stage });
+                if apply {
+                    self.dispatch(ev, now);
+                }
+            }
+            Verdict::Apply => {
+                seq.advance(&ev);
+                self.dispatch(ev, now);
+                self.release(id, now);
+            }
+        }
+        self.poll(now);
+    }
+
+    /// Apply held events for `id` that are no longer early, earliest in
+    /// the lifecycle first, e.g. a fill before an out that arrived ahead of
+    /// it
+    fn release(&mut self, id: OrderId, now: DateTime<Utc>) {
+        loop {
+            let Some(seq) = &mut self.sequencer else { return };
+            let Some(held) = seq.held.get_mut(&id) else { return };
+            let stage = seq.stages.get(&id).copied();
+            let Some((i, _)) = held
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, ev))| !matches!(Verdict::of(ev, stage), Verdict::Hold))
+                .min_by_key(|(i, (_, ev))| (Sequencer::rank(ev), *i))
+            else {
+                return;
+            };
+            let (_, ev) = held.remove(i);
+            if held.is_empty() {
+                seq.held.remove(&id);
+            }
+            // re-judge it, it may be late now
+            self.handle(ev, now);
+        }
+    }
+
+    /// Apply a held event whose hold time ran out, synthesizing the ack
+    /// it is missing if there is one
+    fn force(&mut self, ev: OrderEvent, now: DateTime<Utc>) {
+        let Some(seq) = &mut self.sequencer else { return };
+        let id = ev.id();
+        let stage = seq.stages.get(&id).copied();
+        match Verdict::of(&ev, stage) {
+            Verdict::Hold => {
+                seq.flag(SequenceViolation::Timeout { event: ev.clone(), stage });
+                let needs_ack = stage == Some(OrderStage::Sent)
+                    && matches!(ev, OrderEvent::Fill { .. } | OrderEvent::Out(_));
+                if needs_ack {
+                    let ack = OrderEvent::Ack(id);
+                    seq.advance(&ack);
+                    self.dispatch(ack, now);
+                }
+                if let Some(seq) = &mut self.sequencer {
+                    seq.advance(&ev);
+                }
+                self.dispatch(ev, now);
+            }
+            // the events it was waiting for arrived after all
+            Verdict::Apply | Verdict::Late(_) => self.handle(ev, now),
+        }
+    }
+
+    fn dispatch(&mut self, ev: OrderEvent, now: DateTime<Utc>) {
+        let id = ev.id();
+        self.apply(ev, now);
+        self.settle_cancel(id);
+    }
+
+    fn apply(&mut self, ev: OrderEvent, now: DateTime<Utc>) {
+        let id = ev.id();
+        let Some(t) = self.log.apply(&ev, now) else { return };
+        self.epoch += 1;
+        let order =
+            self.log.get(&id).map(|o| (o.request.market, o.sent_at, o.cancel_sent_at));
+        let filled = match &ev {
+            OrderEvent::Fill { quantity, .. } => *quantity,
+            OrderEvent::Bust { quantity, .. } => -*quantity,
+            OrderEvent::Correction { old_quantity, quantity, .. } => {
+                *quantity - *old_quantity
+            }
+            _ => Decimal::ZERO,
+        };
+        if let Some(o) = self.log.get(&id).filter(|_| !filled.is_zero()) {
+            let signed = match o.request.dir {
+                Dir::Buy => filled,
+                Dir::Sell => -filled,
+            };
+            *self.positions.entry(o.request.market).or_default() += signed;
+        }
+        match &ev {
+            OrderEvent::Ack(_) if t.from == TrackedOrderState::Pending => {
+                if let Some((market, sent_at, _)) = order {
+                    self.record_latency(market, LatencyKind::Ack, sent_at, now);
+                }
+            }
+            OrderEvent::Out(_) if !t.from.is_done() => {
+                if let Some((market, _, Some(cancel_sent_at))) = order {
+                    self.record_latency(market, LatencyKind::Cancel, cancel_sent_at, now);
+                }
+            }
+            OrderEvent::CancelSent(_) => {
+                let (c, _) = self.cancels.entry(id).or_insert_with(|| {
+                    let (tx, _) = watch::channel(CancelState::Pending);
+                    let c = CancelRequest {
+                        id,
+                        state: CancelState::Pending,
+                        first_sent_at: now,
+                        last_sent_at: now,
+                        attempts: 0,
+                    };
+                    (c, tx)
+                });
+                c.last_sent_at = now;
+                c.attempts += 1;
+            }
+            OrderEvent::CancelReject { reason, .. } => {
+                const TOO_LATE: &[&str] = &[
+                    "too late",
+                    "unknown order",
+                    "not found",
+                    "already filled",
+                    "already closed",
+                ];
+                let too_late = t.to.is_done()
+                    || matches!(reason, RejectReason::Unknown(m)
+                        if TOO_LATE.iter().any(|p| m.to_lowercase().contains(p)));
+                let state = if too_late {
+                    CancelState::TooLate
+                } else {
+                    CancelState::Rejected(reason.clone())
+                };
+                self.set_cancel_state(id, state);
+            }
+            _ => (),
+        }
+        self.emit(ev);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbology::test_market;
+    use anyhow::Result;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    fn test_order() -> Result<OrderRequest> {
+        let market = test_market("USDCHF", "USD", "CHF")?;
+        Ok(OrderRequest {
+            id: OrderId { seqid: Default::default(), seqno: 0 },
+            market,
+            dir: Dir::Buy,
+            price: dec!(0.9),
+            quantity: dec!(10),
+        })
+    }
+
+    /// The kinds of the events broadcast since the last call
+    fn kinds(rx: &mut broadcast::Receiver<OrderEvent>) -> Vec<&'static str> {
+        let mut res = vec![];
+        while let Ok(ev) = rx.try_recv() {
+            res.push(match ev {
+                OrderEvent::Sent(_) => "sent",
+                OrderEvent::Ack(_) => "ack",
+                OrderEvent::Fill { .. } => "fill",
+                OrderEvent::Out(_) => "out",
+                _ => "other",
+            });
+        }
+        res
+    }
+
+    #[test]
+    fn test_early_events_released_in_order() -> Result<()> {
+        let req = test_order()?;
+        let t0 = Utc.with_ymd_and_hms(2024, 6, 3, 14, 0, 0).unwrap();
+        let mut tracker = OrderTracker::new();
+        tracker.set_strict_sequencing(Some(Duration::seconds(5)), t0);
+        let mut rx = tracker.subscribe();
+        tracker.on_sent(req, t0);
+        // the out and the fill both arrive ahead of the ack
+        tracker.on_out(req.id, t0);
+        tracker.on_fill(req.id, dec!(4), dec!(0.9), t0);
+        assert_eq!(tracker.held_count(), 2);
+        assert_eq!(kinds(&mut rx), ["sent"]);
+        tracker.on_ack(req.id, t0 + Duration::seconds(1));
+        assert_eq!(tracker.held_count(), 0);
+        // the fill is released before the out that arrived ahead of it
+        assert_eq!(kinds(&mut rx), ["ack", "fill", "out"]);
+        assert!(tracker.take_violations().is_empty());
+        let o = tracker.get(&req.id).unwrap();
+        assert_eq!((o.state, o.filled), (TrackedOrderState::Canceled, dec!(4)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_held_event_forced_after_hold() -> Result<()> {
+        let req = test_order()?;
+        let t0 = Utc.with_ymd_and_hms(2024, 6, 3, 14, 0, 0).unwrap();
+        let mut tracker = OrderTracker::new();
+        tracker.set_strict_sequencing(Some(Duration::seconds(5)), t0);
+        let mut rx = tracker.subscribe();
+        tracker.on_sent(req, t0);
+        tracker.on_fill(req.id, dec!(10), dec!(0.9), t0 + Duration::seconds(1));
+        tracker.poll(t0 + Duration::seconds(5));
+        assert_eq!(tracker.held_count(), 1);
+        assert_eq!(kinds(&mut rx), ["sent"]);
+        // the ack never came: the fill goes out behind a synthesized one
+        tracker.poll(t0 + Duration::seconds(6));
+        assert_eq!(tracker.held_count(), 0);
+        assert_eq!(kinds(&mut rx), ["ack", "fill"]);
+        let violations = tracker.take_violations();
+        assert!(matches!(
+            violations[..],
+            [SequenceViolation::Timeout {
+                event: OrderEvent::Fill { .. },
+                stage: Some(OrderStage::Sent)
+            }]
+        ));
+        assert_eq!(tracker.get(&req.id).unwrap().state, TrackedOrderState::Filled);
+        // the real ack is late now, and dropped
+        tracker.on_ack(req.id, t0 + Duration::seconds(7));
+        assert!(kinds(&mut rx).is_empty());
+        assert!(matches!(
+            tracker.take_violations()[..],
+            [SequenceViolation::Late { event: OrderEvent::Ack(_), .. }]
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! Best execution venue ranking.  Venues trading the same product are ranked
+//! by the estimated all-in cost of taking liquidity: half the spread, the
+//! taker fee from the configured fee schedule, recent fill slippage, and a
+//! penalty for displayed depth below the target size.  Each score carries its
+//! components so routing decisions can be audited after the fact.
+//!
+//! With `set_fees` the taker fee comes from a tiered `Fees` model instead of
+//! the flat schedules in the config.
+
+pub use super::fees::FeeSchedule;
+use super::fees::{Fees, Liquidity};
+use crate::{
+    marketdata::market_view::MarketState,
+    symbology::{MarketRef, ProductRef, VenueRef},
+};
+use api::Dir;
+use chrono::{DateTime, Utc};
+use fxhash::FxHashMap;
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+
+const BPS: Decimal = Decimal::from_parts(10_000, 0, 0, false, 0);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankingConfig {
+    /// fee schedules by venue name
+    #[serde(default)]
+    pub fees: FxHashMap<String, FeeSchedule>,
+    /// fees for venues not listed in `fees`
+    #[serde(default)]
+    pub default_fees: FeeSchedule,
+    /// number of levels per side counted as displayed depth
+    pub depth_levels: usize,
+    /// the size we expect to trade; venues displaying less are penalized
+    pub target_size: Decimal,
+    /// the penalty, in bps, for a venue displaying no depth at all; scaled
+    /// linearly by the shortfall from `target_size`
+    pub depth_penalty_bps: Decimal,
+    /// weight of each new fill in the slippage moving average, in (0, 1]
+    pub slippage_alpha: Decimal,
+}
+
+impl Default for RankingConfig {
+    fn default() -> Self {
+        Self {
+            fees: FxHashMap::default(),
+            default_fees: FeeSchedule::default(),
+            depth_levels: 5,
+            target_size: Decimal::ONE,
+            depth_penalty_bps: Decimal::from(10),
+            slippage_alpha: Decimal::new(1, 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VenueScore {
+    pub market: MarketRef,
+    pub venue: VenueRef,
+    pub spread_bps: Decimal,
+    /// displayed size within `depth_levels` on the side we would take
+    pub depth: Decimal,
+    /// moving average of fill slippage vs arrival mid, positive is worse
+    pub slippage_bps: Option<Decimal>,
+    pub fee_bps: Decimal,
+    pub depth_penalty_bps: Decimal,
+    /// estimated all-in cost of taking, lower is better
+    pub cost_bps: Decimal,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct MarketStats {
+    bid: Option<Decimal>,
+    ask: Option<Decimal>,
+    depth_bid: Decimal,
+    depth_ask: Decimal,
+    slippage_bps: Option<Decimal>,
+    updated: DateTime<Utc>,
+}
+
+pub struct VenueRanker {
+    config: RankingConfig,
+    stats: FxHashMap<MarketRef, MarketStats>,
+    by_product: FxHashMap<ProductRef, Vec<MarketRef>>,
+    fees: Option<Fees>,
+}
+
+impl VenueRanker {
+    pub fn new(config: RankingConfig) -> Self {
+        Self {
+            config,
+            stats: FxHashMap::default(),
+            by_product: FxHashMap::default(),
+            fees: None,
+        }
+    }
+
+    pub fn config(&self) -> &RankingConfig {
+        &self.config
+    }
+
+    /// Take taker fees from a fee model, at the current tier, instead of
+    /// the config's schedules
+    pub fn set_fees(&mut self, fees: Option<Fees>) {
+        self.fees = fees;
+    }
+
+    pub fn fees_mut(&mut self) -> Option<&mut Fees> {
+        self.fees.as_mut()
+    }
+
+    fn taker_bps(&self, market: &MarketRef) -> Decimal {
+        match &self.fees {
+            Some(fees) => fees.bps(market, Liquidity::Taker),
+            None => {
+                self.config
+                    .fees
+                    .get(market.venue.name.as_str())
+                    .copied()
+                    .unwrap_or(self.config.default_fees)
+                    .taker_bps
+            }
+        }
+    }
+
+    fn stats_mut(&mut self, market: MarketRef) -> &mut MarketStats {
+        if !self.stats.contains_key(&market) {
+            if let Some(base) = market.base() {
+                self.by_product.entry(base).or_default().push(market);
+            }
+        }
+        self.stats.entry(market).or_default()
+    }
+
+    /// Update book derived stats for a market
+    pub fn update_book(&mut self, market: MarketRef, state: &MarketState) {
+        let depth_levels = self.config.depth_levels;
+        let depth = |dir: Dir| -> Decimal {
+            state.depth.get(dir).iter().take(depth_levels).map(|(_, size)| *size).sum()
+        };
+        let (depth_bid, depth_ask) = (depth(Dir::Buy), depth(Dir::Sell));
+        let st = self.stats_mut(market);
+        st.bid = state.best(Dir::Buy).map(|(px, _)| px);
+        st.ask = state.best(Dir::Sell).map(|(px, _)| px);
+        st.depth_bid = depth_bid;
+        st.depth_ask = depth_ask;
+        st.updated = state.timestamp;
+    }
+
+    /// Record a fill for fill quality; `arrival_mid` is the mid when the
+    /// order was sent
+    pub fn record_fill(
+        &mut self,
+        market: MarketRef,
+        dir: Dir,
+        arrival_mid: Decimal,
+        price: Decimal,
+    ) {
+        if arrival_mid.is_zero() {
+            return;
+        }
+        let slippage = match dir {
+            Dir::Buy => (price - arrival_mid) / arrival_mid * BPS,
+            Dir::Sell => (arrival_mid - price) / arrival_mid * BPS,
+        };
+        let alpha = self.config.slippage_alpha;
+        let st = self.stats_mut(market);
+        st.slippage_bps = Some(match st.slippage_bps {
+            None => slippage,
+            Some(avg) => avg + alpha * (slippage - avg),
+        });
+    }
+
+    /// Score a single market for taking in the given direction
+    pub fn score(&self, market: MarketRef, dir: Dir) -> Option<VenueScore> {
+        let st = self.stats.get(&market)?;
+        let (bid, ask) = (st.bid?, st.ask?);
+        let mid = (bid + ask) / Decimal::TWO;
+        if mid <= Decimal::ZERO {
+            return None;
+        }
+        let spread_bps = (ask - bid) / mid * BPS;
+        let depth = match dir {
+            Dir::Buy => st.depth_ask,
+            Dir::Sell => st.depth_bid,
+        };
+        let target = self.config.target_size;
+        let depth_penalty_bps = if target > Decimal::ZERO && depth < target {
+            self.config.depth_penalty_bps * (target - depth) / target
+        } else {
+            Decimal::ZERO
+        };
+        let fee_bps = self.taker_bps(&market);
+        let cost_bps = spread_bps / Decimal::TWO
+            + fee_bps
+            + st.slippage_bps.unwrap_or(Decimal::ZERO)
+            + depth_penalty_bps;
+        Some(VenueScore {
+            market,
+            venue: market.venue,
+            spread_bps,
+            depth,
+            slippage_bps: st.slippage_bps,
+            fee_bps,
+            depth_penalty_bps,
+            cost_bps,
+            updated: st.updated,
+        })
+    }
+
+    /// Rank all known markets for the product, best first
+    pub fn ranking(&self, product: ProductRef, dir: Dir) -> Vec<VenueScore> {
+        let mut scores: Vec<VenueScore> = self
+            .by_product
+            .get(&product)
+            .into_iter()
+            .flatten()
+            .filter_map(|m| self.score(*m, dir))
+            .collect();
+        scores.sort_by(|a, b| a.cost_bps.cmp(&b.cost_bps));
+        scores
+    }
+
+    /// The best market for the product, if any has a two sided book
+    pub fn best(&self, product: ProductRef, dir: Dir) -> Option<VenueScore> {
+        self.ranking(product, dir).into_iter().next()
+    }
+}
@@ -0,0 +1,379 @@
+//! Live tunable strategy parameters.
+//!
+//! Strategies declare their knobs (spreads, sizes, toggles) in a
+//! `ParamRegistry` and hold a `Param<T>` handle that always reads the
+//! current value.  Values can be changed at runtime, locally with `set` or
+//! remotely by writing the netidx paths published by `publish`, and every
+//! change is validated, logged and kept in an audit trail.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use fxhash::FxHashMap;
+use log::info;
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use std::{collections::VecDeque, fmt, marker::PhantomData, str::FromStr, sync::Arc};
+use tokio::sync::{broadcast, watch};
+
+const MAX_AUDIT: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamValue {
+    Decimal(Decimal),
+    Int(i64),
+    Bool(bool),
+    String(String),
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamValue::Decimal(d) => write!(f, "{d}"),
+            ParamValue::Int(i) => write!(f, "{i}"),
+            ParamValue::Bool(b) => write!(f, "{b}"),
+            ParamValue::String(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+impl ParamValue {
+    /// Parse `s` as a value of the same kind as `self`
+    pub fn parse_like(&self, s: &str) -> Result<ParamValue> {
+        let s = s.trim();
+        Ok(match self {
+            ParamValue::Decimal(_) => ParamValue::Decimal(Decimal::from_str(s)?),
+            ParamValue::Int(_) => ParamValue::Int(s.parse()?),
+            ParamValue::Bool(_) => ParamValue::Bool(s.parse()?),
+            ParamValue::String(_) => ParamValue::String(s.to_string()),
+        })
+    }
+
+    fn same_kind(&self, other: &ParamValue) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    fn as_decimal(&self) -> Option<Decimal> {
+        match self {
+            ParamValue::Decimal(d) => Some(*d),
+            ParamValue::Int(i) => Some(Decimal::from(*i)),
+            _ => None,
+        }
+    }
+}
+
+/// Rust types that can be held in a `Param`
+pub trait ParamType: Sized {
+    fn into_value(self) -> ParamValue;
+    fn from_value(value: &ParamValue) -> Option<Self>;
+}
+
+macro_rules! param_type {
+    ($t:ty, $variant:ident) => {
+        impl ParamType for $t {
+            fn into_value(self) -> ParamValue {
+                ParamValue::$variant(self)
+            }
+
+            fn from_value(value: &ParamValue) -> Option<Self> {
+                match value {
+                    ParamValue::$variant(v) => Some(v.clone()),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+param_type!(Decimal, Decimal);
+param_type!(i64, Int);
+param_type!(bool, Bool);
+param_type!(String, String);
+
+#[derive(Debug, Clone)]
+pub struct ParamSpec {
+    pub name: String,
+    pub description: String,
+    pub default: ParamValue,
+    /// inclusive bounds, only for decimal and int params
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParamChange {
+    pub name: String,
+    pub old: ParamValue,
+    pub new: ParamValue,
+    /// who made the change, e.g. "local" or the netidx writer
+    pub source: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A handle to the current value of a declared parameter
+#[derive(Debug, Clone)]
+pub struct Param<T> {
+    rx: watch::Receiver<ParamValue>,
+    ty: PhantomData<T>,
+}
+
+impl<T: ParamType> Param<T> {
+    pub fn get(&self) -> T {
+        // kind is checked on every set, so this can't fail
+        T::from_value(&self.rx.borrow()).unwrap()
+    }
+
+    /// Wait for the value to change
+    pub async fn changed(&mut self) -> Result<T> {
+        self.rx.changed().await?;
+        Ok(self.get())
+    }
+}
+
+struct Entry {
+    spec: ParamSpec,
+    tx: watch::Sender<ParamValue>,
+}
+
+struct Inner {
+    params: FxHashMap<String, Entry>,
+    audit: VecDeque<ParamChange>,
+}
+
+#[derive(Clone)]
+pub struct ParamRegistry {
+    inner: Arc<Mutex<Inner>>,
+    tx: broadcast::Sender<ParamChange>,
+}
+
+impl Default for ParamRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParamRegistry {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        let inner = Inner { params: FxHashMap::default(), audit: VecDeque::new() };
+        Self { inner: Arc::new(Mutex::new(inner)), tx }
+    }
+
+    /// Declare a parameter, or get a handle to it if it was already declared
+    /// with the same type
+    pub fn declare<T: ParamType>(
+        &self,
+        name: &str,
+        default: T,
+        description: &str,
+    ) -> Result<Param<T>> {
+        self.declare_spec(ParamSpec {
+            name: name.to_string(),
+            description: description.to_string(),
+            default: default.into_value(),
+            min: None,
+            max: None,
+        })
+    }
+
+    /// Declare a decimal parameter that may only be set within `min..=max`
+    pub fn declare_bounded(
+        &self,
+        name: &str,
+        default: Decimal,
+        min: Decimal,
+        max: Decimal,
+        description: &str,
+    ) -> Result<Param<Decimal>> {
+        self.declare_spec(ParamSpec {
+            name: name.to_string(),
+            description: description.to_string(),
+            default: ParamValue::Decimal(default),
+            min: Some(min),
+            max: Some(max),
+        })
+    }
+
+    pub fn declare_spec<T: ParamType>(&self, spec: ParamSpec) -> Result<Param<T>> {
+        if T::from_value(&spec.default).is_none() {
+            bail!("param {} default {} has the wrong type", spec.name, spec.default);
+        }
+        check_bounds(&spec, &spec.default)?;
+        let mut inner = self.inner.lock();
+        let rx = match inner.params.get(&spec.name) {
+            Some(e) => {
+                if !e.spec.default.same_kind(&spec.default) {
+                    bail!("param {} already declared with a different type", spec.name);
+                }
+                e.tx.subscribe()
+            }
+            None => {
+                let (tx, rx) = watch::channel(spec.default.clone());
+                inner.params.insert(spec.name.clone(), Entry { spec, tx });
+                rx
+            }
+        };
+        Ok(Param { rx, ty: PhantomData })
+    }
+
+    pub fn get(&self, name: &str) -> Option<ParamValue> {
+        self.inner.lock().params.get(name).map(|e| e.tx.borrow().clone())
+    }
+
+    /// Every declared parameter and its current value, sorted by name
+    pub fn list(&self) -> Vec<(ParamSpec, ParamValue)> {
+        let inner = self.inner.lock();
+        let mut res: Vec<_> = inner
+            .params
+            .values()
+            .map(|e| (e.spec.clone(), e.tx.borrow().clone()))
+            .collect();
+        res.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+        res
+    }
+
+    pub fn set(
+        &self,
+        name: &str,
+        value: ParamValue,
+        source: &str,
+    ) -> Result<ParamChange> {
+        let change = {
+            let mut inner = self.inner.lock();
+            let entry =
+                inner.params.get(name).ok_or_else(|| anyhow!("no such param {name}"))?;
+            if !entry.spec.default.same_kind(&value) {
+                bail!("param {name} can't be set to {value}, wrong type");
+            }
+            check_bounds(&entry.spec, &value)?;
+            let old = entry.tx.send_replace(value.clone());
+            let change = ParamChange {
+                name: name.to_string(),
+                old,
+                new: value,
+                source: source.to_string(),
+                timestamp: Utc::now(),
+            };
+            if inner.audit.len() >= MAX_AUDIT {
+                inner.audit.pop_front();
+            }
+            inner.audit.push_back(change.clone());
+            change
+        };
+        info!(
+            "param {} changed from {} to {} by {}",
+            change.name, change.old, change.new, change.source
+        );
+        let _ = self.tx.send(change.clone());
+        Ok(change)
+    }
+
+    /// Parse `value` according to the param's type and set it
+    pub fn set_str(&self, name: &str, value: &str, source: &str) -> Result<ParamChange> {
+        let cur = self.get(name).ok_or_else(|| anyhow!("no such param {name}"))?;
+        self.set(name, cur.parse_like(value)?, source)
+    }
+
+    pub fn reset(&self, name: &str, source: &str) -> Result<ParamChange> {
+        let default = self
+            .inner
+            .lock()
+            .params
+            .get(name)
+            .map(|e| e.spec.default.clone())
+            .ok_or_else(|| anyhow!("no such param {name}"))?;
+        self.set(name, default, source)
+    }
+
+    /// The most recent changes, oldest first
+    pub fn audit(&self) -> Vec<ParamChange> {
+        self.inner.lock().audit.iter().cloned().collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ParamChange> {
+        self.tx.subscribe()
+    }
+
+    /// Publish every declared parameter under `base` as a writable netidx
+    /// value.  Writes are applied with `set_str`; rejected writes are logged
+    /// and the published value is left unchanged.  Params declared after
+    /// this is called are not published.
+    #[cfg(feature = "netidx")]
+    pub fn publish(
+        &self,
+        publisher: &netidx::publisher::Publisher,
+        base: netidx::path::Path,
+    ) -> Result<tokio::task::JoinHandle<()>> {
+        use futures::{channel::mpsc, select_biased, FutureExt, StreamExt};
+        use log::warn;
+        use netidx::publisher::Val;
+        use tokio::sync::broadcast::error::RecvError;
+
+        let (write_tx, mut write_rx) = mpsc::channel(10);
+        let mut vals: FxHashMap<String, Val> = FxHashMap::default();
+        let mut by_id = FxHashMap::default();
+        for (spec, value) in self.list() {
+            let val = publisher.publish(base.append(&spec.name), value.to_string())?;
+            publisher.writes(val.id(), write_tx.clone());
+            by_id.insert(val.id(), spec.name.clone());
+            vals.insert(spec.name, val);
+        }
+        let registry = self.clone();
+        let mut changes = self.subscribe();
+        let publisher = publisher.clone();
+        Ok(tokio::spawn(async move {
+            loop {
+                select_biased! {
+                    r = write_rx.select_next_some() => {
+                        let mut r: netidx::pool::Pooled<Vec<_>> = r;
+                        for wr in r.drain(..) {
+                            let Some(name) = by_id.get(&wr.id) else { continue };
+                            let source = format!("netidx {:?}", wr.client);
+                            let res = match wr.value.clone().cast_to::<String>() {
+                                Ok(s) => registry.set_str(name, &s, &source),
+                                Err(e) => Err(e),
+                            };
+                            if let Err(e) = res {
+                                warn!("rejected write to param {name}: {e:?}");
+                            }
+                        }
+                    },
+                    c = changes.recv().fuse() => match c {
+                        Ok(c) => {
+                            if let Some(val) = vals.get(&c.name) {
+                                let mut batch = publisher.start_batch();
+                                val.update(&mut batch, c.new.to_string());
+                                batch.commit(None).await;
+                            }
+                        }
+                        Err(RecvError::Lagged(_)) => {
+                            let mut batch = publisher.start_batch();
+                            for (spec, value) in registry.list() {
+                                if let Some(val) = vals.get(&spec.name) {
+                                    val.update(&mut batch, value.to_string());
+                                }
+                            }
+                            batch.commit(None).await;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                }
+            }
+        }))
+    }
+}
+
+fn check_bounds(spec: &ParamSpec, value: &ParamValue) -> Result<()> {
+    if let Some(v) = value.as_decimal() {
+        if let Some(min) = spec.min {
+            if v < min {
+                bail!("param {} value {v} is below the minimum {min}", spec.name);
+            }
+        }
+        if let Some(max) = spec.max {
+            if v > max {
+                bail!("param {} value {v} is above the maximum {max}", spec.name);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,214 @@
+//! Tick aware price arithmetic on `Decimal`.
+//!
+//! Use these instead of ad hoc rounding: every operation that can land off
+//! tick takes an explicit rounding direction, and the passive/aggressive
+//! helpers pick the safe direction for the side of the order.
+
+use crate::symbology::MarketRef;
+use anyhow::{bail, Result};
+use api::{symbology::market::NormalizedMarketInfo, Dir};
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
+
+const BPS: Decimal = Decimal::from_parts(10000, 0, 0, false, 0);
+
+/// How to break ties when rounding to the nearest tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Midpoint {
+    Up,
+    Down,
+    /// to the even multiple of the tick
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    Down,
+    Up,
+    Nearest(Midpoint),
+}
+
+impl Rounding {
+    /// Round away from crossing the spread: buys down, sells up
+    pub fn passive(dir: Dir) -> Self {
+        match dir {
+            Dir::Buy => Rounding::Down,
+            Dir::Sell => Rounding::Up,
+        }
+    }
+
+    /// Round towards crossing the spread: buys up, sells down
+    pub fn aggressive(dir: Dir) -> Self {
+        match dir {
+            Dir::Buy => Rounding::Up,
+            Dir::Sell => Rounding::Down,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickSize(Decimal);
+
+impl TickSize {
+    pub fn new(tick: Decimal) -> Result<Self> {
+        if tick <= Decimal::ZERO {
+            bail!("tick size must be positive, got {tick}");
+        }
+        Ok(Self(tick))
+    }
+
+    pub fn for_market(market: &MarketRef) -> Result<Self> {
+        Self::new(market.extra_info.tick_size())
+    }
+
+    pub fn get(&self) -> Decimal {
+        self.0
+    }
+
+    pub fn is_on_tick(&self, price: Decimal) -> bool {
+        (price % self.0).is_zero()
+    }
+
+    pub fn round(&self, price: Decimal, rounding: Rounding) -> Decimal {
+        let n = price / self.0;
+        let n = match rounding {
+            Rounding::Down => n.floor(),
+            Rounding::Up => n.ceil(),
+            Rounding::Nearest(mid) => {
+                let (lo, hi) = (n.floor(), n.ceil());
+                match (n - lo).cmp(&(hi - n)) {
+                    Ordering::Less => lo,
+                    Ordering::Greater => hi,
+                    Ordering::Equal => match mid {
+                        Midpoint::Up => hi,
+                        Midpoint::Down => lo,
+                        Midpoint::Even => {
+                            if (lo % Decimal::TWO).is_zero() {
+                                lo
+                            } else {
+                                hi
+                            }
+                        }
+                    },
+                }
+            }
+        };
+        (n * self.0).normalize()
+    }
+
+    pub fn round_passive(&self, price: Decimal, dir: Dir) -> Decimal {
+        self.round(price, Rounding::passive(dir))
+    }
+
+    pub fn round_aggressive(&self, price: Decimal, dir: Dir) -> Decimal {
+        self.round(price, Rounding::aggressive(dir))
+    }
+
+    /// Move `price` by `ticks`, which may be negative; an off tick price is
+    /// first rounded with `rounding`
+    pub fn add_ticks(&self, price: Decimal, ticks: i64, rounding: Rounding) -> Decimal {
+        (self.round(price, rounding) + self.0 * Decimal::from(ticks)).normalize()
+    }
+
+    /// Move `price` by `ticks` towards the far touch for `dir`, i.e. up for
+    /// buys and down for sells; negative `ticks` moves away
+    pub fn improve(&self, price: Decimal, dir: Dir, ticks: i64) -> Decimal {
+        let ticks = match dir {
+            Dir::Buy => ticks,
+            Dir::Sell => -ticks,
+        };
+        self.add_ticks(price, ticks, Rounding::passive(dir))
+    }
+
+    /// Signed number of ticks from `a` to `b`, not necessarily integral if
+    /// either is off tick
+    pub fn ticks_between(&self, a: Decimal, b: Decimal) -> Decimal {
+        (b - a) / self.0
+    }
+
+    /// Compare two prices after rounding both to the nearest tick, so
+    /// representation noise below the tick doesn't matter
+    pub fn cmp(&self, a: Decimal, b: Decimal) -> Ordering {
+        let r = Rounding::Nearest(Midpoint::Even);
+        self.round(a, r).cmp(&self.round(b, r))
+    }
+}
+
+/// Offset `price` by `bps` basis points, positive is up
+pub fn offset_bps(price: Decimal, bps: Decimal) -> Decimal {
+    price + price * bps / BPS
+}
+
+/// Offset `price` by `bps` basis points away from crossing for `dir`, and
+/// round passively to the tick
+pub fn passive_offset_bps(
+    price: Decimal,
+    bps: Decimal,
+    dir: Dir,
+    tick: TickSize,
+) -> Decimal {
+    let signed = match dir {
+        Dir::Buy => -bps,
+        Dir::Sell => bps,
+    };
+    tick.round_passive(offset_bps(price, signed), dir)
+}
+
+/// The distance from `from` to `to` in basis points of `from`
+pub fn bps_between(from: Decimal, to: Decimal) -> Option<Decimal> {
+    (!from.is_zero()).then(|| (to - from) / from * BPS)
+}
+
+/// Whether an order at `price` on `dir` would trade against the opposite
+/// touch at `opposite`
+pub fn crosses(dir: Dir, price: Decimal, opposite: Decimal) -> bool {
+    match dir {
+        Dir::Buy => price >= opposite,
+        Dir::Sell => price <= opposite,
+    }
+}
+
+/// Whether a bid and ask are locked or crossed
+pub fn is_crossed(bid: Decimal, ask: Decimal) -> bool {
+    bid >= ask
+}
+
+/// The midpoint rounded to the tick with the given rounding
+pub fn mid(bid: Decimal, ask: Decimal, tick: TickSize, rounding: Rounding) -> Decimal {
+    tick.round((bid + ask) / Decimal::TWO, rounding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_rounding() -> Result<()> {
+        let tick = TickSize::new(dec!(0.25))?;
+        assert_eq!(tick.round(dec!(100.1), Rounding::Down), dec!(100));
+        assert_eq!(tick.round(dec!(100.1), Rounding::Up), dec!(100.25));
+        assert_eq!(
+            tick.round(dec!(100.125), Rounding::Nearest(Midpoint::Up)),
+            dec!(100.25)
+        );
+        assert_eq!(
+            tick.round(dec!(100.125), Rounding::Nearest(Midpoint::Down)),
+            dec!(100)
+        );
+        assert_eq!(
+            tick.round(dec!(100.125), Rounding::Nearest(Midpoint::Even)),
+            dec!(100)
+        );
+        assert_eq!(
+            tick.round(dec!(100.375), Rounding::Nearest(Midpoint::Even)),
+            dec!(100.5)
+        );
+        assert_eq!(tick.round_passive(dec!(100.1), Dir::Sell), dec!(100.25));
+        assert_eq!(tick.improve(dec!(100), Dir::Sell, 2), dec!(99.5));
+        assert_eq!(tick.cmp(dec!(100.0000001), dec!(100)), Ordering::Equal);
+        assert_eq!(offset_bps(dec!(100), dec!(10)), dec!(100.1));
+        assert_eq!(passive_offset_bps(dec!(100), dec!(10), Dir::Buy, tick), dec!(99.75));
+        Ok(())
+    }
+}
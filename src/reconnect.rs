@@ -0,0 +1,167 @@
+//! Process wide reconnect coordination.
+//!
+//! When the core restarts, every channel driver, stream and external
+//! driver in a process loses its connection at once.  Reconnecting each on
+//! its own fixed timer sends them all back at the same instant, over and
+//! over.  Instead each reconnect loop keeps a `Backoff`, which waits an
+//! exponentially growing, jittered delay, and every wait goes through the
+//! global `ReconnectCoordinator`, which spaces reconnect attempts from the
+//! whole process at least `min_spacing` apart.  An attempt pushed back
+//! past its own delay to keep that spacing counts as suppressed.
+//!
+//! Counters of attempted and suppressed reconnects per subsystem are in
+//! `ReconnectCoordinator::counts`.
+
+use fxhash::FxHashMap;
+use log::debug;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::{
+    collections::{hash_map::RandomState, BTreeSet},
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BackoffConfig {
+    /// the delay before the first reconnect
+    pub base: Duration,
+    /// the delay doubles after each failed reconnect, up to this
+    pub max: Duration,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self { base: Duration::from_secs(1), max: Duration::from_secs(30) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReconnectCounts {
+    pub attempted: u64,
+    /// attempts delayed beyond their backoff to keep the process wide
+    /// spacing
+    pub suppressed: u64,
+}
+
+struct CoordinatorState {
+    min_spacing: Duration,
+    /// the times attempts are scheduled for, recent and upcoming
+    slots: BTreeSet<Instant>,
+    counts: FxHashMap<&'static str, ReconnectCounts>,
+}
+
+pub struct ReconnectCoordinator(Mutex<CoordinatorState>);
+
+static COORDINATOR: Lazy<ReconnectCoordinator> = Lazy::new(|| {
+    ReconnectCoordinator(Mutex::new(CoordinatorState {
+        min_spacing: Duration::from_millis(50),
+        slots: BTreeSet::new(),
+        counts: FxHashMap::default(),
+    }))
+});
+
+impl ReconnectCoordinator {
+    pub fn global() -> &'static ReconnectCoordinator {
+        &COORDINATOR
+    }
+
+    /// The minimum time between any two reconnect attempts in the process
+    pub fn set_min_spacing(&self, spacing: Duration) {
+        self.0.lock().min_spacing = spacing;
+    }
+
+    /// Reserve the first time at or after `earliest` that is at least
+    /// `min_spacing` from every other reserved time
+    fn schedule(&self, subsystem: &'static str, earliest: Instant) -> Instant {
+        let mut st = self.0.lock();
+        let spacing = st.min_spacing;
+        if let Some(cutoff) = Instant::now().checked_sub(spacing) {
+            st.slots = st.slots.split_off(&cutoff);
+        }
+        let mut slot = earliest;
+        let from = earliest.checked_sub(spacing).unwrap_or(earliest);
+        for t in st.slots.range(from..) {
+            if *t >= slot + spacing {
+                break;
+            }
+            if *t + spacing > slot {
+                slot = *t + spacing;
+            }
+        }
+        st.slots.insert(slot);
+        let counts = st.counts.entry(subsystem).or_default();
+        counts.attempted += 1;
+        if slot > earliest {
+            counts.suppressed += 1;
+        }
+        slot
+    }
+
+    pub fn counts(&self, subsystem: &str) -> ReconnectCounts {
+        self.0.lock().counts.get(subsystem).copied().unwrap_or_default()
+    }
+
+    /// Counts for every subsystem that has reconnected
+    pub fn all_counts(&self) -> Vec<(&'static str, ReconnectCounts)> {
+        self.0.lock().counts.iter().map(|(s, c)| (*s, *c)).collect()
+    }
+}
+
+/// The reconnect delay of one reconnect loop
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    subsystem: &'static str,
+    config: BackoffConfig,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(subsystem: &'static str, config: BackoffConfig) -> Self {
+        Self { subsystem, config, failures: 0 }
+    }
+
+    /// Call once connected, so the next reconnect starts from the base
+    /// delay again
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Call when a connection is lost after `connected_for`; a connection
+    /// that stayed up longer than the max delay resets the backoff
+    pub fn on_disconnect(&mut self, connected_for: Duration) {
+        if connected_for > self.config.max {
+            self.reset();
+        }
+    }
+
+    /// The delay for the next attempt, before jitter
+    pub fn delay(&self) -> Duration {
+        let factor = 1u32.checked_shl(self.failures.min(31)).unwrap_or(u32::MAX);
+        self.config.base.saturating_mul(factor).min(self.config.max)
+    }
+
+    /// Wait until it's this loop's turn to reconnect: between half and all
+    /// of the current delay, then however long the coordinator needs to
+    /// space it from other reconnects in the process
+    pub async fn wait(&mut self) {
+        let delay = self.delay();
+        self.failures = self.failures.saturating_add(1);
+        let jittered = delay / 2 + delay.mul_f64(jitter() / 2.);
+        let slot = ReconnectCoordinator::global()
+            .schedule(self.subsystem, Instant::now() + jittered);
+        debug!(
+            "{} reconnecting in {:?}",
+            self.subsystem,
+            slot.saturating_duration_since(Instant::now())
+        );
+        tokio::time::sleep_until(slot).await
+    }
+}
+
+/// Uniform in [0, 1)
+fn jitter() -> f64 {
+    // each RandomState is freshly keyed
+    (RandomState::new().build_hasher().finish() >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,98 @@
+//! Typed calls to component RPCs.
+//!
+//! Each RPC is a request struct implementing `Rpc`, which names the proc,
+//! turns the fields into the proc's arguments, and fixes the response type,
+//! so a call site can't misspell an argument or decode the reply as the
+//! wrong thing.  `RpcClient` makes the call under a base path with a
+//! timeout, and retries failed calls; an error returned by the proc itself
+//! is not retried.
+
+use anyhow::{bail, Result};
+use log::debug;
+use netidx::{
+    path::Path,
+    publisher::FromValue,
+    subscriber::{Subscriber, Value},
+};
+use netidx_protocols::rpc::client::Proc;
+use std::time::Duration;
+
+pub trait Rpc: Clone {
+    /// the proc's path relative to the component base
+    const NAME: &'static str;
+    type Response: FromValue;
+
+    fn args(self) -> Result<Vec<(&'static str, Value)>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct RpcClient {
+    subscriber: Subscriber,
+    base: Path,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl RpcClient {
+    /// Call procs under `base`, with a 10s timeout and no retries
+    pub fn new(subscriber: &Subscriber, base: Path) -> Self {
+        Self {
+            subscriber: subscriber.clone(),
+            base,
+            timeout: Duration::from_secs(10),
+            retries: 0,
+            backoff: Duration::from_millis(250),
+        }
+    }
+
+    /// The timeout for resolving the proc and for each call attempt
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Retry failed calls up to `retries` times, doubling `backoff` after
+    /// each attempt
+    pub fn with_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    pub async fn call<R: Rpc>(&self, req: R) -> Result<R::Response> {
+        let path = self.base.append(R::NAME);
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        let res = loop {
+            let res = async {
+                let proc =
+                    Proc::new_with_timeout(&self.subscriber, path.clone(), self.timeout)?;
+                let args = req.clone().args()?;
+                match tokio::time::timeout(self.timeout, proc.call(args)).await {
+                    Ok(res) => res,
+                    Err(_) => bail!("timed out"),
+                }
+            }
+            .await;
+            match res {
+                Ok(v) => break v,
+                Err(e) if attempt < self.retries => {
+                    debug!("rpc {path} failed, retrying in {backoff:?}: {e:?}");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => bail!("rpc {path} failed: {e:?}"),
+            }
+        };
+        match res {
+            Value::Error(e) => bail!("rpc {path} error: {e}"),
+            v => R::Response::from_value(v),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Segmented journals, so recording can run indefinitely on bounded disk.
+//!
+//! A `SegmentDir` is a directory of journal segments named by the time they
+//! were started.  Writers roll to a new segment according to a
+//! `RollPolicy`; the newest segment is the open one and everything before
+//! it is closed.  Closed segments can be compacted, rewritten with strong
+//! zstd compression (`netidx` feature), and deleted once they fall outside
+//! a `RetentionPolicy`.  `open_segment` reads a segment whether or not it
+//! was compacted, and `SegmentDir::verify` runs a journal's integrity check
+//! over every segment.
+//!
+//! The book journal (`marketdata::journal`) and the order audit journal
+//! (`orderflow::audit`) both have rolling writers on top of this.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use log::{info, warn};
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+const COMPACTED: &str = ".zst";
+
+/// When a writer should start a new segment
+#[derive(Debug, Clone, Copy)]
+pub struct RollPolicy {
+    pub max_bytes: u64,
+    pub max_age: Duration,
+}
+
+impl Default for RollPolicy {
+    fn default() -> Self {
+        Self { max_bytes: 256 * 1024 * 1024, max_age: Duration::hours(1) }
+    }
+}
+
+impl RollPolicy {
+    pub fn due(&self, bytes: u64, started: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        bytes >= self.max_bytes || now - started >= self.max_age
+    }
+}
+
+/// Which closed segments to keep; a segment is deleted when either limit
+/// is exceeded.  The open segment is never deleted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetentionPolicy {
+    /// delete segments whose newest data is older than this
+    pub max_age: Option<Duration>,
+    /// delete the oldest segments until the directory is at most this size
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub path: PathBuf,
+    pub started: DateTime<Utc>,
+    pub bytes: u64,
+    pub compacted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SegmentDir {
+    dir: PathBuf,
+    prefix: String,
+    extension: String,
+}
+
+impl SegmentDir {
+    /// Segments are named `<prefix>-<start ns><extension>` in `dir`, which
+    /// is created if needed
+    pub fn open(
+        dir: impl AsRef<Path>,
+        prefix: impl Into<String>,
+        extension: impl Into<String>,
+    ) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, prefix: prefix.into(), extension: extension.into() })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The path for a new segment started at `now`
+    pub fn segment_path(&self, now: DateTime<Utc>) -> PathBuf {
+        let ns = now.timestamp_nanos_opt().unwrap_or(i64::MAX);
+        self.dir.join(format!("{}-{ns:020}{}", self.prefix, self.extension))
+    }
+
+    fn parse(&self, name: &str) -> Option<(DateTime<Utc>, bool)> {
+        let (name, compacted) = match name.strip_suffix(COMPACTED) {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let ns = name
+            .strip_prefix(self.prefix.as_str())?
+            .strip_prefix('-')?
+            .strip_suffix(self.extension.as_str())?;
+        Some((Utc.timestamp_nanos(ns.parse().ok()?), compacted))
+    }
+
+    /// Every segment, oldest first; the last one is the open segment
+    pub fn segments(&self) -> Result<Vec<Segment>> {
+        let mut res = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some((started, compacted)) = name.to_str().and_then(|n| self.parse(n))
+            else {
+                continue;
+            };
+            let bytes = entry.metadata()?.len();
+            res.push(Segment { path: entry.path(), started, bytes, compacted });
+        }
+        res.sort_by_key(|s| s.started);
+        Ok(res)
+    }
+
+    /// Every segment except the open one, oldest first
+    pub fn closed(&self) -> Result<Vec<Segment>> {
+        let mut segments = self.segments()?;
+        segments.pop();
+        Ok(segments)
+    }
+
+    /// Delete closed segments outside the policy, returning their paths
+    pub fn apply_retention(
+        &self,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<PathBuf>> {
+        let segments = self.segments()?;
+        let mut total: u64 = segments.iter().map(|s| s.bytes).sum();
+        let mut deleted = vec![];
+        // a closed segment ends where the next one starts
+        for (seg, next) in segments.iter().zip(segments.iter().skip(1)) {
+            let expired = policy.max_age.map(|a| now - next.started > a).unwrap_or(false);
+            let oversize = policy.max_bytes.map(|m| total > m).unwrap_or(false);
+            if !expired && !oversize {
+                break;
+            }
+            fs::remove_file(&seg.path)?;
+            info!("retention deleted journal segment {}", seg.path.display());
+            total -= seg.bytes;
+            deleted.push(seg.path.clone());
+        }
+        Ok(deleted)
+    }
+
+    /// Rewrite closed segments with zstd at `level` (up to 22), returning
+    /// the compacted paths.  Each is written to a temporary file, synced,
+    /// and renamed over before the original is removed, so a crash never
+    /// loses a segment.
+    #[cfg(feature = "netidx")]
+    pub fn compact(&self, level: i32) -> Result<Vec<PathBuf>> {
+        use std::io::Write;
+        let mut res = vec![];
+        for seg in self.closed()? {
+            if seg.compacted {
+                continue;
+            }
+            let mut name = seg.path.clone().into_os_string();
+            name.push(COMPACTED);
+            let path = PathBuf::from(name);
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            let tmp = PathBuf::from(tmp);
+            let mut rd = BufReader::new(File::open(&seg.path)?);
+            let mut enc = zstd::stream::write::Encoder::new(File::create(&tmp)?, level)?;
+            std::io::copy(&mut rd, &mut enc)?;
+            let mut file = enc.finish()?;
+            file.flush()?;
+            file.sync_all()?;
+            fs::rename(&tmp, &path)?;
+            fs::remove_file(&seg.path)?;
+            res.push(path);
+        }
+        Ok(res)
+    }
+
+    /// Run a journal's integrity check, returning the number of records it
+    /// read, over every segment.  The open segment is checked too, so a
+    /// partially written last record there is reported as an error.
+    pub fn verify(
+        &self,
+        check: impl Fn(Box<dyn Read + Send>) -> Result<u64>,
+    ) -> Result<Vec<(Segment, Result<u64>)>> {
+        let mut res = vec![];
+        for seg in self.segments()? {
+            let r = open_segment(&seg.path).and_then(&check);
+            if let Err(e) = &r {
+                warn!("journal segment {} failed verification: {e}", seg.path.display());
+            }
+            res.push((seg, r));
+        }
+        Ok(res)
+    }
+}
+
+/// Open a segment for reading, decompressing it if it was compacted
+pub fn open_segment(path: impl AsRef<Path>) -> Result<Box<dyn Read + Send>> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    let compacted = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("invalid segment path {}", path.display()))?
+        .ends_with(COMPACTED);
+    if !compacted {
+        return Ok(Box::new(BufReader::new(file)));
+    }
+    #[cfg(feature = "netidx")]
+    {
+        Ok(Box::new(zstd::stream::read::Decoder::new(file)?))
+    }
+    #[cfg(not(feature = "netidx"))]
+    anyhow::bail!(
+        "reading compacted segment {} requires the netidx feature",
+        path.display()
+    )
+}
@@ -0,0 +1,184 @@
+//! Coordinated shutdown.  Subsystems register teardown hooks against a
+//! stage; on `run` (or a signal, see `run_on_signal`) the stages execute in
+//! order, each hook bounded by its stage's timeout, and a report of what
+//! happened is returned.
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use log::{info, warn};
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+/// Teardown stages, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    StopOrders,
+    CancelWorkingOrders,
+    Flush,
+    UnsubscribeMarketdata,
+    CloseChannels,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::StopOrders,
+        Stage::CancelWorkingOrders,
+        Stage::Flush,
+        Stage::UnsubscribeMarketdata,
+        Stage::CloseChannels,
+    ];
+}
+
+#[derive(Debug, Clone)]
+pub enum HookOutcome {
+    Ok,
+    Failed(String),
+    TimedOut,
+    /// the stage was disabled, e.g. cancel working orders
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct HookReport {
+    pub stage: Stage,
+    pub name: String,
+    pub outcome: HookOutcome,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownReport {
+    pub hooks: Vec<HookReport>,
+}
+
+impl ShutdownReport {
+    pub fn is_clean(&self) -> bool {
+        self.hooks
+            .iter()
+            .all(|h| matches!(h.outcome, HookOutcome::Ok | HookOutcome::Skipped))
+    }
+}
+
+type Hook = Box<dyn FnOnce() -> BoxFuture<'static, Result<()>> + Send>;
+
+pub struct Shutdown {
+    hooks: Vec<(Stage, String, Hook)>,
+    timeouts: Vec<(Stage, Duration)>,
+    default_timeout: Duration,
+    cancel_working_orders: bool,
+    tx: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (tx, _) = watch::channel(false);
+        Self {
+            hooks: vec![],
+            timeouts: vec![],
+            default_timeout: Duration::from_secs(5),
+            cancel_working_orders: true,
+            tx,
+        }
+    }
+
+    /// Whether the cancel working orders stage runs, defaults to true
+    pub fn cancel_working_orders(&mut self, cancel: bool) -> &mut Self {
+        self.cancel_working_orders = cancel;
+        self
+    }
+
+    pub fn stage_timeout(&mut self, stage: Stage, timeout: Duration) -> &mut Self {
+        self.timeouts.retain(|(s, _)| *s != stage);
+        self.timeouts.push((stage, timeout));
+        self
+    }
+
+    /// Register a teardown hook; hooks in the same stage run concurrently
+    pub fn on<F, Fut>(&mut self, stage: Stage, name: impl Into<String>, f: F) -> &mut Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.hooks.push((stage, name.into(), Box::new(move || Box::pin(f()))));
+        self
+    }
+
+    /// Becomes true when shutdown starts; order entry paths should check it
+    /// and refuse new orders.
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.tx.subscribe()
+    }
+
+    fn timeout(&self, stage: Stage) -> Duration {
+        self.timeouts
+            .iter()
+            .find(|(s, _)| *s == stage)
+            .map(|(_, t)| *t)
+            .unwrap_or(self.default_timeout)
+    }
+
+    /// Run every stage in order and report the outcome of each hook
+    pub async fn run(mut self) -> ShutdownReport {
+        self.tx.send_replace(true);
+        let mut report = ShutdownReport::default();
+        let mut hooks = std::mem::take(&mut self.hooks);
+        for stage in Stage::ALL {
+            let timeout = self.timeout(stage);
+            let (now, later) = hooks.into_iter().partition(|(s, _, _)| *s == stage);
+            hooks = later;
+            let now: Vec<(Stage, String, Hook)> = now;
+            if stage == Stage::CancelWorkingOrders && !self.cancel_working_orders {
+                for (stage, name, _) in now {
+                    report.hooks.push(HookReport {
+                        stage,
+                        name,
+                        outcome: HookOutcome::Skipped,
+                        elapsed: Duration::ZERO,
+                    });
+                }
+                continue;
+            }
+            info!("shutdown: {stage:?}");
+            let running = now.into_iter().map(|(stage, name, hook)| async move {
+                let start = Instant::now();
+                let outcome = match tokio::time::timeout(timeout, hook()).await {
+                    Ok(Ok(())) => HookOutcome::Ok,
+                    Ok(Err(e)) => HookOutcome::Failed(e.to_string()),
+                    Err(_) => HookOutcome::TimedOut,
+                };
+                if !matches!(outcome, HookOutcome::Ok) {
+                    warn!("shutdown: {stage:?} {name}: {outcome:?}");
+                }
+                HookReport { stage, name, outcome, elapsed: start.elapsed() }
+            });
+            report.hooks.extend(futures::future::join_all(running).await);
+        }
+        report
+    }
+
+    /// Wait for ctrl-c (or SIGTERM on unix), then `run`
+    pub async fn run_on_signal(self) -> Result<ShutdownReport> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut term = signal(SignalKind::terminate())?;
+            tokio::select! {
+                r = tokio::signal::ctrl_c() => r?,
+                _ = term.recv() => (),
+            }
+        }
+        #[cfg(not(unix))]
+        tokio::signal::ctrl_c().await?;
+        info!("shutdown: signal received");
+        Ok(self.run().await)
+    }
+}
@@ -0,0 +1,83 @@
+//! A resolver for hot loops that look up the same few symbols over and
+//! over.
+//!
+//! `StaticRef::get` loads the global map and searches it on every call.
+//! A `SymbolCache` pins the refs it has resolved in a local hash map and
+//! only checks a single atomic, the symbology commit epoch, per lookup;
+//! after any commit it drops everything it pinned and resolves afresh, so
+//! renamed or removed symbols are never served stale.
+
+use super::{commit_epoch, MarketRef, ProductRef, RouteRef, StaticRef, VenueRef};
+use anyhow::{anyhow, Result};
+use fxhash::FxHashMap;
+
+pub struct SymbolCache<R> {
+    epoch: u64,
+    /// misses are cached too, until the next commit
+    pinned: FxHashMap<String, Option<R>>,
+    lookup: fn(&str) -> Option<R>,
+}
+
+impl<R: Copy> SymbolCache<R> {
+    pub fn new(lookup: fn(&str) -> Option<R>) -> Self {
+        Self { epoch: commit_epoch(), pinned: FxHashMap::default(), lookup }
+    }
+
+    pub fn get(&mut self, name: &str) -> Option<R> {
+        let epoch = commit_epoch();
+        if epoch != self.epoch {
+            self.pinned.clear();
+            self.epoch = epoch;
+        }
+        if let Some(r) = self.pinned.get(name) {
+            return *r;
+        }
+        let r = (self.lookup)(name);
+        self.pinned.insert(name.to_string(), r);
+        r
+    }
+
+    pub fn find(&mut self, name: &str) -> Result<R> {
+        self.get(name).ok_or_else(|| anyhow!("missing symbol: {name}"))
+    }
+
+    /// Resolve the names ahead of time, so the first lookup in the hot
+    /// loop doesn't pay for the search
+    pub fn pin<'a>(&mut self, names: impl IntoIterator<Item = &'a str>) {
+        for name in names {
+            self.get(name);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pinned.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pinned.is_empty()
+    }
+}
+
+impl SymbolCache<MarketRef> {
+    pub fn markets() -> Self {
+        Self::new(MarketRef::get)
+    }
+}
+
+impl SymbolCache<ProductRef> {
+    pub fn products() -> Self {
+        Self::new(ProductRef::get)
+    }
+}
+
+impl SymbolCache<VenueRef> {
+    pub fn venues() -> Self {
+        Self::new(VenueRef::get)
+    }
+}
+
+impl SymbolCache<RouteRef> {
+    pub fn routes() -> Self {
+        Self::new(RouteRef::get)
+    }
+}
@@ -0,0 +1,98 @@
+//! Re-serve this process's symbology over gRPC.
+//!
+//! `SymbologyService` answers the standard symbology gRPC protocol from the
+//! global symbology already loaded in memory, so internal tools can point
+//! `ArchitectClient::load_symbology_from` at a strategy node instead of the
+//! central service.  Embed it in an existing tonic server with
+//! `into_server`, or run it standalone with `serve`.
+//!
+//! Like `SymbologyProvider::serve_local`, the snapshot is rebuilt only when
+//! the global symbology has changed since it was last served.
+
+use super::{MarketIndex, MarketRef, ProductRef, RouteRef, StaticRef, VenueRef};
+use anyhow::Result;
+use api::{
+    external::symbology::{SymbologySnapshot, SymbologySnapshotRequest},
+    grpc::json_service::symbology_server::{Symbology, SymbologyServer},
+};
+use chrono::Utc;
+use fxhash::FxHashSet;
+use log::info;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{sync::Mutex, task};
+use tonic::{transport::Server, Request, Response, Status};
+
+#[derive(Default)]
+pub struct SymbologyService {
+    cache: Mutex<Option<(Arc<MarketIndex>, Arc<SymbologySnapshot>)>>,
+}
+
+/// Products in an order `Txn::add_product` accepts, underlyings before
+/// the products that reference them
+fn add_product(
+    seen: &mut FxHashSet<ProductRef>,
+    products: &mut Vec<api::symbology::Product>,
+    product: ProductRef,
+) {
+    if !seen.insert(product) {
+        return;
+    }
+    product.iter_references(|p| add_product(seen, products, p));
+    products.push((&product).into());
+}
+
+fn snapshot() -> SymbologySnapshot {
+    let routes =
+        (&*RouteRef::all_by_id()).into_iter().map(|(_, r)| (**r).clone()).collect();
+    let venues =
+        (&*VenueRef::all_by_id()).into_iter().map(|(_, v)| (**v).clone()).collect();
+    let mut seen = FxHashSet::default();
+    let mut products = vec![];
+    for (_, product) in &*ProductRef::all_by_id() {
+        add_product(&mut seen, &mut products, *product);
+    }
+    let markets =
+        (&*MarketRef::all_by_id()).into_iter().map(|(_, m)| (*m).into()).collect();
+    SymbologySnapshot { epoch: Utc::now(), routes, venues, products, markets }
+}
+
+impl SymbologyService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_server(self) -> SymbologyServer<Self> {
+        SymbologyServer::new(self)
+    }
+
+    /// Serve symbology at `addr` until the server fails
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        info!("serving symbology over grpc at {addr}");
+        Server::builder().add_service(self.into_server()).serve(addr).await?;
+        Ok(())
+    }
+
+    async fn current(&self) -> Result<Arc<SymbologySnapshot>> {
+        let mut cache = self.cache.lock().await;
+        let index = Arc::clone(&MarketIndex::current());
+        match &*cache {
+            Some((cached, snap)) if Arc::ptr_eq(cached, &index) => Ok(snap.clone()),
+            _ => {
+                let snap = Arc::new(task::spawn_blocking(snapshot).await?);
+                *cache = Some((index, snap.clone()));
+                Ok(snap)
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Symbology for SymbologyService {
+    async fn symbology_snapshot(
+        &self,
+        _request: Request<SymbologySnapshotRequest>,
+    ) -> Result<Response<SymbologySnapshot>, Status> {
+        let snap = self.current().await.map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new((*snap).clone()))
+    }
+}
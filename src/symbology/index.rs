@@ -358,6 +358,17 @@ impl MarketIndex {
         self.all.clone()
     }
 
+    /// Return all markets listed on the venue
+    pub fn by_venue(&self, venue: &VenueRef) -> Set<MarketRef> {
+        self.by_venue.get(venue).cloned().unwrap_or_else(Set::new)
+    }
+
+    /// Return all markets whose base product has the given underlying;
+    /// for spreads this includes both legs.
+    pub fn by_underlying(&self, underlying: &ProductRef) -> Set<MarketRef> {
+        self.by_underlying.get(underlying).cloned().unwrap_or_else(Set::new)
+    }
+
     pub fn find_exactly_one_by_exchange_symbol<S: AsRef<str> + Ord>(
         &self,
         venue: VenueRef,
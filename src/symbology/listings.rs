@@ -0,0 +1,137 @@
+//! Discover new listings and delistings per venue.
+//!
+//! A `ListingWatcher` periodically lists the markets on each watched venue
+//! from the current symbology, diffs them against the previous listing and
+//! broadcasts a `ListingEvent` for every market that appeared or went away.
+//! The first listing of a venue is the baseline and produces no events.
+//!
+//! With `auto_subscribe`, new listings are checked against a `Universe` and
+//! flagged `in_universe`; `UniverseSubscription::on_listing` subscribes
+//! them.
+
+use super::{
+    commit_epoch,
+    index::Set,
+    universe::{Universe, UniverseDiff},
+    MarketIndex, MarketRef, VenueRef,
+};
+use log::{info, warn};
+use std::{collections::BTreeMap, time::Duration};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone)]
+pub enum ListingEvent {
+    NewListing {
+        venue: VenueRef,
+        market: MarketRef,
+        /// the market is in the auto subscribe universe
+        in_universe: bool,
+    },
+    Delisting {
+        venue: VenueRef,
+        market: MarketRef,
+    },
+}
+
+impl ListingEvent {
+    pub fn market(&self) -> MarketRef {
+        match self {
+            ListingEvent::NewListing { market, .. }
+            | ListingEvent::Delisting { market, .. } => *market,
+        }
+    }
+}
+
+pub struct ListingWatcher {
+    venues: Vec<VenueRef>,
+    last: BTreeMap<VenueRef, Set<MarketRef>>,
+    /// the symbology commit epoch of the last listing
+    last_epoch: Option<u64>,
+    auto_subscribe: Option<Universe>,
+    tx: broadcast::Sender<ListingEvent>,
+}
+
+impl ListingWatcher {
+    pub fn new(venues: impl IntoIterator<Item = VenueRef>) -> Self {
+        let (tx, _) = broadcast::channel(1000);
+        Self {
+            venues: venues.into_iter().collect(),
+            last: BTreeMap::new(),
+            last_epoch: None,
+            auto_subscribe: None,
+            tx,
+        }
+    }
+
+    /// Flag new listings that resolve into `universe`, e.g. a query for all
+    /// perpetuals on a venue
+    pub fn auto_subscribe(mut self, universe: Universe) -> Self {
+        self.auto_subscribe = Some(universe);
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ListingEvent> {
+        self.tx.subscribe()
+    }
+
+    /// List every watched venue now and return the changes since the last
+    /// listing; they are also broadcast to subscribers
+    pub fn poll(&mut self) -> Vec<ListingEvent> {
+        let epoch = commit_epoch();
+        if self.last_epoch == Some(epoch) {
+            // symbology hasn't changed
+            return vec![];
+        }
+        self.last_epoch = Some(epoch);
+        let index = MarketIndex::current();
+        let mut events = vec![];
+        for venue in &self.venues {
+            let listed = index.by_venue(venue);
+            let prev = match self.last.insert(*venue, listed.clone()) {
+                None => continue,
+                Some(prev) => prev,
+            };
+            let diff = UniverseDiff::new(&prev, &listed);
+            for market in diff.removed {
+                events.push(ListingEvent::Delisting { venue: *venue, market });
+            }
+            if diff.added.is_empty() {
+                continue;
+            }
+            let universe = match self.auto_subscribe.as_ref().map(|u| u.resolve()) {
+                None => None,
+                Some(Ok(markets)) => Some(markets),
+                Some(Err(e)) => {
+                    warn!("resolving auto subscribe universe: {e:?}");
+                    None
+                }
+            };
+            for market in diff.added {
+                let in_universe = universe.as_ref().is_some_and(|u| u.contains(&market));
+                events.push(ListingEvent::NewListing {
+                    venue: *venue,
+                    market,
+                    in_universe,
+                });
+            }
+        }
+        for ev in &events {
+            info!("{ev:?}");
+            let _ = self.tx.send(ev.clone());
+        }
+        events
+    }
+
+    /// Poll every `interval` until there are no subscribers left
+    pub async fn run(mut self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.poll();
+            if self.tx.receiver_count() == 0 {
+                break;
+            }
+        }
+    }
+}
@@ -73,6 +73,14 @@ impl MarketRef {
             None
         }
     }
+
+    pub fn quote(&self) -> Option<ProductRef> {
+        if let MarketKind::Exchange(ExchangeMarketKind { quote, .. }) = &self.kind {
+            Some(*quote)
+        } else {
+            None
+        }
+    }
 }
 
 impl From<MarketRef> for api::symbology::Market {
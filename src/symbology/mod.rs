@@ -1,19 +1,32 @@
 use arc_swap::ArcSwap;
 use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::watch;
 
 pub(self) mod allocator;
+pub mod cache;
 #[cfg(feature = "netidx")]
 pub mod client;
 pub mod cpty;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod external_client;
+#[cfg(feature = "symbology-server")]
+pub mod grpc_server;
 pub mod index;
+pub mod listings;
 pub mod market;
+pub mod partial;
 pub mod product;
+#[cfg(feature = "netidx")]
+pub mod provider;
+pub mod resolve;
 pub mod route;
 pub mod static_ref;
 pub mod txn;
+pub mod universe;
 pub mod venue;
 
+pub use cache::SymbolCache;
 pub use cpty::Cpty;
 pub use index::MarketIndex;
 pub use market::{MarketKind, MarketRef};
@@ -25,3 +38,61 @@ pub use venue::VenueRef;
 
 pub static GLOBAL_INDEX: Lazy<ArcSwap<MarketIndex>> =
     Lazy::new(|| ArcSwap::from_pointee(MarketIndex::new()));
+
+static COMMIT_EPOCH: AtomicU64 = AtomicU64::new(0);
+static COMMITS: Lazy<watch::Sender<u64>> = Lazy::new(|| watch::channel(0).0);
+
+/// The number of symbology transactions committed so far; a cheap way to
+/// tell whether anything resolved earlier may be stale
+pub fn commit_epoch() -> u64 {
+    COMMIT_EPOCH.load(Ordering::Acquire)
+}
+
+/// Notified with the new epoch after each symbology commit
+pub fn subscribe_commits() -> watch::Receiver<u64> {
+    COMMITS.subscribe()
+}
+
+static CONTENT_HASH: AtomicU64 = AtomicU64::new(0);
+
+/// The content hash of the committed symbology, see `Txn::content_hash`
+pub fn content_hash() -> u64 {
+    CONTENT_HASH.load(Ordering::Acquire)
+}
+
+pub(crate) fn set_content_hash(hash: u64) {
+    CONTENT_HASH.store(hash, Ordering::Release);
+}
+
+pub(crate) fn notify_commit() {
+    let epoch = COMMIT_EPOCH.fetch_add(1, Ordering::AcqRel) + 1;
+    COMMITS.send_replace(epoch);
+}
+
+/// A market `name` trading fiat `base` for fiat `quote` on the TEST venue,
+/// for tests; the transaction adding it isn't committed
+#[cfg(test)]
+pub(crate) fn test_market(
+    name: &str,
+    base: &str,
+    quote: &str,
+) -> anyhow::Result<MarketRef> {
+    use api::symbology::{market::TestMarketInfo, MarketInfo};
+    let mut txn = Txn::begin();
+    let direct = txn.add_route(RouteRef::new("DIRECT")?)?;
+    let test = txn.add_venue(VenueRef::new("TEST")?)?;
+    let base = txn.add_product(ProductRef::new(base, ProductKind::Fiat)?)?;
+    let quote = txn.add_product(ProductRef::new(quote, ProductKind::Fiat)?)?;
+    txn.add_market(MarketRef::exchange(
+        base,
+        quote,
+        test,
+        direct,
+        name,
+        MarketInfo::Test(TestMarketInfo {
+            tick_size: Default::default(),
+            step_size: Default::default(),
+            is_delisted: false,
+        }),
+    )?)
+}
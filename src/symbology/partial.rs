@@ -0,0 +1,300 @@
+//! Partial symbology universes, for services that only ever touch a slice
+//! of the markets.
+//!
+//! Install a `SymbologyFilter` with `set_filter` before loading symbology
+//! and every market update applied through `Txn::apply` (snapshots, the
+//! symbology client, a local provider) is checked against it; markets it
+//! rejects are never hydrated into the global store.  Venues, routes and
+//! products are always loaded, since markets and other products refer to
+//! them.  Markets added directly with `Txn::add_market` bypass the filter.
+//!
+//! Markets outside the filter can still be pulled in when they turn up at
+//! runtime, e.g. in a fill: `get_or_fetch` asks the `MarketFetcher`
+//! installed with `set_fetcher` for the definition and adds it.
+
+use super::{market::MarketInner, MarketKind, MarketRef, StaticRef, Txn};
+use anyhow::{anyhow, Result};
+use api::symbology::{
+    query::{DateQ, Query},
+    Symbolic,
+};
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use log::debug;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+static FILTER: Lazy<ArcSwapOption<SymbologyFilter>> = Lazy::new(ArcSwapOption::empty);
+static FETCHER: Lazy<ArcSwapOption<Box<dyn MarketFetcher>>> =
+    Lazy::new(ArcSwapOption::empty);
+static FILTERED: AtomicU64 = AtomicU64::new(0);
+
+/// A compiled symbology query, evaluated against one market at a time
+#[derive(Debug, Clone)]
+enum Pred {
+    And(Vec<Pred>),
+    Or(Vec<Pred>),
+    Not(Box<Pred>),
+    All,
+    Regex(Regex),
+    Base(String),
+    BaseKind(String),
+    Quote(String),
+    Pool(String),
+    Route(String),
+    Venue(String),
+    ExchangeSymbol(String),
+    Underlying(String),
+    Expiration(DateQ),
+}
+
+impl Pred {
+    fn compile(q: &Query) -> Result<Self> {
+        Ok(match q {
+            Query::And(terms) => {
+                Pred::And(terms.iter().map(Self::compile).collect::<Result<_>>()?)
+            }
+            Query::Or(terms) => {
+                Pred::Or(terms.iter().map(Self::compile).collect::<Result<_>>()?)
+            }
+            Query::Not(term) => Pred::Not(Box::new(Self::compile(term)?)),
+            Query::All => Pred::All,
+            Query::Regex(s) => Pred::Regex(Regex::new(s.as_str())?),
+            Query::Base(s) => Pred::Base(s.to_string()),
+            Query::BaseKind(s) => Pred::BaseKind(s.to_string()),
+            Query::Quote(s) => Pred::Quote(s.to_string()),
+            Query::Pool(s) => Pred::Pool(s.to_string()),
+            Query::Route(s) => Pred::Route(s.to_string()),
+            Query::Venue(s) => Pred::Venue(s.to_string()),
+            Query::ExchangeSymbol(s) => Pred::ExchangeSymbol(s.to_string()),
+            Query::Underlying(s) => Pred::Underlying(s.to_string()),
+            Query::Expiration(dq) => Pred::Expiration(dq.clone()),
+        })
+    }
+
+    /// The same semantics as `MarketIndex::query`, for a single market
+    fn matches(&self, m: &MarketInner) -> bool {
+        fn is<T: Symbolic>(t: &T, s: &str) -> bool {
+            t.name().as_str() == s || t.id().to_string() == s
+        }
+        let base = match &m.kind {
+            MarketKind::Exchange(x) => Some(x.base),
+            _ => None,
+        };
+        match self {
+            Pred::And(terms) => terms.iter().all(|t| t.matches(m)),
+            Pred::Or(terms) => terms.iter().any(|t| t.matches(m)),
+            Pred::Not(term) => !term.matches(m),
+            Pred::All => true,
+            Pred::Regex(re) => re.is_match(m.name.as_str()),
+            Pred::Base(s) => base.is_some_and(|p| is(&*p, s)),
+            Pred::BaseKind(s) => base.is_some_and(|p| p.kind.name() == s),
+            Pred::Quote(s) => match &m.kind {
+                MarketKind::Exchange(x) => is(&*x.quote, s),
+                _ => false,
+            },
+            Pred::Pool(s) => match &m.kind {
+                MarketKind::Pool(p) => p.products.iter().any(|p| is(&**p, s)),
+                _ => false,
+            },
+            Pred::Route(s) => is(&*m.route, s),
+            Pred::Venue(s) => is(&*m.venue, s),
+            Pred::ExchangeSymbol(s) => m.exchange_symbol.as_str() == s,
+            Pred::Underlying(s) => {
+                base.and_then(|p| p.kind.underlying()).is_some_and(|u| is(&*u, s))
+            }
+            Pred::Expiration(dq) => {
+                let Some(e) = base.and_then(|p| p.kind.expiration()) else {
+                    return false;
+                };
+                let day = |d: &DateTime<Utc>| d.naive_utc().date();
+                match dq {
+                    DateQ::On(d) => day(&e) == day(d),
+                    DateQ::OnOrAfter(d) => day(&e) >= day(d),
+                    DateQ::OnOrBefore(d) => day(&e) <= day(d),
+                    DateQ::Between(st, en) => day(&e) >= day(st) && day(&e) <= day(en),
+                }
+            }
+        }
+    }
+}
+
+/// Which markets to load into this process
+#[derive(Debug, Clone)]
+pub struct SymbologyFilter {
+    pred: Pred,
+}
+
+impl SymbologyFilter {
+    /// Load only the markets matching the query
+    pub fn query(q: &Query) -> Result<Self> {
+        Ok(Self { pred: Pred::compile(q)? })
+    }
+
+    /// Load only markets on these venues, by name or id
+    pub fn venues<S: AsRef<str>>(venues: impl IntoIterator<Item = S>) -> Self {
+        let terms = venues.into_iter().map(|v| Pred::Venue(v.as_ref().to_string()));
+        Self { pred: Pred::Or(terms.collect()) }
+    }
+
+    /// Load only markets whose base product is one of these kinds, e.g.
+    /// "Future" or "Equity"
+    pub fn base_kinds<S: AsRef<str>>(kinds: impl IntoIterator<Item = S>) -> Self {
+        let terms = kinds.into_iter().map(|k| Pred::BaseKind(k.as_ref().to_string()));
+        Self { pred: Pred::Or(terms.collect()) }
+    }
+
+    /// Load only markets matching both filters
+    pub fn and(self, other: SymbologyFilter) -> Self {
+        Self { pred: Pred::And(vec![self.pred, other.pred]) }
+    }
+
+    pub fn admits(&self, market: &MarketInner) -> bool {
+        self.pred.matches(market)
+    }
+}
+
+/// Restrict the markets loaded into the global symbology from now on, or
+/// lift the restriction with None.  Markets already loaded stay loaded.
+pub fn set_filter(filter: Option<SymbologyFilter>) {
+    FILTER.store(filter.map(Arc::new));
+}
+
+pub fn filter() -> Option<Arc<SymbologyFilter>> {
+    FILTER.load_full()
+}
+
+/// The number of market updates the filter has rejected so far
+pub fn filtered_count() -> u64 {
+    FILTERED.load(Ordering::Relaxed)
+}
+
+pub(super) fn note_filtered() {
+    FILTERED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A source of market definitions that weren't loaded, e.g. the symbology
+/// server
+pub trait MarketFetcher: Send + Sync {
+    /// Fetch the market with this name or id, or None if there is no such
+    /// market.  Its venue, route and products must already be loaded.
+    fn fetch(
+        &self,
+        name: &str,
+    ) -> BoxFuture<'static, Result<Option<api::symbology::Market>>>;
+}
+
+pub fn set_fetcher(fetcher: Option<Box<dyn MarketFetcher>>) {
+    FETCHER.store(fetcher.map(Arc::new));
+}
+
+/// Look up a market by name or id, fetching and adding it to the global
+/// symbology if it isn't loaded
+pub async fn get_or_fetch(name: &str) -> Result<MarketRef> {
+    if let Some(m) = MarketRef::get_by_name_or_id(name) {
+        return Ok(m);
+    }
+    let fetcher = FETCHER
+        .load_full()
+        .ok_or_else(|| anyhow!("missing market: {name}, and no fetcher is set"))?;
+    let market =
+        fetcher.fetch(name).await?.ok_or_else(|| anyhow!("no such market: {name}"))?;
+    debug!("fetched market {} on demand", market.name);
+    // another task may have fetched it while we waited
+    if let Some(m) = MarketRef::get_by_id(&market.id) {
+        return Ok(m);
+    }
+    let mut txn = Txn::begin();
+    let m = txn.add_market(market)?;
+    txn.commit()?;
+    Ok(m)
+}
+
+#[cfg(feature = "netidx")]
+pub use history::HistoryFetcher;
+
+#[cfg(feature = "netidx")]
+mod history {
+    use super::{super::txn::unpack_snapshot, MarketFetcher};
+    use anyhow::{bail, Result};
+    use api::symbology::{SymbologyUpdate, SymbologyUpdateKind};
+    use bytes::Buf;
+    use futures::future::BoxFuture;
+    use netidx::{
+        pack::Pack,
+        path::Path,
+        subscriber::{Subscriber, Value},
+    };
+    use netidx_protocols::{call_rpc, rpc::client::Proc};
+
+    /// Fetches markets by scanning the symbology server's update history.
+    /// This downloads the whole history, so it suits the occasional
+    /// stray symbol, not a hot path.
+    pub struct HistoryFetcher {
+        subscriber: Subscriber,
+        base: Path,
+    }
+
+    impl HistoryFetcher {
+        pub fn new(subscriber: Subscriber, base: Path) -> Self {
+            Self { subscriber, base }
+        }
+    }
+
+    fn scan(
+        found: &mut Option<api::symbology::Market>,
+        name: &str,
+        up: SymbologyUpdateKind,
+    ) -> Result<()> {
+        match up {
+            SymbologyUpdateKind::AddMarket(m)
+                if m.name.as_str() == name || m.id.to_string() == name =>
+            {
+                *found = Some(m)
+            }
+            SymbologyUpdateKind::RemoveMarket(id)
+                if found.as_ref().is_some_and(|m| m.id == id) =>
+            {
+                *found = None
+            }
+            SymbologyUpdateKind::Snapshot { original_length, compressed } => {
+                let mut updates = unpack_snapshot(original_length, &compressed[..])?;
+                for up in updates.drain(..) {
+                    scan(found, name, up)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    impl MarketFetcher for HistoryFetcher {
+        fn fetch(
+            &self,
+            name: &str,
+        ) -> BoxFuture<'static, Result<Option<api::symbology::Market>>> {
+            let subscriber = self.subscriber.clone();
+            let path = self.base.append("query-updates");
+            let name = name.to_string();
+            Box::pin(async move {
+                let query_updates = Proc::new(&subscriber, path)?;
+                let mut history = match call_rpc!(query_updates, end: Value::Null).await?
+                {
+                    Value::Bytes(history) => history,
+                    _ => bail!("protocol error"),
+                };
+                let mut found = None;
+                while history.has_remaining() {
+                    let u: SymbologyUpdate = Pack::decode(&mut history)?;
+                    scan(&mut found, &name, u.kind)?;
+                }
+                Ok(found)
+            })
+        }
+    }
+}
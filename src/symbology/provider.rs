@@ -0,0 +1,150 @@
+//! Warm-start symbology for short-lived processes.
+//!
+//! A long running process (or a dedicated daemon) that already has symbology
+//! loaded calls `SymbologyProvider::serve_local`; short-lived tools call
+//! `SymbologyProvider::attach_local` to copy the already hydrated universe
+//! over a unix socket instead of loading it from scratch on every run.
+//!
+//! The socket carries a single frame per connection: a big endian u32 length
+//! followed by the Pack encoded squashed snapshot from `Txn::dump_squashed`.
+
+use super::{MarketIndex, Txn};
+use anyhow::{bail, Result};
+use api::symbology::SymbologyUpdateKind;
+use bytes::{Bytes, BytesMut};
+use log::{debug, info};
+use netidx::pack::Pack;
+use std::{
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::Mutex,
+    task,
+};
+
+const MAX_SNAPSHOT_LEN: usize = 1 << 31;
+
+pub struct SymbologyProvider;
+
+impl SymbologyProvider {
+    /// `$ARCHITECT_SYMBOLOGY_SOCKET` if set, otherwise a socket in
+    /// `$XDG_RUNTIME_DIR`, falling back to the system temp dir
+    pub fn default_socket_path() -> PathBuf {
+        if let Some(path) = std::env::var_os("ARCHITECT_SYMBOLOGY_SOCKET") {
+            return PathBuf::from(path);
+        }
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        dir.join("architect-symbology.sock")
+    }
+
+    /// Load symbology from a local provider at the default socket path
+    pub async fn attach_local() -> Result<()> {
+        Self::attach_local_at(Self::default_socket_path()).await
+    }
+
+    pub async fn attach_local_at(path: impl AsRef<Path>) -> Result<()> {
+        let mut stream = UnixStream::connect(path.as_ref()).await?;
+        let len = stream.read_u32().await? as usize;
+        if len > MAX_SNAPSHOT_LEN {
+            bail!("symbology snapshot too large {len}");
+        }
+        let mut buf = BytesMut::zeroed(len);
+        stream.read_exact(&mut buf).await?;
+        let up: SymbologyUpdateKind = Pack::decode(&mut buf.freeze())?;
+        let (mut progress, mut load) = Txn::spawn_load(up);
+        loop {
+            tokio::select! {
+                res = &mut load => break res??,
+                Ok(()) = progress.changed() => {
+                    if let Some(p) = *progress.borrow_and_update() {
+                        debug!(
+                            "loading local symbology, {}/{} entities, eta {:?}",
+                            p.entities_applied,
+                            p.entities_total,
+                            p.eta()
+                        );
+                    }
+                }
+            }
+        }
+        debug!("attached to local symbology at {}", path.as_ref().display());
+        Ok(())
+    }
+
+    /// Try the local provider first, and fall back to `load` if there isn't
+    /// one running
+    pub async fn attach_local_or_else<F, Fut>(load: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<()>>,
+    {
+        match Self::attach_local().await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                debug!("no local symbology provider ({e:?}), loading");
+                load().await
+            }
+        }
+    }
+
+    /// Serve the current global symbology at `path` to local clients,
+    /// replacing a stale socket file but nothing else.  The snapshot is
+    /// re-encoded only when the global symbology has changed since it was
+    /// last served.
+    pub async fn serve_local(path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        match std::fs::symlink_metadata(path) {
+            Ok(md) if md.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => bail!("{} exists and is not a socket", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        let listener = UnixListener::bind(path)?;
+        info!("serving local symbology at {}", path.display());
+        let cache: Arc<Mutex<Option<(Arc<MarketIndex>, Bytes)>>> =
+            Arc::new(Mutex::new(None));
+        loop {
+            let (mut stream, _) = listener.accept().await?;
+            let cache = cache.clone();
+            task::spawn(async move {
+                let res = async {
+                    let snapshot = {
+                        let mut cache = cache.lock().await;
+                        let current = Arc::clone(&MarketIndex::current());
+                        match &*cache {
+                            Some((index, bytes)) if Arc::ptr_eq(index, &current) => {
+                                bytes.clone()
+                            }
+                            _ => {
+                                let bytes =
+                                    task::spawn_blocking(encode_snapshot).await??;
+                                *cache = Some((current, bytes.clone()));
+                                bytes
+                            }
+                        }
+                    };
+                    stream.write_u32(snapshot.len() as u32).await?;
+                    stream.write_all(&snapshot).await?;
+                    Ok::<_, anyhow::Error>(())
+                }
+                .await;
+                if let Err(e) = res {
+                    debug!("serving local symbology: {e:?}");
+                }
+            });
+        }
+    }
+}
+
+fn encode_snapshot() -> Result<Bytes> {
+    let (_, up) = Txn::begin().dump_squashed()?;
+    let mut buf = BytesMut::new();
+    Pack::encode(&up, &mut buf)?;
+    Ok(buf.freeze())
+}
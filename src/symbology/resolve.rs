@@ -0,0 +1,77 @@
+//! Forgiving symbol resolution for CLI tools and UIs: accept a market or
+//! product name or id, or an exchange symbol, and return the canonical
+//! product along with the markets it trades on.
+
+use super::{MarketIndex, MarketRef, ProductRef, StaticRef};
+use anyhow::{bail, Result};
+use api::symbology::query::Query;
+use itertools::Itertools;
+
+#[derive(Debug, Clone)]
+pub struct ResolvedSymbol {
+    pub product: ProductRef,
+    /// markets trading the product; if the input named a specific market
+    /// it comes first
+    pub markets: Vec<MarketRef>,
+}
+
+fn markets_for(product: ProductRef, first: Option<MarketRef>) -> ResolvedSymbol {
+    let index = MarketIndex::current();
+    let mut markets: Vec<MarketRef> = first.into_iter().collect();
+    for m in &index.query(&Query::Base(product.name.to_string())) {
+        if Some(*m) != first {
+            markets.push(*m);
+        }
+    }
+    ResolvedSymbol { product, markets }
+}
+
+fn unique_product(input: &str, markets: &[MarketRef]) -> Result<Option<ResolvedSymbol>> {
+    let products: Vec<ProductRef> =
+        markets.iter().filter_map(|m| m.base()).unique().collect();
+    match &products[..] {
+        [] => Ok(None),
+        [product] => {
+            let first = if markets.len() == 1 { Some(markets[0]) } else { None };
+            Ok(Some(markets_for(*product, first)))
+        }
+        products => bail!(
+            "{input} is ambiguous, it could be any of {}",
+            products.iter().map(|p| p.name.to_string()).join(", ")
+        ),
+    }
+}
+
+/// Resolve user input to a product and its markets, trying in order: a
+/// market name or id, a product name or id, an exact exchange symbol, the
+/// exchange symbol in upper case, and finally a case insensitive prefix of
+/// market names.
+pub fn resolve_symbol(input: &str) -> Result<ResolvedSymbol> {
+    let input = input.trim();
+    if input.is_empty() {
+        bail!("empty symbol");
+    }
+    if let Some(market) = MarketRef::get_by_name_or_id(input) {
+        if let Some(product) = market.base() {
+            return Ok(markets_for(product, Some(market)));
+        }
+    }
+    if let Some(product) = ProductRef::get_by_name_or_id(input) {
+        return Ok(markets_for(product, None));
+    }
+    let index = MarketIndex::current();
+    for symbol in [input.to_string(), input.to_uppercase()] {
+        let markets: Vec<MarketRef> =
+            index.query(&Query::ExchangeSymbol(symbol)).into_iter().copied().collect();
+        if let Some(resolved) = unique_product(input, &markets)? {
+            return Ok(resolved);
+        }
+    }
+    let prefix = format!("(?i)^{}", regex::escape(input));
+    let markets: Vec<MarketRef> =
+        index.query(&Query::Regex(prefix)).into_iter().copied().collect();
+    match unique_product(input, &markets)? {
+        Some(resolved) => Ok(resolved),
+        None => bail!("no product or market matches {input}"),
+    }
+}
@@ -1,5 +1,6 @@
 use super::{
-    market::*, product::*, route::*, venue::*, MarketIndex, StaticRef, GLOBAL_INDEX,
+    market::*, partial, product::*, route::*, venue::*, MarketIndex, StaticRef,
+    GLOBAL_INDEX,
 };
 use anyhow::{anyhow, bail, Result};
 #[cfg(feature = "netidx")]
@@ -18,16 +19,88 @@ use md5::{Digest, Md5};
 #[cfg(feature = "netidx")]
 use netidx::{pack::Pack, pool::Pooled};
 use parking_lot::{Mutex, MutexGuard};
+use serde::Serialize;
 use smallvec::SmallVec;
 #[cfg(feature = "netidx")]
-use std::cell::RefCell;
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant},
+};
 use std::{
     collections::BTreeMap,
+    io,
     sync::{atomic::Ordering, Arc},
 };
+#[cfg(feature = "netidx")]
+use tokio::{sync::watch, task::JoinHandle};
 
 static TXN_LOCK: Mutex<()> = Mutex::new(());
 
+/// Streaming dumps write in chunks of about this many bytes
+#[cfg(feature = "netidx")]
+const DUMP_CHUNK: usize = 64 * 1024;
+
+/// Snapshot loads report progress every this many updates
+#[cfg(feature = "netidx")]
+pub const PROGRESS_INTERVAL: usize = 10_000;
+
+/// The progress of applying an update, see `Txn::apply_with_progress`
+#[cfg(feature = "netidx")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub entities_applied: usize,
+    pub entities_total: usize,
+    pub bytes_decompressed: usize,
+    pub elapsed: Duration,
+}
+
+#[cfg(feature = "netidx")]
+impl LoadProgress {
+    /// Estimated time to finish, assuming the remaining updates apply at
+    /// the rate seen so far
+    pub fn eta(&self) -> Option<Duration> {
+        if self.entities_applied == 0 {
+            return None;
+        }
+        let remaining = self.entities_total.saturating_sub(self.entities_applied);
+        Some(self.elapsed.mul_f64(remaining as f64 / self.entities_applied as f64))
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.entities_applied >= self.entities_total
+    }
+}
+
+/// FNV-1a, stable across processes and versions unlike std's hashers
+struct Fnv(u64);
+
+impl io::Write for Fnv {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for b in buf {
+            self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The hash of one entity in its API form, tagged with its kind so equal
+/// encodings of different kinds don't collide
+fn entity_hash(kind: u8, entity: &impl Serialize) -> Result<u64> {
+    let mut h = Fnv(0xcbf29ce484222325);
+    io::Write::write_all(&mut h, &[kind])?;
+    serde_json::to_writer(&mut h, entity)?;
+    Ok(h.0)
+}
+
+const ROUTE: u8 = 0;
+const VENUE: u8 = 1;
+const PRODUCT: u8 = 2;
+const MARKET: u8 = 3;
+
 /// A symbology update transaction.
 pub struct Txn {
     venue_by_name: Arc<Map<Str, VenueRef>>,
@@ -39,6 +112,8 @@ pub struct Txn {
     market_by_name: Arc<Map<Str, MarketRef>>,
     market_by_id: Arc<Map<MarketId, MarketRef>>,
     index: Arc<MarketIndex>,
+    /// the wrapping sum of every entity's hash, see `content_hash`
+    content_hash: u64,
     corrupted: bool,
     num_products_added: usize,
     num_markets_added: usize,
@@ -70,6 +145,7 @@ impl Txn {
             market_by_name,
             market_by_id,
             index,
+            content_hash,
             corrupted,
             num_products_added,
             num_markets_added,
@@ -104,6 +180,8 @@ impl Txn {
         MARKET_REF_BY_NAME.store(Arc::clone(market_by_name));
         MARKET_REF_BY_ID.store(Arc::clone(market_by_id));
         GLOBAL_INDEX.store(Arc::clone(index));
+        super::set_content_hash(*content_hash);
+        super::notify_commit();
         Ok(())
     }
 
@@ -136,6 +214,7 @@ impl Txn {
             market_by_name: MARKET_REF_BY_NAME.load_full(),
             market_by_id: MARKET_REF_BY_ID.load_full(),
             index: GLOBAL_INDEX.load_full(),
+            content_hash: super::content_hash(),
             corrupted: false,
             num_products_added: 0,
             num_markets_added: 0,
@@ -156,6 +235,7 @@ impl Txn {
             market_by_name: Arc::new(Map::new()),
             market_by_id: Arc::new(Map::new()),
             index: Arc::new(MarketIndex::new()),
+            content_hash: 0,
             corrupted: false,
             num_products_added: 0,
             num_markets_added: 0,
@@ -177,6 +257,25 @@ impl Txn {
         TXN_LOCK.try_lock().map(Self::empty_inner)
     }
 
+    /// Begin a transaction, apply `up` and commit it on the blocking thread
+    /// pool, so loading a large snapshot doesn't stall the async runtime.
+    /// Progress is published on the returned watch channel, which holds
+    /// None until the load has started.
+    #[cfg(feature = "netidx")]
+    pub fn spawn_load(
+        up: SymbologyUpdateKind,
+    ) -> (watch::Receiver<Option<LoadProgress>>, JoinHandle<Result<()>>) {
+        let (tx, rx) = watch::channel(None);
+        let task = tokio::task::spawn_blocking(move || {
+            let mut txn = Txn::begin();
+            txn.apply_with_progress(&up, |p| {
+                let _ = tx.send(Some(*p));
+            })?;
+            txn.commit()
+        });
+        (rx, task)
+    }
+
     pub fn get_route_by_id(&self, id: &RouteId) -> Option<RouteRef> {
         self.route_by_id.get(id).copied()
     }
@@ -209,38 +308,83 @@ impl Txn {
         self.get_market_by_id(id).ok_or_else(|| anyhow!("no such market"))
     }
 
+    /// An order independent hash of everything in the transaction,
+    /// maintained as entities are added and removed, so two symbologies
+    /// can be compared for equality in O(1).  Equal symbologies have equal
+    /// hashes in any process; the hash is not collision resistant.
+    pub fn content_hash(&self) -> u64 {
+        self.content_hash
+    }
+
+    /// Check that this symbology matches one with the given content hash
+    pub fn verify_content_hash(&self, expected: u64) -> Result<()> {
+        if self.content_hash != expected {
+            bail!(
+                "symbology content hash {:016x} doesn't match expected {expected:016x}",
+                self.content_hash
+            );
+        }
+        Ok(())
+    }
+
+    /// Replace an entity's contribution to the content hash
+    fn rehash(&mut self, old: Option<u64>, new: Option<u64>) {
+        self.content_hash = self
+            .content_hash
+            .wrapping_sub(old.unwrap_or(0))
+            .wrapping_add(new.unwrap_or(0));
+    }
+
     pub fn add_route(&mut self, route: api::symbology::Route) -> Result<RouteRef> {
-        RouteRef::insert(
+        let old = match self.get_route_by_id(&route.id) {
+            Some(r) => Some(entity_hash(ROUTE, &*r)?),
+            None => None,
+        };
+        let route = RouteRef::insert(
             Arc::make_mut(&mut self.route_by_name),
             Arc::make_mut(&mut self.route_by_id),
             route,
             true,
-        )
+        )?;
+        self.rehash(old, Some(entity_hash(ROUTE, &*route)?));
+        Ok(route)
     }
 
     pub fn remove_route(&mut self, route: &RouteId) -> Result<()> {
         let route = self.find_route_by_id(route)?;
-        Ok(route.remove(
+        let old = entity_hash(ROUTE, &*route)?;
+        route.remove(
             Arc::make_mut(&mut self.route_by_name),
             Arc::make_mut(&mut self.route_by_id),
-        ))
+        );
+        self.rehash(Some(old), None);
+        Ok(())
     }
 
     pub fn add_venue(&mut self, venue: api::symbology::Venue) -> Result<VenueRef> {
-        VenueRef::insert(
+        let old = match self.get_venue_by_id(&venue.id) {
+            Some(v) => Some(entity_hash(VENUE, &*v)?),
+            None => None,
+        };
+        let venue = VenueRef::insert(
             Arc::make_mut(&mut self.venue_by_name),
             Arc::make_mut(&mut self.venue_by_id),
             venue,
             true,
-        )
+        )?;
+        self.rehash(old, Some(entity_hash(VENUE, &*venue)?));
+        Ok(venue)
     }
 
     pub fn remove_venue(&mut self, venue: &VenueId) -> Result<()> {
         let venue = self.find_venue_by_id(venue)?;
-        Ok(venue.remove(
+        let old = entity_hash(VENUE, &*venue)?;
+        venue.remove(
             Arc::make_mut(&mut self.venue_by_name),
             Arc::make_mut(&mut self.venue_by_id),
-        ))
+        );
+        self.rehash(Some(old), None);
+        Ok(())
     }
 
     pub fn add_product(
@@ -250,6 +394,10 @@ impl Txn {
         // manually construct the inner ref type, because we are inside a transaction
         // and the TryFrom impl might not know all the refs yet
         let existing = self.get_product_by_id(&product.id);
+        let old = match existing {
+            Some(p) => Some(entity_hash(PRODUCT, &api::symbology::Product::from(&p))?),
+            None => None,
+        };
         let inner = self.hydrate_product_inner(product)?;
         // atomic section--all operations must succeed for txn to be considered uncorrupted
         self.corrupted = true;
@@ -341,6 +489,12 @@ impl Txn {
                 Arc::make_mut(&mut self.index).insert(market);
             }
         }
+        // referers are rehydrated from their own API form, which refers to
+        // products by id, so only this product's hash changes
+        self.rehash(
+            old,
+            Some(entity_hash(PRODUCT, &api::symbology::Product::from(&product))?),
+        );
         self.corrupted = false;
         self.num_products_added += 1;
         Ok(product)
@@ -483,16 +637,43 @@ impl Txn {
 
     pub fn remove_product(&mut self, product: &ProductId) -> Result<()> {
         let product = self.find_product_by_id(product)?;
-        Ok(product.remove(
+        let old = entity_hash(PRODUCT, &api::symbology::Product::from(&product))?;
+        product.remove(
             Arc::make_mut(&mut self.product_by_name),
             Arc::make_mut(&mut self.product_by_id),
-        ))
+        );
+        self.rehash(Some(old), None);
+        Ok(())
     }
 
     pub fn add_market(&mut self, market: api::symbology::Market) -> Result<MarketRef> {
         // manually construct the inner ref type, because we are inside a transaction
         // and the TryFrom impl might not know all the refs yet
         let inner = self.hydrate_market_inner(market)?;
+        self.insert_market(inner)
+    }
+
+    /// Same as add_market, unless the process's symbology filter rejects
+    /// the market, see `partial::set_filter`
+    pub fn add_market_filtered(
+        &mut self,
+        market: api::symbology::Market,
+    ) -> Result<Option<MarketRef>> {
+        let inner = self.hydrate_market_inner(market)?;
+        if let Some(filter) = partial::filter() {
+            if !filter.admits(&inner) {
+                partial::note_filtered();
+                return Ok(None);
+            }
+        }
+        self.insert_market(inner).map(Some)
+    }
+
+    fn insert_market(&mut self, inner: MarketInner) -> Result<MarketRef> {
+        let old = match self.get_market_by_id(&inner.id) {
+            Some(m) => Some(entity_hash(MARKET, &api::symbology::Market::from(m))?),
+            None => None,
+        };
         // atomic section--both operations must succeed for txn to be considered uncorrupted
         self.corrupted = true;
         let market = MarketRef::insert(
@@ -503,6 +684,10 @@ impl Txn {
         )?;
         Arc::make_mut(&mut self.index).insert(market.clone());
         self.corrupted = false;
+        self.rehash(
+            old,
+            Some(entity_hash(MARKET, &api::symbology::Market::from(market))?),
+        );
         self.num_markets_added += 1;
         Ok(market)
     }
@@ -556,6 +741,7 @@ impl Txn {
 
     pub fn remove_market(&mut self, market: &MarketId) -> Result<()> {
         let market = self.find_market_by_id(market)?;
+        let old = entity_hash(MARKET, &api::symbology::Market::from(market))?;
         // atomic section--both operations must succeed for txn to be considered uncorrupted
         self.corrupted = true;
         Arc::make_mut(&mut self.index).remove(&market);
@@ -564,87 +750,82 @@ impl Txn {
             Arc::make_mut(&mut self.market_by_id),
         );
         self.corrupted = false;
+        self.rehash(Some(old), None);
         Ok(())
     }
 
     /// Updates are idempotent; symbology update replays should be harmless
     #[cfg(feature = "netidx")]
     pub fn apply(&mut self, up: &SymbologyUpdateKind) -> Result<()> {
+        self.apply_with_progress(up, |_| ())
+    }
+
+    /// Same as apply, but report progress: for a snapshot after
+    /// decompressing it, every `PROGRESS_INTERVAL` updates, and when done;
+    /// for any other update as a single entity once it is applied
+    #[cfg(feature = "netidx")]
+    pub fn apply_with_progress(
+        &mut self,
+        up: &SymbologyUpdateKind,
+        mut progress: impl FnMut(&LoadProgress),
+    ) -> Result<()> {
         use api::symbology::SymbologyUpdateKind::*;
-        match up {
+        let start = Instant::now();
+        let res = match up {
             AddRoute(route) => self.add_route(route.clone()).map(|_| ()),
             RemoveRoute(route) => self.remove_route(route),
             AddVenue(venue) => self.add_venue(venue.clone()).map(|_| ()),
             RemoveVenue(venue) => self.remove_venue(venue),
             AddProduct(product) => self.add_product(product.clone()).map(|_| ()),
             RemoveProduct(product) => self.remove_product(product),
-            AddMarket(market) => self.add_market(market.clone()).map(|_| ()),
+            AddMarket(market) => self.add_market_filtered(market.clone()).map(|_| ()),
             RemoveMarket(market) => self.remove_market(market),
             SnapshotUnchanged(_md5) => {
-                // CR alee: add an option to check md5 hash against current txn dump,
-                // for correctness verification; honestly we probably need a better
-                // way of keeping a hash of the current state of the symbology,
-                // something that can be quickly update on each change; then this
-                // check here can just be an Eq;
-                //
-                // see https://kerkour.com/fast-hashing-algorithms
-                // cf collision-resistant not necessarily cryptographic
+                // the md5 is of the publisher's squashed dump, which can't be
+                // checked without dumping; publishers that also send
+                // `content_hash` can be checked in O(1) with
+                // `verify_content_hash`
                 Ok(())
             }
             Snapshot { original_length, compressed } => {
-                thread_local! {
-                    static BUF: RefCell<BytesMut> = RefCell::new(BytesMut::new());
-                }
-                pool!(pool_updates, Vec<SymbologyUpdateKind>, 10, 100_000);
+                let mut updates = unpack_snapshot(*original_length, &compressed[..])?;
                 let original_length = *original_length;
-                if original_length > compressed.len() << 6
-                    || original_length < compressed.len()
-                {
-                    bail!("suspicious looking original length {}", original_length)
-                }
-                let mut updates = BUF.with(|buf| {
-                    let mut buf = buf.borrow_mut();
-                    buf.resize(original_length, 0u8);
-                    let len =
-                        zstd::bulk::decompress_to_buffer(&compressed[..], &mut buf[..])?;
-                    if len != original_length {
-                        bail!(
-                            "unexpected decompressed length {} expected {}",
-                            len,
-                            original_length
-                        )
-                    }
-                    let mut updates = pool_updates().take();
-                    loop {
-                        let rem = buf.remaining();
-                        if rem == 0 {
-                            break Ok::<_, anyhow::Error>(updates)
-                        }
-                        match Pack::decode(&mut *buf) {
-                            Ok(up) => updates.push(up),
-                            Err(e) => {
-                                // make sure len_wrapped_decode skipped the bad message
-                                if buf.remaining() < rem {
-                                    warn!("failed to unpack symbology update {:?}, skipping", e);
-                                } else {
-                                    break Err(e.into())
-                                }
-                            }
-                        }
-                    }
-                })?;
-                for up in updates.drain(..) {
+                let mut p = LoadProgress {
+                    entities_applied: 0,
+                    entities_total: updates.len(),
+                    bytes_decompressed: original_length,
+                    elapsed: start.elapsed(),
+                };
+                progress(&p);
+                for (i, up) in updates.drain(..).enumerate() {
                     if let Err(e) = self.apply(&up) {
                         warn!(
                             "could not apply symbology update from snapshot {:?} {:?}",
                             up, e
                         )
                     }
+                    if (i + 1) % PROGRESS_INTERVAL == 0 {
+                        p.entities_applied = i + 1;
+                        p.elapsed = start.elapsed();
+                        progress(&p);
+                    }
                 }
-                Ok(())
+                p.entities_applied = p.entities_total;
+                p.elapsed = start.elapsed();
+                progress(&p);
+                return Ok(());
             }
             Unknown => Ok(()),
+        };
+        if res.is_ok() {
+            progress(&LoadProgress {
+                entities_applied: 1,
+                entities_total: 1,
+                bytes_decompressed: 0,
+                elapsed: start.elapsed(),
+            });
         }
+        res
     }
 
     #[cfg(feature = "netidx")]
@@ -668,23 +849,100 @@ impl Txn {
     /// dump the current symbology as of Txn to a series of symbology updates
     #[cfg(feature = "netidx")]
     pub fn dump(&self) -> Pooled<Vec<SymbologyUpdateKind>> {
-        pool!(pool_pset, FxHashSet<ProductRef>, 2, 1_000_000);
         pool!(pool_update, Vec<SymbologyUpdateKind>, 2, 1_000_000);
-        let mut pset = pool_pset().take();
         let mut updates = pool_update().take();
-        for (_, venue) in &*self.venue_by_id {
-            updates.push(SymbologyUpdateKind::AddVenue((**venue).clone()));
-        }
-        for (_, route) in &*self.route_by_id {
-            updates.push(SymbologyUpdateKind::AddRoute((**route).clone()));
-        }
-        for (_, product) in &*self.product_by_id {
-            self.dump_product(&mut pset, &mut updates, &product);
+        updates.extend(self.dump_iter());
+        updates
+    }
+
+    /// the same updates as `dump`, in the same order, generated one at a
+    /// time; only the set of products already emitted is held in memory
+    #[cfg(feature = "netidx")]
+    pub fn dump_iter(&self) -> impl Iterator<Item = SymbologyUpdateKind> + '_ {
+        let mut pset: FxHashSet<ProductRef> = FxHashSet::default();
+        let venues = (&*self.venue_by_id)
+            .into_iter()
+            .map(|(_, venue)| SymbologyUpdateKind::AddVenue((**venue).clone()));
+        let routes = (&*self.route_by_id)
+            .into_iter()
+            .map(|(_, route)| SymbologyUpdateKind::AddRoute((**route).clone()));
+        let products =
+            (&*self.product_by_id).into_iter().flat_map(move |(_, product)| {
+                // a product and any products it references not yet emitted
+                let mut updates = vec![];
+                self.dump_product(&mut pset, &mut updates, product);
+                updates
+            });
+        let markets = (&*self.market_by_id)
+            .into_iter()
+            .map(|(_, market)| SymbologyUpdateKind::AddMarket((*market).into()));
+        venues.chain(routes).chain(products).chain(markets)
+    }
+
+    /// write the dump to `w` as concatenated packed updates, the same
+    /// encoding as the uncompressed body of `dump_squashed`, with bounded
+    /// memory; returns the number of updates and bytes written
+    #[cfg(feature = "netidx")]
+    pub fn dump_to(&self, mut w: impl std::io::Write) -> Result<(usize, usize)> {
+        let mut buf = BytesMut::new();
+        let (mut n, mut bytes) = (0, 0);
+        for up in self.dump_iter() {
+            Pack::encode(&up, &mut buf)?;
+            n += 1;
+            if buf.len() >= DUMP_CHUNK {
+                w.write_all(&buf)?;
+                bytes += buf.len();
+                buf.clear();
+            }
         }
-        for (_, market) in &*self.market_by_id {
-            updates.push(SymbologyUpdateKind::AddMarket((*market).into()));
+        w.write_all(&buf)?;
+        w.flush()?;
+        Ok((n, bytes + buf.len()))
+    }
+
+    /// like `dump_to`, but zstd compressed at `level` as it is written
+    #[cfg(feature = "netidx")]
+    pub fn dump_compressed_to(
+        &self,
+        w: impl std::io::Write,
+        level: i32,
+    ) -> Result<(usize, usize)> {
+        let mut enc = zstd::stream::write::Encoder::new(w, level)?;
+        let res = self.dump_to(&mut enc)?;
+        enc.finish()?;
+        Ok(res)
+    }
+
+    /// like `dump_to`, for an async writer.  Updates are encoded in chunks
+    /// between writes, so the txn is borrowed across awaits.
+    #[cfg(feature = "netidx")]
+    pub async fn dump_to_async<W>(&self, w: &mut W) -> Result<(usize, usize)>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::AsyncWriteExt;
+        let mut buf = BytesMut::new();
+        let (mut n, mut bytes) = (0, 0);
+        let mut updates = self.dump_iter();
+        loop {
+            let mut done = true;
+            for up in updates.by_ref() {
+                Pack::encode(&up, &mut buf)?;
+                n += 1;
+                if buf.len() >= DUMP_CHUNK {
+                    done = false;
+                    break;
+                }
+            }
+            w.write_all(&buf).await?;
+            bytes += buf.len();
+            buf.clear();
+            if done {
+                break;
+            }
         }
-        updates
+        w.flush().await?;
+        Ok((n, bytes))
     }
 
     /// dump the symbology db in a squashed form, return the md5 sum and
@@ -738,6 +996,48 @@ impl Txn {
     }
 }
 
+/// Decompress and decode the updates in a squashed snapshot, skipping any
+/// that fail to decode
+#[cfg(feature = "netidx")]
+pub(super) fn unpack_snapshot(
+    original_length: usize,
+    compressed: &[u8],
+) -> Result<Pooled<Vec<SymbologyUpdateKind>>> {
+    thread_local! {
+        static BUF: RefCell<BytesMut> = RefCell::new(BytesMut::new());
+    }
+    pool!(pool_updates, Vec<SymbologyUpdateKind>, 10, 100_000);
+    if original_length > compressed.len() << 6 || original_length < compressed.len() {
+        bail!("suspicious looking original length {}", original_length)
+    }
+    BUF.with(|buf| {
+        let mut buf = buf.borrow_mut();
+        buf.resize(original_length, 0u8);
+        let len = zstd::bulk::decompress_to_buffer(compressed, &mut buf[..])?;
+        if len != original_length {
+            bail!("unexpected decompressed length {} expected {}", len, original_length)
+        }
+        let mut updates = pool_updates().take();
+        loop {
+            let rem = buf.remaining();
+            if rem == 0 {
+                break Ok::<_, anyhow::Error>(updates);
+            }
+            match Pack::decode(&mut *buf) {
+                Ok(up) => updates.push(up),
+                Err(e) => {
+                    // make sure len_wrapped_decode skipped the bad message
+                    if buf.remaining() < rem {
+                        warn!("failed to unpack symbology update {:?}, skipping", e);
+                    } else {
+                        break Err(e.into());
+                    }
+                }
+            }
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -819,4 +1119,73 @@ mod tests {
         txn.commit().unwrap();
         Ok(())
     }
+
+    /// The content hash depends on what is in the symbology, not the order
+    /// it was added in
+    #[test]
+    fn test_content_hash() -> Result<()> {
+        let build = |fiats: &[&str]| -> Result<u64> {
+            let mut txn = Txn::empty();
+            txn.add_route(RouteRef::new("DIRECT")?)?;
+            for fiat in fiats {
+                txn.add_product(ProductRef::new(fiat, ProductKind::Fiat)?)?;
+            }
+            Ok(txn.content_hash())
+        };
+        let hash = build(&["USD", "EUR", "JPY"])?;
+        assert_eq!(hash, build(&["JPY", "USD", "EUR"])?);
+        assert_ne!(hash, build(&["USD", "EUR"])?);
+        let mut txn = Txn::empty();
+        txn.add_route(RouteRef::new("DIRECT")?)?;
+        txn.add_product(ProductRef::new("USD", ProductKind::Fiat)?)?;
+        txn.add_product(ProductRef::new("EUR", ProductKind::Fiat)?)?;
+        let before = txn.content_hash();
+        let jpy = ProductRef::new("JPY", ProductKind::Fiat)?;
+        let jpy_id = jpy.id;
+        txn.add_product(jpy.clone())?;
+        assert_eq!(txn.content_hash(), hash);
+        // re-adding the same product changes nothing
+        txn.add_product(jpy)?;
+        assert_eq!(txn.content_hash(), hash);
+        txn.remove_product(&jpy_id)?;
+        assert_eq!(txn.content_hash(), before);
+        txn.verify_content_hash(before)?;
+        assert!(txn.verify_content_hash(hash).is_err());
+        Ok(())
+    }
+
+    /// The streaming dump must produce exactly the updates of `dump`
+    #[cfg(feature = "netidx")]
+    #[test]
+    fn test_streaming_dump() -> Result<()> {
+        let mut txn = Txn::begin();
+        let direct = txn.add_route(RouteRef::new("DIRECT")?)?;
+        let test = txn.add_venue(VenueRef::new("TEST")?)?;
+        let usd = txn.add_product(ProductRef::new("USD", ProductKind::Fiat)?)?;
+        let eur = txn.add_product(ProductRef::new("EUR", ProductKind::Fiat)?)?;
+        txn.add_market(MarketRef::exchange(
+            eur,
+            usd,
+            test,
+            direct,
+            "EURUSD",
+            MarketInfo::Test(TestMarketInfo {
+                tick_size: Default::default(),
+                step_size: Default::default(),
+                is_delisted: false,
+            }),
+        )?)?;
+        let mut out = vec![];
+        let (n, bytes) = txn.dump_to(&mut out)?;
+        assert_eq!(bytes, out.len());
+        let mut buf = Bytes::from(out);
+        let mut streamed = vec![];
+        while buf.has_remaining() {
+            streamed.push(<SymbologyUpdateKind as Pack>::decode(&mut buf)?);
+        }
+        assert_eq!(n, streamed.len());
+        let dumped = txn.dump();
+        assert_eq!(format!("{:?}", &*dumped), format!("{streamed:?}"));
+        Ok(())
+    }
 }
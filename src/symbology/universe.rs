@@ -0,0 +1,66 @@
+//! Symbol universes: a named set of markets defined by a static list, a
+//! symbology query, or a file, which can be re-resolved as symbology or the
+//! definition changes.
+
+use super::{index::Set, MarketIndex, MarketRef, StaticRef};
+use anyhow::{Context, Result};
+use api::symbology::query::Query;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub enum Universe {
+    /// a fixed list of market names or ids
+    Static(Vec<String>),
+    /// all markets matching the query, e.g. all active CME equity index futures
+    Query(Query),
+    /// a file with one market name or id per line; blank lines and lines
+    /// beginning with `#` are ignored.  Re-read on each resolve.
+    File(PathBuf),
+}
+
+impl Universe {
+    /// Resolve the universe against the current symbology
+    pub fn resolve(&self) -> Result<Set<MarketRef>> {
+        let mut set = Set::new();
+        match self {
+            Universe::Static(names) => {
+                for name in names {
+                    set.insert_cow(MarketRef::find_by_name_or_id(name)?);
+                }
+            }
+            Universe::Query(q) => set = MarketIndex::current().query(q),
+            Universe::File(path) => {
+                let contents = std::fs::read_to_string(path).with_context(|| {
+                    format!("reading universe file {}", path.display())
+                })?;
+                for line in contents.lines().map(str::trim) {
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    set.insert_cow(MarketRef::find_by_name_or_id(line)?);
+                }
+            }
+        }
+        Ok(set)
+    }
+}
+
+/// The difference between two resolutions of a universe
+#[derive(Debug, Clone, Default)]
+pub struct UniverseDiff {
+    pub added: Vec<MarketRef>,
+    pub removed: Vec<MarketRef>,
+}
+
+impl UniverseDiff {
+    pub fn new(prev: &Set<MarketRef>, next: &Set<MarketRef>) -> Self {
+        Self {
+            added: next.diff(prev).into_iter().copied().collect(),
+            removed: prev.diff(next).into_iter().copied().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}